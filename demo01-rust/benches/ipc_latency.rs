@@ -0,0 +1,209 @@
+//! 跨进程通信的往返延迟
+//!
+//! ```shell
+//! cargo bench --bench ipc_latency
+//! cargo bench --bench ipc_latency -- --save-baseline main
+//! cargo bench --bench ipc_latency -- --baseline main
+//! ```
+//!
+//! 每个测试先 `fork` 出一个子进程作为回声端, 父进程计时的是 "发出去再收到回复" 的完整往返:
+//!
+//! - `round_trip`: 64 字节的消息经过管道, Unix 域套接字和 `shm_ring` 共享内存环形缓冲区
+//! - `wake`: 不带数据, 只比较唤醒对方进程的方式, eventfd 的 `read`/`write` 和共享内存上的 futex
+//!
+//! 往返时间包含两次唤醒和两次上下文切换, 单程延迟大约是它的一半. 结果受 CPU 调度影响很大,
+//! 需要稳定的结果时可以用 `taskset -c 2,3 cargo bench ...` 把进程固定在两个核上.
+
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use demo01_rust::shm_ring::{Consumer, Producer};
+use rustix::event::{eventfd, EventfdFlags};
+use rustix::mm::{mmap_anonymous, munmap, MapFlags, ProtFlags};
+use rustix::thread::futex;
+
+const MESSAGE: [u8; 64] = [7; 64];
+
+fn config() -> Criterion {
+    Criterion::default()
+        .sample_size(50)
+        .warm_up_time(Duration::from_secs(1))
+        .measurement_time(Duration::from_secs(3))
+        .noise_threshold(0.05)
+}
+
+/// 回声子进程, drop 时杀掉并回收
+struct Echo(libc::pid_t);
+
+impl Echo {
+    /// 在基准测试开始前, 还没有其他线程的时候调用. 子进程只执行 `echo`, 不返回.
+    fn fork(echo: impl FnOnce()) -> Echo {
+        match unsafe { libc::fork() } {
+            -1 => panic!("fork failed: {}", std::io::Error::last_os_error()),
+            0 => {
+                echo();
+                unsafe { libc::_exit(0) }
+            }
+            pid => Echo(pid),
+        }
+    }
+}
+
+impl Drop for Echo {
+    fn drop(&mut self) {
+        unsafe {
+            libc::kill(self.0, libc::SIGKILL);
+            libc::waitpid(self.0, ptr::null_mut(), 0);
+        }
+    }
+}
+
+/// 子进程从 `reader` 读满一条消息后原样写回 `writer`, 对端关闭时退出
+fn echo_stream(mut reader: impl Read, mut writer: impl Write) {
+    let mut buf = [0u8; MESSAGE.len()];
+    while reader.read_exact(&mut buf).is_ok() {
+        if writer.write_all(&buf).is_err() {
+            break;
+        }
+    }
+}
+
+fn round_trip(mut writer: impl Write, mut reader: impl Read) -> [u8; MESSAGE.len()] {
+    let mut buf = [0u8; MESSAGE.len()];
+    writer.write_all(&MESSAGE).unwrap();
+    reader.read_exact(&mut buf).unwrap();
+    buf
+}
+
+fn bench_round_trip(c: &mut Criterion) {
+    let mut group = c.benchmark_group("round_trip");
+    group.throughput(Throughput::Bytes(2 * MESSAGE.len() as u64));
+
+    {
+        let (request_rx, mut request_tx) = std::io::pipe().unwrap();
+        let (mut response_rx, response_tx) = std::io::pipe().unwrap();
+        let _echo = Echo::fork(|| echo_stream(&request_rx, &response_tx));
+        group.bench_function("pipe", |b| b.iter(|| round_trip(&mut request_tx, &mut response_rx)));
+    }
+
+    {
+        let (mut parent, child) = UnixStream::pair().unwrap();
+        let _echo = Echo::fork(|| echo_stream(&child, &child));
+        group.bench_function("unix_socket", |b| {
+            b.iter(|| {
+                let mut buf = [0u8; MESSAGE.len()];
+                parent.write_all(&MESSAGE).unwrap();
+                parent.read_exact(&mut buf).unwrap();
+                buf
+            })
+        });
+    }
+
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let (requests, responses) = (dir.path().join("requests.bin"), dir.path().join("responses.bin"));
+        let mut producer = Producer::create(&requests, 1 << 16).unwrap();
+        let mut consumer = Consumer::create(&responses, 1 << 16).unwrap();
+        let _echo = Echo::fork(|| {
+            let mut consumer = Consumer::open(&requests).unwrap();
+            let mut producer = Producer::open(&responses).unwrap();
+            while let Ok(record) = consumer.recv() {
+                producer.push(&record).unwrap();
+            }
+        });
+        group.bench_function("shm_ring", |b| {
+            b.iter(|| {
+                producer.push(&MESSAGE).unwrap();
+                consumer.recv().unwrap().len()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// 共享匿名映射中的一个 futex 字, fork 之后父子进程看到的是同一块内存
+struct SharedWord(*mut AtomicU32);
+
+impl SharedWord {
+    fn new() -> SharedWord {
+        let ptr = unsafe {
+            mmap_anonymous(ptr::null_mut(), size_of::<AtomicU32>(), ProtFlags::READ | ProtFlags::WRITE, MapFlags::SHARED).unwrap()
+        };
+        SharedWord(ptr as *mut AtomicU32)
+    }
+
+    fn get(&self) -> &AtomicU32 {
+        unsafe { &*self.0 }
+    }
+}
+
+impl Drop for SharedWord {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = munmap(self.0 as *mut _, size_of::<AtomicU32>());
+        }
+    }
+}
+
+/// 0: 轮到父进程, 1: 轮到子进程
+const PARENT_TURN: u32 = 0;
+const CHILD_TURN: u32 = 1;
+
+/// 把字改成 `next` 并唤醒对方, 然后等待对方改回来
+fn futex_hand_over(word: &AtomicU32, next: u32) {
+    word.store(next, Ordering::Release);
+    futex::wake(word, futex::Flags::empty(), 1).unwrap();
+    while word.load(Ordering::Acquire) == next {
+        // EAGAIN 说明对方已经改过了, EINTR 只需要重新检查
+        let _ = futex::wait(word, futex::Flags::empty(), next, None);
+    }
+}
+
+fn bench_wake(c: &mut Criterion) {
+    let mut group = c.benchmark_group("wake");
+
+    {
+        let ping = eventfd(0, EventfdFlags::CLOEXEC).unwrap();
+        let pong = eventfd(0, EventfdFlags::CLOEXEC).unwrap();
+        let _echo = Echo::fork(|| {
+            let mut buf = [0u8; 8];
+            while rustix::io::read(&ping, &mut buf).is_ok() {
+                rustix::io::write(&pong, &1u64.to_ne_bytes()).unwrap();
+            }
+        });
+        group.bench_function("eventfd", |b| {
+            let mut buf = [0u8; 8];
+            b.iter(|| {
+                rustix::io::write(&ping, &1u64.to_ne_bytes()).unwrap();
+                rustix::io::read(&pong, &mut buf).unwrap();
+            })
+        });
+    }
+
+    {
+        let word = SharedWord::new();
+        word.get().store(PARENT_TURN, Ordering::Relaxed);
+        let _echo = Echo::fork(|| {
+            let word = word.get();
+            while word.load(Ordering::Acquire) != CHILD_TURN {
+                let _ = futex::wait(word, futex::Flags::empty(), PARENT_TURN, None);
+            }
+            loop {
+                futex_hand_over(word, PARENT_TURN);
+            }
+        });
+        group.bench_function("futex", |b| b.iter(|| futex_hand_over(word.get(), CHILD_TURN)));
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = config();
+    targets = bench_round_trip, bench_wake
+}
+criterion_main!(benches);
@@ -0,0 +1,139 @@
+//! 进程内同步原语的对比
+//!
+//! ```shell
+//! cargo bench --bench sync_primitives
+//! # 保存为基线, 之后的运行和它比较
+//! cargo bench --bench sync_primitives -- --save-baseline main
+//! cargo bench --bench sync_primitives -- --baseline main
+//! ```
+//!
+//! - `mutex`: `std::sync::Mutex` 和 futex 实现的 `IpcMutexRaw`, 分别测无竞争和两个线程竞争
+//! - `channel`: `mpsc::channel` 和 `mpsc::sync_channel` 在两个线程之间的往返延迟和单向吞吐量
+//!
+//! 报告在 `target/criterion/report/index.html`, 每次运行的原始数据在 `target/criterion/<group>/<bench>/new/`.
+
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::sync::{Barrier, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use demo01_rust::rutex::IpcMutex;
+
+/// 固定采样参数, 不同机器, 不同时间的结果才有可比性
+fn config() -> Criterion {
+    Criterion::default()
+        .sample_size(50)
+        .warm_up_time(Duration::from_secs(1))
+        .measurement_time(Duration::from_secs(3))
+        .noise_threshold(0.03)
+}
+
+/// 两个线程各自执行 `iters / 2` 次 `op`, 返回总耗时
+fn contended(iters: u64, op: impl Fn() + Sync) -> Duration {
+    let barrier = Barrier::new(2);
+    thread::scope(|scope| {
+        let worker = scope.spawn(|| {
+            barrier.wait();
+            for _ in 0..iters / 2 {
+                op();
+            }
+        });
+        barrier.wait();
+        let start = Instant::now();
+        for _ in 0..iters - iters / 2 {
+            op();
+        }
+        worker.join().unwrap();
+        start.elapsed()
+    })
+}
+
+fn bench_mutex(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mutex");
+    let std_mutex = Mutex::new(0u64);
+    let ipc_mutex = IpcMutex::new(0u64);
+
+    group.bench_function("std/uncontended", |b| b.iter(|| *std_mutex.lock().unwrap() += 1));
+    group.bench_function("ipc/uncontended", |b| b.iter(|| *ipc_mutex.lock() += 1));
+    group.bench_function("std/contended", |b| b.iter_custom(|iters| contended(iters, || *std_mutex.lock().unwrap() += 1)));
+    group.bench_function("ipc/contended", |b| b.iter_custom(|iters| contended(iters, || *ipc_mutex.lock() += 1)));
+    group.finish();
+}
+
+/// 对端线程把收到的值原样发回, 发送端被 drop 时退出
+fn echo_thread<S: Send + 'static>(requests: Receiver<u64>, responses: S, send: fn(&S, u64)) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        for value in requests {
+            send(&responses, value);
+        }
+    })
+}
+
+fn bench_channel(c: &mut Criterion) {
+    let mut group = c.benchmark_group("channel");
+
+    // 往返延迟
+    {
+        let (tx, requests) = mpsc::channel();
+        let (responses, rx) = mpsc::channel();
+        let echo = echo_thread(requests, responses, |s: &Sender<u64>, v| s.send(v).unwrap());
+        group.bench_function("round_trip/mpsc", |b| {
+            b.iter(|| {
+                tx.send(1).unwrap();
+                rx.recv().unwrap()
+            })
+        });
+        drop(tx);
+        echo.join().unwrap();
+    }
+    for bound in [0, 1] {
+        let (tx, requests) = mpsc::sync_channel(bound);
+        let (responses, rx) = mpsc::sync_channel(bound);
+        let echo = echo_thread(requests, responses, |s: &SyncSender<u64>, v| s.send(v).unwrap());
+        group.bench_function(BenchmarkId::new("round_trip/sync_channel", bound), |b| {
+            b.iter(|| {
+                tx.send(1).unwrap();
+                rx.recv().unwrap()
+            })
+        });
+        drop(tx);
+        echo.join().unwrap();
+    }
+
+    // 单向吞吐量: 一批消息全部被接收线程收到为止
+    const BATCH: u64 = 10_000;
+    group.throughput(Throughput::Elements(BATCH));
+    group.bench_function("throughput/mpsc", |b| {
+        b.iter(|| {
+            let (tx, rx) = mpsc::channel();
+            let receiver = thread::spawn(move || rx.iter().count());
+            for i in 0..BATCH {
+                tx.send(i).unwrap();
+            }
+            drop(tx);
+            receiver.join().unwrap()
+        })
+    });
+    for bound in [1, 1024] {
+        group.bench_function(BenchmarkId::new("throughput/sync_channel", bound), |b| {
+            b.iter(|| {
+                let (tx, rx) = mpsc::sync_channel(bound);
+                let receiver = thread::spawn(move || rx.iter().count());
+                for i in 0..BATCH {
+                    tx.send(i).unwrap();
+                }
+                drop(tx);
+                receiver.join().unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = config();
+    targets = bench_mutex, bench_channel
+}
+criterion_main!(benches);
@@ -1,118 +1,79 @@
-#![allow(unused)]
+//! 手写 Future 和执行器
+//!
+//! `timer_future::TimeFuture` 是最朴素的定时器: 每个定时器开一个线程, 到期后调用 waker.
+//! 完整的执行器和 reactor 整理到了 `demo01_rust::mini_runtime`, 所有定时器共用一个时间轮,
+//! 套接字和 eventfd 通过 epoll 等待, 这里的 `main` 在它上面运行.
+//!
+//! ```shell
+//! cargo run --example async_future
+//! ```
 
-use std::{future::Future, time::Duration};
+use std::time::{Duration, Instant};
+
+use demo01_rust::mini_runtime::{self, sleep, spawn};
 
-use executor::Executor;
 #[cfg(test)]
 mod future_test {
-    use std::future::Future;
-    use std::io::{Read, Write};
-    use std::net::{Shutdown, TcpListener, TcpStream};
     use std::str::from_utf8;
-    use std::thread;
-    use std::time::Duration;
 
-    fn handle_client(mut stream: TcpStream) {
+    use demo01_rust::mini_runtime::{block_on, spawn, TcpListener, TcpStream};
+    use futures::io::{AsyncReadExt, AsyncWriteExt};
+
+    async fn handle_client(mut stream: TcpStream) {
         let mut data = [0 as u8; 50]; // 50 byte buffer
-        while match stream.read(&mut data) {
-            Ok(size) => {
-                stream.write(&data[0..size]).unwrap();
-                true
-            }
-            Err(_) => {
-                println!(
-                    "An error occured, terminating connection with {}",
-                    stream.peer_addr().unwrap()
-                );
-                stream.shutdown(Shutdown::Both).unwrap();
-                false
+        loop {
+            match stream.read(&mut data).await {
+                Ok(0) => break,
+                Ok(size) => stream.write_all(&data[0..size]).await.unwrap(),
+                Err(_) => {
+                    println!("An error occured, terminating connection with {}", stream.peer_addr().unwrap());
+                    break;
+                }
             }
-        } {}
+        }
     }
 
     #[test]
     fn socket_create_tcp_listener() {
-        println!("==");
-        let socket_path = "127.0.0.1:3333";
-        let listener = TcpListener::bind(socket_path).unwrap();
-
-        let server_thread = thread::spawn(move || {
-            for stream in listener.incoming() {
-                match stream {
-                    Ok(mut stream) => {
-                        println!("New connection: {}", stream.peer_addr().unwrap());
-                        thread::spawn(move || {
-                            handle_client(stream);
-                        });
-                    }
-                    Err(e) => {
-                        println!("Failed to establish a connection: {}", e);
-                    }
-                }
-            }
-        });
-
-        thread::sleep(Duration::from_millis(2));
-        let client_thread = thread::spawn(move || {
-            match TcpStream::connect(socket_path) {
-                Ok(mut stream) => {
-                    let msg = b"Hello!";
-                    stream.write(msg).unwrap();
-                    let mut data = [0 as u8; 6]; // using 6 byte buffer
-                    match stream.read_exact(&mut data) {
-                        Ok(_) => {
-                            if &data == msg {
-                                println!("Reply is ok!");
-                            } else {
-                                let text = from_utf8(&data).unwrap();
-                                println!("Unexpectd reply {}", text);
-                            }
-                        }
-                        Err(e) => {
-                            println!("Failed to receive data: {}", e);
+        block_on(async {
+            // 端口 0 由系统分配, 测试可以并行运行
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+
+            spawn(async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, peer)) => {
+                            println!("New connection: {}", peer);
+                            spawn(handle_client(stream));
                         }
+                        Err(e) => println!("Failed to establish a connection: {}", e),
                     }
                 }
-                Err(e) => {
-                    println!("Failed to connect: {}", e)
-                }
-            }
+            });
+
+            let client = spawn(async move {
+                let mut stream = TcpStream::connect(addr).await.unwrap();
+                let msg = b"Hello!";
+                stream.write_all(msg).await.unwrap();
+                let mut data = [0 as u8; 6]; // using 6 byte buffer
+                stream.read_exact(&mut data).await.unwrap();
+                assert_eq!(&data, msg, "Unexpectd reply {}", from_utf8(&data).unwrap());
+                println!("Reply is ok!");
+            });
+            // 服务端任务一直在循环, 客户端结束后随运行时一起销毁
+            client.await;
         });
-        server_thread.join().unwrap();
-        client_thread.join().unwrap();
     }
 
     #[test]
-    fn future_base() {}
-}
+    fn future_base() {
+        use super::timer_future::TimeFuture;
+        use std::time::Duration;
 
-// pub mod runtime {
-//     use std::{collections::HashMap, sync::Arc, task::Waker};
-
-//     pub struct Task {
-
-//     }
-//     pub struct Reactor {
-
-//     }
-//     pub struct Executor {
-//         task_map: HashMap<usize, Arc<Task>>,
-//     }
-//     impl Executor {
-//         pub fn new() -> Executor {
-//             Self {
-//                 task_map: HashMap::new(),
-//             }
-//         }
-//         pub fn run(&mut self) {
-//             for task in self.task_map.iter() {
-//                 let waker = Waker::from(Arc::clone(task));
-//                 waker.wake();
-//             }
-//         }
-//     }
-
-// }
+        block_on(TimeFuture::new(Duration::from_millis(1)));
+    }
+}
 
 pub mod timer_future {
     use std::{
@@ -124,8 +85,6 @@ pub mod timer_future {
         time::Duration,
     };
 
-    use futures::FutureExt;
-
     pub struct TimeFuture {
         shared_state: Arc<Mutex<SharedState>>,
     }
@@ -167,58 +126,24 @@ pub mod timer_future {
     }
 }
 
-pub mod executor {
-    use crate::timer_future::TimeFuture;
-    use futures::{
-        future::{BoxFuture, FutureExt},
-        task::{waker_ref, ArcWake, UnsafeFutureObj},
-    };
-    use std::{
-        borrow::BorrowMut, clone, collections::VecDeque, future::{Future, IntoFuture}, process::Output, sync::{
-            mpsc::{sync_channel, Receiver, SyncSender},
-            Arc, Mutex,
-        }, task::Context, time::Duration
-    };
-
-    pub struct Executor {
-        has_pending: bool,
-        task_list: Vec<Box<dyn Future<Output = ()> + 'static>>,
-    }
-
-    impl Executor {
-        pub fn new() -> Self {
-            Executor::with_capacity(8)
-        }
-        pub fn with_capacity(num: usize) -> Executor {
-            Executor {
-                task_list: Vec::with_capacity(num),
-                has_pending: false,
-            }
-        }
-        pub fn task(&mut self, task: Box<dyn Future<Output = ()>>) -> &Executor {
-            self.task_list.push(task);
-            self.has_pending = true;
-            self
-        }
-        pub fn run(&mut self) {
-            let a = self.task_list.get(0).unwrap();
-            let b = async {
-                ()
-            };
-            // let is_pending =b.boxed().as_mut().poll(cx).is_pending();
-        }
-    }
-}
 fn main() {
-    let mut executor = Executor::new();
-    executor.task(Box::new(async {
+    let start = Instant::now();
+    mini_runtime::block_on(async {
+        // 一千个定时器只占用时间轮里的槽位, 不会创建一千个线程
+        let sleepers: Vec<_> = (0..1000u64)
+            .map(|i| {
+                spawn(async move {
+                    sleep(Duration::from_millis(i % 100)).await;
+                })
+            })
+            .collect();
         println!("hello1");
-    }));
-    // spanwer.spawn(async {
-    //     println!("hello1");
-    //     timer_future::TimeFuture::new(Duration::new(2, 0)).await;
-    //     println!("done");
-    // });
-    // drop(spanwer);
-    // executor.run();
+        for sleeper in sleepers {
+            sleeper.await;
+        }
+        println!("1000 sleepers done after {:?}", start.elapsed());
+
+        timer_future::TimeFuture::new(Duration::new(1, 0)).await;
+        println!("thread based TimeFuture done after {:?}", start.elapsed());
+    });
 }
@@ -1,116 +1,53 @@
-use std::io::{Read, Write};
-use std::os::unix::net::{UnixListener, UnixStream};
+//! Unix 域套接字上的请求/响应, 协议的实现在 `demo01_rust::ipc_socket`
+//!
+//! ```shell
+//! cargo run --example ipc_socket server
+//! cargo run --example ipc_socket client
+//! # tokio 版本, 两种客户端/服务端可以混用
+//! cargo run --example ipc_socket async-server
+//! cargo run --example ipc_socket async-client
+//! ```
+//!
+//! 第二个参数可以指定套接字路径, 默认是临时目录下的 `demo01.sock`.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
-use bincode;
-use bincode::{config, Decode, Encode};
 
-// 定义消息枚举
-#[derive(Encode, Decode, Debug, Clone)]
-enum Message {
-    Text(String),
-    Data(Vec<u8>),
-    Command { cmd: String, args: Vec<String> },
-    Ping,
-    Pong,
-}
-
-
-fn send_message(stream: &mut UnixStream, message: Message) -> std::io::Result<()> {
-    let config = config::standard();
-    let res = bincode::encode_to_vec(&message, config).unwrap();
-    println!("{:#?}",res.len());
-
-    // 先发送长度（4字节）
-    stream.write_all(&res.len().to_be_bytes())?;
-    // 再发送数据
-    stream.write_all(&res)?;
-    stream.flush()?;
+use demo01_rust::ipc_socket::asynchronous::{AsyncClient, AsyncServer};
+use demo01_rust::ipc_socket::{blocking, echo, Request};
 
-    Ok(())
+fn requests() -> Vec<Request> {
+    vec![
+        Request::Text("BBBBBBBBBBB".to_string()),
+        Request::Data(vec![1, 2, 3, 4, 5]),
+        Request::Command { cmd: "ls".to_string(), args: vec!["-la".to_string()] },
+        Request::Ping,
+    ]
 }
 
-fn receive_message(stream: &mut UnixStream) -> anyhow::Result<Message> {
-    let mut str = [0u8; 8];
-    stream.read_exact(&mut str)?;
-    let len = u64::from_be_bytes(str) as usize;
-    println!("{:#?}", len);
-
-    let mut buffer = vec![0u8; len];
-    stream.read_exact(&mut buffer)?;
-    let config = config::standard();
-    let decoded: (Message, usize) = bincode::decode_from_slice(&buffer, config).unwrap();
-    println!("{:#?}", decoded);
-    todo!()
-}
+fn run_server(socket_path: &Path) -> anyhow::Result<()> {
+    let server = blocking::Server::bind(socket_path, |request| {
+        println!("📨 收到消息: {:?}", request);
+        echo(request)
+    })?;
+    println!("🚀 服务器启动，监听在: {}", server.path().display());
 
-fn run_server(socket_path: &str) -> anyhow::Result<()> {
-    // 清理可能存在的旧套接字文件
-    let _ = std::fs::remove_file(socket_path);
-
-    // 创建Unix域套接字监听器
-    let listener = UnixListener::bind(socket_path)?;
-    println!("🚀 服务器启动，监听在: {}", socket_path);
-
-    // 接受客户端连接
-    for stream in listener.incoming() {
-        match stream {
-            Ok(mut stream) => {
-                println!("✅ 客户端连接成功");
-
-                // 为每个客户端创建新线程
-                let socket_path = socket_path.to_string();
-                thread::spawn(move || {
-                    if let Err(e) = handle_client(&mut stream) {
-                        eprintln!("客户端处理错误: {}", e);
-                    }
-                });
-            }
-            Err(err) => {
-                eprintln!("❌ 接受连接错误: {}", err);
-                break;
-            }
-        }
-    }
-
-    // 清理套接字文件
-    std::fs::remove_file(socket_path)?;
+    // 回车后关闭服务器
+    let _ = std::io::stdin().read_line(&mut String::new());
+    server.shutdown();
     Ok(())
 }
-fn run_client(socket_path: &str) -> anyhow::Result<()> {
-    // 连接服务器
-    let mut stream = UnixStream::connect(socket_path)?;
-    println!("✅ 连接到服务器: {}", socket_path);
-
-    // 发送各种类型的消息
-    let messages = vec![
-        Message::Text("BBBBBBBBBBB".to_string()),
-        Message::Data(vec![1, 2, 3, 4, 5]),
-        Message::Command {
-            cmd: "ls".to_string(),
-            args: vec!["-la".to_string()],
-        },
-        Message::Ping,
-    ];
 
-    for message in messages {
-        println!("📤 发送: {:?}", message);
-        match send_message(&mut stream, message.clone()) {
-            Ok(_) => {}
-            Err(e) => {eprintln!("send error!")}
-        }
-
-        // 接收响应
-        match receive_message(&mut stream) {
-            Ok(response) => {
-                println!("📥 收到响应: {:?}", response);
-            }
-            Err(e) => {
-                eprintln!("❌ 接收响应错误: {}", e);
-                break;
-            }
-        }
+fn run_client(socket_path: &Path) -> anyhow::Result<()> {
+    let mut client = blocking::Client::connect(socket_path)?;
+    println!("✅ 连接到服务器: {}", socket_path.display());
 
+    for request in requests() {
+        println!("📤 发送: {:?}", request);
+        let response = client.call(request)?;
+        println!("📥 收到响应: {:?}", response);
         thread::sleep(Duration::from_millis(500));
     }
 
@@ -118,58 +55,54 @@ fn run_client(socket_path: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn handle_client(stream: &mut UnixStream) -> anyhow::Result<()> {
-    loop {
-        // 接收消息
-        let message = match receive_message(stream) {
-            Ok(msg) => msg,
-            Err(e) => {
-                println!("客户端断开连接: {}", e);
-                break;
-            }
-        };
-
-        println!("📨 收到消息: {:?}", message);
+async fn run_async_server(socket_path: &Path) -> anyhow::Result<()> {
+    let server = AsyncServer::bind(socket_path)?;
+    println!("🚀 服务器启动，监听在: {}", server.path().display());
+
+    let handler = |request: Request| async move {
+        println!("📨 收到消息: {:?}", request);
+        echo(request)
+    };
+    // 回车后关闭服务器, 已经收到的请求会处理完再退出
+    let shutdown = async {
+        let _ = tokio::task::spawn_blocking(|| std::io::stdin().read_line(&mut String::new())).await;
+    };
+    server.serve(handler, shutdown).await?;
+    Ok(())
+}
 
-        // 根据消息类型处理
-        match message {
-            Message::Text(text) => {
-                let response = Message::Text(format!("ECHO: {}", text));
-                send_message(stream, response)?;
-            }
-            Message::Data(data) => {
-                let response = Message::Data(data.iter().map(|b| b.wrapping_add(1)).collect());
-                send_message(stream, response)?;
-            }
-            Message::Command { cmd, args } => {
-                let response = Message::Text(format!("执行命令: {} {:?}", cmd, args));
-                send_message(stream, response)?;
-            }
-            Message::Ping => {
-                send_message(stream, Message::Pong)?;
-            }
-            Message::Pong => {
-                println!("收到Pong响应");
-            }
-        }
+async fn run_async_client(socket_path: &Path) -> anyhow::Result<()> {
+    let client = Arc::new(AsyncClient::connect(socket_path).await?);
+    println!("✅ 连接到服务器: {}", socket_path.display());
+
+    // 所有请求同时发出, 在同一个连接上 pipelining
+    let calls: Vec<_> = requests()
+        .into_iter()
+        .map(|request| {
+            let client = client.clone();
+            tokio::spawn(async move {
+                println!("📤 发送: {:?}", request);
+                client.call(request).await
+            })
+        })
+        .collect();
+    for call in calls {
+        println!("📥 收到响应: {:?}", call.await??);
     }
 
+    println!("🎉 客户端完成");
     Ok(())
 }
 
-fn main() -> anyhow::Result<()> {
-
-    let socket_path = "/mydata/code/rust-demo/target/demo01.sock";
-
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
     let args: Vec<String> = std::env::args().collect();
-    //
-    if args.len() > 1 && args[1] == "client" {
-        run_client(socket_path)?
-    } else {
-        run_server(socket_path)?
-    }
-
-    run_server(socket_path)?;
+    let socket_path = args.get(2).map(PathBuf::from).unwrap_or_else(|| std::env::temp_dir().join("demo01.sock"));
 
-    Ok(())
-}
\ No newline at end of file
+    match args.get(1).map(String::as_str) {
+        Some("client") => tokio::task::spawn_blocking(move || run_client(&socket_path)).await?,
+        Some("async-server") => run_async_server(&socket_path).await,
+        Some("async-client") => run_async_client(&socket_path).await,
+        _ => tokio::task::spawn_blocking(move || run_server(&socket_path)).await?,
+    }
+}
@@ -1,7 +1,7 @@
 use std::{env, thread};
-use std::os::fd::{AsRawFd, FromRawFd};
-use std::process::Command;
 use std::time::Duration;
+use anyhow::Context;
+use demo01_rust::supervisor::{inherited_fd, ChildSpec};
 use rustix::event;
 use rustix::io::{read, write};
 use rustix::fs::flock;
@@ -44,24 +44,18 @@ fn thread_eventfd() -> anyhow::Result<()> {
 }
 
 
+/// 父进程创建 eventfd, 通过 [ChildSpec::fd] 显式传给两个子进程 (就是当前程序本身), 子进程用 [inherited_fd] 取出
 fn process_eventfd() ->anyhow::Result<()> {
     let op = env::args().collect::<Vec<_>>().get(1).unwrap_or(&"both".to_string()).clone();
 
     match op.as_str() {
         _send @ "send" => {
-
-            let fd_str = env::var("EVENTFD_FD")?;
-            let fd: i32 = fd_str.parse()?;
-            let event_fd = unsafe { std::fs::File::from_raw_fd(fd) };
-            println!("Created eventfd: {:?}", event_fd);
+            let event_fd = inherited_fd("eventfd").context("父进程没有传递 eventfd")?;
+            println!("Inherited eventfd: {:?}", event_fd);
             write(&event_fd, &1u64.to_ne_bytes())?;
         },
         _read @ "read" => {
-            thread::sleep(Duration::from_millis(4));
-
-            let fd_str = env::var("EVENTFD_FD")?;
-            let fd: i32 = fd_str.parse()?;
-            let event_fd = unsafe { std::fs::File::from_raw_fd(fd) };
+            let event_fd = inherited_fd("eventfd").context("父进程没有传递 eventfd")?;
             println!("Main: Waiting for notification...");
             let mut buffer = [0u8; 8];
             read(&event_fd, &mut buffer)?;
@@ -69,10 +63,12 @@ fn process_eventfd() ->anyhow::Result<()> {
             println!("Main: Received value: {}", value);
         },
         _both @ "both" => {
-            let event_fd = event::eventfd(0, event::EventfdFlags::empty())?;
-            let fd_num = event_fd.as_raw_fd().to_string();
-            Command::new("cargo").arg("run").arg("--example").arg("rustix_eventfd").arg("send").env("EVENTFD_FD", fd_num.to_string()).spawn()?;
-            Command::new("cargo").arg("run").arg("--example").arg("rustix_eventfd").arg("read").env("EVENTFD_FD", fd_num.to_string()).spawn()?;
+            let event_fd = event::eventfd(0, event::EventfdFlags::CLOEXEC)?;
+            let exe = env::current_exe()?;
+            let mut reader = ChildSpec::new("read", &exe).arg("read").fd("eventfd", event_fd.try_clone()?).spawn()?;
+            let mut sender = ChildSpec::new("send", &exe).arg("send").fd("eventfd", event_fd).spawn()?;
+            sender.wait()?;
+            reader.wait()?;
         }
         _ => todo!()
     }
@@ -8,6 +8,7 @@ use std::sync::atomic::{AtomicI32, Ordering};
 use std::time::Duration;
 use rustix::mm::{mmap, MapFlags, ProtFlags};
 use rustix::thread::futex;
+use demo01_rust::ipc_rwlock::IpcRwLock;
 
 /// 使用 rustix_futex_sync 中的同步工具
 ///
@@ -53,12 +54,7 @@ fn main() -> anyhow::Result<()>{
         file.set_len(size as u64);
         multi_processor_with_mutex();
     } else if op == "rw" {
-        while !std::fs::exists("rustix_futex_lock.bin")? {
-            std::hint::spin_loop();
-            thread::sleep(Duration::from_secs(1));
-            println!("not exists");
-        }
-        multi_processor_with_wr();
+        multi_processor_with_wr()?;
     }
 
     Ok(())
@@ -130,8 +126,59 @@ fn multi_processor_with_mutex() -> anyhow::Result<()>{
     Ok(())
 }
 
-fn multi_processor_with_wr() {
+/// 跨进程读写锁, 多开几个进程:
+///
+/// ```shell
+/// cargo run --example rustix_futex_lock rw write
+/// cargo run --example rustix_futex_lock rw
+/// ```
+///
+/// 读者每次读到的 16 个数都相同, 说明写者修改到一半时读者进不来.
+/// 文件内容全 0 就是未加锁的状态, 所以新建文件后不需要初始化.
+fn multi_processor_with_wr() -> anyhow::Result<()> {
+    let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open("rustix_futex_rwlock.bin")?;
+    let size = std::mem::size_of::<IpcRwLock<[i32; 16]>>();
+    file.set_len(size as u64)?;
 
+    let shared_ptr = unsafe {
+        mmap(ptr::null_mut(), size, ProtFlags::READ | ProtFlags::WRITE, MapFlags::SHARED, file.as_fd(), 0)? as *const IpcRwLock<[i32; 16]>
+    };
+    let lock: &'static IpcRwLock<[i32; 16]> = unsafe { &*shared_ptr };
+
+    let op = env::args().nth(2).unwrap_or_default();
+    if op == "write" {
+        loop {
+            {
+                let mut data = lock.write();
+                let next = data[0] + 1;
+                for v in data.iter_mut() {
+                    *v = next;
+                    thread::sleep(Duration::from_millis(10));
+                }
+                println!("write: {}", next);
+            }
+            thread::sleep(Duration::from_millis(random_range(100..500)));
+        }
+    }
+
+    let readers: Vec<_> = (0..4)
+        .map(|i| {
+            thread::spawn(move || loop {
+                match lock.try_read_for(Duration::from_secs(1)) {
+                    Some(data) => {
+                        assert!(data.iter().all(|v| *v == data[0]), "inconsistent read: {:?}", *data);
+                        println!("reader {}: {}", i, data[0]);
+                    }
+                    None => println!("reader {}: timed out", i),
+                }
+                thread::sleep(Duration::from_millis(200));
+            })
+        })
+        .collect();
+    for reader in readers {
+        let _ = reader.join();
+    }
+    Ok(())
 }
 
 
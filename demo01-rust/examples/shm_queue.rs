@@ -0,0 +1,49 @@
+//! 多进程共享的消息队列, 实现在 `demo01_rust::shm_queue`
+//!
+//! 先创建队列 (`shm_queue.bin` 已经存在时需要先删除), 再开几个终端分别启动消费者 (最后一个参数是消费组) 和生产者:
+//!
+//! ```shell
+//! cargo run --example shm_queue create
+//! cargo run --example shm_queue consumer 0
+//! cargo run --example shm_queue consumer 1
+//! cargo run --example shm_queue producer
+//! ```
+//!
+//! 生产者把偶数号消息发给 0 号组, 奇数号消息发给 1 号组, 只唤醒对应组的消费者.
+//! 在消费者读取期间 `kill -9` 掉它, 其他进程会回收它占用的槽位.
+
+use std::env;
+use std::thread;
+use std::time::Duration;
+
+use demo01_rust::shm_queue::Queue;
+
+const PATH: &str = "shm_queue.bin";
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("create") => {
+            let queue = Queue::create(PATH, 64, 256)?;
+            println!("created {:?}, capacity {}, max message {}", queue.path(), queue.capacity(), queue.max_message());
+        }
+        Some("producer") => {
+            let queue = Queue::open(PATH)?;
+            for i in 0..20u32 {
+                let message = format!("message {} from {}", i, std::process::id());
+                queue.push_to(message.as_bytes(), 1 << (i % 2))?;
+                thread::sleep(Duration::from_millis(200));
+            }
+        }
+        Some("consumer") => {
+            let group = args.get(2).map(|s| s.parse()).transpose()?.unwrap_or(0);
+            let queue = Queue::open(PATH)?.with_group(group)?;
+            while let Ok(message) = queue.pop_timeout(Duration::from_secs(10)) {
+                println!("group {} received: {}", group, String::from_utf8_lossy(&message));
+            }
+            println!("no message for 10s, abandoned slots: {}", queue.abandoned());
+        }
+        _ => println!("usage: shm_queue create | producer | consumer [group]"),
+    }
+    Ok(())
+}
@@ -0,0 +1,147 @@
+//! `demo01_rust::shm_ring` 的延迟和吞吐量测试
+//!
+//! ```shell
+//! cargo run --release --example shm_ring_bench
+//! # 指定消息数量和消息大小
+//! cargo run --release --example shm_ring_bench -- 200000 256
+//! ```
+//!
+//! 父进程创建缓冲区文件, 再以不同的参数启动自身作为子进程:
+//!
+//! - 延迟: 两个缓冲区组成一来一回, 子进程把收到的消息原样写回, 单程延迟按往返时间的一半计算.
+//! - 吞吐量: 父进程连续写入, 子进程只读不写, 读完后通过另一个缓冲区回一条确认消息.
+
+use std::env;
+use std::path::Path;
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+use demo01_rust::shm_ring::{Consumer, Producer};
+
+const CAPACITY: u64 = 1 << 20;
+
+fn spawn_child(args: &[&str]) -> anyhow::Result<Child> {
+    Ok(Command::new(env::current_exe()?).args(args).spawn()?)
+}
+
+fn path_arg(path: &Path) -> &str {
+    path.to_str().expect("临时目录路径不是 UTF-8")
+}
+
+/// 子进程: 从 `request` 读, 原样写到 `response`
+fn run_echo(request: &str, response: &str, count: usize) -> anyhow::Result<()> {
+    let mut consumer = Consumer::open(request)?;
+    let mut producer = Producer::open(response)?;
+    for _ in 0..count {
+        let record = consumer.recv()?;
+        producer.push(&record)?;
+    }
+    Ok(())
+}
+
+/// 子进程: 读完 `count` 条消息后回一条确认
+fn run_sink(data: &str, ack: &str, count: usize) -> anyhow::Result<()> {
+    let mut consumer = Consumer::open(data)?;
+    let mut producer = Producer::open(ack)?;
+    let mut bytes = 0u64;
+    for _ in 0..count {
+        bytes += consumer.recv()?.len() as u64;
+    }
+    producer.push(&bytes.to_le_bytes())?;
+    Ok(())
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let index = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[index]
+}
+
+fn bench_latency(dir: &Path, count: usize, size: usize) -> anyhow::Result<()> {
+    let request_path = dir.join("request.bin");
+    let response_path = dir.join("response.bin");
+    let mut producer = Producer::create(&request_path, CAPACITY)?;
+    // 回程的缓冲区也由父进程创建, 子进程以生产者身份打开
+    let mut consumer = Consumer::create(&response_path, CAPACITY)?;
+
+    let count_arg = count.to_string();
+    let mut child = spawn_child(&["echo", path_arg(&request_path), path_arg(&response_path), &count_arg])?;
+
+    let message = vec![0x5a; size];
+    let mut samples = Vec::with_capacity(count);
+    for _ in 0..count {
+        let start = Instant::now();
+        producer.push(&message)?;
+        let record = consumer.recv()?;
+        samples.push(start.elapsed() / 2);
+        assert_eq!(record.len(), size);
+    }
+    child.wait()?;
+
+    samples.sort_unstable();
+    let mean = samples.iter().sum::<Duration>() / samples.len() as u32;
+    println!(
+        "latency    {:>8} msgs x {:>6} B: mean {:>9.2?}  p50 {:>9.2?}  p99 {:>9.2?}  max {:>9.2?}",
+        count,
+        size,
+        mean,
+        percentile(&samples, 0.5),
+        percentile(&samples, 0.99),
+        samples[samples.len() - 1]
+    );
+    Ok(())
+}
+
+fn bench_throughput(dir: &Path, count: usize, size: usize) -> anyhow::Result<()> {
+    let data_path = dir.join("data.bin");
+    let ack_path = dir.join("ack.bin");
+    let mut producer = Producer::create(&data_path, CAPACITY)?;
+    let mut ack = Consumer::create(&ack_path, 64)?;
+
+    let count_arg = count.to_string();
+    let mut child = spawn_child(&["sink", path_arg(&data_path), path_arg(&ack_path), &count_arg])?;
+
+    let message = vec![0xa5; size];
+    let start = Instant::now();
+    for _ in 0..count {
+        producer.push(&message)?;
+    }
+    let bytes = u64::from_le_bytes(ack.recv()?[..8].try_into()?);
+    let elapsed = start.elapsed();
+    child.wait()?;
+
+    assert_eq!(bytes, (count * size) as u64);
+    let secs = elapsed.as_secs_f64();
+    println!(
+        "throughput {:>8} msgs x {:>6} B: {:>9.2?}  {:>12.0} msg/s  {:>9.1} MB/s",
+        count,
+        size,
+        elapsed,
+        count as f64 / secs,
+        bytes as f64 / secs / 1e6
+    );
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("echo") => return run_echo(&args[1], &args[2], args[3].parse()?),
+        Some("sink") => return run_sink(&args[1], &args[2], args[3].parse()?),
+        _ => {}
+    }
+
+    let count: usize = args.first().map(|s| s.parse()).transpose()?.unwrap_or(100_000);
+    let sizes: Vec<usize> = match args.get(1) {
+        Some(size) => vec![size.parse()?],
+        None => vec![8, 64, 512, 4096],
+    };
+
+    // Producer::create 不会覆盖已有的文件, 每一轮用新的目录
+    for &size in &sizes {
+        bench_latency(tempfile::tempdir()?.path(), count, size)?;
+    }
+    for &size in &sizes {
+        bench_throughput(tempfile::tempdir()?.path(), count, size)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,71 @@
+//! 带版本校验的共享内存对象, 实现在 `demo01_rust::shm_segment`
+//!
+//! 同时开两个终端:
+//!
+//! ```shell
+//! cargo run --example shm_segment writer
+//! cargo run --example shm_segment reader
+//! ```
+//!
+//! 写者持锁修改 [Stats], 读者用 seqlock 不加锁读取, 每次读到的 `sum` 都等于 `values` 之和.
+//! 修改 [Stats] 的字段或 `VERSION` 后重新编译运行 reader, 会因为布局不一致拒绝打开旧文件.
+
+use std::env;
+use std::thread;
+use std::time::Duration;
+
+use demo01_rust::rutex::ShmSafe;
+use demo01_rust::shm_fields;
+use demo01_rust::shm_segment::{Field, ShmObject, ShmSegment};
+
+const PATH: &str = "shm_segment.bin";
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Stats {
+    round: u64,
+    values: [u32; 8],
+    sum: u64,
+}
+
+unsafe impl ShmSafe for Stats {}
+
+impl ShmObject for Stats {
+    const NAME: &'static str = "shm_segment.Stats";
+    const VERSION: u32 = 1;
+
+    fn fields() -> Vec<Field> {
+        shm_fields!(Stats { round, values, sum })
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let segment = ShmSegment::open_or_create(PATH, || Stats { round: 0, values: [0; 8], sum: 0 })?;
+    println!("opened {:?}, creator: {}", segment.path(), segment.is_creator());
+
+    match env::args().nth(1).as_deref() {
+        Some("writer") => {
+            for round in 1..=1000u64 {
+                let mut stats = segment.lock();
+                stats.round = round;
+                for (i, value) in stats.values.iter_mut().enumerate() {
+                    *value = (round * (i as u64 + 1) % 1000) as u32;
+                }
+                stats.sum = stats.values.iter().map(|&v| v as u64).sum();
+                drop(stats);
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+        _ => loop {
+            let snapshot = segment.snapshot();
+            if snapshot.recovered {
+                println!("recovered: 写者在修改途中退出了, 这一轮的数据可能不完整");
+            }
+            let stats = snapshot.value;
+            assert!(snapshot.recovered || stats.sum == stats.values.iter().map(|&v| v as u64).sum::<u64>());
+            println!("round {:>4}, sum {:>5}, sequence {}", stats.round, stats.sum, segment.sequence());
+            thread::sleep(Duration::from_millis(200));
+        },
+    }
+    Ok(())
+}
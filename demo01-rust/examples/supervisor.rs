@@ -0,0 +1,66 @@
+//! 子进程监督, 实现在 `demo01_rust::supervisor`
+//!
+//! ```shell
+//! cargo run --example supervisor
+//! ```
+//!
+//! 启动两个 worker 子进程 (就是当前程序本身), 通过显式传递的管道向父进程汇报心跳.
+//! `crashy` 每隔几秒崩溃一次, 会按退避时间被重启. 按 Ctrl-C 时信号被转发给所有子进程,
+//! 子进程退出后不再重启, 父进程打印最终状态后退出.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+use std::{env, process, thread};
+
+use demo01_rust::supervisor::{inherited_fd, Backoff, ChildSpec, RestartPolicy, Supervisor};
+use rustix::process::Signal;
+
+fn run_worker(name: &str, crash_after: Option<u32>) -> anyhow::Result<()> {
+    let mut report = UnixStream::from(inherited_fd("report").expect("父进程没有传递 report"));
+    for i in 0.. {
+        writeln!(report, "{} (pid {}) heartbeat {}", name, process::id(), i)?;
+        if crash_after == Some(i) {
+            process::abort();
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("steady") => return run_worker("steady", None),
+        Some("crashy") => return run_worker("crashy", Some(4)),
+        _ => {}
+    }
+
+    let (report, peer) = UnixStream::pair()?;
+    let exe = env::current_exe()?;
+    let backoff = Backoff { initial: Duration::from_millis(500), ..Backoff::default() };
+    // 必须在创建其他线程之前调用 start, 转发的信号才会在所有线程中被屏蔽
+    let supervisor = Supervisor::builder()
+        .child(ChildSpec::new("steady", &exe).arg("steady").fd("report", report.try_clone()?))
+        .child(ChildSpec::new("crashy", &exe).arg("crashy").fd("report", report).restart(RestartPolicy::Always).backoff(backoff))
+        .forward_signals(&[Signal::INT, Signal::TERM])
+        .reap_orphans(true)
+        .start()?;
+
+    thread::spawn(move || {
+        for line in BufReader::new(peer).lines().map_while(Result::ok) {
+            println!("{}", line);
+        }
+    });
+
+    loop {
+        let done = supervisor.wait_until(Duration::from_secs(3), |status| status.iter().all(|child| child.is_finished()));
+        for child in supervisor.status() {
+            println!("  {:<8} {:?}, restarts: {}, last exit: {:?}", child.name, child.state, child.restarts, child.last_exit);
+        }
+        if done {
+            break;
+        }
+    }
+    Ok(())
+}
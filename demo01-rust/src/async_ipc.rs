@@ -0,0 +1,157 @@
+//! IPC 原语的 tokio 封装
+//!
+//! 共享内存互斥锁, eventfd 通知和 Unix 套接字消息原本都是阻塞的, 直接在 tokio 任务中使用会占住 worker 线程:
+//!
+//! - [AsyncEventFd]: 通过 `tokio::io::unix::AsyncFd` 注册到 tokio 的 reactor, 等待通知时不阻塞线程
+//! - [Rutex::lock_async](crate::rutex::Rutex::lock_async): 跨进程互斥锁的异步加锁, 在 blocking 线程池中睡在 futex 上
+//! - [MessageCodec](crate::ipc_socket::MessageCodec): `ipc_socket` 分帧协议的 `tokio_util::codec` 版本
+//!
+//! ```no_run
+//! # async fn run() -> std::io::Result<()> {
+//! use std::sync::Arc;
+//! use demo01_rust::async_ipc::AsyncEventFd;
+//!
+//! let event = Arc::new(AsyncEventFd::new(0)?);
+//! let notifier = event.clone();
+//! std::thread::spawn(move || notifier.notify(1));
+//! assert_eq!(event.wait().await?, 1);
+//! # Ok(()) }
+//! ```
+
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+
+use rustix::event::{eventfd, EventfdFlags};
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+
+/// 注册在 tokio reactor 上的 eventfd
+///
+/// [notify](AsyncEventFd::notify) 是普通的非阻塞写, 可以在任意线程甚至其他进程中调用 (把描述符传给子进程即可),
+/// [wait](AsyncEventFd::wait) 必须在 tokio 运行时中调用.
+#[derive(Debug)]
+pub struct AsyncEventFd {
+    inner: AsyncFd<OwnedFd>,
+}
+
+impl AsyncEventFd {
+    /// 必须在 tokio 运行时中调用
+    pub fn new(initval: u32) -> io::Result<Self> {
+        Self::from_fd(eventfd(initval, EventfdFlags::CLOEXEC | EventfdFlags::NONBLOCK)?)
+    }
+
+    /// 信号量模式: 每次 [wait](AsyncEventFd::wait) 只取走 1, 多个等待者各自消费一次通知
+    pub fn semaphore(initval: u32) -> io::Result<Self> {
+        Self::from_fd(eventfd(initval, EventfdFlags::CLOEXEC | EventfdFlags::NONBLOCK | EventfdFlags::SEMAPHORE)?)
+    }
+
+    /// 接管一个已有的 eventfd, 例如用 [inherited_fd](crate::supervisor::inherited_fd) 从父进程继承的描述符.
+    /// 描述符会被设置为非阻塞模式.
+    pub fn from_fd(fd: OwnedFd) -> io::Result<Self> {
+        rustix::io::ioctl_fionbio(&fd, true)?;
+        // SAFETY: `OwnedFd` 在 `AsyncFd` 存活期间一直打开, `as_raw_fd` 总是返回同一个描述符
+        let inner = unsafe { AsyncFd::register_with_interest(fd, Interest::READABLE)? };
+        Ok(Self { inner })
+    }
+
+    /// 计数器加上 `n`. 计数器会溢出时返回 `WouldBlock`.
+    pub fn notify(&self, n: u64) -> io::Result<()> {
+        rustix::io::write(self.inner.get_ref(), &n.to_ne_bytes())?;
+        Ok(())
+    }
+
+    /// 等待计数器变为非零, 返回读到的值 (信号量模式下总是 1). 是取消安全的.
+    pub async fn wait(&self) -> io::Result<u64> {
+        self.inner.async_io(Interest::READABLE, read).await
+    }
+
+    /// 不等待, 计数器为零时返回 `None`
+    pub fn try_wait(&self) -> io::Result<Option<u64>> {
+        match read(self.inner.get_ref()) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// 从 reactor 中注销, 取回描述符
+    pub fn into_inner(self) -> OwnedFd {
+        self.inner.into_inner()
+    }
+}
+
+fn read(fd: &OwnedFd) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    rustix::io::read(fd, &mut buf)?;
+    Ok(u64::from_ne_bytes(buf))
+}
+
+impl AsFd for AsyncEventFd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.inner.get_ref().as_fd()
+    }
+}
+
+impl AsRawFd for AsyncEventFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::rutex::test::{fork_child, wait_child};
+
+    #[tokio::test]
+    async fn test_notify_from_thread() {
+        let event = Arc::new(AsyncEventFd::new(0).unwrap());
+        assert_eq!(event.try_wait().unwrap(), None);
+
+        let notifier = event.clone();
+        let thread = thread::spawn(move || {
+            for _ in 0..3 {
+                thread::sleep(Duration::from_millis(5));
+                notifier.notify(2).unwrap();
+            }
+        });
+        let mut total = 0;
+        while total < 6 {
+            total += event.wait().await.unwrap();
+        }
+        thread.join().unwrap();
+        assert_eq!(total, 6);
+    }
+
+    #[tokio::test]
+    async fn test_semaphore() {
+        let event = AsyncEventFd::semaphore(2).unwrap();
+        assert_eq!(event.wait().await.unwrap(), 1);
+        assert_eq!(event.wait().await.unwrap(), 1);
+        assert_eq!(event.try_wait().unwrap(), None);
+
+        // 等待被取消后不会丢失通知
+        assert!(tokio::time::timeout(Duration::from_millis(10), event.wait()).await.is_err());
+        event.notify(1).unwrap();
+        assert_eq!(event.wait().await.unwrap(), 1);
+    }
+
+    #[test]
+    fn test_notify_from_child_process() {
+        // 先 fork 再创建运行时, 子进程只需要一个普通的描述符
+        let fd = eventfd(0, EventfdFlags::CLOEXEC).unwrap();
+        let child = fork_child(|| {
+            thread::sleep(Duration::from_millis(20));
+            rustix::io::write(&fd, &7u64.to_ne_bytes()).is_ok()
+        });
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+        let value = runtime.block_on(async { AsyncEventFd::from_fd(fd).unwrap().wait().await.unwrap() });
+        assert_eq!(value, 7);
+        assert!(wait_child(child));
+    }
+}
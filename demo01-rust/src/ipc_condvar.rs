@@ -0,0 +1,170 @@
+//! 基于 futex 的跨进程条件变量
+//!
+//! 只有一个 `AtomicU32` 序号, 每次通知加一. 等待者先记下序号再解锁, 然后在序号上睡眠,
+//! 解锁和睡眠之间发生的通知会改变序号, `futex::wait` 发现值不一致会立即返回, 不会丢失通知.
+//!
+//! 和标准库的 `Condvar` 一样可能出现虚假唤醒, 需要在循环中检查条件, 或者直接使用 [IpcCondvar::wait_while].
+//! 可以配合任意 `lock_api::RawMutex` 使用, 跨进程时配合 [crate::rutex::IpcMutex].
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+use lock_api::{MutexGuard, RawMutex};
+
+use crate::rutex::{futex_wait, futex_wake, ShmSafe};
+
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct IpcCondvar {
+    seq: AtomicU32,
+}
+
+unsafe impl ShmSafe for IpcCondvar {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+impl IpcCondvar {
+    pub const fn new() -> Self {
+        Self { seq: AtomicU32::new(0) }
+    }
+
+    /// 唤醒一个等待者, 返回是否有等待者被唤醒
+    pub fn notify_one(&self) -> bool {
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        futex_wake(&self.seq, 1)
+    }
+
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        futex_wake(&self.seq, i32::MAX as u32);
+    }
+
+    pub fn wait<R: RawMutex, T>(&self, guard: &mut MutexGuard<'_, R, T>) {
+        self.wait_until_internal(guard, None);
+    }
+
+    pub fn wait_for<R: RawMutex, T>(&self, guard: &mut MutexGuard<'_, R, T>, timeout: Duration) -> WaitTimeoutResult {
+        self.wait_until(guard, Instant::now() + timeout)
+    }
+
+    pub fn wait_until<R: RawMutex, T>(&self, guard: &mut MutexGuard<'_, R, T>, deadline: Instant) -> WaitTimeoutResult {
+        WaitTimeoutResult(!self.wait_until_internal(guard, Some(deadline)))
+    }
+
+    /// 一直等待, 直到 `condition` 返回 `false`
+    pub fn wait_while<R: RawMutex, T>(&self, guard: &mut MutexGuard<'_, R, T>, mut condition: impl FnMut(&mut T) -> bool) {
+        while condition(&mut **guard) {
+            self.wait(guard);
+        }
+    }
+
+    /// 超时返回时 `condition` 仍然为 `true`
+    pub fn wait_while_for<R: RawMutex, T>(
+        &self,
+        guard: &mut MutexGuard<'_, R, T>,
+        timeout: Duration,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> WaitTimeoutResult {
+        let deadline = Instant::now() + timeout;
+        while condition(&mut **guard) {
+            if self.wait_until(guard, deadline).timed_out() {
+                return WaitTimeoutResult(condition(&mut **guard));
+            }
+        }
+        WaitTimeoutResult(false)
+    }
+
+    /// 返回 `false` 表示超时
+    fn wait_until_internal<R: RawMutex, T>(&self, guard: &mut MutexGuard<'_, R, T>, deadline: Option<Instant>) -> bool {
+        // 必须在解锁之前读取序号
+        let seq = self.seq.load(Ordering::Relaxed);
+        MutexGuard::unlocked(guard, || futex_wait(&self.seq, seq, deadline))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+    use crate::rutex::test::{fork_child, wait_child, SharedFile};
+    use crate::rutex::IpcMutex;
+
+    #[test]
+    fn test_notify_one() {
+        let pair = Arc::new((IpcMutex::new(false), IpcCondvar::new()));
+        let waiter = {
+            let pair = pair.clone();
+            thread::spawn(move || {
+                let (mutex, condvar) = &*pair;
+                let mut ready = mutex.lock();
+                condvar.wait_while(&mut ready, |ready| !*ready);
+            })
+        };
+        thread::sleep(Duration::from_millis(20));
+        *pair.0.lock() = true;
+        pair.1.notify_one();
+        waiter.join().unwrap();
+    }
+
+    #[test]
+    fn test_wait_timeout() {
+        let mutex = IpcMutex::new(0);
+        let condvar = IpcCondvar::new();
+        let mut guard = mutex.lock();
+
+        let start = Instant::now();
+        assert!(condvar.wait_for(&mut guard, Duration::from_millis(30)).timed_out());
+        assert!(start.elapsed() >= Duration::from_millis(30));
+
+        let result = condvar.wait_while_for(&mut guard, Duration::from_millis(30), |value| *value == 0);
+        assert!(result.timed_out());
+        // 超时返回后仍然持有锁
+        assert!(mutex.is_locked());
+    }
+
+    #[repr(C)]
+    struct Shared {
+        mutex: IpcMutex<u32>,
+        condvar: IpcCondvar,
+    }
+
+    /// 子进程各自映射同一个文件, 等待父进程修改计数后广播
+    #[test]
+    fn test_multi_process_notify_all() {
+        let file = SharedFile::<Shared>::create();
+        let children: Vec<_> = (0..3)
+            .map(|_| {
+                let path = file.path().to_path_buf();
+                fork_child(move || {
+                    let file = SharedFile::<Shared>::open(&path);
+                    let shared = file.get();
+                    let mut guard = shared.mutex.lock();
+                    *guard += 1;
+                    // 最后一个子进程到达时也会唤醒父进程
+                    shared.condvar.notify_all();
+                    !shared.condvar.wait_while_for(&mut guard, Duration::from_secs(10), |value| *value < 100).timed_out()
+                })
+            })
+            .collect();
+
+        let shared = file.get();
+        let mut guard = shared.mutex.lock();
+        assert!(!shared.condvar.wait_while_for(&mut guard, Duration::from_secs(10), |value| *value < 3).timed_out());
+        *guard = 100;
+        shared.condvar.notify_all();
+        drop(guard);
+
+        for child in children {
+            assert!(wait_child(child));
+        }
+    }
+}
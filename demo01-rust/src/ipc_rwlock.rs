@@ -0,0 +1,407 @@
+//! 基于 futex 的跨进程读写锁
+//!
+//! 算法和标准库 Linux 版本的 `RwLock` 相同, 区别是 futex 不使用 `FUTEX_PRIVATE_FLAG`,
+//! 这样不同进程映射同一个文件时, 内核能把它们的等待队列对应到同一个物理地址上.
+//!
+//! - 状态全部在两个 `AtomicU32` 中, 全 0 就是未加锁, 新建 (被 truncate 清零) 的共享内存文件不需要初始化.
+//! - 写者优先: 只要有写者在等待, 新的读者就不能加锁, 持续不断的读者不会饿死写者.
+//! - 实现了 `lock_api::RawRwLockTimed`, 可以带超时加锁.
+//!
+//! 与 [crate::rutex::IpcMutexRaw] 不同, 读写锁不记录持有者, 持锁进程崩溃后锁无法恢复.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+use lock_api::{GuardSend, RawRwLock, RawRwLockTimed};
+use crate::rutex::{futex_wait, futex_wake, ShmSafe};
+
+/// 低 30 位是读者数量, 全 1 表示写锁
+const MASK: u32 = (1 << 30) - 1;
+const READ_LOCKED: u32 = 1;
+const WRITE_LOCKED: u32 = MASK;
+const MAX_READERS: u32 = MASK - 1;
+const READERS_WAITING: u32 = 1 << 30;
+const WRITERS_WAITING: u32 = 1 << 31;
+
+fn is_unlocked(state: u32) -> bool {
+    state & MASK == 0
+}
+
+fn is_write_locked(state: u32) -> bool {
+    state & MASK == WRITE_LOCKED
+}
+
+fn has_readers_waiting(state: u32) -> bool {
+    state & READERS_WAITING != 0
+}
+
+fn has_writers_waiting(state: u32) -> bool {
+    state & WRITERS_WAITING != 0
+}
+
+/// 有人在等待时不允许新的读者加锁, 这样写者不会被饿死
+fn is_read_lockable(state: u32) -> bool {
+    state & MASK < MAX_READERS && !has_readers_waiting(state) && !has_writers_waiting(state)
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct IpcRwLockRaw {
+    state: AtomicU32,
+    /// 写者在这里等待, 每次唤醒写者时加一
+    writer_notify: AtomicU32,
+}
+
+unsafe impl ShmSafe for IpcRwLockRaw {}
+
+impl IpcRwLockRaw {
+    pub const fn new() -> Self {
+        Self { state: AtomicU32::new(0), writer_notify: AtomicU32::new(0) }
+    }
+
+    fn read_contended(&self, deadline: Option<Instant>) -> bool {
+        let mut state = self.spin_read();
+        loop {
+            if is_read_lockable(state) {
+                match self.state.compare_exchange_weak(state, state + READ_LOCKED, Ordering::Acquire, Ordering::Relaxed) {
+                    Ok(_) => return true,
+                    Err(s) => {
+                        state = s;
+                        continue;
+                    }
+                }
+            }
+
+            // 标记有读者在等待
+            if !has_readers_waiting(state) {
+                if let Err(s) = self.state.compare_exchange(state, state | READERS_WAITING, Ordering::Relaxed, Ordering::Relaxed) {
+                    state = s;
+                    continue;
+                }
+            }
+
+            // 读者被 futex_wake 全部唤醒, 超时离开不会导致其他人错过唤醒
+            if !futex_wait(&self.state, state | READERS_WAITING, deadline) {
+                return false;
+            }
+            state = self.spin_read();
+        }
+    }
+
+    fn write_contended(&self, deadline: Option<Instant>) -> bool {
+        let mut state = self.spin_write();
+        let mut other_writers_waiting = 0;
+        loop {
+            // 锁空闲时直接加写锁. 如果之前等待过, 可能还有其他写者在等, 需要保留 WRITERS_WAITING.
+            if is_unlocked(state) {
+                match self.state.compare_exchange_weak(
+                    state,
+                    state | WRITE_LOCKED | other_writers_waiting,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return true,
+                    Err(s) => {
+                        state = s;
+                        continue;
+                    }
+                }
+            }
+
+            if !has_writers_waiting(state) {
+                if let Err(s) = self.state.compare_exchange(state, state | WRITERS_WAITING, Ordering::Relaxed, Ordering::Relaxed) {
+                    state = s;
+                    continue;
+                }
+            }
+            other_writers_waiting = WRITERS_WAITING;
+
+            // 必须在检查 state 之后读取, 否则可能错过 unlock 之后的唤醒
+            let seq = self.writer_notify.load(Ordering::Acquire);
+            state = self.state.load(Ordering::Relaxed);
+            if is_unlocked(state) || !has_writers_waiting(state) {
+                continue;
+            }
+
+            if !futex_wait(&self.writer_notify, seq, deadline) {
+                // 超时前唤醒我们的那次通知可能已经被消费了, 最后再尝试一次, 锁空闲就直接拿下,
+                // 否则当前持有者解锁时会处理剩下的等待者
+                state = self.state.load(Ordering::Relaxed);
+                return is_unlocked(state)
+                    && self
+                        .state
+                        .compare_exchange(state, state | WRITE_LOCKED | other_writers_waiting, Ordering::Acquire, Ordering::Relaxed)
+                        .is_ok();
+            }
+            state = self.spin_write();
+        }
+    }
+
+    /// 锁已经完全释放, 有等待者时唤醒它们, 写者优先
+    fn wake_writer_or_readers(&self, mut state: u32) {
+        debug_assert!(is_unlocked(state));
+
+        if state == WRITERS_WAITING {
+            match self.state.compare_exchange(state, 0, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => {
+                    self.wake_writer();
+                    return;
+                }
+                Err(s) => state = s,
+            }
+        }
+
+        // 读者和写者都在等, 先唤醒写者, 没有写者真正醒来时 (例如已经超时离开) 再唤醒读者
+        if state == READERS_WAITING + WRITERS_WAITING {
+            if self.state.compare_exchange(state, READERS_WAITING, Ordering::Relaxed, Ordering::Relaxed).is_err() {
+                return;
+            }
+            if self.wake_writer() {
+                return;
+            }
+            state = READERS_WAITING;
+        }
+
+        if state == READERS_WAITING && self.state.compare_exchange(state, 0, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+            futex_wake(&self.state, i32::MAX as u32);
+        }
+    }
+
+    fn wake_writer(&self) -> bool {
+        self.writer_notify.fetch_add(1, Ordering::Release);
+        futex_wake(&self.writer_notify, 1)
+    }
+
+    /// 锁被占用且没有等待者时短暂自旋, 很多临界区很短, 自旋比睡眠划算
+    fn spin_until(&self, f: impl Fn(u32) -> bool) -> u32 {
+        let mut spin = 100;
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if f(state) || spin == 0 {
+                return state;
+            }
+            std::hint::spin_loop();
+            spin -= 1;
+        }
+    }
+
+    fn spin_write(&self) -> u32 {
+        self.spin_until(|state| is_unlocked(state) || has_writers_waiting(state))
+    }
+
+    fn spin_read(&self) -> u32 {
+        self.spin_until(|state| !is_write_locked(state) || has_readers_waiting(state) || has_writers_waiting(state))
+    }
+}
+
+impl Default for IpcRwLockRaw {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl RawRwLock for IpcRwLockRaw {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: IpcRwLockRaw = IpcRwLockRaw::new();
+    type GuardMarker = GuardSend;
+
+    fn lock_shared(&self) {
+        if !self.try_lock_shared() {
+            self.read_contended(None);
+        }
+    }
+
+    fn try_lock_shared(&self) -> bool {
+        self.state
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |s| is_read_lockable(s).then(|| s + READ_LOCKED))
+            .is_ok()
+    }
+
+    unsafe fn unlock_shared(&self) {
+        let state = self.state.fetch_sub(READ_LOCKED, Ordering::Release) - READ_LOCKED;
+        // 最后一个读者离开时才需要唤醒. 读锁状态下读者只会因为有写者在等待而等待, 所以只检查写者标志.
+        if is_unlocked(state) && has_writers_waiting(state) {
+            self.wake_writer_or_readers(state);
+        }
+    }
+
+    fn lock_exclusive(&self) {
+        if !self.try_lock_exclusive() {
+            self.write_contended(None);
+        }
+    }
+
+    fn try_lock_exclusive(&self) -> bool {
+        self.state.compare_exchange(0, WRITE_LOCKED, Ordering::Acquire, Ordering::Relaxed).is_ok()
+    }
+
+    unsafe fn unlock_exclusive(&self) {
+        let state = self.state.fetch_sub(WRITE_LOCKED, Ordering::Release) - WRITE_LOCKED;
+        debug_assert!(is_unlocked(state));
+        if has_writers_waiting(state) || has_readers_waiting(state) {
+            self.wake_writer_or_readers(state);
+        }
+    }
+
+    fn is_locked(&self) -> bool {
+        !is_unlocked(self.state.load(Ordering::Relaxed))
+    }
+
+    fn is_locked_exclusive(&self) -> bool {
+        is_write_locked(self.state.load(Ordering::Relaxed))
+    }
+}
+
+unsafe impl RawRwLockTimed for IpcRwLockRaw {
+    type Duration = Duration;
+    type Instant = Instant;
+
+    fn try_lock_shared_for(&self, timeout: Duration) -> bool {
+        self.try_lock_shared_until(Instant::now() + timeout)
+    }
+
+    fn try_lock_shared_until(&self, deadline: Instant) -> bool {
+        self.try_lock_shared() || self.read_contended(Some(deadline))
+    }
+
+    fn try_lock_exclusive_for(&self, timeout: Duration) -> bool {
+        self.try_lock_exclusive_until(Instant::now() + timeout)
+    }
+
+    fn try_lock_exclusive_until(&self, deadline: Instant) -> bool {
+        self.try_lock_exclusive() || self.write_contended(Some(deadline))
+    }
+}
+
+pub type IpcRwLock<T> = lock_api::RwLock<IpcRwLockRaw, T>;
+pub type IpcRwLockReadGuard<'a, T> = lock_api::RwLockReadGuard<'a, IpcRwLockRaw, T>;
+pub type IpcRwLockWriteGuard<'a, T> = lock_api::RwLockWriteGuard<'a, IpcRwLockRaw, T>;
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+    use crate::rutex::test::{fork_child, wait_child, SharedFile};
+
+    #[test]
+    fn test_readers_share_writer_excludes() {
+        let lock = IpcRwLock::new(0u32);
+        let r1 = lock.read();
+        let r2 = lock.try_read().unwrap();
+        assert!(lock.try_write().is_none());
+        drop((r1, r2));
+
+        let mut w = lock.write();
+        *w = 1;
+        assert!(lock.try_read().is_none());
+        assert!(lock.try_write().is_none());
+        drop(w);
+        assert_eq!(*lock.read(), 1);
+        assert!(!lock.is_locked());
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let lock = Arc::new(IpcRwLock::new(()));
+        let r = lock.read();
+        let start = Instant::now();
+        assert!(lock.try_write_for(Duration::from_millis(50)).is_none());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        drop(r);
+        // 超时离开的写者不会让锁卡在 "有写者等待" 的状态
+        assert!(lock.try_read_for(Duration::from_millis(50)).is_some());
+
+        let w = lock.write();
+        let start = Instant::now();
+        assert!(lock.try_read_for(Duration::from_millis(50)).is_none());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        drop(w);
+        assert!(lock.try_write_for(Duration::from_millis(50)).is_some());
+    }
+
+    /// 有写者在等待时, 新的读者不能插队
+    #[test]
+    fn test_writer_not_starved() {
+        let lock = Arc::new(IpcRwLock::new(0u32));
+        let reader = lock.read();
+
+        let writer = {
+            let lock = lock.clone();
+            thread::spawn(move || *lock.write() += 1)
+        };
+        while unsafe { lock.raw() }.state.load(Ordering::Relaxed) & WRITERS_WAITING == 0 {
+            thread::yield_now();
+        }
+        assert!(lock.try_read().is_none());
+
+        drop(reader);
+        writer.join().unwrap();
+        assert_eq!(*lock.read(), 1);
+    }
+
+    /// 读者不停地来来去去, 写者依然能拿到锁
+    #[test]
+    fn test_writer_progress_under_read_pressure() {
+        let lock = Arc::new(IpcRwLock::new(0u64));
+        let stop = Arc::new(AtomicBool::new(false));
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let lock = lock.clone();
+                let stop = stop.clone();
+                thread::spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        let _guard = lock.read();
+                        thread::sleep(Duration::from_micros(200));
+                    }
+                })
+            })
+            .collect();
+
+        for _ in 0..20 {
+            *lock.try_write_for(Duration::from_secs(5)).expect("writer starved") += 1;
+        }
+        stop.store(true, Ordering::Relaxed);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(*lock.read(), 20);
+    }
+
+    /// 互不相关的进程各自映射同一个文件
+    #[test]
+    fn test_multi_process() {
+        let file = SharedFile::<IpcRwLock<[u64; 8]>>::create();
+        let children: Vec<_> = (0..4)
+            .map(|i| {
+                let path = file.path().to_path_buf();
+                fork_child(move || {
+                    let file = SharedFile::<IpcRwLock<[u64; 8]>>::open(&path);
+                    let lock = file.get();
+                    for _ in 0..500 {
+                        if i % 2 == 0 {
+                            let mut data = lock.write();
+                            for slot in data.iter_mut() {
+                                *slot += 1;
+                                thread::yield_now();
+                            }
+                        } else {
+                            // 读者看到的数据必须是一致的
+                            let data = lock.read();
+                            if data.iter().any(|v| *v != data[0]) {
+                                return false;
+                            }
+                        }
+                    }
+                    true
+                })
+            })
+            .collect();
+        for child in children {
+            assert!(wait_child(child));
+        }
+        assert_eq!(*file.get().read(), [1000; 8]);
+    }
+}
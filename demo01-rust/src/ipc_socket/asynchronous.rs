@@ -0,0 +1,327 @@
+//! tokio 实现
+//!
+//! - [AsyncClient] 可以在多个任务之间共享, 同时发起的请求在同一个连接上 pipelining.
+//! - [AsyncServer] 对每个请求都 spawn 一个任务, 同一连接上的响应按完成顺序写回.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), demo01_rust::ipc_socket::RpcError> {
+//! use demo01_rust::ipc_socket::asynchronous::{AsyncClient, AsyncServer};
+//! use demo01_rust::ipc_socket::{echo, Request};
+//!
+//! let server = AsyncServer::bind("/tmp/demo01.sock")?;
+//! tokio::spawn(server.serve(|request| async move { echo(request) }, std::future::pending()));
+//! let client = AsyncClient::connect("/tmp/demo01.sock").await?;
+//! println!("{:?}", client.call(Request::Ping).await?);
+//! # Ok(()) }
+//! ```
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::unix::OwnedWriteHalf;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::{JoinHandle, JoinSet};
+
+use super::{remove_stale_socket, Envelope, FrameCodec, Request, Response, RpcError};
+
+/// `None` 表示连接已经断开, 之后的请求直接失败
+type Pending = Arc<Mutex<Option<HashMap<u64, oneshot::Sender<Response>>>>>;
+
+pub struct AsyncClient {
+    codec: FrameCodec,
+    next_id: AtomicU64,
+    pending: Pending,
+    /// 编码好的帧交给写任务发送, 调用方被取消时不会在连接上留下半个帧
+    frames: mpsc::Sender<Vec<u8>>,
+    reader: JoinHandle<()>,
+    writer: JoinHandle<()>,
+}
+
+impl AsyncClient {
+    pub async fn connect(path: impl AsRef<Path>) -> Result<Self, RpcError> {
+        Self::connect_with(path, FrameCodec::default()).await
+    }
+
+    pub async fn connect_with(path: impl AsRef<Path>, codec: FrameCodec) -> Result<Self, RpcError> {
+        let (mut read_half, mut write_half) = UnixStream::connect(path).await?.into_split();
+        let pending: Pending = Arc::new(Mutex::new(Some(HashMap::new())));
+
+        let reader = {
+            let pending = pending.clone();
+            tokio::spawn(async move {
+                loop {
+                    match codec.read_frame_async::<_, Envelope<Response>>(&mut read_half).await {
+                        Ok(Some(response)) => {
+                            let waiter = pending.lock().unwrap().as_mut().and_then(|p| p.remove(&response.id));
+                            // 调用方已经放弃等待时发送失败, 忽略即可
+                            if let Some(waiter) = waiter {
+                                let _ = waiter.send(response.body);
+                            }
+                        }
+                        Ok(None) => break,
+                        Err(e) => {
+                            eprintln!("接收响应错误: {}", e);
+                            break;
+                        }
+                    }
+                }
+                // drop 所有等待者, 它们会收到 Closed
+                pending.lock().unwrap().take();
+            })
+        };
+
+        let (frames, mut rx) = mpsc::channel::<Vec<u8>>(64);
+        let writer = tokio::spawn(async move {
+            while let Some(frame) = rx.recv().await {
+                if write_half.write_all(&frame).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self { codec, next_id: AtomicU64::new(0), pending, frames, reader, writer })
+    }
+
+    pub async fn call(&self, request: Request) -> Result<Response, RpcError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let frame = self.codec.encode(&Envelope { id, body: request })?;
+
+        let (tx, rx) = oneshot::channel();
+        match self.pending.lock().unwrap().as_mut() {
+            Some(pending) => pending.insert(id, tx),
+            None => return Err(RpcError::Closed),
+        };
+        if self.frames.send(frame).await.is_err() {
+            if let Some(pending) = self.pending.lock().unwrap().as_mut() {
+                pending.remove(&id);
+            }
+            return Err(RpcError::Closed);
+        }
+        rx.await.map_err(|_| RpcError::Closed)
+    }
+}
+
+impl Drop for AsyncClient {
+    fn drop(&mut self) {
+        self.reader.abort();
+        self.writer.abort();
+    }
+}
+
+pub struct AsyncServer {
+    listener: UnixListener,
+    path: PathBuf,
+    codec: FrameCodec,
+}
+
+impl AsyncServer {
+    /// 需要在 tokio 运行时中调用
+    pub fn bind(path: impl AsRef<Path>) -> Result<Self, RpcError> {
+        let path = path.as_ref().to_path_buf();
+        remove_stale_socket(&path)?;
+        let listener = UnixListener::bind(&path)?;
+        Ok(Self { listener, path, codec: FrameCodec::default() })
+    }
+
+    pub fn codec(mut self, codec: FrameCodec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 处理连接直到 `shutdown` 完成. 之后不再接受新连接和新请求,
+    /// 等已经收到的请求处理完并把响应写回后才返回, 最后删除套接字文件.
+    pub async fn serve<F, Fut>(self, handler: F, shutdown: impl Future<Output = ()>) -> Result<(), RpcError>
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let mut connections = JoinSet::new();
+        tokio::pin!(shutdown);
+
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => break,
+                accepted = self.listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        connections.spawn(handle_client(stream, self.codec, handler.clone(), stop_rx.clone()));
+                    }
+                    Err(e) => eprintln!("❌ 接受连接错误: {}", e),
+                },
+                // 顺便回收已经结束的连接
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            }
+        }
+
+        drop(self.listener);
+        let _ = stop_tx.send(true);
+        while connections.join_next().await.is_some() {}
+        let _ = std::fs::remove_file(&self.path);
+        Ok(())
+    }
+}
+
+async fn handle_client<F, Fut>(stream: UnixStream, codec: FrameCodec, handler: Arc<F>, mut stop: watch::Receiver<bool>)
+where
+    F: Fn(Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send + 'static,
+{
+    let (mut read_half, write_half) = stream.into_split();
+    let (tx, rx) = mpsc::channel::<Envelope<Response>>(64);
+    let writer = tokio::spawn(write_responses(write_half, codec, rx));
+
+    let mut requests = JoinSet::new();
+    loop {
+        let request = tokio::select! {
+            _ = stop.wait_for(|stop| *stop) => break,
+            request = codec.read_frame_async::<_, Envelope<Request>>(&mut read_half) => request,
+        };
+        match request {
+            Ok(Some(Envelope { id, body })) => {
+                let handler = handler.clone();
+                let tx = tx.clone();
+                requests.spawn(async move {
+                    let body = handler(body).await;
+                    let _ = tx.send(Envelope { id, body }).await;
+                });
+                // 回收已经完成的请求; 不能放进上面的 select!, 读帧被取消会丢数据
+                while requests.try_join_next().is_some() {}
+            }
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("客户端处理错误: {}", e);
+                break;
+            }
+        }
+    }
+
+    // 已经收到的请求处理完, 响应全部写回之后再关闭连接
+    while requests.join_next().await.is_some() {}
+    drop(tx);
+    let _ = writer.await;
+}
+
+async fn write_responses(mut write_half: OwnedWriteHalf, codec: FrameCodec, mut rx: mpsc::Receiver<Envelope<Response>>) {
+    while let Some(response) = rx.recv().await {
+        let result = match codec.write_frame_async(&mut write_half, &response).await {
+            // 响应太大时返回错误信息, 连接可以继续使用
+            Err(RpcError::FrameTooLarge { len, max }) => {
+                let body = Response::Error(format!("response of {} bytes exceeds limit of {} bytes", len, max));
+                codec.write_frame_async(&mut write_half, &Envelope { id: response.id, body }).await
+            }
+            result => result,
+        };
+        if let Err(e) = result {
+            eprintln!("发送响应错误: {}", e);
+            break;
+        }
+    }
+    let _ = write_half.shutdown().await;
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::ipc_socket::echo;
+
+    /// 按请求内容决定处理时长, 用来制造乱序完成
+    async fn slow_echo(request: Request) -> Response {
+        if let Request::Text(text) = &request {
+            if let Ok(ms) = text.parse() {
+                tokio::time::sleep(Duration::from_millis(ms)).await;
+            }
+        }
+        echo(request)
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_calls() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rpc.sock");
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+        let server = AsyncServer::bind(&path).unwrap();
+        let serving = tokio::spawn(server.serve(slow_echo, async move {
+            let _ = stop_rx.await;
+        }));
+
+        let client = Arc::new(AsyncClient::connect(&path).await.unwrap());
+        let start = std::time::Instant::now();
+        let calls: Vec<_> = [200, 100, 0]
+            .into_iter()
+            .map(|ms| {
+                let client = client.clone();
+                tokio::spawn(async move { client.call(Request::Text(ms.to_string())).await.unwrap() })
+            })
+            .collect();
+        for (call, ms) in calls.into_iter().zip([200, 100, 0]) {
+            assert_eq!(call.await.unwrap(), Response::Text(format!("ECHO: {}", ms)));
+        }
+        // 请求在服务端并发处理, 总耗时接近最慢的那个而不是总和
+        assert!(start.elapsed() < Duration::from_millis(290));
+
+        stop_tx.send(()).unwrap();
+        serving.await.unwrap().unwrap();
+        assert!(!path.exists());
+        assert!(matches!(client.call(Request::Ping).await, Err(RpcError::Closed)));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_waits_for_in_flight() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rpc.sock");
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+        let server = AsyncServer::bind(&path).unwrap();
+        let serving = tokio::spawn(server.serve(slow_echo, async move {
+            let _ = stop_rx.await;
+        }));
+
+        let client = Arc::new(AsyncClient::connect(&path).await.unwrap());
+        let call = {
+            let client = client.clone();
+            tokio::spawn(async move { client.call(Request::Text("100".into())).await })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        stop_tx.send(()).unwrap();
+
+        // 关闭之前收到的请求仍然会得到响应
+        assert_eq!(call.await.unwrap().unwrap(), Response::Text("ECHO: 100".into()));
+        serving.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_blocking_client_interop() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rpc.sock");
+        let server = AsyncServer::bind(&path).unwrap().codec(FrameCodec::new(1024));
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+        let serving = tokio::spawn(server.serve(|request| async move { echo(request) }, async move {
+            let _ = stop_rx.await;
+        }));
+
+        let client_path = path.clone();
+        let (pong, oversized) = tokio::task::spawn_blocking(move || {
+            let mut client = crate::ipc_socket::blocking::Client::connect(client_path).unwrap();
+            (client.call(Request::Ping), client.call(Request::Data(vec![0; 2000])))
+        })
+        .await
+        .unwrap();
+        assert_eq!(pong.unwrap(), Response::Pong);
+        // 请求超过服务端的上限, 连接被断开
+        assert!(matches!(oversized, Err(RpcError::Closed) | Err(RpcError::Io(_))));
+
+        stop_tx.send(()).unwrap();
+        serving.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,298 @@
+//! 同步实现: 每个客户端连接一个线程
+//!
+//! ```no_run
+//! use demo01_rust::ipc_socket::{blocking, echo, Request};
+//!
+//! let server = blocking::Server::bind("/tmp/demo01.sock", echo).unwrap();
+//! let mut client = blocking::Client::connect("/tmp/demo01.sock").unwrap();
+//! println!("{:?}", client.call(Request::Ping).unwrap());
+//! server.shutdown();
+//! ```
+
+use std::collections::HashMap;
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use super::{remove_stale_socket, Envelope, FrameCodec, Request, Response, RpcError};
+
+pub struct Client {
+    stream: UnixStream,
+    codec: FrameCodec,
+    next_id: u64,
+    /// `call` 等待自己的响应时, 先收到的其他请求的响应暂存在这里
+    buffered: HashMap<u64, Response>,
+}
+
+impl Client {
+    pub fn connect(path: impl AsRef<Path>) -> Result<Self, RpcError> {
+        Self::connect_with(path, FrameCodec::default())
+    }
+
+    pub fn connect_with(path: impl AsRef<Path>, codec: FrameCodec) -> Result<Self, RpcError> {
+        let stream = UnixStream::connect(path)?;
+        Ok(Self { stream, codec, next_id: 0, buffered: HashMap::new() })
+    }
+
+    /// 只发送, 不等待响应, 返回请求 id. 配合 [Client::recv] 实现 pipelining.
+    pub fn send(&mut self, request: &Request) -> Result<u64, RpcError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.codec.write_frame(&mut self.stream, &Envelope { id, body: request })?;
+        Ok(id)
+    }
+
+    /// 接收下一个响应, 不保证和发送顺序一致
+    pub fn recv(&mut self) -> Result<Envelope<Response>, RpcError> {
+        if let Some(id) = self.buffered.keys().next().copied() {
+            let body = self.buffered.remove(&id).unwrap();
+            return Ok(Envelope { id, body });
+        }
+        self.codec.read_frame(&mut self.stream)?.ok_or(RpcError::Closed)
+    }
+
+    /// 发送请求并等待对应的响应
+    pub fn call(&mut self, request: Request) -> Result<Response, RpcError> {
+        let id = self.send(&request)?;
+        loop {
+            let response: Envelope<Response> = self.codec.read_frame(&mut self.stream)?.ok_or(RpcError::Closed)?;
+            if response.id == id {
+                return Ok(response.body);
+            }
+            self.buffered.insert(response.id, response.body);
+        }
+    }
+}
+
+type Handler = Arc<dyn Fn(Request) -> Response + Send + Sync>;
+
+/// 服务端句柄, drop 或者调用 [Server::shutdown] 时停止接受连接, 断开所有客户端并删除套接字文件
+pub struct Server {
+    path: PathBuf,
+    stopping: Arc<AtomicBool>,
+    accept_thread: Option<JoinHandle<()>>,
+}
+
+impl Server {
+    pub fn bind<F>(path: impl AsRef<Path>, handler: F) -> Result<Self, RpcError>
+    where
+        F: Fn(Request) -> Response + Send + Sync + 'static,
+    {
+        Self::bind_with(path, FrameCodec::default(), handler)
+    }
+
+    pub fn bind_with<F>(path: impl AsRef<Path>, codec: FrameCodec, handler: F) -> Result<Self, RpcError>
+    where
+        F: Fn(Request) -> Response + Send + Sync + 'static,
+    {
+        let path = path.as_ref().to_path_buf();
+        remove_stale_socket(&path)?;
+        let listener = UnixListener::bind(&path)?;
+
+        let stopping = Arc::new(AtomicBool::new(false));
+        let handler: Handler = Arc::new(handler);
+        let accept_thread = {
+            let stopping = stopping.clone();
+            thread::spawn(move || accept_loop(listener, codec, handler, stopping))
+        };
+        Ok(Self { path, stopping, accept_thread: Some(accept_thread) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 等待所有连接线程退出后返回
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        let Some(accept_thread) = self.accept_thread.take() else { return };
+        self.stopping.store(true, Ordering::SeqCst);
+        // accept 是阻塞的, 自己连一次把它唤醒
+        let _ = UnixStream::connect(&self.path);
+        let _ = accept_thread.join();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn accept_loop(listener: UnixListener, codec: FrameCodec, handler: Handler, stopping: Arc<AtomicBool>) {
+    let connections: Arc<Mutex<HashMap<u64, UnixStream>>> = Arc::default();
+    let mut threads = Vec::new();
+
+    for (conn_id, stream) in (0u64..).zip(listener.incoming()) {
+        if stopping.load(Ordering::SeqCst) {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("❌ 接受连接错误: {}", e);
+                continue;
+            }
+        };
+        match stream.try_clone() {
+            Ok(clone) => {
+                connections.lock().unwrap().insert(conn_id, clone);
+            }
+            Err(e) => {
+                eprintln!("❌ 接受连接错误: {}", e);
+                continue;
+            }
+        }
+
+        let handler = handler.clone();
+        let connections = connections.clone();
+        threads.push(thread::spawn(move || {
+            if let Err(e) = handle_client(stream, codec, &*handler) {
+                eprintln!("客户端处理错误: {}", e);
+            }
+            connections.lock().unwrap().remove(&conn_id);
+        }));
+        threads.retain(|t| !t.is_finished());
+    }
+
+    // 关闭读端, 正在阻塞读取的连接线程会收到 EOF 后退出
+    for stream in connections.lock().unwrap().values() {
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+    for thread in threads {
+        let _ = thread.join();
+    }
+}
+
+fn handle_client(mut stream: UnixStream, codec: FrameCodec, handler: &dyn Fn(Request) -> Response) -> Result<(), RpcError> {
+    while let Some(request) = codec.read_frame::<_, Envelope<Request>>(&mut stream)? {
+        let response = Envelope { id: request.id, body: handler(request.body) };
+        match codec.write_frame(&mut stream, &response) {
+            // 响应太大时返回错误信息, 连接可以继续使用
+            Err(RpcError::FrameTooLarge { len, max }) => {
+                let body = Response::Error(format!("response of {} bytes exceeds limit of {} bytes", len, max));
+                codec.write_frame(&mut stream, &Envelope { id: request.id, body })?;
+            }
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::ipc_socket::echo;
+
+    #[test]
+    fn test_call() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rpc.sock");
+        let server = Server::bind(&path, echo).unwrap();
+
+        let mut client = Client::connect(&path).unwrap();
+        assert_eq!(client.call(Request::Ping).unwrap(), Response::Pong);
+        assert_eq!(client.call(Request::Text("hi".into())).unwrap(), Response::Text("ECHO: hi".into()));
+        assert_eq!(client.call(Request::Data(vec![1, 255])).unwrap(), Response::Data(vec![2, 0]));
+
+        server.shutdown();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_pipelining() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rpc.sock");
+        let _server = Server::bind(&path, echo).unwrap();
+
+        let mut client = Client::connect(&path).unwrap();
+        let ids: Vec<u64> = (0..10).map(|i| client.send(&Request::Text(i.to_string())).unwrap()).collect();
+        // 先等最后一个, 前面的响应会被暂存
+        assert_eq!(client.call(Request::Ping).unwrap(), Response::Pong);
+
+        let mut responses: Vec<_> = (0..10).map(|_| client.recv().unwrap()).collect();
+        responses.sort_by_key(|r| r.id);
+        for (id, response) in ids.into_iter().zip(responses) {
+            assert_eq!(response, Envelope { id, body: Response::Text(format!("ECHO: {}", id)) });
+        }
+    }
+
+    #[test]
+    fn test_concurrent_clients() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rpc.sock");
+        let _server = Server::bind(&path, |request| {
+            thread::sleep(Duration::from_millis(50));
+            echo(request)
+        })
+        .unwrap();
+
+        // 一个慢请求不会挡住其他连接
+        let start = std::time::Instant::now();
+        let clients: Vec<_> = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                thread::spawn(move || Client::connect(path).unwrap().call(Request::Text(i.to_string())).unwrap())
+            })
+            .collect();
+        for (i, client) in clients.into_iter().enumerate() {
+            assert_eq!(client.join().unwrap(), Response::Text(format!("ECHO: {}", i)));
+        }
+        assert!(start.elapsed() < Duration::from_millis(400));
+    }
+
+    #[test]
+    fn test_shutdown_disconnects_clients() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rpc.sock");
+        let server = Server::bind(&path, echo).unwrap();
+        let mut client = Client::connect(&path).unwrap();
+        assert_eq!(client.call(Request::Ping).unwrap(), Response::Pong);
+
+        // 客户端还连着, shutdown 也不会卡住
+        server.shutdown();
+        assert!(client.call(Request::Ping).is_err());
+    }
+
+    #[test]
+    fn test_oversized_response() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rpc.sock");
+        let _server = Server::bind_with(&path, FrameCodec::new(64), echo).unwrap();
+
+        let mut client = Client::connect(&path).unwrap();
+        assert!(matches!(client.call(Request::Text("x".repeat(60))).unwrap(), Response::Error(_)));
+        assert_eq!(client.call(Request::Ping).unwrap(), Response::Pong);
+    }
+
+    #[test]
+    fn test_bind_existing_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rpc.sock");
+
+        // 上次异常退出留下的套接字可以直接复用
+        drop(UnixListener::bind(&path).unwrap());
+        let server = Server::bind(&path, echo).unwrap();
+
+        // 正在监听的地址和普通文件都不会被删掉
+        let err = Server::bind(&path, echo).err().unwrap();
+        assert!(matches!(err, RpcError::Io(ref e) if e.kind() == std::io::ErrorKind::AddrInUse));
+        assert_eq!(Client::connect(&path).unwrap().call(Request::Ping).unwrap(), Response::Pong);
+        server.shutdown();
+
+        let file = dir.path().join("data.txt");
+        std::fs::write(&file, "keep me").unwrap();
+        let err = Server::bind(&file, echo).err().unwrap();
+        assert!(matches!(err, RpcError::Io(ref e) if e.kind() == std::io::ErrorKind::AddrInUse));
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "keep me");
+    }
+}
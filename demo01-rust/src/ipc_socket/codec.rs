@@ -0,0 +1,323 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+
+use bincode::{Decode, Encode};
+use bytes::{Buf, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::{Decoder, Encoder};
+
+use super::{Envelope, Request, Response, RpcError};
+
+/// 默认的单帧上限: 16 MiB
+pub const DEFAULT_MAX_FRAME: usize = 16 * 1024 * 1024;
+
+const HEADER_LEN: usize = 8;
+
+/// 长度前缀的分帧编解码
+#[derive(Debug, Clone, Copy)]
+pub struct FrameCodec {
+    max_frame: usize,
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAME)
+    }
+}
+
+impl FrameCodec {
+    pub fn new(max_frame: usize) -> Self {
+        Self { max_frame }
+    }
+
+    pub fn max_frame(&self) -> usize {
+        self.max_frame
+    }
+
+    /// 编码成完整的一帧 (包含长度前缀)
+    pub fn encode<T: Encode>(&self, value: &T) -> Result<Vec<u8>, RpcError> {
+        let mut frame = vec![0u8; HEADER_LEN];
+        bincode::encode_into_std_write(value, &mut frame, bincode::config::standard()).map_err(RpcError::Encode)?;
+        let len = frame.len() - HEADER_LEN;
+        self.check_len(len as u64)?;
+        frame[..HEADER_LEN].copy_from_slice(&(len as u64).to_be_bytes());
+        Ok(frame)
+    }
+
+    /// 解码一帧的内容 (不包含长度前缀), 必须正好用完所有字节
+    pub fn decode<T: Decode<()>>(&self, payload: &[u8]) -> Result<T, RpcError> {
+        let (value, used) = bincode::decode_from_slice(payload, bincode::config::standard()).map_err(RpcError::Decode)?;
+        if used != payload.len() {
+            return Err(RpcError::TrailingBytes(payload.len() - used));
+        }
+        Ok(value)
+    }
+
+    pub fn write_frame<W: Write, T: Encode>(&self, writer: &mut W, value: &T) -> Result<(), RpcError> {
+        let frame = self.encode(value)?;
+        writer.write_all(&frame)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// 对端在帧边界上正常关闭时返回 `Ok(None)`, 在帧中间断开时返回 `UnexpectedEof`
+    pub fn read_frame<R: Read, T: Decode<()>>(&self, reader: &mut R) -> Result<Option<T>, RpcError> {
+        let mut header = [0u8; HEADER_LEN];
+        let mut filled = 0;
+        while filled < HEADER_LEN {
+            match reader.read(&mut header[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let len = self.check_len(u64::from_be_bytes(header))?;
+
+        let mut payload = vec![0u8; len];
+        reader.read_exact(&mut payload)?;
+        self.decode(&payload).map(Some)
+    }
+
+    pub async fn write_frame_async<W: AsyncWrite + Unpin, T: Encode>(&self, writer: &mut W, value: &T) -> Result<(), RpcError> {
+        let frame = self.encode(value)?;
+        writer.write_all(&frame).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    /// [FrameCodec::read_frame] 的异步版本. 不是取消安全的, 被取消后连接上的数据不能再继续读.
+    pub async fn read_frame_async<R: AsyncRead + Unpin, T: Decode<()>>(&self, reader: &mut R) -> Result<Option<T>, RpcError> {
+        let mut header = [0u8; HEADER_LEN];
+        let mut filled = 0;
+        while filled < HEADER_LEN {
+            match reader.read(&mut header[filled..]).await? {
+                0 if filled == 0 => return Ok(None),
+                0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                n => filled += n,
+            }
+        }
+        let len = self.check_len(u64::from_be_bytes(header))?;
+
+        let mut payload = vec![0u8; len];
+        reader.read_exact(&mut payload).await?;
+        self.decode(&payload).map(Some)
+    }
+
+    fn check_len(&self, len: u64) -> Result<usize, RpcError> {
+        match usize::try_from(len) {
+            Ok(n) if n <= self.max_frame => Ok(n),
+            _ => Err(RpcError::FrameTooLarge { len, max: self.max_frame }),
+        }
+    }
+}
+
+/// [FrameCodec] 的 `tokio_util::codec` 版本, 配合 `Framed` 把连接变成 `Stream<Item = In>` + `Sink<Out>`.
+///
+/// 线路格式和 [FrameCodec] 完全相同, 可以和同步的一端互通.
+/// 和 [FrameCodec::read_frame_async] 不同, 未读完的帧留在 `Framed` 的缓冲区中, 所以读取是取消安全的.
+///
+/// ```no_run
+/// # async fn run() -> Result<(), demo01_rust::ipc_socket::RpcError> {
+/// use futures::{SinkExt, StreamExt};
+/// use demo01_rust::ipc_socket::{ClientCodec, Envelope, Request};
+/// use tokio_util::codec::Framed;
+///
+/// let stream = tokio::net::UnixStream::connect("/tmp/demo01.sock").await?;
+/// let mut framed = Framed::new(stream, ClientCodec::default());
+/// framed.send(Envelope { id: 1, body: Request::Ping }).await?;
+/// println!("{:?}", framed.next().await.transpose()?);
+/// # Ok(()) }
+/// ```
+pub struct MessageCodec<In, Out> {
+    frame: FrameCodec,
+    _marker: PhantomData<fn(Out) -> In>,
+}
+
+impl<In, Out> MessageCodec<In, Out> {
+    pub fn new(frame: FrameCodec) -> Self {
+        Self { frame, _marker: PhantomData }
+    }
+}
+
+impl<In, Out> Default for MessageCodec<In, Out> {
+    fn default() -> Self {
+        Self::new(FrameCodec::default())
+    }
+}
+
+impl<In, Out> Clone for MessageCodec<In, Out> {
+    fn clone(&self) -> Self {
+        Self::new(self.frame)
+    }
+}
+
+impl<In, Out> fmt::Debug for MessageCodec<In, Out> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("MessageCodec").field(&self.frame).finish()
+    }
+}
+
+impl<In: Decode<()>, Out> Decoder for MessageCodec<In, Out> {
+    type Item = In;
+    type Error = RpcError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<In>, RpcError> {
+        if src.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        let len = self.frame.check_len(u64::from_be_bytes(src[..HEADER_LEN].try_into().unwrap()))?;
+        if src.len() < HEADER_LEN + len {
+            src.reserve(HEADER_LEN + len - src.len());
+            return Ok(None);
+        }
+        src.advance(HEADER_LEN);
+        let payload = src.split_to(len);
+        self.frame.decode(&payload).map(Some)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<In>, RpcError> {
+        match self.decode(src)? {
+            Some(item) => Ok(Some(item)),
+            None if src.is_empty() => Ok(None),
+            None => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        }
+    }
+}
+
+impl<In, Out: Encode> Encoder<Out> for MessageCodec<In, Out> {
+    type Error = RpcError;
+
+    fn encode(&mut self, item: Out, dst: &mut BytesMut) -> Result<(), RpcError> {
+        dst.extend_from_slice(&self.frame.encode(&item)?);
+        Ok(())
+    }
+}
+
+/// 客户端: 发送请求, 接收响应
+pub type ClientCodec = MessageCodec<Envelope<Response>, Envelope<Request>>;
+/// 服务端: 接收请求, 发送响应
+pub type ServerCodec = MessageCodec<Envelope<Request>, Envelope<Response>>;
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let codec = FrameCodec::default();
+        let request = Envelope { id: 7, body: Request::Command { cmd: "ls".into(), args: vec!["-la".into()] } };
+        let mut buf = Vec::new();
+        codec.write_frame(&mut buf, &request).unwrap();
+        codec.write_frame(&mut buf, &Envelope { id: 8, body: Request::Ping }).unwrap();
+        assert_eq!(u64::from_be_bytes(buf[..8].try_into().unwrap()) as usize, codec.encode(&request).unwrap().len() - 8);
+
+        let mut reader = Cursor::new(buf);
+        assert_eq!(codec.read_frame(&mut reader).unwrap(), Some(request));
+        assert_eq!(codec.read_frame(&mut reader).unwrap(), Some(Envelope { id: 8, body: Request::Ping }));
+        assert_eq!(codec.read_frame::<_, Envelope<Request>>(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_max_frame() {
+        let codec = FrameCodec::new(16);
+        let big = Envelope { id: 1, body: Request::Data(vec![0; 64]) };
+        assert!(matches!(codec.encode(&big), Err(RpcError::FrameTooLarge { max: 16, .. })));
+
+        // 对端声明了一个巨大的长度, 不能去分配这么多内存
+        let mut reader = Cursor::new(u64::MAX.to_be_bytes().to_vec());
+        let result = codec.read_frame::<_, Envelope<Request>>(&mut reader);
+        assert!(matches!(result, Err(RpcError::FrameTooLarge { len: u64::MAX, max: 16 })));
+    }
+
+    #[test]
+    fn test_truncated_frame() {
+        let codec = FrameCodec::default();
+        let mut frame = codec.encode(&Envelope { id: 1, body: Request::Text("hello".into()) }).unwrap();
+        frame.truncate(frame.len() - 1);
+        let result = codec.read_frame::<_, Envelope<Request>>(&mut Cursor::new(frame));
+        assert!(matches!(result, Err(RpcError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof));
+
+        let result = codec.read_frame::<_, Envelope<Request>>(&mut Cursor::new(vec![0, 0, 0]));
+        assert!(matches!(result, Err(RpcError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof));
+    }
+
+    #[test]
+    fn test_trailing_bytes() {
+        let codec = FrameCodec::default();
+        let mut payload = codec.encode(&Envelope { id: 1, body: Request::Ping }).unwrap().split_off(8);
+        payload.push(0);
+        assert!(matches!(codec.decode::<Envelope<Request>>(&payload), Err(RpcError::TrailingBytes(1))));
+    }
+
+    #[test]
+    fn test_message_codec_partial_frames() {
+        let mut server = ServerCodec::default();
+        let request = Envelope { id: 3, body: Request::Text("hello".into()) };
+        let frame = FrameCodec::default().encode(&request).unwrap();
+
+        // 一次只到达一个字节, 直到整帧到齐才解码
+        let mut buf = BytesMut::new();
+        for &byte in &frame[..frame.len() - 1] {
+            buf.extend_from_slice(&[byte]);
+            assert_eq!(server.decode(&mut buf).unwrap(), None);
+        }
+        buf.extend_from_slice(&frame[frame.len() - 1..]);
+        buf.extend_from_slice(&frame[..3]);
+        assert_eq!(server.decode(&mut buf).unwrap(), Some(request));
+        assert_eq!(buf.len(), 3);
+        assert!(matches!(server.decode_eof(&mut buf), Err(RpcError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof));
+
+        let mut small = ServerCodec::new(FrameCodec::new(16));
+        let mut buf = BytesMut::from(&u64::MAX.to_be_bytes()[..]);
+        assert!(matches!(small.decode(&mut buf), Err(RpcError::FrameTooLarge { len: u64::MAX, max: 16 })));
+    }
+
+    #[tokio::test]
+    async fn test_framed_interop() {
+        use futures::{SinkExt, StreamExt};
+        use tokio_util::codec::Framed;
+
+        let (client, server) = std::os::unix::net::UnixStream::pair().unwrap();
+        // 同步的一端用 FrameCodec 读写, 异步的一端用 Framed
+        let blocking = std::thread::spawn(move || {
+            let codec = FrameCodec::default();
+            let mut client = client;
+            for id in 0..3 {
+                codec.write_frame(&mut client, &Envelope { id, body: Request::Data(vec![id as u8; 1000]) }).unwrap();
+            }
+            (0..3).map(|_| codec.read_frame::<_, Envelope<Response>>(&mut client).unwrap().unwrap().id).collect::<Vec<_>>()
+        });
+
+        server.set_nonblocking(true).unwrap();
+        let mut framed = Framed::new(tokio::net::UnixStream::from_std(server).unwrap(), ServerCodec::default());
+        for id in 0..3 {
+            let request = framed.next().await.unwrap().unwrap();
+            assert_eq!(request, Envelope { id, body: Request::Data(vec![id as u8; 1000]) });
+            framed.send(Envelope { id, body: Response::Pong }).await.unwrap();
+        }
+        assert_eq!(blocking.join().unwrap(), [0, 1, 2]);
+        assert!(framed.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_async_roundtrip() {
+        let codec = FrameCodec::default();
+        let (mut a, mut b) = tokio::io::duplex(64);
+        let writer = tokio::spawn(async move {
+            for id in 0..3 {
+                codec.write_frame_async(&mut a, &Envelope { id, body: Request::Data(vec![id as u8; 100]) }).await.unwrap();
+            }
+        });
+        for id in 0..3 {
+            let frame: Envelope<Request> = codec.read_frame_async(&mut b).await.unwrap().unwrap();
+            assert_eq!(frame, Envelope { id, body: Request::Data(vec![id as u8; 100]) });
+        }
+        writer.await.unwrap();
+        assert_eq!(codec.read_frame_async::<_, Envelope<Request>>(&mut b).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,125 @@
+//! 基于 Unix 域套接字的请求/响应协议
+//!
+//! 每一帧是 8 字节大端序的长度, 后面跟着 bincode 编码的 [Envelope]. 长度超过
+//! [FrameCodec] 设置的上限时直接报错, 不会按照对方给的长度去分配内存.
+//!
+//! 每个请求都带有一个 id, 响应原样带回这个 id, 所以客户端可以连续发送多个请求 (pipelining),
+//! 再按 id 把响应对应回去, 不要求服务端按顺序回复.
+//!
+//! - [blocking] 模块: 标准库 `UnixStream` + 线程的实现
+//! - [asynchronous] 模块: tokio 的实现, 服务端并发处理同一连接上的多个请求
+//! - [MessageCodec]: 同一协议的 `tokio_util::codec` 实现, 配合 `Framed` 得到 `Stream` + `Sink`
+
+pub mod asynchronous;
+pub mod blocking;
+mod codec;
+
+use std::fmt;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+use bincode::{Decode, Encode};
+
+pub use codec::{ClientCodec, FrameCodec, MessageCodec, ServerCodec, DEFAULT_MAX_FRAME};
+
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Text(String),
+    Data(Vec<u8>),
+    Command { cmd: String, args: Vec<String> },
+    Ping,
+}
+
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    Text(String),
+    Data(Vec<u8>),
+    Pong,
+    Error(String),
+}
+
+/// 线路上实际传输的内容, `id` 用来把响应和请求对应起来
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct Envelope<T> {
+    pub id: u64,
+    pub body: T,
+}
+
+#[derive(Debug)]
+pub enum RpcError {
+    Io(io::Error),
+    /// 帧长度超过上限, 连接上剩余的数据已经无法解析, 只能断开
+    FrameTooLarge { len: u64, max: usize },
+    Encode(bincode::error::EncodeError),
+    Decode(bincode::error::DecodeError),
+    /// 解码完成后帧里还有多余的字节, 说明两端的消息定义不一致
+    TrailingBytes(usize),
+    /// 对端关闭了连接
+    Closed,
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Io(e) => write!(f, "io error: {}", e),
+            RpcError::FrameTooLarge { len, max } => write!(f, "frame of {} bytes exceeds limit of {} bytes", len, max),
+            RpcError::Encode(e) => write!(f, "encode error: {}", e),
+            RpcError::Decode(e) => write!(f, "decode error: {}", e),
+            RpcError::TrailingBytes(n) => write!(f, "{} trailing bytes after message", n),
+            RpcError::Closed => write!(f, "connection closed"),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RpcError::Io(e) => Some(e),
+            RpcError::Encode(e) => Some(e),
+            RpcError::Decode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RpcError {
+    fn from(e: io::Error) -> Self {
+        RpcError::Io(e)
+    }
+}
+
+/// 绑定前清理上次异常退出留下的套接字文件
+///
+/// 只删除连不上 (`ECONNREFUSED`) 的套接字; 路径是普通文件, 或者还有服务在监听时返回
+/// `AddrInUse`, 不会误删别人的文件或抢走正在运行的服务的地址.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let in_use = |reason: &str| io::Error::new(io::ErrorKind::AddrInUse, format!("{}: {}", path.display(), reason));
+    if !metadata.file_type().is_socket() {
+        return Err(in_use("path exists and is not a socket"));
+    }
+    match UnixStream::connect(path) {
+        Ok(_) => Err(in_use("another server is listening")),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => match std::fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        },
+        Err(e) => Err(e),
+    }
+}
+
+/// 示例中使用的处理函数
+pub fn echo(request: Request) -> Response {
+    match request {
+        Request::Text(text) => Response::Text(format!("ECHO: {}", text)),
+        Request::Data(data) => Response::Data(data.iter().map(|b| b.wrapping_add(1)).collect()),
+        Request::Command { cmd, args } => Response::Text(format!("执行命令: {} {:?}", cmd, args)),
+        Request::Ping => Response::Pong,
+    }
+}
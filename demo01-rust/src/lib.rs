@@ -0,0 +1,25 @@
+//! 从 `examples/` 中整理出来的可复用模块
+//!
+//! - [ipc_socket] 模块: 基于 Unix 域套接字的请求/响应协议, 同步和 tokio 两种实现
+//! - [async_ipc] 模块: eventfd, 跨进程互斥锁和消息协议的 tokio 封装
+//! - [rutex] 模块: 基于 `mmap` 共享内存和 futex 的跨进程互斥锁
+//! - [ipc_rwlock] 模块: 跨进程读写锁, 写者优先
+//! - [ipc_condvar] 模块: 跨进程条件变量
+//! - [shm_ring] 模块: 共享内存中的单生产者/单消费者环形缓冲区
+//! - [shm_queue] 模块: 共享内存中的多生产者/多消费者有界队列
+//! - [shm_segment] 模块: 带版本和布局校验的共享内存对象, 支持 seqlock 无锁读取
+//! - [supervisor] 模块: 子进程监督, 显式传递描述符, 崩溃重启, 转发信号
+//! - [signal_cleanup] 模块: 收到退出信号时在普通线程中执行清理回调
+//! - [mini_runtime] 模块: 基于 epoll 和时间轮的单线程异步运行时
+
+pub mod async_ipc;
+pub mod ipc_condvar;
+pub mod ipc_rwlock;
+pub mod ipc_socket;
+pub mod mini_runtime;
+pub mod rutex;
+pub mod shm_queue;
+pub mod shm_ring;
+pub mod shm_segment;
+pub mod signal_cleanup;
+pub mod supervisor;
@@ -0,0 +1,177 @@
+//! 任务队列和 `block_on` 主循环
+//!
+//! 每个任务是一个 `Arc<Task>`, 它本身就实现了 [ArcWake], 被唤醒时把自己放回运行队列.
+//! 同一个任务在队列中最多出现一次, 由 `scheduled` 标记保证.
+
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::{pin, Pin};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use futures::future::{BoxFuture, FutureExt};
+use futures::task::{waker, waker_ref, ArcWake};
+
+use super::reactor::Reactor;
+
+/// 每轮最多执行的任务数, 之后先检查一次 I/O 和定时器, 避免一直就绪的任务饿死其他事件
+const BUDGET: usize = 128;
+
+pub(crate) struct Scheduler {
+    queue: Mutex<VecDeque<Arc<Task>>>,
+    /// 所有尚未完成的任务, 运行时销毁时用来释放它们持有的 future
+    tasks: Mutex<HashMap<u64, Arc<Task>>>,
+    next_id: AtomicU64,
+    pub(crate) reactor: Arc<Reactor>,
+}
+
+struct Task {
+    id: u64,
+    future: Mutex<Option<BoxFuture<'static, ()>>>,
+    scheduled: AtomicBool,
+    scheduler: Arc<Scheduler>,
+}
+
+impl ArcWake for Task {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        if !arc_self.scheduled.swap(true, Ordering::AcqRel) {
+            arc_self.scheduler.queue.lock().unwrap().push_back(arc_self.clone());
+            arc_self.scheduler.reactor.unpark();
+        }
+    }
+}
+
+impl Task {
+    fn run(self: &Arc<Self>) {
+        // 先清除标记, 这样在 poll 期间被唤醒的任务会重新入队
+        self.scheduled.store(false, Ordering::Release);
+        let mut slot = self.future.lock().unwrap();
+        let Some(future) = slot.as_mut() else { return };
+        let waker = waker_ref(self);
+        if future.as_mut().poll(&mut Context::from_waker(&waker)).is_ready() {
+            slot.take();
+            drop(slot);
+            self.scheduler.tasks.lock().unwrap().remove(&self.id);
+        }
+    }
+}
+
+impl Scheduler {
+    pub(crate) fn new(reactor: Arc<Reactor>) -> Self {
+        Scheduler { queue: Mutex::default(), tasks: Mutex::default(), next_id: AtomicU64::new(0), reactor }
+    }
+
+    pub(crate) fn spawn<F>(self: &Arc<Self>, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let join = Arc::new(Mutex::new(JoinState { result: None, waker: None }));
+        let state = join.clone();
+        let future = async move {
+            // 任务 panic 时不影响执行器和其他任务, 由 JoinHandle 把 panic 转交给等待它的一方
+            let result = AssertUnwindSafe(future).catch_unwind().await;
+            let mut state = state.lock().unwrap();
+            state.result = Some(result);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        };
+        let task = Arc::new(Task {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            future: Mutex::new(Some(future.boxed())),
+            scheduled: AtomicBool::new(false),
+            scheduler: self.clone(),
+        });
+        self.tasks.lock().unwrap().insert(task.id, task.clone());
+        ArcWake::wake(task);
+        JoinHandle { state: join }
+    }
+
+    /// 运行 `future` 直到完成, 期间执行所有派生的任务
+    pub(crate) fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let main = Arc::new(MainWaker { woken: AtomicBool::new(true), reactor: self.reactor.clone() });
+        let main_waker = waker(main.clone());
+        let mut future = pin!(future);
+        loop {
+            if main.woken.swap(false, Ordering::AcqRel) {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut Context::from_waker(&main_waker)) {
+                    return output;
+                }
+            }
+            for _ in 0..BUDGET {
+                let Some(task) = self.queue.lock().unwrap().pop_front() else { break };
+                task.run();
+            }
+
+            self.reactor.park();
+            let idle = !main.woken.load(Ordering::SeqCst) && self.queue.lock().unwrap().is_empty();
+            let timeout = if idle { self.reactor.next_timeout() } else { Some(Default::default()) };
+            self.reactor.turn(timeout).expect("epoll_wait failed");
+        }
+    }
+
+    /// 释放所有未完成任务的 future, 打破任务和调度器之间的循环引用
+    pub(crate) fn shutdown(&self) {
+        self.queue.lock().unwrap().clear();
+        let tasks: Vec<_> = self.tasks.lock().unwrap().drain().map(|(_, task)| task).collect();
+        for task in tasks {
+            // future 的析构函数里可能会唤醒其他任务, 不能在持有锁的时候 drop
+            let future = task.future.lock().unwrap().take();
+            drop(future);
+        }
+        self.queue.lock().unwrap().clear();
+    }
+}
+
+struct MainWaker {
+    woken: AtomicBool,
+    reactor: Arc<Reactor>,
+}
+
+impl ArcWake for MainWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.woken.store(true, Ordering::SeqCst);
+        arc_self.reactor.unpark();
+    }
+}
+
+struct JoinState<T> {
+    result: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+/// [spawn](super::spawn) 返回的句柄, `.await` 得到任务的返回值.
+///
+/// 任务 panic 时, `.await` 会在等待方重新 panic. 丢弃句柄不会取消任务.
+pub struct JoinHandle<T> {
+    state: Arc<Mutex<JoinState<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn is_finished(&self) -> bool {
+        self.state.lock().unwrap().result.is_some()
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.state.lock().unwrap();
+        match state.result.take() {
+            Some(Ok(value)) => Poll::Ready(value),
+            Some(Err(payload)) => {
+                drop(state);
+                panic::resume_unwind(payload)
+            }
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
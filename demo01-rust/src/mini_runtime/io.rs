@@ -0,0 +1,138 @@
+//! 把非阻塞的描述符接入 reactor
+
+use std::future::poll_fn;
+use std::io;
+use std::os::fd::{AsFd, OwnedFd};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+
+use rustix::event::{eventfd, EventfdFlags};
+
+use super::reactor::{Direction, Reactor, ScheduledIo};
+
+/// 注册到 reactor 中的描述符, 析构时自动注销.
+///
+/// `io` 必须已经设置为非阻塞模式, 否则读写会阻塞整个执行器.
+pub struct Async<T: AsFd> {
+    io: Option<T>,
+    token: u64,
+    source: Arc<ScheduledIo>,
+    reactor: Arc<Reactor>,
+}
+
+impl<T: AsFd> Async<T> {
+    /// 只能在 [block_on](super::block_on) 中调用
+    pub fn new(io: T) -> io::Result<Self> {
+        let reactor = super::context::reactor();
+        let (token, source) = reactor.register(io.as_fd())?;
+        Ok(Async { io: Some(io), token, source, reactor })
+    }
+
+    pub fn get_ref(&self) -> &T {
+        self.io.as_ref().unwrap()
+    }
+
+    pub fn into_inner(mut self) -> T {
+        let io = self.io.take().unwrap();
+        self.reactor.deregister(self.token, io.as_fd());
+        io
+    }
+
+    pub fn poll_readable(&self, cx: &mut Context<'_>) -> Poll<()> {
+        self.source.poll_ready(cx, Direction::Read).map(drop)
+    }
+
+    pub fn poll_writable(&self, cx: &mut Context<'_>) -> Poll<()> {
+        self.source.poll_ready(cx, Direction::Write).map(drop)
+    }
+
+    pub async fn readable(&self) {
+        poll_fn(|cx| self.poll_readable(cx)).await
+    }
+
+    pub async fn writable(&self) {
+        poll_fn(|cx| self.poll_writable(cx)).await
+    }
+
+    /// 反复执行 `op` 直到它不再返回 `WouldBlock`, 用于实现 `AsyncRead` 这类基于 poll 的接口
+    pub fn poll_read_with<R>(&self, cx: &mut Context<'_>, op: impl FnMut(&T) -> io::Result<R>) -> Poll<io::Result<R>> {
+        self.poll_with(cx, Direction::Read, op)
+    }
+
+    pub fn poll_write_with<R>(&self, cx: &mut Context<'_>, op: impl FnMut(&T) -> io::Result<R>) -> Poll<io::Result<R>> {
+        self.poll_with(cx, Direction::Write, op)
+    }
+
+    pub async fn read_with<R>(&self, mut op: impl FnMut(&T) -> io::Result<R>) -> io::Result<R> {
+        poll_fn(|cx| self.poll_read_with(cx, &mut op)).await
+    }
+
+    pub async fn write_with<R>(&self, mut op: impl FnMut(&T) -> io::Result<R>) -> io::Result<R> {
+        poll_fn(|cx| self.poll_write_with(cx, &mut op)).await
+    }
+
+    fn poll_with<R>(
+        &self,
+        cx: &mut Context<'_>,
+        direction: Direction,
+        mut op: impl FnMut(&T) -> io::Result<R>,
+    ) -> Poll<io::Result<R>> {
+        loop {
+            let tick = ready!(self.source.poll_ready(cx, direction));
+            match op(self.get_ref()) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => self.source.clear_readiness(direction, tick),
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+}
+
+impl<T: AsFd> Drop for Async<T> {
+    fn drop(&mut self) {
+        if let Some(io) = &self.io {
+            self.reactor.deregister(self.token, io.as_fd());
+        }
+    }
+}
+
+/// 可以在异步任务中等待的 eventfd, 通知方可以在任意线程.
+///
+/// 整理自 `examples/rustix_eventfd.rs`, 在那里等待方是阻塞在 `read` 上的线程.
+pub struct EventFd {
+    inner: Async<Arc<OwnedFd>>,
+}
+
+/// [EventFd] 的通知端, 可以跨线程发送
+#[derive(Clone)]
+pub struct EventFdNotifier {
+    fd: Arc<OwnedFd>,
+}
+
+impl EventFd {
+    pub fn new() -> io::Result<Self> {
+        let fd = eventfd(0, EventfdFlags::CLOEXEC | EventfdFlags::NONBLOCK)?;
+        Ok(EventFd { inner: Async::new(Arc::new(fd))? })
+    }
+
+    pub fn notifier(&self) -> EventFdNotifier {
+        EventFdNotifier { fd: self.inner.get_ref().clone() }
+    }
+
+    /// 等待计数器变为非零, 返回累计的值并清零
+    pub async fn wait(&self) -> io::Result<u64> {
+        self.inner
+            .read_with(|fd| {
+                let mut buf = [0u8; 8];
+                rustix::io::read(fd, &mut buf)?;
+                Ok(u64::from_ne_bytes(buf))
+            })
+            .await
+    }
+}
+
+impl EventFdNotifier {
+    pub fn notify(&self, n: u64) -> io::Result<()> {
+        rustix::io::write(&self.fd, &n.to_ne_bytes())?;
+        Ok(())
+    }
+}
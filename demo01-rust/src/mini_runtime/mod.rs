@@ -0,0 +1,327 @@
+//! 单线程的迷你异步运行时
+//!
+//! 把 `examples/async_future.rs` 里没写完的 `executor` 补全, 由三部分组成:
+//!
+//! - 执行器: 任务实现 `ArcWake`, 被唤醒时把自己放回运行队列, [Runtime::block_on] 在当前线程上依次执行
+//! - reactor: 一个 epoll 实例, 以边沿触发方式监听套接字和 eventfd, 队列为空时阻塞在 `epoll_wait` 上
+//! - 时间轮: 所有 [sleep] 共用一个哈希时间轮, `epoll_wait` 的超时就是下一个定时器的到期时间,
+//!   不再像 `TimeFuture` 那样每个定时器开一个线程
+//!
+//! ```
+//! use std::time::Duration;
+//! use demo01_rust::mini_runtime::{block_on, sleep, spawn};
+//!
+//! let sum = block_on(async {
+//!     let tasks: Vec<_> = (1..=3u64)
+//!         .map(|i| spawn(async move {
+//!             sleep(Duration::from_millis(i)).await;
+//!             i
+//!         }))
+//!         .collect();
+//!     let mut sum = 0;
+//!     for task in tasks {
+//!         sum += task.await;
+//!     }
+//!     sum
+//! });
+//! assert_eq!(sum, 6);
+//! ```
+//!
+//! 任务可以在别的线程上被唤醒, 所以派生的 future 需要是 `Send` 的, 但它们始终只在调用 `block_on` 的线程上执行.
+
+mod executor;
+mod io;
+mod net;
+mod reactor;
+mod timer;
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+pub use executor::JoinHandle;
+pub use io::{Async, EventFd, EventFdNotifier};
+pub use net::{TcpListener, TcpStream};
+
+use executor::Scheduler;
+use reactor::Reactor;
+use timer::TimerEntry;
+
+pub struct Runtime {
+    scheduler: Arc<Scheduler>,
+}
+
+impl Runtime {
+    pub fn new() -> std::io::Result<Self> {
+        let reactor = Arc::new(Reactor::new()?);
+        Ok(Runtime { scheduler: Arc::new(Scheduler::new(reactor)) })
+    }
+
+    /// 在当前线程上运行 `future` 直到完成. 派生的任务只在 `block_on` 期间执行,
+    /// `future` 完成时还没结束的任务会留到下一次 `block_on`.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let _guard = context::enter(self.scheduler.clone());
+        self.scheduler.block_on(future)
+    }
+
+    /// 派生一个任务, 可以在 `block_on` 之外调用
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.scheduler.spawn(future)
+    }
+}
+
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        // 未完成的任务在析构时可能会注销描述符或取消定时器, 需要能找到当前的运行时
+        let _guard = context::enter(self.scheduler.clone());
+        self.scheduler.shutdown();
+    }
+}
+
+/// 创建一个新的 [Runtime] 并在上面运行 `future`
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    Runtime::new().expect("failed to create runtime").block_on(future)
+}
+
+/// 在当前的运行时上派生一个任务. 只能在 [block_on] 中调用.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    context::scheduler().spawn(future)
+}
+
+/// 只能在 [block_on] 中调用
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(Instant::now() + duration)
+}
+
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep { deadline, entry: None, reactor: context::reactor() }
+}
+
+/// [sleep] 返回的 future, 第一次被 poll 时才插入时间轮, 被丢弃时从时间轮中取消
+pub struct Sleep {
+    deadline: Instant,
+    entry: Option<Arc<TimerEntry>>,
+    reactor: Arc<Reactor>,
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        let deadline = self.deadline;
+        let reactor = self.reactor.clone();
+        let entry = self.entry.get_or_insert_with(|| reactor.insert_timer(deadline));
+        entry.set_waker(cx.waker());
+        if entry.is_fired() {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            entry.cancel();
+        }
+    }
+}
+
+mod context {
+    use std::cell::RefCell;
+    use std::sync::Arc;
+
+    use super::executor::Scheduler;
+    use super::reactor::Reactor;
+
+    thread_local! {
+        static CURRENT: RefCell<Option<Arc<Scheduler>>> = const { RefCell::new(None) };
+    }
+
+    pub(super) struct EnterGuard;
+
+    pub(super) fn enter(scheduler: Arc<Scheduler>) -> EnterGuard {
+        CURRENT.with(|current| {
+            let mut current = current.borrow_mut();
+            assert!(current.is_none(), "不能在 block_on 中嵌套调用 block_on");
+            *current = Some(scheduler);
+        });
+        EnterGuard
+    }
+
+    impl Drop for EnterGuard {
+        fn drop(&mut self) {
+            CURRENT.with(|current| current.borrow_mut().take());
+        }
+    }
+
+    pub(super) fn scheduler() -> Arc<Scheduler> {
+        CURRENT.with(|current| current.borrow().clone()).expect("必须在 mini_runtime 的 block_on 中调用")
+    }
+
+    pub(super) fn reactor() -> Arc<Reactor> {
+        scheduler().reactor.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use futures::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    async fn handle_client(mut stream: TcpStream) {
+        let mut data = [0u8; 50];
+        loop {
+            match stream.read(&mut data).await {
+                Ok(0) | Err(_) => break,
+                Ok(size) => stream.write_all(&data[..size]).await.unwrap(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_socket_create_tcp_listener() {
+        block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            // 服务端一直循环, 随运行时一起销毁
+            spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    spawn(handle_client(stream));
+                }
+            });
+
+            let clients: Vec<_> = (0..8)
+                .map(|i| {
+                    spawn(async move {
+                        let mut stream = TcpStream::connect(addr).await.unwrap();
+                        let msg = format!("Hello {}!", i);
+                        stream.write_all(msg.as_bytes()).await.unwrap();
+                        let mut data = vec![0u8; msg.len()];
+                        stream.read_exact(&mut data).await.unwrap();
+                        assert_eq!(data, msg.as_bytes());
+                    })
+                })
+                .collect();
+            for client in clients {
+                client.await;
+            }
+
+            // 数据量超过套接字缓冲区, 读写两端都会遇到 WouldBlock
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let (mut reader, mut writer) = stream.split();
+            let payload: Vec<u8> = (0..4 << 20).map(|i| i as u8).collect();
+            let expected = payload.clone();
+            let sender = spawn(async move {
+                writer.write_all(&payload).await.unwrap();
+                writer.close().await.unwrap();
+            });
+            let mut received = vec![0u8; expected.len()];
+            reader.read_exact(&mut received).await.unwrap();
+            assert!(received == expected);
+            sender.await;
+        });
+    }
+
+    #[test]
+    fn test_connect_refused() {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let err = block_on(TcpStream::connect(addr)).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::ConnectionRefused);
+    }
+
+    #[test]
+    fn test_sleep_wakes_in_deadline_order() {
+        let start = Instant::now();
+        let order = Arc::new(Mutex::new(Vec::new()));
+        block_on(async {
+            let tasks: Vec<_> = [30u64, 10, 20]
+                .into_iter()
+                .map(|ms| {
+                    let order = order.clone();
+                    spawn(async move {
+                        sleep(Duration::from_millis(ms)).await;
+                        order.lock().unwrap().push(ms);
+                    })
+                })
+                .collect();
+            for task in tasks {
+                task.await;
+            }
+        });
+        assert_eq!(*order.lock().unwrap(), [10, 20, 30]);
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_dropped_sleep_is_cancelled() {
+        let runtime = Runtime::new().unwrap();
+        runtime.block_on(async {
+            let long = sleep(Duration::from_secs(60));
+            futures::select! {
+                _ = futures::FutureExt::fuse(long) => unreachable!(),
+                _ = futures::FutureExt::fuse(sleep(Duration::from_millis(5))) => {}
+            }
+        });
+        // 取消的定时器只是打上标记, 不会让 epoll_wait 提前醒来, 也不会唤醒已经结束的任务
+        assert_eq!(runtime.block_on(async { 1 }), 1);
+    }
+
+    #[test]
+    fn test_join_handle_propagates_panic() {
+        let runtime = Runtime::new().unwrap();
+        let value = runtime.spawn(async { 42 });
+        assert_eq!(runtime.block_on(value), 42);
+
+        let failed = runtime.spawn(async { panic!("task failed") });
+        let other = runtime.spawn(async { "still running" });
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| runtime.block_on(failed)));
+        assert!(result.is_err());
+        assert_eq!(runtime.block_on(other), "still running");
+    }
+
+    #[test]
+    fn test_eventfd_notified_from_other_thread() {
+        block_on(async {
+            let event = EventFd::new().unwrap();
+            let notifier = event.notifier();
+            let thread = thread::spawn(move || {
+                for _ in 0..3 {
+                    thread::sleep(Duration::from_millis(5));
+                    notifier.notify(2).unwrap();
+                }
+            });
+            let mut total = 0;
+            while total < 6 {
+                total += event.wait().await.unwrap();
+            }
+            assert_eq!(total, 6);
+            thread.join().unwrap();
+        });
+    }
+}
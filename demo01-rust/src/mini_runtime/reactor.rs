@@ -0,0 +1,229 @@
+//! 基于 epoll 的 reactor
+//!
+//! 所有描述符都以边沿触发 (`EPOLLET`) 方式同时注册读写事件, 只在注册时调用一次 `epoll_ctl`.
+//! 每个描述符对应一个 [ScheduledIo], 记录已经就绪的方向和等待这个方向的所有 waker.
+//! 同一个方向可以有多个任务在等 (例如共享同一个描述符的两个任务都在等可读), 事件到达时全部唤醒.
+//!
+//! 边沿触发只在状态变化时通知一次, 所以只有在读写返回 `WouldBlock` 之后才能清除就绪状态.
+//! 为了不丢掉 "读写失败之后, 清除之前" 到达的事件, 每次收到事件都会增加 `tick`,
+//! 清除时带上读写之前看到的 `tick`, 不一致就说明期间来过新事件, 不能清除.
+
+use std::collections::HashMap;
+use std::io;
+use std::os::fd::{BorrowedFd, OwnedFd};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use rustix::buffer::spare_capacity;
+use rustix::event::epoll::{self, CreateFlags, EventData, EventFlags};
+use rustix::event::{eventfd, EventfdFlags};
+use rustix::io::Errno;
+use rustix::time::Timespec;
+
+use super::timer::{TimerEntry, TimerWheel};
+
+/// token 0 留给用来唤醒 `epoll_wait` 的 eventfd
+const WAKE_TOKEN: u64 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Read,
+    Write,
+}
+
+#[derive(Default)]
+struct IoState {
+    tick: u64,
+    readable: bool,
+    writable: bool,
+    read_wakers: Vec<Waker>,
+    write_wakers: Vec<Waker>,
+}
+
+#[derive(Default)]
+pub(crate) struct ScheduledIo {
+    state: Mutex<IoState>,
+}
+
+impl ScheduledIo {
+    /// 就绪时返回当前的 `tick`, 否则登记 waker
+    pub(crate) fn poll_ready(&self, cx: &mut Context<'_>, direction: Direction) -> Poll<u64> {
+        let mut state = self.state.lock().unwrap();
+        let (ready, wakers) = match direction {
+            Direction::Read => (state.readable, &mut state.read_wakers),
+            Direction::Write => (state.writable, &mut state.write_wakers),
+        };
+        if ready {
+            return Poll::Ready(state.tick);
+        }
+        // 同一个任务重复 poll 时不重复登记
+        if !wakers.iter().any(|old| old.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+
+    /// 读写返回 `WouldBlock` 后调用, `tick` 是读写之前 [poll_ready](Self::poll_ready) 返回的值
+    pub(crate) fn clear_readiness(&self, direction: Direction, tick: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state.tick != tick {
+            return;
+        }
+        match direction {
+            Direction::Read => state.readable = false,
+            Direction::Write => state.writable = false,
+        }
+    }
+
+    fn set_readiness(&self, flags: EventFlags) {
+        let mut wakers = Vec::new();
+        {
+            let mut state = self.state.lock().unwrap();
+            state.tick = state.tick.wrapping_add(1);
+            // 出错或对端关闭时两个方向都要唤醒, 让读写操作自己把错误返回出去
+            let closed = flags.intersects(EventFlags::ERR | EventFlags::HUP);
+            if closed || flags.intersects(EventFlags::IN | EventFlags::PRI | EventFlags::RDHUP) {
+                state.readable = true;
+                wakers.append(&mut state.read_wakers);
+            }
+            if closed || flags.contains(EventFlags::OUT) {
+                state.writable = true;
+                wakers.append(&mut state.write_wakers);
+            }
+        }
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+pub(crate) struct Reactor {
+    epoll: OwnedFd,
+    wake: OwnedFd,
+    /// 执行器即将阻塞在 `epoll_wait` 上, 此时唤醒任务需要写 eventfd
+    parked: AtomicBool,
+    next_token: AtomicU64,
+    sources: Mutex<HashMap<u64, Arc<ScheduledIo>>>,
+    timers: Mutex<TimerWheel>,
+}
+
+impl Reactor {
+    pub(crate) fn new() -> io::Result<Self> {
+        let epoll = epoll::create(CreateFlags::CLOEXEC)?;
+        let wake = eventfd(0, EventfdFlags::CLOEXEC | EventfdFlags::NONBLOCK)?;
+        epoll::add(&epoll, &wake, EventData::new_u64(WAKE_TOKEN), EventFlags::IN | EventFlags::ET)?;
+        Ok(Reactor {
+            epoll,
+            wake,
+            parked: AtomicBool::new(false),
+            next_token: AtomicU64::new(WAKE_TOKEN + 1),
+            sources: Mutex::new(HashMap::new()),
+            timers: Mutex::new(TimerWheel::new(Instant::now())),
+        })
+    }
+
+    pub(crate) fn register(&self, fd: BorrowedFd<'_>) -> io::Result<(u64, Arc<ScheduledIo>)> {
+        let token = self.next_token.fetch_add(1, Ordering::Relaxed);
+        let io = Arc::new(ScheduledIo::default());
+        self.sources.lock().unwrap().insert(token, io.clone());
+        let flags = EventFlags::IN | EventFlags::OUT | EventFlags::RDHUP | EventFlags::ET;
+        if let Err(err) = epoll::add(&self.epoll, fd, EventData::new_u64(token), flags) {
+            self.sources.lock().unwrap().remove(&token);
+            return Err(err.into());
+        }
+        Ok((token, io))
+    }
+
+    pub(crate) fn deregister(&self, token: u64, fd: BorrowedFd<'_>) {
+        // 描述符马上就要关闭, 内核会自动把它移出 epoll, 这里失败也没有关系
+        let _ = epoll::delete(&self.epoll, fd);
+        self.sources.lock().unwrap().remove(&token);
+    }
+
+    pub(crate) fn insert_timer(&self, deadline: Instant) -> Arc<TimerEntry> {
+        let entry = self.timers.lock().unwrap().insert(deadline);
+        // 新的定时器可能比执行器正在等待的超时更早
+        self.unpark();
+        entry
+    }
+
+    pub(crate) fn next_timeout(&self) -> Option<Duration> {
+        self.timers.lock().unwrap().next_timeout(Instant::now())
+    }
+
+    /// 执行器在检查完任务队列之后, 阻塞之前调用.
+    /// 和 [unpark](Self::unpark) 配合保证不会在有任务待执行时睡过去.
+    pub(crate) fn park(&self) {
+        self.parked.store(true, Ordering::SeqCst);
+    }
+
+    pub(crate) fn unpark(&self) {
+        if self.parked.load(Ordering::SeqCst) {
+            let _ = rustix::io::write(&self.wake, &1u64.to_ne_bytes());
+        }
+    }
+
+    /// 等待事件最多 `timeout`, 唤醒就绪的描述符和到期的定时器
+    pub(crate) fn turn(&self, timeout: Option<Duration>) -> io::Result<()> {
+        let timeout = timeout.map(|timeout| Timespec { tv_sec: timeout.as_secs() as _, tv_nsec: timeout.subsec_nanos() as _ });
+        let mut events = Vec::with_capacity(64);
+        let result = epoll::wait(&self.epoll, spare_capacity(&mut events), timeout.as_ref());
+        self.parked.store(false, Ordering::SeqCst);
+        match result {
+            Ok(_) | Err(Errno::INTR) => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        for event in events {
+            let token = event.data.u64();
+            if token == WAKE_TOKEN {
+                let mut buf = [0u8; 8];
+                let _ = rustix::io::read(&self.wake, &mut buf);
+                continue;
+            }
+            let io = self.sources.lock().unwrap().get(&token).cloned();
+            if let Some(io) = io {
+                io.set_readiness(event.flags);
+            }
+        }
+        self.timers.lock().unwrap().advance(Instant::now());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicUsize;
+    use std::task::Wake;
+
+    use super::*;
+
+    struct CountWaker(AtomicUsize);
+
+    impl Wake for CountWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// 两个任务等待同一个方向, 事件到达时都要被唤醒, 后登记的不能覆盖先登记的
+    #[test]
+    fn test_wakes_every_waiter() {
+        let io = ScheduledIo::default();
+        let counters: Vec<_> = (0..2).map(|_| Arc::new(CountWaker(AtomicUsize::new(0)))).collect();
+        for counter in &counters {
+            let waker = Waker::from(counter.clone());
+            // 同一个任务 poll 两次只登记一次
+            for _ in 0..2 {
+                assert!(io.poll_ready(&mut Context::from_waker(&waker), Direction::Read).is_pending());
+            }
+        }
+        let writer = Waker::from(Arc::new(CountWaker(AtomicUsize::new(0))));
+        assert!(io.poll_ready(&mut Context::from_waker(&writer), Direction::Write).is_pending());
+
+        io.set_readiness(EventFlags::IN);
+        assert!(counters.iter().all(|counter| counter.0.load(Ordering::Relaxed) == 1));
+        assert_eq!(io.state.lock().unwrap().write_wakers.len(), 1);
+        assert!(io.poll_ready(&mut Context::from_waker(&Waker::from(counters[0].clone())), Direction::Read).is_ready());
+    }
+}
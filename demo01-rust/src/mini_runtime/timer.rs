@@ -0,0 +1,156 @@
+//! 哈希时间轮
+//!
+//! 以 [TICK] 为刻度, 共 [SLOTS] 个槽位, 截止时间为第 `n` 个刻度的定时器放在 `n % SLOTS` 号槽位中.
+//! 每次 reactor 醒来时推进时间轮, 只检查经过的槽位, 不用为每个定时器单独开线程或排序.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::Waker;
+use std::time::{Duration, Instant};
+
+pub(crate) const SLOTS: usize = 256;
+pub(crate) const TICK: Duration = Duration::from_millis(1);
+
+pub(crate) struct TimerEntry {
+    deadline_tick: u64,
+    fired: AtomicBool,
+    cancelled: AtomicBool,
+    waker: Mutex<Option<Waker>>,
+}
+
+impl TimerEntry {
+    pub(crate) fn is_fired(&self) -> bool {
+        self.fired.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn set_waker(&self, waker: &Waker) {
+        let mut slot = self.waker.lock().unwrap();
+        if !slot.as_ref().is_some_and(|old| old.will_wake(waker)) {
+            *slot = Some(waker.clone());
+        }
+    }
+
+    /// 取消后的定时器不会再唤醒任何人, 在时间轮下次经过它所在的槽位时被移除
+    pub(crate) fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+        self.waker.lock().unwrap().take();
+    }
+
+    fn fire(&self) {
+        self.fired.store(true, Ordering::Release);
+        if let Some(waker) = self.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+}
+
+pub(crate) struct TimerWheel {
+    start: Instant,
+    /// 已经处理完的最后一个刻度
+    current_tick: u64,
+    slots: Vec<Vec<Arc<TimerEntry>>>,
+    len: usize,
+}
+
+impl TimerWheel {
+    pub(crate) fn new(start: Instant) -> Self {
+        TimerWheel { start, current_tick: 0, slots: (0..SLOTS).map(|_| Vec::new()).collect(), len: 0 }
+    }
+
+    fn tick_of(&self, instant: Instant) -> u64 {
+        (instant.saturating_duration_since(self.start).as_nanos() / TICK.as_nanos()) as u64
+    }
+
+    fn instant_of(&self, tick: u64) -> Instant {
+        self.start + TICK * u32::try_from(tick).unwrap_or(u32::MAX)
+    }
+
+    /// 截止时间向上取整到刻度, 保证定时器不会提前触发
+    pub(crate) fn insert(&mut self, deadline: Instant) -> Arc<TimerEntry> {
+        let elapsed = deadline.saturating_duration_since(self.start).as_nanos();
+        let tick = (elapsed.div_ceil(TICK.as_nanos()) as u64).max(self.current_tick + 1);
+        let entry = Arc::new(TimerEntry {
+            deadline_tick: tick,
+            fired: AtomicBool::new(false),
+            cancelled: AtomicBool::new(false),
+            waker: Mutex::new(None),
+        });
+        self.slots[tick as usize % SLOTS].push(entry.clone());
+        self.len += 1;
+        entry
+    }
+
+    /// 触发截止时间不晚于 `now` 的定时器, 返回触发的个数.
+    /// 经过的刻度超过一整圈时每个槽位只需要检查一次.
+    pub(crate) fn advance(&mut self, now: Instant) -> usize {
+        let now_tick = self.tick_of(now);
+        if now_tick <= self.current_tick {
+            return 0;
+        }
+        let mut fired = 0;
+        let steps = (now_tick - self.current_tick).min(SLOTS as u64);
+        for step in 1..=steps {
+            let slot = &mut self.slots[(self.current_tick + step) as usize % SLOTS];
+            let before = slot.len();
+            slot.retain(|entry| {
+                if entry.cancelled.load(Ordering::Acquire) {
+                    return false;
+                }
+                if entry.deadline_tick > now_tick {
+                    return true;
+                }
+                entry.fire();
+                fired += 1;
+                false
+            });
+            self.len -= before - slot.len();
+        }
+        self.current_tick = now_tick;
+        fired
+    }
+
+    /// 距离下一个定时器触发还要多久, 没有定时器时返回 `None`
+    pub(crate) fn next_timeout(&self, now: Instant) -> Option<Duration> {
+        if self.len == 0 {
+            return None;
+        }
+        let live = |entry: &&Arc<TimerEntry>| !entry.cancelled.load(Ordering::Acquire);
+        // 先向前扫描一圈, 槽位里存的可能是更晚几圈的定时器, 所以要比较刻度
+        let next = (1..=SLOTS as u64)
+            .map(|step| self.current_tick + step)
+            .find(|&tick| self.slots[tick as usize % SLOTS].iter().filter(live).any(|entry| entry.deadline_tick <= tick))
+            .or_else(|| self.slots.iter().flatten().filter(live).map(|entry| entry.deadline_tick).min())?;
+        Some(self.instant_of(next).saturating_duration_since(now))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wheel_fires_in_order() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::new(start);
+        let late = wheel.insert(start + Duration::from_millis(300));
+        let early = wheel.insert(start + Duration::from_millis(5));
+        let cancelled = wheel.insert(start + Duration::from_millis(2));
+        cancelled.cancel();
+        assert_eq!(wheel.len, 3);
+        assert_eq!(wheel.next_timeout(start), Some(Duration::from_millis(5)));
+
+        assert_eq!(wheel.advance(start + Duration::from_millis(4)), 0);
+        assert_eq!(wheel.len, 2);
+        assert_eq!(wheel.advance(start + Duration::from_millis(5)), 1);
+        assert!(early.is_fired() && !late.is_fired());
+
+        // 超过一整圈的定时器不会被提前触发
+        let now = start + Duration::from_millis(10);
+        assert_eq!(wheel.next_timeout(now), Some(Duration::from_millis(290)));
+        assert_eq!(wheel.advance(start + Duration::from_millis(299)), 0);
+        assert_eq!(wheel.advance(start + Duration::from_secs(10)), 1);
+        assert!(late.is_fired());
+        assert_eq!(wheel.len, 0);
+        assert_eq!(wheel.next_timeout(now), None);
+    }
+}
@@ -0,0 +1,917 @@
+//! 基于 `mmap` 文件共享内存的跨进程互斥锁
+//!
+//! 整理自 `examples/rustix_mmap2.rs`, 主要的变化:
+//!
+//! - 共享内存中的数据必须实现 [ShmSafe], 由使用者保证类型里没有指针, 布局固定.
+//! - 所有错误都通过 [RutexError] 返回, 不再 panic.
+//! - 锁字在加锁时写入持有者的 pid. 持有者在持锁期间退出 (包括 `kill -9`), 等待者会发现这个 pid 已经不存在,
+//!   直接接管这把锁, 并通过 [RutexGuard::recovered] 告知调用方数据可能只修改了一半.
+//! - 初始化和 pid 登记改用 `flock` 串行化. 进程退出时内核会自动释放 `flock`, 不会因为创建者崩溃而卡住其他进程.
+//!   登记表中的 pid 全部退出后, 下一个打开者会重新初始化数据, 不再依赖信号处理函数清理文件.
+//!
+//! 已知限制: pid 被系统回收并分配给新进程后, 旧持有者的死亡无法被发现. 这和 POSIX robust mutex 之外的所有
+//! 基于 pid 的方案一样.
+
+use std::cell::UnsafeCell;
+use std::ffi::c_void;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::marker::PhantomData;
+use std::num::NonZeroU32;
+use std::ops::{Deref, DerefMut};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{self, AtomicI32, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use lock_api::{GuardSend, RawMutex};
+use rustix::fs::{flock, FlockOperation};
+use rustix::mm::{mmap, munmap, MapFlags, ProtFlags};
+use rustix::process::{getpid, test_kill_process, Pid};
+use rustix::thread::futex;
+use rustix::time::{clock_gettime, ClockId};
+
+/// 可以放进共享内存的类型
+///
+/// # Safety
+///
+/// 实现者必须保证:
+///
+/// - 类型中没有指针, 引用, `Box`, `String`, `Vec` 等, 它们指向的是某一个进程自己的地址空间.
+/// - 布局是确定的: 结构体使用 `#[repr(C)]` 或 `#[repr(transparent)]`, 所有字段也实现了 `ShmSafe`.
+/// - 没有 `Drop` 逻辑, 共享内存中的值永远不会被 drop.
+///
+/// ```
+/// use demo01_rust::rutex::ShmSafe;
+///
+/// #[repr(C)]
+/// struct Counter {
+///     value: u64,
+///     last_writer: i32,
+/// }
+/// unsafe impl ShmSafe for Counter {}
+/// ```
+pub unsafe trait ShmSafe: Sized + Send + 'static {}
+
+macro_rules! impl_shm_safe {
+    ($($t:ty),* $(,)?) => {
+        $(unsafe impl ShmSafe for $t {})*
+    };
+}
+
+impl_shm_safe!(
+    u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, bool, char,
+    atomic::AtomicU8, atomic::AtomicU16, atomic::AtomicU32, atomic::AtomicU64, atomic::AtomicUsize,
+    atomic::AtomicI8, atomic::AtomicI16, atomic::AtomicI32, atomic::AtomicI64, atomic::AtomicIsize, atomic::AtomicBool,
+);
+
+unsafe impl<T: ShmSafe, const N: usize> ShmSafe for [T; N] {}
+
+#[derive(Debug)]
+pub enum RutexError {
+    Io(io::Error),
+    /// 文件中已有的共享内存与当前的 `T` 大小或对齐不一致, 很可能是不同的程序用了同一个路径
+    LayoutMismatch { path: PathBuf, expected: (usize, usize), found: (usize, usize) },
+    /// 文件长度和 `SharedMemory<T>` 的大小不一致, 还没读到头部就能确定不是同一种数据
+    FileSizeMismatch { path: PathBuf, expected: u64, found: u64 },
+    /// 同时使用这块共享内存的句柄超过了 [MAX_PROCESSES]
+    RegistryFull,
+}
+
+impl Display for RutexError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RutexError::Io(e) => write!(f, "io error: {}", e),
+            RutexError::LayoutMismatch { path, expected, found } => write!(
+                f,
+                "shared memory {:?} holds data of size/align {:?}, expected {:?}",
+                path, found, expected
+            ),
+            RutexError::FileSizeMismatch { path, expected, found } => {
+                write!(f, "shared memory {:?} is {} bytes long, expected {}", path, found, expected)
+            }
+            RutexError::RegistryFull => write!(f, "more than {} handles attached to shared memory", MAX_PROCESSES),
+        }
+    }
+}
+
+impl std::error::Error for RutexError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RutexError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RutexError {
+    fn from(e: io::Error) -> Self {
+        RutexError::Io(e)
+    }
+}
+
+impl From<rustix::io::Errno> for RutexError {
+    fn from(e: rustix::io::Errno) -> Self {
+        RutexError::Io(e.into())
+    }
+}
+
+/// 当前进程的 pid, 锁字和登记表中都存这个值
+pub(crate) fn current_pid() -> u32 {
+    getpid().as_raw_nonzero().get() as u32
+}
+
+/// `kill(pid, 0)`: 只有 `ESRCH` 说明进程不存在, `EPERM` 说明进程存在但属于其他用户.
+/// 已经退出但还没有被父进程回收的僵尸进程也算退出.
+pub(crate) fn pid_alive(pid: u32) -> bool {
+    let Some(raw) = Pid::from_raw(pid as i32) else { return false };
+    if matches!(test_kill_process(raw), Err(rustix::io::Errno::SRCH)) {
+        return false;
+    }
+    // /proc/<pid>/stat 的格式是 `pid (comm) state ...`, comm 中可能有括号, 所以从最后一个 `)` 开始找
+    match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        Ok(stat) => !matches!(stat.rsplit_once(')').and_then(|(_, rest)| rest.trim_start().chars().next()), Some('Z' | 'X')),
+        Err(_) => true,
+    }
+}
+
+/// 等待者在锁字上睡眠的最长时间. 持有者被 `kill -9` 时没有人会唤醒等待者, 需要定期醒来检查持有者是否还活着.
+pub(crate) const OWNER_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+pub(crate) fn timespec(duration: Duration) -> futex::Timespec {
+    futex::Timespec { tv_sec: duration.as_secs() as _, tv_nsec: duration.subsec_nanos() as _ }
+}
+
+/// 在 `futex` 上等待, 直到被唤醒或超过 `deadline`. 返回 `false` 表示已经超时.
+pub(crate) fn futex_wait(futex: &AtomicU32, expected: u32, deadline: Option<Instant>) -> bool {
+    let timeout = match deadline {
+        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+            Some(remaining) if !remaining.is_zero() => Some(timespec(remaining)),
+            _ => return false,
+        },
+        None => None,
+    };
+    !matches!(futex::wait(futex, futex::Flags::empty(), expected, timeout.as_ref()), Err(rustix::io::Errno::TIMEDOUT))
+}
+
+/// 返回是否真的有等待者被唤醒
+pub(crate) fn futex_wake(futex: &AtomicU32, count: u32) -> bool {
+    matches!(futex::wake(futex, futex::Flags::empty(), count), Ok(n) if n > 0)
+}
+
+/// 同 [futex_wait], 但只会被 `bitset` 有交集的 [futex_wake_bitset] 唤醒.
+/// 注意 `FUTEX_WAIT_BITSET` 的超时是 `CLOCK_MONOTONIC` 上的绝对时间, 不是相对时间.
+pub(crate) fn futex_wait_bitset(futex: &AtomicU32, expected: u32, deadline: Option<Instant>, bitset: NonZeroU32) -> bool {
+    let timeout = match deadline {
+        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+            Some(remaining) if !remaining.is_zero() => {
+                let now = clock_gettime(ClockId::Monotonic);
+                let nanos = now.tv_nsec as u64 + remaining.subsec_nanos() as u64;
+                Some(futex::Timespec {
+                    tv_sec: now.tv_sec + (remaining.as_secs() + nanos / 1_000_000_000) as i64,
+                    tv_nsec: (nanos % 1_000_000_000) as _,
+                })
+            }
+            _ => return false,
+        },
+        None => None,
+    };
+    !matches!(
+        futex::wait_bitset(futex, futex::Flags::empty(), expected, timeout.as_ref(), bitset),
+        Err(rustix::io::Errno::TIMEDOUT)
+    )
+}
+
+pub(crate) fn futex_wake_bitset(futex: &AtomicU32, count: u32, bitset: NonZeroU32) -> bool {
+    matches!(futex::wake_bitset(futex, futex::Flags::empty(), count, bitset), Ok(n) if n > 0)
+}
+
+/// 锁字的最高位表示有等待者, 其余位是持有者的 pid (Linux 的 pid 不超过 2^22)
+const WAITERS: u32 = 1 << 31;
+const UN_LOCKED: u32 = 0;
+
+/// 基于 futex 的跨进程互斥锁
+///
+/// 锁字为 0 表示未加锁, 否则保存持有者的 pid. 只有在有等待者时 unlock 才会调用 `futex::wake`.
+/// 同一进程内的多个线程也可以使用, 但同一进程中某个线程退出而没有解锁的情况无法发现.
+#[derive(Debug)]
+#[repr(transparent)]
+pub struct IpcMutexRaw(AtomicU32);
+
+unsafe impl ShmSafe for IpcMutexRaw {}
+
+impl IpcMutexRaw {
+    pub const fn new() -> Self {
+        Self(AtomicU32::new(UN_LOCKED))
+    }
+
+    /// 加锁, 返回 `true` 表示锁是从一个已经退出的进程手里接管过来的
+    pub fn lock_robust(&self) -> bool {
+        let me = current_pid();
+        // 睡眠过之后, 可能还有其他等待者, 加锁时要保留等待标志, 保证 unlock 时会唤醒它们
+        let mut waited = false;
+        let mut spins = 0;
+        // 检查持有者是否存活需要系统调用, 只在第一次睡眠前和每次睡眠超时后检查
+        let mut check_owner = true;
+        loop {
+            let current = self.0.load(Ordering::Relaxed);
+            let owner = current & !WAITERS;
+            let wanted = if waited { me | WAITERS } else { me };
+
+            if current == UN_LOCKED {
+                if self.0.compare_exchange(UN_LOCKED, wanted, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+                    return false;
+                }
+                continue;
+            }
+            if spins < 100 {
+                spins += 1;
+                std::hint::spin_loop();
+                continue;
+            }
+            if check_owner && owner != me && !pid_alive(owner) {
+                if self.0.compare_exchange(current, me | (current & WAITERS), Ordering::Acquire, Ordering::Relaxed).is_ok() {
+                    return true;
+                }
+                continue;
+            }
+            if current & WAITERS == 0
+                && self.0.compare_exchange(current, current | WAITERS, Ordering::Relaxed, Ordering::Relaxed).is_err()
+            {
+                continue;
+            }
+            // EAGAIN (值已经变了) 和 EINTR 只需要重新检查锁字
+            let result = futex::wait(&self.0, futex::Flags::empty(), current | WAITERS, Some(&timespec(OWNER_CHECK_INTERVAL)));
+            check_owner = matches!(result, Err(rustix::io::Errno::TIMEDOUT));
+            waited = true;
+        }
+    }
+
+    /// 和 [IpcMutexRaw::lock_robust] 一样, 锁被占用时直接返回 `None`
+    pub fn try_lock_robust(&self) -> Option<bool> {
+        self.try_lock_with(UN_LOCKED)
+    }
+
+    /// 睡眠过的等待者加锁时要带上 `WAITERS`, 理由同 [IpcMutexRaw::lock_robust]
+    pub(crate) fn try_lock_after_wait(&self) -> Option<bool> {
+        self.try_lock_with(WAITERS)
+    }
+
+    fn try_lock_with(&self, waiters: u32) -> Option<bool> {
+        let me = current_pid();
+        let current = self.0.load(Ordering::Relaxed);
+        if current == UN_LOCKED {
+            return self.0.compare_exchange(UN_LOCKED, me | waiters, Ordering::Acquire, Ordering::Relaxed).ok().map(|_| false);
+        }
+        let owner = current & !WAITERS;
+        if owner != me && !pid_alive(owner) {
+            return self.0.compare_exchange(current, me | (current & WAITERS), Ordering::Acquire, Ordering::Relaxed).ok().map(|_| true);
+        }
+        None
+    }
+
+    /// 锁被占用时在锁字上睡眠, 直到被 unlock 唤醒或超过 `timeout`, 不加锁.
+    /// 给不能阻塞的调用方在单独的线程中使用, 醒来后再用 [IpcMutexRaw::try_lock_after_wait] 加锁.
+    pub(crate) fn wait_unlocked(&self, timeout: Duration) {
+        let current = self.0.load(Ordering::Relaxed);
+        if current == UN_LOCKED {
+            return;
+        }
+        if current & WAITERS == 0 && self.0.compare_exchange(current, current | WAITERS, Ordering::Relaxed, Ordering::Relaxed).is_err() {
+            return;
+        }
+        let _ = futex::wait(&self.0, futex::Flags::empty(), current | WAITERS, Some(&timespec(timeout)));
+    }
+
+    /// 当前持有者的 pid
+    pub fn owner(&self) -> Option<u32> {
+        match self.0.load(Ordering::Relaxed) & !WAITERS {
+            UN_LOCKED => None,
+            pid => Some(pid),
+        }
+    }
+}
+
+impl Default for IpcMutexRaw {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl RawMutex for IpcMutexRaw {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: IpcMutexRaw = IpcMutexRaw::new();
+    type GuardMarker = GuardSend;
+
+    fn lock(&self) {
+        self.lock_robust();
+    }
+
+    fn try_lock(&self) -> bool {
+        self.try_lock_robust().is_some()
+    }
+
+    unsafe fn unlock(&self) {
+        if self.0.swap(UN_LOCKED, Ordering::Release) & WAITERS != 0 {
+            let _ = futex::wake(&self.0, futex::Flags::empty(), 1);
+        }
+    }
+
+    fn is_locked(&self) -> bool {
+        self.0.load(Ordering::Relaxed) != UN_LOCKED
+    }
+}
+
+pub type IpcMutex<T> = lock_api::Mutex<IpcMutexRaw, T>;
+pub type IpcMutexGuard<'a, T> = lock_api::MutexGuard<'a, IpcMutexRaw, T>;
+
+/// 同时使用同一块共享内存的句柄数上限
+pub const MAX_PROCESSES: usize = 16;
+
+const INITIAL: u32 = 0;
+const CREATED: u32 = 2;
+
+/// 共享内存的布局. 所有字段在所有进程中都必须一致, 所以用 `repr(C)`.
+#[repr(C, align(64))]
+struct SharedMemory<T> {
+    status: AtomicU32,
+    data_size: AtomicU32,
+    data_align: AtomicU32,
+    /// 正在使用这块内存的 pid, 只在持有文件 `flock` 时修改
+    registry: [AtomicI32; MAX_PROCESSES],
+    lock: IpcMutexRaw,
+    data: UnsafeCell<T>,
+}
+
+/// 跨进程互斥锁, 多个进程用同一个 `path` 打开时共享同一份 `T`
+///
+/// ```no_run
+/// use demo01_rust::rutex::Rutex;
+///
+/// let rutex = Rutex::open("/tmp/rutex-demo.bin", 0u64).unwrap();
+/// let mut guard = rutex.lock();
+/// if guard.recovered() {
+///     println!("上一个持有者在持锁时退出了");
+/// }
+/// *guard += 1;
+/// ```
+pub struct Rutex<T: ShmSafe> {
+    memory: *mut SharedMemory<T>,
+    path: PathBuf,
+    file: File,
+    created: bool,
+}
+
+unsafe impl<T: ShmSafe> Send for Rutex<T> {}
+unsafe impl<T: ShmSafe> Sync for Rutex<T> {}
+
+impl<T: ShmSafe> Rutex<T> {
+    /// 打开或创建共享内存
+    ///
+    /// 文件不存在, 或者登记过的进程都已经退出时, 用 `initial` 初始化数据, 否则忽略 `initial`,
+    /// 直接使用共享内存中已有的数据.
+    pub fn open(path: impl AsRef<Path>, initial: T) -> Result<Self, RutexError> {
+        let path = path.as_ref().to_path_buf();
+        let file = lock_file(&path)?;
+        let result = Self::attach(file, path, initial);
+        if let Ok(rutex) = &result {
+            flock(&rutex.file, FlockOperation::Unlock)?;
+        }
+        result
+    }
+
+    /// 持有 `flock` 时调用, 返回时仍然持有
+    fn attach(file: File, path: PathBuf, initial: T) -> Result<Self, RutexError> {
+        let size = size_of::<SharedMemory<T>>();
+        let len = file.metadata()?.len();
+        if len != 0 && len != size as u64 {
+            return Err(RutexError::FileSizeMismatch { path, expected: size as u64, found: len });
+        }
+        if len == 0 {
+            file.set_len(size as u64)?;
+        }
+
+        let memory = unsafe {
+            mmap(ptr::null_mut(), size, ProtFlags::READ | ProtFlags::WRITE, MapFlags::SHARED, &file, 0)? as *mut SharedMemory<T>
+        };
+        // 从这里开始出错时需要 munmap, 交给 Drop 处理
+        let mut rutex = Self { memory, path, file, created: false };
+        let shared = rutex.shared();
+
+        let initialized = shared.status.load(Ordering::Acquire) == CREATED;
+        if initialized {
+            let found = (shared.data_size.load(Ordering::Relaxed) as usize, shared.data_align.load(Ordering::Relaxed) as usize);
+            if found != (size_of::<T>(), align_of::<T>()) {
+                let path = rutex.path.clone();
+                rutex.detach_without_registry();
+                return Err(layout_mismatch::<T>(path, found.0, found.1));
+            }
+        }
+
+        let alive = rutex.prune_registry();
+        if !initialized || alive == 0 {
+            unsafe {
+                ptr::write(memory, SharedMemory {
+                    status: AtomicU32::new(INITIAL),
+                    data_size: AtomicU32::new(size_of::<T>() as u32),
+                    data_align: AtomicU32::new(align_of::<T>() as u32),
+                    registry: Default::default(),
+                    lock: IpcMutexRaw::new(),
+                    data: UnsafeCell::new(initial),
+                });
+            }
+            rutex.shared().status.store(CREATED, Ordering::Release);
+            rutex.created = true;
+        }
+
+        let pid = current_pid() as i32;
+        match rutex.shared().registry.iter().find(|slot| slot.load(Ordering::Relaxed) == 0) {
+            Some(slot) => slot.store(pid, Ordering::Relaxed),
+            None => {
+                rutex.detach_without_registry();
+                return Err(RutexError::RegistryFull);
+            }
+        }
+        Ok(rutex)
+    }
+
+    fn shared(&self) -> &SharedMemory<T> {
+        unsafe { &*self.memory }
+    }
+
+    /// 清掉已经退出的进程, 返回剩下的数量
+    fn prune_registry(&self) -> usize {
+        let mut alive = 0;
+        for slot in &self.shared().registry {
+            let pid = slot.load(Ordering::Relaxed);
+            if pid != 0 {
+                if pid_alive(pid as u32) {
+                    alive += 1;
+                } else {
+                    slot.store(0, Ordering::Relaxed);
+                }
+            }
+        }
+        alive
+    }
+
+    /// 未登记时出错, 只需要取消映射, 让 Drop 不再处理
+    fn detach_without_registry(&mut self) {
+        unsafe {
+            let _ = munmap(self.memory as *mut c_void, size_of::<SharedMemory<T>>());
+        }
+        self.memory = ptr::null_mut();
+    }
+
+    pub fn lock(&self) -> RutexGuard<'_, T> {
+        let recovered = self.shared().lock.lock_robust();
+        RutexGuard { rutex: self, recovered, _marker: PhantomData }
+    }
+
+    pub fn try_lock(&self) -> Option<RutexGuard<'_, T>> {
+        let recovered = self.shared().lock.try_lock_robust()?;
+        Some(RutexGuard { rutex: self, recovered, _marker: PhantomData })
+    }
+
+    /// 异步加锁, 不会阻塞 tokio 的 worker 线程
+    ///
+    /// 锁被占用时, 在 `spawn_blocking` 的线程中睡在锁字上, 被唤醒或者每隔 [OWNER_CHECK_INTERVAL] 回到任务中重新尝试,
+    /// 所以持有者退出后同样能接管. 返回的 [OwnedRutexGuard] 持有 `Arc`, 可以跨 `.await` 并在任务之间移动.
+    ///
+    /// 被取消是安全的: 等待线程不会加锁, 最多再睡一个 [OWNER_CHECK_INTERVAL]. 但它可能已经消耗了一次 unlock 的唤醒,
+    /// 此时其他等待者要到超时才会重新尝试.
+    ///
+    /// ```no_run
+    /// # async fn run() -> Result<(), demo01_rust::rutex::RutexError> {
+    /// use std::sync::Arc;
+    /// use demo01_rust::rutex::Rutex;
+    ///
+    /// let rutex = Arc::new(Rutex::open("/tmp/rutex-demo.bin", 0u64)?);
+    /// let mut guard = rutex.lock_async().await;
+    /// *guard += 1;
+    /// tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    /// # Ok(()) }
+    /// ```
+    pub async fn lock_async(self: &Arc<Self>) -> OwnedRutexGuard<T> {
+        if let Some(recovered) = self.shared().lock.try_lock_robust() {
+            return OwnedRutexGuard { rutex: self.clone(), recovered };
+        }
+        loop {
+            let rutex = self.clone();
+            let _ = tokio::task::spawn_blocking(move || rutex.shared().lock.wait_unlocked(OWNER_CHECK_INTERVAL)).await;
+            if let Some(recovered) = self.shared().lock.try_lock_after_wait() {
+                return OwnedRutexGuard { rutex: self.clone(), recovered };
+            }
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.shared().lock.is_locked()
+    }
+
+    /// 持有锁的进程
+    pub fn owner(&self) -> Option<u32> {
+        self.shared().lock.owner()
+    }
+
+    /// 本次打开时是否初始化了数据 (写入了 `initial`)
+    pub fn is_creator(&self) -> bool {
+        self.created
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl<T: ShmSafe> Debug for Rutex<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Rutex").field("path", &self.path).field("owner", &self.owner()).finish()
+    }
+}
+
+/// 从登记表中移除自己. 如果自己是最后一个使用者, 删除文件, 下次打开时重新初始化.
+impl<T: ShmSafe> Drop for Rutex<T> {
+    fn drop(&mut self) {
+        if self.memory.is_null() {
+            return;
+        }
+        let _ = flock(&self.file, FlockOperation::LockExclusive);
+        let pid = current_pid() as i32;
+        if let Some(slot) = self.shared().registry.iter().find(|slot| slot.load(Ordering::Relaxed) == pid) {
+            slot.store(0, Ordering::Relaxed);
+        }
+        if self.prune_registry() == 0 && is_same_file(&self.file, &self.path) {
+            let _ = fs::remove_file(&self.path);
+        }
+        unsafe {
+            let _ = munmap(self.memory as *mut c_void, size_of::<SharedMemory<T>>());
+        }
+        // 关闭文件时 flock 自动释放
+    }
+}
+
+/// 打开文件并加上 `flock`
+///
+/// 等待 `flock` 期间, 文件可能被最后一个使用者删除了. 此时拿到的是一个已经不在目录中的文件,
+/// 其他进程再打开同一个路径会得到另一块内存, 所以需要确认路径仍然指向这个文件, 否则重新打开.
+fn lock_file(path: &Path) -> Result<File, RutexError> {
+    loop {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        flock(&file, FlockOperation::LockExclusive)?;
+        if is_same_file(&file, path) {
+            return Ok(file);
+        }
+    }
+}
+
+fn is_same_file(file: &File, path: &Path) -> bool {
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+fn layout_mismatch<T>(path: PathBuf, size: usize, align: usize) -> RutexError {
+    RutexError::LayoutMismatch { path, expected: (size_of::<T>(), align_of::<T>()), found: (size, align) }
+}
+
+pub struct RutexGuard<'a, T: ShmSafe> {
+    rutex: &'a Rutex<T>,
+    recovered: bool,
+    /// 锁字中记录的是 pid, 同一进程的任何线程都可以解锁, 不能跨线程发送只是为了和 `std::sync::MutexGuard` 的用法一致.
+    /// 需要跨 `.await` 或在线程之间移动时用 [Rutex::lock_async] 返回的 [OwnedRutexGuard].
+    _marker: PhantomData<*const ()>,
+}
+
+impl<T: ShmSafe> RutexGuard<'_, T> {
+    /// 锁是否从一个已经退出的进程手里接管过来的. 为 `true` 时数据可能只修改了一半, 需要调用方自己检查或修复.
+    pub fn recovered(&self) -> bool {
+        self.recovered
+    }
+}
+
+impl<T: ShmSafe> Deref for RutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.rutex.shared().data.get() }
+    }
+}
+
+impl<T: ShmSafe> DerefMut for RutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.rutex.shared().data.get() }
+    }
+}
+
+impl<T: ShmSafe> Drop for RutexGuard<'_, T> {
+    fn drop(&mut self) {
+        unsafe { self.rutex.shared().lock.unlock() }
+    }
+}
+
+/// [Rutex::lock_async] 返回的锁守卫
+///
+/// 锁字中记录的是 pid, 同一进程的任何线程都可以解锁, 所以它可以跨线程发送.
+/// [RutexGuard] 不能发送只是沿用 `std::sync::MutexGuard` 的约定, 不是锁本身的限制.
+pub struct OwnedRutexGuard<T: ShmSafe> {
+    rutex: Arc<Rutex<T>>,
+    recovered: bool,
+}
+
+impl<T: ShmSafe> OwnedRutexGuard<T> {
+    /// 见 [RutexGuard::recovered]
+    pub fn recovered(&self) -> bool {
+        self.recovered
+    }
+
+    pub fn rutex(&self) -> &Arc<Rutex<T>> {
+        &self.rutex
+    }
+}
+
+impl<T: ShmSafe> Deref for OwnedRutexGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.rutex.shared().data.get() }
+    }
+}
+
+impl<T: ShmSafe> DerefMut for OwnedRutexGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.rutex.shared().data.get() }
+    }
+}
+
+impl<T: ShmSafe> Drop for OwnedRutexGuard<T> {
+    fn drop(&mut self) {
+        unsafe { self.rutex.shared().lock.unlock() }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::thread;
+
+    use super::*;
+
+    /// fork 一个子进程执行 `f`, 返回子进程的 pid. 子进程中 `f` 返回或 panic 后立即 `_exit`, 不运行任何析构.
+    pub(crate) fn fork_child(f: impl FnOnce() -> bool) -> libc::pid_t {
+        match unsafe { libc::fork() } {
+            -1 => panic!("fork failed: {}", io::Error::last_os_error()),
+            0 => {
+                let ok = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).unwrap_or(false);
+                unsafe { libc::_exit(if ok { 0 } else { 1 }) }
+            }
+            pid => pid,
+        }
+    }
+
+    /// 等待子进程退出, 返回是否成功
+    pub(crate) fn wait_child(pid: libc::pid_t) -> bool {
+        let mut status = 0;
+        let ret = unsafe { libc::waitpid(pid, &mut status, 0) };
+        ret == pid && libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0
+    }
+
+    /// 测试用的共享内存文件, 内容全 0. 子进程用 [SharedFile::open] 重新映射, 而不是继承父进程的映射.
+    pub(crate) struct SharedFile<T> {
+        _dir: Option<tempfile::TempDir>,
+        path: PathBuf,
+        map: memmap2::MmapMut,
+        _marker: PhantomData<T>,
+    }
+
+    impl<T> SharedFile<T> {
+        pub(crate) fn create() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("shared.bin");
+            File::create(&path).unwrap().set_len(size_of::<T>() as u64).unwrap();
+            Self { _dir: Some(dir), ..Self::open(&path) }
+        }
+
+        pub(crate) fn open(path: &Path) -> Self {
+            let file = OpenOptions::new().read(true).write(true).open(path).unwrap();
+            let map = unsafe { memmap2::MmapMut::map_mut(&file).unwrap() };
+            Self { _dir: None, path: path.to_path_buf(), map, _marker: PhantomData }
+        }
+
+        pub(crate) fn path(&self) -> &Path {
+            &self.path
+        }
+
+        pub(crate) fn get(&self) -> &T {
+            unsafe { &*(self.map.as_ptr() as *const T) }
+        }
+    }
+
+    #[test]
+    fn test_lock_and_cleanup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rutex.bin");
+
+        let rutex = Rutex::open(&path, 1u64).unwrap();
+        assert!(rutex.is_creator());
+        {
+            let mut guard = rutex.lock();
+            assert!(!guard.recovered());
+            assert_eq!(rutex.owner(), Some(current_pid()));
+            *guard += 1;
+            assert!(rutex.try_lock().is_none());
+        }
+        assert!(!rutex.is_locked());
+
+        // 第二个句柄共享同一份数据, 忽略 initial
+        let other = Rutex::open(&path, 100u64).unwrap();
+        assert!(!other.is_creator());
+        assert_eq!(*other.lock(), 2);
+
+        drop(rutex);
+        assert!(path.exists());
+        drop(other);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_layout_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rutex.bin");
+        let _rutex = Rutex::open(&path, 0u32).unwrap();
+        let result = Rutex::open(&path, [0u64; 4]);
+        assert!(matches!(result, Err(RutexError::LayoutMismatch { .. })), "{:?}", result);
+        // 大到文件长度都对不上
+        let result = Rutex::open(&path, [0u64; 512]);
+        assert!(matches!(result, Err(RutexError::FileSizeMismatch { .. })), "{:?}", result);
+        // 对齐不同但大小相同
+        let result = Rutex::open(&path, [0u8; 4]);
+        assert!(matches!(result, Err(RutexError::LayoutMismatch { found: (4, 4), .. })), "{:?}", result);
+    }
+
+    #[test]
+    fn test_multi_process_counter() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rutex.bin");
+        let rutex = Rutex::open(&path, 0u64).unwrap();
+
+        let children: Vec<_> = (0..4)
+            .map(|_| {
+                let path = path.clone();
+                fork_child(move || {
+                    let rutex = Rutex::open(&path, 0u64).unwrap();
+                    for _ in 0..1000 {
+                        let mut guard = rutex.lock();
+                        // 读和写分开, 没有锁保护时很容易丢失更新
+                        let value = *guard;
+                        thread::yield_now();
+                        *guard = value + 1;
+                    }
+                    true
+                })
+            })
+            .collect();
+        for child in children {
+            assert!(wait_child(child));
+        }
+        assert_eq!(*rutex.lock(), 4000);
+    }
+
+    #[test]
+    fn test_owner_death_recovery() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rutex.bin");
+        let rutex = Rutex::open(&path, 0u64).unwrap();
+
+        // 子进程加锁后直接退出, 不解锁
+        let child = fork_child(|| {
+            let rutex = Rutex::open(&path, 0u64).unwrap();
+            let mut guard = rutex.lock();
+            *guard = 42;
+            std::mem::forget(guard);
+            true
+        });
+        assert!(wait_child(child));
+        assert_eq!(rutex.owner(), Some(child as u32));
+
+        let guard = rutex.lock();
+        assert!(guard.recovered());
+        assert_eq!(*guard, 42);
+        drop(guard);
+        assert!(!rutex.lock().recovered());
+    }
+
+    /// 持有者在别人等待期间被 kill, 等待者不需要被唤醒也能接管
+    #[test]
+    fn test_waiter_recovers_from_killed_owner() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rutex.bin");
+        let rutex = Rutex::open(&path, 0u64).unwrap();
+
+        let child = fork_child(|| {
+            let rutex = Rutex::open(&path, 0u64).unwrap();
+            let _guard = rutex.lock();
+            thread::sleep(Duration::from_secs(60));
+            true
+        });
+        while rutex.owner() != Some(child as u32) {
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        let killer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            unsafe { libc::kill(child, libc::SIGKILL) };
+        });
+        let guard = rutex.lock();
+        assert!(guard.recovered());
+        killer.join().unwrap();
+        assert!(!wait_child(child));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_lock_async_contended() {
+        let dir = tempfile::tempdir().unwrap();
+        let rutex = Arc::new(Rutex::open(dir.path().join("rutex.bin"), 0u64).unwrap());
+
+        // 同步加锁的线程和异步任务争用同一把锁, 任务持锁期间会让出执行权
+        let blocking = {
+            let rutex = rutex.clone();
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    let mut guard = rutex.lock();
+                    let value = *guard;
+                    thread::sleep(Duration::from_micros(50));
+                    *guard = value + 1;
+                }
+            })
+        };
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let rutex = rutex.clone();
+                tokio::spawn(async move {
+                    for _ in 0..50 {
+                        let mut guard = rutex.lock_async().await;
+                        let value = *guard;
+                        tokio::task::yield_now().await;
+                        *guard = value + 1;
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        blocking.join().unwrap();
+        assert_eq!(*rutex.lock(), 500);
+    }
+
+    #[tokio::test]
+    async fn test_lock_async_cancelled() {
+        let dir = tempfile::tempdir().unwrap();
+        let rutex = Arc::new(Rutex::open(dir.path().join("rutex.bin"), 0u64).unwrap());
+
+        let guard = rutex.lock_async().await;
+        let waiting = tokio::time::timeout(Duration::from_millis(20), rutex.lock_async()).await;
+        assert!(waiting.is_err());
+        drop(guard);
+
+        // 被取消的等待没有拿走锁
+        let start = Instant::now();
+        let guard = rutex.lock_async().await;
+        assert!(!guard.recovered());
+        assert!(start.elapsed() < OWNER_CHECK_INTERVAL);
+    }
+
+    /// 所有登记的进程都没有正常退出, 下一个打开者重新初始化
+    #[test]
+    fn test_stale_file_reinitialized() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rutex.bin");
+
+        let child = fork_child(|| {
+            let rutex = Rutex::open(&path, 0u64).unwrap();
+            *rutex.lock() = 42;
+            std::mem::forget(rutex);
+            true
+        });
+        assert!(wait_child(child));
+        assert!(path.exists());
+
+        let rutex = Rutex::open(&path, 7u64).unwrap();
+        assert!(rutex.is_creator());
+        assert_eq!(*rutex.lock(), 7);
+    }
+
+    #[test]
+    fn test_registry_full() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rutex.bin");
+        let handles: Vec<_> = (0..MAX_PROCESSES).map(|_| Rutex::open(&path, 0u8).unwrap()).collect();
+        assert!(matches!(Rutex::open(&path, 0u8), Err(RutexError::RegistryFull)));
+        drop(handles);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,737 @@
+//! 基于 `mmap` 文件的多生产者/多消费者有界队列
+//!
+//! 和 [crate::shm_ring] 不同, 任意多个进程 (和线程) 可以同时写入和读取, 代价是每条消息占用一个定长槽位.
+//!
+//! - 算法是 Vyukov 的有界 MPMC 队列: 每个槽位有一个序号, `seq == pos` 表示第 `pos` 个位置可以写入,
+//!   `seq == pos + 1` 表示已经写入可以读取, 读完后设为 `pos + capacity` 留给下一圈. 生产者和消费者
+//!   各自用 CAS 推进 `enqueue` / `dequeue` 位置来占用槽位, 不需要锁.
+//! - 队列空 (或满) 时在共享内存中的 futex 上睡眠, 只有存在等待者时才调用 `futex::wake`.
+//!   消费者可以加入 0..32 号消费组, 用 `FUTEX_WAIT_BITSET` 睡眠, 生产者用 [Queue::push_to] 只唤醒指定的组
+//!   (参考 `examples/rustix_futex.rs` 中的 `bitset_processor`). 消息本身仍然按先后顺序被任意消费者取走,
+//!   分组只决定谁被叫醒.
+//! - 占用槽位之前先把自己的 pid 写进槽位. 生产者在写入过程中退出, 消费者会发现这个槽位的写入者已经不在,
+//!   把它标记为废弃后跳过; 消费者在读取过程中退出, 生产者同样会回收这个槽位, 这条消息丢失.
+//!   被回收的槽位数量可以通过 [Queue::abandoned] 查看.
+//!
+//! 已知限制和 [crate::rutex] 一样: 只能发现进程退出, 同一进程中的某个线程在写入过程中 panic 无法发现.
+//!
+//! ```no_run
+//! use demo01_rust::shm_queue::Queue;
+//!
+//! let queue = Queue::create("/tmp/queue.bin", 1024, 256).unwrap();
+//! queue.push(b"hello").unwrap();
+//!
+//! // 另一个进程, 加入 1 号消费组
+//! let queue = Queue::open("/tmp/queue.bin").unwrap().with_group(1).unwrap();
+//! assert_eq!(queue.pop().unwrap(), b"hello");
+//! ```
+
+use std::fmt::{self, Display, Formatter};
+use std::fs::OpenOptions;
+use std::io;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use memmap2::MmapMut;
+
+use crate::rutex::{current_pid, futex_wait_bitset, futex_wake_bitset, pid_alive, OWNER_CHECK_INTERVAL};
+
+const MAGIC: u64 = u64::from_le_bytes(*b"SHMQUEU1");
+/// 生产者在写入过程中退出, 消费者把槽位的长度设为这个值, 读到时直接跳过
+const ABANDONED: u32 = u32::MAX;
+const SPIN_LIMIT: u32 = 100;
+
+pub const MAX_GROUPS: u32 = 32;
+/// 唤醒所有消费组中的任意一个等待者
+pub const ALL_GROUPS: u32 = u32::MAX;
+
+#[derive(Debug)]
+pub enum QueueError {
+    Io(io::Error),
+    /// 文件不是 [Queue::create] 创建的, 或者还没有初始化完成
+    InvalidHeader(PathBuf),
+    /// 槽位数必须是 2 的幂且不小于 2, 槽位大小必须大于 0
+    InvalidConfig { capacity: u64, slot_size: u64 },
+    /// 消费组编号必须小于 [MAX_GROUPS]
+    InvalidGroup(u32),
+    MessageTooLarge { len: usize, max: usize },
+    /// 槽位中的消息长度超过槽位大小, 共享内存被破坏. 这个槽位已经被跳过.
+    Corrupted { pos: u64, len: u32 },
+    Full,
+    Timeout,
+}
+
+impl Display for QueueError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            QueueError::Io(e) => write!(f, "io error: {}", e),
+            QueueError::InvalidHeader(path) => write!(f, "{:?} is not an initialized queue", path),
+            QueueError::InvalidConfig { capacity, slot_size } => {
+                write!(f, "invalid queue config: capacity {}, slot size {}", capacity, slot_size)
+            }
+            QueueError::InvalidGroup(group) => write!(f, "consumer group {} must be less than {}", group, MAX_GROUPS),
+            QueueError::MessageTooLarge { len, max } => write!(f, "message of {} bytes exceeds limit of {} bytes", len, max),
+            QueueError::Corrupted { pos, len } => write!(f, "corrupted message of {} bytes at position {}", len, pos),
+            QueueError::Full => write!(f, "queue is full"),
+            QueueError::Timeout => write!(f, "timed out"),
+        }
+    }
+}
+
+impl std::error::Error for QueueError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            QueueError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for QueueError {
+    fn from(e: io::Error) -> Self {
+        QueueError::Io(e)
+    }
+}
+
+/// 单独占一个缓存行, 避免生产者和消费者互相干扰
+#[repr(C, align(64))]
+struct Padded<T>(T);
+
+/// 一类等待者 (生产者或消费者) 共用的 futex
+#[repr(C)]
+struct Waiters {
+    /// 每次有等待者需要唤醒时加一, 等待者在这个值上睡眠
+    futex: AtomicU32,
+    /// 正在等待的总数, 为 0 时不调用 `futex::wake`
+    count: AtomicU32,
+    /// 每个消费组正在等待的数量, 生产者只用到 `count`
+    groups: [AtomicU32; MAX_GROUPS as usize],
+}
+
+impl Waiters {
+    /// 唤醒 `bitset` 中的一个等待者. 调用前已经用 Release 发布了数据.
+    fn notify(&self, bitset: u32) {
+        // 与 [Waiters::wait] 中的 fence 配对: 要么我们看到等待者, 要么等待者看到数据
+        fence(Ordering::SeqCst);
+        if self.count.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let mut mask = 0;
+        for group in 0..MAX_GROUPS {
+            if bitset & (1 << group) != 0 && self.groups[group as usize].load(Ordering::Relaxed) > 0 {
+                mask |= 1 << group;
+            }
+        }
+        // 目标组里没有人在等, 唤醒其他组的等待者, 避免消息一直没人取
+        if mask == 0 {
+            mask = ALL_GROUPS;
+        }
+        self.futex.fetch_add(1, Ordering::Release);
+        futex_wake_bitset(&self.futex, 1, NonZeroU32::new(mask).unwrap());
+    }
+
+    /// 以 `group` 的身份等待, 直到 `attempt` 返回 `Some` 或超过 `deadline`
+    fn wait<R>(&self, group: u32, deadline: Option<Instant>, mut attempt: impl FnMut() -> Option<R>) -> Option<R> {
+        for _ in 0..SPIN_LIMIT {
+            if let Some(result) = attempt() {
+                return Some(result);
+            }
+            std::hint::spin_loop();
+        }
+        let counter = &self.groups[group as usize];
+        loop {
+            self.count.fetch_add(1, Ordering::Relaxed);
+            counter.fetch_add(1, Ordering::Relaxed);
+            fence(Ordering::SeqCst);
+            let seq = self.futex.load(Ordering::Acquire);
+            let result = attempt();
+            if result.is_none() {
+                // 写入者或读取者被 kill 时不会唤醒我们, 定期醒来检查
+                let check = Instant::now() + OWNER_CHECK_INTERVAL;
+                let until = deadline.map_or(check, |deadline| deadline.min(check));
+                futex_wait_bitset(&self.futex, seq, Some(until), NonZeroU32::new(1 << group).unwrap());
+            }
+            counter.fetch_sub(1, Ordering::Relaxed);
+            self.count.fetch_sub(1, Ordering::Relaxed);
+            if result.is_some() {
+                return result;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return attempt();
+            }
+        }
+    }
+}
+
+#[repr(C)]
+struct Header {
+    magic: AtomicU64,
+    capacity: u64,
+    slot_size: u64,
+    /// 被回收的槽位数
+    abandoned: AtomicU64,
+    enqueue: Padded<AtomicU64>,
+    dequeue: Padded<AtomicU64>,
+    not_empty: Padded<Waiters>,
+    not_full: Padded<Waiters>,
+}
+
+/// 槽位头, 后面紧跟 `slot_size` 字节的数据
+#[repr(C)]
+struct Slot {
+    seq: AtomicU64,
+    /// 占用这个槽位写入的进程, 读完后清零
+    writer: AtomicU32,
+    /// 占用这个槽位读取的进程, 读完后清零
+    reader: AtomicU32,
+    len: AtomicU32,
+}
+
+fn slot_stride(slot_size: u64) -> u64 {
+    (size_of::<Slot>() as u64 + slot_size).next_multiple_of(64)
+}
+
+/// 进程已经退出. 0 表示没有人占用, 视为已经退出.
+fn gone(pid: u32) -> bool {
+    pid == 0 || !pid_alive(pid)
+}
+
+pub struct Queue {
+    _map: MmapMut,
+    header: *const Header,
+    slots: *mut u8,
+    capacity: u64,
+    slot_size: u64,
+    group: u32,
+    path: PathBuf,
+}
+
+unsafe impl Send for Queue {}
+unsafe impl Sync for Queue {}
+
+impl Queue {
+    /// 创建新文件并初始化, 一共 `capacity` 个槽位, 每条消息最多 `slot_size` 字节.
+    ///
+    /// 文件已经存在时返回 [io::ErrorKind::AlreadyExists], 不会清空其他进程正在使用的队列.
+    pub fn create(path: impl AsRef<Path>, capacity: u64, slot_size: u64) -> Result<Self, QueueError> {
+        Self::map(path.as_ref(), Some((capacity, slot_size)))
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, QueueError> {
+        Self::map(path.as_ref(), None)
+    }
+
+    fn map(path: &Path, create: Option<(u64, u64)>) -> Result<Self, QueueError> {
+        let header_len = size_of::<Header>() as u64;
+        let file = match create {
+            Some((capacity, slot_size)) => {
+                if !capacity.is_power_of_two() || capacity < 2 || slot_size == 0 || slot_size >= ABANDONED as u64 {
+                    return Err(QueueError::InvalidConfig { capacity, slot_size });
+                }
+                let file = OpenOptions::new().read(true).write(true).create_new(true).open(path)?;
+                file.set_len(header_len + capacity * slot_stride(slot_size))?;
+                file
+            }
+            None => OpenOptions::new().read(true).write(true).open(path)?,
+        };
+        if file.metadata()?.len() < header_len {
+            return Err(QueueError::InvalidHeader(path.to_path_buf()));
+        }
+
+        let mut map = unsafe { MmapMut::map_mut(&file)? };
+        let base = map.as_mut_ptr();
+        let header = base as *const Header;
+        let slots = unsafe { base.add(header_len as usize) };
+        if let Some((capacity, slot_size)) = create {
+            unsafe {
+                ptr::addr_of!((*header).capacity).cast_mut().write(capacity);
+                ptr::addr_of!((*header).slot_size).cast_mut().write(slot_size);
+                for i in 0..capacity {
+                    let slot = &*(slots.add((i * slot_stride(slot_size)) as usize) as *const Slot);
+                    slot.seq.store(i, Ordering::Relaxed);
+                }
+                // magic 最后写入, 对方看到 magic 时其他字段一定已经可见
+                (*header).magic.store(MAGIC, Ordering::Release);
+            }
+        }
+
+        let (magic, capacity, slot_size) =
+            unsafe { ((*header).magic.load(Ordering::Acquire), (*header).capacity, (*header).slot_size) };
+        if magic != MAGIC
+            || !capacity.is_power_of_two()
+            || map.len() as u64 != header_len + capacity * slot_stride(slot_size)
+        {
+            return Err(QueueError::InvalidHeader(path.to_path_buf()));
+        }
+        Ok(Self { _map: map, header, slots, capacity, slot_size, group: 0, path: path.to_path_buf() })
+    }
+
+    /// 以 `group` 号消费组的身份等待消息, 默认是 0 号组
+    pub fn with_group(mut self, group: u32) -> Result<Self, QueueError> {
+        if group >= MAX_GROUPS {
+            return Err(QueueError::InvalidGroup(group));
+        }
+        self.group = group;
+        Ok(self)
+    }
+
+    pub fn group(&self) -> u32 {
+        self.group
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    pub fn max_message(&self) -> usize {
+        self.slot_size as usize
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 已经占用但还没有读完的槽位数, 只是一个瞬时值
+    pub fn len(&self) -> u64 {
+        let header = self.header();
+        let dequeue = header.dequeue.0.load(Ordering::Acquire);
+        header.enqueue.0.load(Ordering::Acquire).saturating_sub(dequeue)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 因为进程在读写过程中退出而被回收的槽位数
+    pub fn abandoned(&self) -> u64 {
+        self.header().abandoned.load(Ordering::Relaxed)
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*self.header }
+    }
+
+    fn slot(&self, pos: u64) -> &Slot {
+        let index = pos & (self.capacity - 1);
+        unsafe { &*(self.slots.add((index * slot_stride(self.slot_size)) as usize) as *const Slot) }
+    }
+
+    fn slot_data(&self, pos: u64) -> *mut u8 {
+        let index = pos & (self.capacity - 1);
+        unsafe { self.slots.add((index * slot_stride(self.slot_size)) as usize + size_of::<Slot>()) }
+    }
+
+    pub fn try_push(&self, message: &[u8]) -> Result<(), QueueError> {
+        self.try_push_to(message, ALL_GROUPS)
+    }
+
+    /// 写入后只唤醒 `groups` (按位表示的消费组) 中的一个等待者
+    pub fn try_push_to(&self, message: &[u8], groups: u32) -> Result<(), QueueError> {
+        let max = self.max_message();
+        if message.len() > max {
+            return Err(QueueError::MessageTooLarge { len: message.len(), max });
+        }
+        let pos = self.claim_write().ok_or(QueueError::Full)?;
+        self.commit(pos, message, groups);
+        Ok(())
+    }
+
+    /// 队列满时阻塞
+    pub fn push(&self, message: &[u8]) -> Result<(), QueueError> {
+        self.push_until(message, ALL_GROUPS, None)
+    }
+
+    pub fn push_timeout(&self, message: &[u8], timeout: Duration) -> Result<(), QueueError> {
+        self.push_until(message, ALL_GROUPS, Some(Instant::now() + timeout))
+    }
+
+    pub fn push_to(&self, message: &[u8], groups: u32) -> Result<(), QueueError> {
+        self.push_until(message, groups, None)
+    }
+
+    fn push_until(&self, message: &[u8], groups: u32, deadline: Option<Instant>) -> Result<(), QueueError> {
+        let max = self.max_message();
+        if message.len() > max {
+            return Err(QueueError::MessageTooLarge { len: message.len(), max });
+        }
+        // 生产者都在 0 号组等待, 消费者读完后唤醒任意一个
+        let pos = self.header().not_full.0.wait(0, deadline, || self.claim_write()).ok_or(QueueError::Timeout)?;
+        self.commit(pos, message, groups);
+        Ok(())
+    }
+
+    /// 队列空时返回 `Ok(None)`
+    pub fn try_pop(&self) -> Result<Option<Vec<u8>>, QueueError> {
+        loop {
+            let Some(pos) = self.claim_read() else {
+                return Ok(None);
+            };
+            if let Some(message) = self.finish_read(pos)? {
+                return Ok(Some(message));
+            }
+        }
+    }
+
+    /// 队列空时阻塞
+    pub fn pop(&self) -> Result<Vec<u8>, QueueError> {
+        self.pop_until(None)
+    }
+
+    pub fn pop_timeout(&self, timeout: Duration) -> Result<Vec<u8>, QueueError> {
+        self.pop_until(Some(Instant::now() + timeout))
+    }
+
+    fn pop_until(&self, deadline: Option<Instant>) -> Result<Vec<u8>, QueueError> {
+        self.header().not_empty.0.wait(self.group, deadline, || self.try_pop().transpose()).ok_or(QueueError::Timeout)?
+    }
+
+    /// 占用下一个可写的槽位, 返回它的位置. 队列满 (或者最早的槽位正在被读取) 时返回 `None`.
+    fn claim_write(&self) -> Option<u64> {
+        let header = self.header();
+        let me = current_pid();
+        let mut pos = header.enqueue.0.load(Ordering::Relaxed);
+        loop {
+            let slot = self.slot(pos);
+            let seq = slot.seq.load(Ordering::Acquire);
+            let diff = seq.wrapping_sub(pos) as i64;
+            if diff == 0 {
+                // 先登记为写入者, 再推进 enqueue. 这样别人看到 enqueue 越过这个位置时, 一定能看到写入者.
+                let writer = slot.writer.load(Ordering::Acquire);
+                if !gone(writer) || slot.writer.compare_exchange(writer, me, Ordering::AcqRel, Ordering::Relaxed).is_err() {
+                    // 另一个生产者正在占用这个位置
+                    std::hint::spin_loop();
+                    pos = header.enqueue.0.load(Ordering::Relaxed);
+                    continue;
+                }
+                match header.enqueue.0.compare_exchange(pos, pos + 1, Ordering::AcqRel, Ordering::Relaxed) {
+                    Ok(_) => return Some(pos),
+                    Err(current) => {
+                        // 这个位置已经被别人推进过了 (那个生产者写到一半退出了), 留给消费者回收
+                        slot.writer.store(0, Ordering::Release);
+                        pos = current;
+                    }
+                }
+            } else if diff < 0 {
+                // 上一圈的消息还没有读完: 要么队列满, 要么有消费者正在读
+                let previous = pos.wrapping_sub(self.capacity);
+                if header.dequeue.0.load(Ordering::Acquire) <= previous {
+                    return None;
+                }
+                let reader = slot.reader.load(Ordering::Acquire);
+                if !gone(reader) {
+                    return None;
+                }
+                self.recover_read(slot, reader, previous);
+            } else {
+                pos = header.enqueue.0.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn commit(&self, pos: u64, message: &[u8], groups: u32) {
+        let slot = self.slot(pos);
+        unsafe {
+            ptr::copy_nonoverlapping(message.as_ptr(), self.slot_data(pos), message.len());
+        }
+        slot.len.store(message.len() as u32, Ordering::Relaxed);
+        slot.seq.store(pos + 1, Ordering::Release);
+        self.header().not_empty.0.notify(groups);
+    }
+
+    /// 占用下一个可读的槽位. 队列空 (或者最早的槽位正在被写入) 时返回 `None`.
+    fn claim_read(&self) -> Option<u64> {
+        let header = self.header();
+        let me = current_pid();
+        let mut pos = header.dequeue.0.load(Ordering::Relaxed);
+        loop {
+            let slot = self.slot(pos);
+            let seq = slot.seq.load(Ordering::Acquire);
+            let diff = seq.wrapping_sub(pos + 1) as i64;
+            if diff == 0 {
+                let reader = slot.reader.load(Ordering::Acquire);
+                if !gone(reader) || slot.reader.compare_exchange(reader, me, Ordering::AcqRel, Ordering::Relaxed).is_err() {
+                    std::hint::spin_loop();
+                    pos = header.dequeue.0.load(Ordering::Relaxed);
+                    continue;
+                }
+                match header.dequeue.0.compare_exchange(pos, pos + 1, Ordering::AcqRel, Ordering::Relaxed) {
+                    Ok(_) => return Some(pos),
+                    Err(current) => {
+                        slot.reader.store(0, Ordering::Release);
+                        pos = current;
+                    }
+                }
+            } else if diff < 0 {
+                // 还没有写入: 要么队列空, 要么有生产者正在写
+                if header.enqueue.0.load(Ordering::Acquire) <= pos {
+                    return None;
+                }
+                let writer = slot.writer.load(Ordering::Acquire);
+                if !gone(writer) {
+                    return None;
+                }
+                self.recover_write(slot, writer, pos);
+            } else {
+                pos = header.dequeue.0.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// 读出 `pos` 处的消息并释放槽位, 废弃的槽位返回 `None`.
+    ///
+    /// 长度字段来自共享内存, 不能信任: 超过槽位大小时同样释放槽位, 返回 [QueueError::Corrupted].
+    fn finish_read(&self, pos: u64) -> Result<Option<Vec<u8>>, QueueError> {
+        let slot = self.slot(pos);
+        let len = slot.len.load(Ordering::Relaxed);
+        let message = match len {
+            ABANDONED => Ok(None),
+            len if len as u64 > self.slot_size => Err(QueueError::Corrupted { pos, len }),
+            len => Ok(Some(unsafe { std::slice::from_raw_parts(self.slot_data(pos), len as usize).to_vec() })),
+        };
+        self.release(slot, pos + self.capacity);
+        message
+    }
+
+    /// 把槽位交给下一圈的生产者. `reader` 最后清零, 生产者看到 `reader == 0` 时 `seq` 一定已经更新.
+    fn release(&self, slot: &Slot, next: u64) {
+        slot.writer.store(0, Ordering::Relaxed);
+        slot.seq.store(next, Ordering::Release);
+        slot.reader.store(0, Ordering::Release);
+        self.header().not_full.0.notify(ALL_GROUPS);
+    }
+
+    /// 第 `pos` 个位置的生产者 `writer` 在写入过程中退出, 把槽位标记为废弃, 让消费者跳过
+    fn recover_write(&self, slot: &Slot, writer: u32, pos: u64) {
+        // 只有抢到写入者身份的进程才能修改槽位
+        if slot.writer.compare_exchange(writer, current_pid(), Ordering::AcqRel, Ordering::Relaxed).is_err() {
+            return;
+        }
+        if slot.seq.load(Ordering::Acquire) == pos {
+            slot.len.store(ABANDONED, Ordering::Relaxed);
+            slot.seq.store(pos + 1, Ordering::Release);
+            self.header().abandoned.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// 第 `pos` 个位置的消费者 `reader` 在读取过程中退出, 直接释放槽位, 这条消息丢失
+    fn recover_read(&self, slot: &Slot, reader: u32, pos: u64) {
+        if slot.reader.compare_exchange(reader, current_pid(), Ordering::AcqRel, Ordering::Relaxed).is_err() {
+            return;
+        }
+        if slot.seq.load(Ordering::Acquire) == pos + 1 {
+            self.header().abandoned.fetch_add(1, Ordering::Relaxed);
+            self.release(slot, pos + self.capacity);
+        } else {
+            // 槽位已经被正常释放, 还原
+            slot.reader.store(0, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+    use crate::rutex::test::{fork_child, wait_child};
+
+    fn queue_path() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queue.bin");
+        (dir, path)
+    }
+
+    #[test]
+    fn test_push_and_pop() {
+        let (_dir, path) = queue_path();
+        let producer = Queue::create(&path, 4, 16).unwrap();
+        let consumer = Queue::open(&path).unwrap();
+
+        assert!(consumer.try_pop().unwrap().is_none());
+        producer.try_push(b"hello").unwrap();
+        producer.try_push(b"").unwrap();
+        producer.try_push(b"world").unwrap();
+        assert_eq!(consumer.len(), 3);
+        assert_eq!(consumer.try_pop().unwrap().unwrap(), b"hello");
+        assert_eq!(consumer.try_pop().unwrap().unwrap(), b"");
+        assert_eq!(consumer.pop().unwrap(), b"world");
+        assert!(consumer.try_pop().unwrap().is_none());
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn test_full_and_limits() {
+        let (_dir, path) = queue_path();
+        let queue = Queue::create(&path, 2, 8).unwrap();
+
+        assert!(matches!(Queue::create(&path, 3, 8), Err(QueueError::InvalidConfig { capacity: 3, slot_size: 8 })));
+        assert!(matches!(Queue::open(&path).unwrap().with_group(MAX_GROUPS), Err(QueueError::InvalidGroup(32))));
+        assert!(matches!(queue.try_push(&[0; 9]), Err(QueueError::MessageTooLarge { len: 9, max: 8 })));
+        queue.try_push(&[1; 8]).unwrap();
+        queue.try_push(&[2; 8]).unwrap();
+        assert!(matches!(queue.try_push(&[3]), Err(QueueError::Full)));
+        assert!(matches!(queue.push_timeout(&[3], Duration::from_millis(20)), Err(QueueError::Timeout)));
+        queue.pop_timeout(Duration::from_millis(20)).unwrap();
+        queue.push_timeout(&[3], Duration::from_millis(20)).unwrap();
+        assert_eq!(queue.pop().unwrap(), [2; 8]);
+        assert_eq!(queue.pop().unwrap(), [3]);
+        assert!(matches!(queue.pop_timeout(Duration::from_millis(20)), Err(QueueError::Timeout)));
+    }
+
+    #[test]
+    fn test_multi_thread() {
+        let (_dir, path) = queue_path();
+        let queue = Arc::new(Queue::create(&path, 8, 8).unwrap());
+
+        let producers: Vec<_> = (0..4u64)
+            .map(|id| {
+                let queue = queue.clone();
+                thread::spawn(move || {
+                    for i in 0..5000u64 {
+                        queue.push(&(id << 32 | i).to_le_bytes()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        let consumers: Vec<_> = (0..4)
+            .map(|_| {
+                let queue = queue.clone();
+                thread::spawn(move || {
+                    let mut received = Vec::new();
+                    while let Ok(message) = queue.pop_timeout(Duration::from_millis(500)) {
+                        received.push(u64::from_le_bytes(message.try_into().unwrap()));
+                    }
+                    received
+                })
+            })
+            .collect();
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        let received: Vec<u64> = consumers.into_iter().flat_map(|c| c.join().unwrap()).collect();
+        assert_eq!(received.len(), 20_000);
+        assert_eq!(received.iter().collect::<HashSet<_>>().len(), 20_000);
+    }
+
+    /// 文件已经存在时不会清空正在使用的队列
+    #[test]
+    fn test_create_existing() {
+        let (_dir, path) = queue_path();
+        let queue = Queue::create(&path, 4, 8).unwrap();
+        queue.try_push(b"kept").unwrap();
+
+        assert!(matches!(Queue::create(&path, 4, 8), Err(QueueError::Io(e)) if e.kind() == io::ErrorKind::AlreadyExists));
+        assert_eq!(Queue::open(&path).unwrap().try_pop().unwrap().unwrap(), b"kept");
+    }
+
+    /// 共享内存中的长度被改坏时返回错误, 跳过这个槽位
+    #[test]
+    fn test_corrupted_length() {
+        let (_dir, path) = queue_path();
+        let queue = Queue::create(&path, 4, 8).unwrap();
+        queue.try_push(b"bad").unwrap();
+        queue.try_push(b"good").unwrap();
+        queue.slot(0).len.store(9, Ordering::Relaxed);
+
+        assert!(matches!(queue.try_pop(), Err(QueueError::Corrupted { pos: 0, len: 9 })));
+        assert_eq!(queue.pop().unwrap(), b"good");
+        assert!(queue.is_empty());
+    }
+
+    /// 多个生产者进程, 单个消费者看到的每个生产者的消息都是有序的
+    #[test]
+    fn test_multi_process() {
+        let (_dir, path) = queue_path();
+        let queue = Queue::create(&path, 16, 8).unwrap();
+
+        let children: Vec<_> = (0..3u32)
+            .map(|id| {
+                let path = path.clone();
+                fork_child(move || {
+                    let queue = Queue::open(&path).unwrap();
+                    (0..2000u32).all(|i| queue.push(&[id.to_le_bytes(), i.to_le_bytes()].concat()).is_ok())
+                })
+            })
+            .collect();
+
+        let mut next = [0u32; 3];
+        for _ in 0..6000 {
+            let message = queue.pop_timeout(Duration::from_secs(10)).unwrap();
+            let id = u32::from_le_bytes(message[..4].try_into().unwrap()) as usize;
+            assert_eq!(u32::from_le_bytes(message[4..].try_into().unwrap()), next[id]);
+            next[id] += 1;
+        }
+        for child in children {
+            assert!(wait_child(child));
+        }
+        assert!(queue.is_empty());
+    }
+
+    /// 只唤醒指定消费组的等待者
+    #[test]
+    fn test_group_wakeup() {
+        let (_dir, path) = queue_path();
+        let queue = Queue::create(&path, 4, 8).unwrap();
+
+        let waiters: Vec<_> = (0..2)
+            .map(|group| {
+                let queue = Queue::open(&path).unwrap().with_group(group).unwrap();
+                thread::spawn(move || queue.pop_timeout(Duration::from_millis(300)).is_ok())
+            })
+            .collect();
+        while queue.header().not_empty.0.count.load(Ordering::Relaxed) < 2 {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        queue.try_push_to(b"job", 1 << 1).unwrap();
+        let results: Vec<bool> = waiters.into_iter().map(|w| w.join().unwrap()).collect();
+        assert_eq!(results, [false, true]);
+    }
+
+    /// 生产者占用槽位后还没写完就退出, 消费者跳过这个槽位
+    #[test]
+    fn test_writer_crashed() {
+        let (_dir, path) = queue_path();
+        let queue = Queue::create(&path, 4, 8).unwrap();
+
+        let child = fork_child(|| {
+            let queue = Queue::open(&path).unwrap();
+            queue.claim_write().is_some()
+        });
+        assert!(wait_child(child));
+        assert_eq!(queue.len(), 1);
+        assert!(queue.try_pop().unwrap().is_none());
+
+        queue.try_push(b"after").unwrap();
+        assert_eq!(queue.try_pop().unwrap().unwrap(), b"after");
+        assert_eq!(queue.abandoned(), 1);
+        assert!(queue.is_empty());
+    }
+
+    /// 消费者占用槽位后还没读完就退出, 队列满时生产者回收这个槽位
+    #[test]
+    fn test_reader_crashed() {
+        let (_dir, path) = queue_path();
+        let queue = Queue::create(&path, 2, 8).unwrap();
+        queue.try_push(b"a").unwrap();
+        queue.try_push(b"b").unwrap();
+
+        let child = fork_child(|| {
+            let queue = Queue::open(&path).unwrap();
+            queue.claim_read().is_some()
+        });
+        assert!(wait_child(child));
+
+        queue.try_push(b"c").unwrap();
+        assert_eq!(queue.abandoned(), 1);
+        assert_eq!(queue.try_pop().unwrap().unwrap(), b"b");
+        assert_eq!(queue.try_pop().unwrap().unwrap(), b"c");
+        assert!(queue.try_pop().unwrap().is_none());
+    }
+}
@@ -0,0 +1,703 @@
+//! 基于 `mmap` 文件的单生产者/单消费者环形缓冲区
+//!
+//! 改进自 `examples/ipc_mem.rs` 中的 `RingBuffer`:
+//!
+//! - `head` / `tail` 是单调递增的 `u64` 字节位置, 只在取模后才映射到缓冲区, 不会出现 ABA, 也不需要区分空和满.
+//!   `head` 只由生产者写, `tail` 只由消费者写, 两者放在不同的缓存行里.
+//! - 每条记录是 4 字节长度 + 数据, 按 8 字节对齐. 记录不能跨越缓冲区末尾, 剩余空间放不下时写一个填充标记, 从头开始写.
+//!   所以单条记录最大为容量的一半, 保证缓冲区空的时候任何合法记录都能放下.
+//! - 没有数据 (或没有空间) 时先短暂自旋, 然后在共享内存中的 futex 上睡眠, 对方写入后按需唤醒, 不会一直占用 CPU.
+//!   不相关的进程映射同一个文件就能通信, 不需要像 eventfd 那样传递文件描述符.
+//! - 生产者在 `head` 更新之前崩溃, 写了一半的记录不可见. 消费者在读完之前崩溃, 这条记录下一个消费者会重新读到.
+//!   对方进程退出后, 阻塞的一方返回 [RingError::Disconnected].
+//!
+//! ```no_run
+//! use demo01_rust::shm_ring::{Consumer, Producer};
+//!
+//! let mut producer = Producer::create("/tmp/ring.bin", 1 << 16).unwrap();
+//! producer.push(b"hello").unwrap();
+//!
+//! // 另一个进程
+//! let mut consumer = Consumer::open("/tmp/ring.bin").unwrap();
+//! let record = consumer.recv().unwrap();
+//! assert_eq!(&*record, b"hello");
+//! ```
+
+use std::fmt::{self, Display, Formatter};
+use std::fs::OpenOptions;
+use std::io;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use memmap2::MmapMut;
+
+use crate::rutex::{current_pid, futex_wait, futex_wake, pid_alive, OWNER_CHECK_INTERVAL};
+
+const MAGIC: u64 = u64::from_le_bytes(*b"SHMRING1");
+const RECORD_HEADER: usize = 4;
+const RECORD_ALIGN: u64 = 8;
+/// 长度字段为这个值时表示缓冲区末尾的填充, 跳到开头继续读
+const PADDING: u32 = u32::MAX;
+const SPIN_LIMIT: u32 = 100;
+
+#[derive(Debug)]
+pub enum RingError {
+    Io(io::Error),
+    /// 文件不是 [Producer::create] 创建的, 或者还没有初始化完成
+    InvalidHeader(PathBuf),
+    /// 容量必须是 2 的幂, 且不小于 64
+    InvalidCapacity(u64),
+    RecordTooLarge { len: usize, max: usize },
+    /// 同一个角色已经被另一个存活的进程占用
+    AlreadyAttached(u32),
+    Full,
+    Timeout,
+    /// 对方已经退出, 且缓冲区中没有数据 (消费者) 或者没有空间 (生产者)
+    Disconnected,
+    /// 数据区中 `offset` 处的记录长度 `len` 不合法, 文件被其他进程写坏了
+    Corrupted { offset: u64, len: u32 },
+    /// 生产者位置 `head` 落后于消费者位置 `tail`, 文件被其他进程写坏了
+    CorruptedPosition { head: u64, tail: u64 },
+}
+
+impl Display for RingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RingError::Io(e) => write!(f, "io error: {}", e),
+            RingError::InvalidHeader(path) => write!(f, "{:?} is not an initialized ring buffer", path),
+            RingError::InvalidCapacity(capacity) => write!(f, "invalid capacity {}, must be a power of two >= 64", capacity),
+            RingError::RecordTooLarge { len, max } => write!(f, "record of {} bytes exceeds limit of {} bytes", len, max),
+            RingError::AlreadyAttached(pid) => write!(f, "already attached by process {}", pid),
+            RingError::Full => write!(f, "ring buffer is full"),
+            RingError::Timeout => write!(f, "timed out"),
+            RingError::Disconnected => write!(f, "peer disconnected"),
+            RingError::Corrupted { offset, len } => write!(f, "corrupted record of {} bytes at offset {}", len, offset),
+            RingError::CorruptedPosition { head, tail } => write!(f, "corrupted positions: head {} is behind tail {}", head, tail),
+        }
+    }
+}
+
+impl std::error::Error for RingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RingError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RingError {
+    fn from(e: io::Error) -> Self {
+        RingError::Io(e)
+    }
+}
+
+/// 生产者和消费者各自的状态, 单独占一个缓存行
+#[repr(C, align(64))]
+struct Side {
+    /// 生产者是 head, 消费者是 tail
+    pos: AtomicU64,
+    /// 每次 `pos` 前进且对方在等待时加一, 对方在这个 futex 上睡眠
+    seq: AtomicU32,
+    /// 自己正在等待对方
+    waiting: AtomicU32,
+    pid: AtomicU32,
+    /// 正常 drop 时设置, 区分 "对方已经离开" 和 "对方还没有连上"
+    closed: AtomicU32,
+}
+
+#[repr(C)]
+struct Header {
+    magic: AtomicU64,
+    capacity: u64,
+    producer: Side,
+    consumer: Side,
+}
+
+fn record_size(len: usize) -> u64 {
+    (RECORD_HEADER as u64 + len as u64).next_multiple_of(RECORD_ALIGN)
+}
+
+/// `head` 和 `tail` 之间已经占用的字节数. 位置来自共享内存, `head < tail` 说明文件被写坏了.
+fn used_bytes(head: u64, tail: u64) -> Result<u64, RingError> {
+    head.checked_sub(tail).ok_or(RingError::CorruptedPosition { head, tail })
+}
+
+struct Ring {
+    _map: MmapMut,
+    header: *const Header,
+    data: *mut u8,
+    capacity: u64,
+    path: PathBuf,
+}
+
+unsafe impl Send for Ring {}
+
+impl Ring {
+    fn map(path: &Path, create_capacity: Option<u64>) -> Result<Self, RingError> {
+        let header_len = size_of::<Header>() as u64;
+        let file = match create_capacity {
+            Some(capacity) => {
+                if !capacity.is_power_of_two() || capacity < 64 {
+                    return Err(RingError::InvalidCapacity(capacity));
+                }
+                let file = OpenOptions::new().read(true).write(true).create_new(true).open(path)?;
+                file.set_len(header_len + capacity)?;
+                file
+            }
+            None => OpenOptions::new().read(true).write(true).open(path)?,
+        };
+        if file.metadata()?.len() < header_len {
+            return Err(RingError::InvalidHeader(path.to_path_buf()));
+        }
+
+        let mut map = unsafe { MmapMut::map_mut(&file)? };
+        let base = map.as_mut_ptr();
+        let header = base as *const Header;
+        if let Some(capacity) = create_capacity {
+            unsafe {
+                ptr::addr_of!((*header).capacity).cast_mut().write(capacity);
+                // magic 最后写入, 对方看到 magic 时容量一定已经可见
+                (*header).magic.store(MAGIC, Ordering::Release);
+            }
+        }
+
+        let (magic, capacity) = unsafe { ((*header).magic.load(Ordering::Acquire), (*header).capacity) };
+        if magic != MAGIC || !capacity.is_power_of_two() || map.len() as u64 != header_len + capacity {
+            return Err(RingError::InvalidHeader(path.to_path_buf()));
+        }
+        let data = unsafe { base.add(header_len as usize) };
+        Ok(Self { _map: map, header, data, capacity, path: path.to_path_buf() })
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*self.header }
+    }
+
+    fn max_record(&self) -> usize {
+        self.capacity as usize / 2 - RECORD_HEADER
+    }
+
+    /// 登记为 `side` 的使用者. 原来的使用者已经退出时直接接管.
+    ///
+    /// 同一个进程中已经有一个存活的使用者时同样返回 [RingError::AlreadyAttached], 否则两个生产者 (或消费者)
+    /// 会各自维护自己的位置, 互相覆盖.
+    fn attach(&self, side: &Side) -> Result<(), RingError> {
+        let me = current_pid();
+        loop {
+            let current = side.pid.load(Ordering::Acquire);
+            if current != 0 && pid_alive(current) {
+                return Err(RingError::AlreadyAttached(current));
+            }
+            if side.pid.compare_exchange(current, me, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+                side.closed.store(0, Ordering::Release);
+                return Ok(());
+            }
+        }
+    }
+
+    fn detach(&self, side: &Side) {
+        side.closed.store(1, Ordering::Release);
+        side.pid.store(0, Ordering::Release);
+        // 唤醒正在等待的对方, 让它发现自己已经离开
+        side.seq.fetch_add(1, Ordering::Release);
+        futex_wake(&side.seq, i32::MAX as u32);
+    }
+
+    fn peer_gone(side: &Side) -> bool {
+        if side.closed.load(Ordering::Acquire) != 0 {
+            return true;
+        }
+        match side.pid.load(Ordering::Acquire) {
+            0 => false,
+            pid => !pid_alive(pid),
+        }
+    }
+
+    /// 通知在 `mine.seq` 上等待的对方. 调用前已经用 Release 更新了 `mine.pos`.
+    fn notify(mine: &Side, peer: &Side) {
+        // 与 [Ring::wait] 中的 fence 配对: 要么对方看到新的 pos, 要么我们看到对方的 waiting
+        fence(Ordering::SeqCst);
+        if peer.waiting.load(Ordering::Relaxed) != 0 {
+            mine.seq.fetch_add(1, Ordering::Release);
+            futex_wake(&mine.seq, 1);
+        }
+    }
+
+    /// 等待 `ready` 返回 `true`. `peer` 是对方的状态, 对方会通过 `peer.seq` 唤醒我们.
+    fn wait(mine: &Side, peer: &Side, deadline: Option<Instant>, ready: impl Fn() -> bool) -> Result<(), RingError> {
+        for _ in 0..SPIN_LIMIT {
+            if ready() {
+                return Ok(());
+            }
+            std::hint::spin_loop();
+        }
+        loop {
+            let seq = peer.seq.load(Ordering::Acquire);
+            mine.waiting.store(1, Ordering::Relaxed);
+            fence(Ordering::SeqCst);
+            if ready() {
+                mine.waiting.store(0, Ordering::Relaxed);
+                return Ok(());
+            }
+            if Self::peer_gone(peer) {
+                mine.waiting.store(0, Ordering::Relaxed);
+                // 对方离开前可能刚好写完最后一条
+                return if ready() { Ok(()) } else { Err(RingError::Disconnected) };
+            }
+            // 对方被 kill 时不会唤醒我们, 定期醒来检查
+            let check = Instant::now() + OWNER_CHECK_INTERVAL;
+            let until = deadline.map_or(check, |deadline| deadline.min(check));
+            futex_wait(&peer.seq, seq, Some(until));
+            mine.waiting.store(0, Ordering::Relaxed);
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return if ready() { Ok(()) } else { Err(RingError::Timeout) };
+            }
+        }
+    }
+}
+
+pub struct Producer {
+    ring: Ring,
+    head: u64,
+    /// 上次读到的 tail, 只有空间不够时才重新读取, 减少缓存行争用
+    cached_tail: u64,
+}
+
+impl Producer {
+    /// 创建新文件并初始化, `capacity` 是数据区的字节数.
+    ///
+    /// 文件已经存在时返回 [io::ErrorKind::AlreadyExists], 不会清空正在使用的缓冲区.
+    pub fn create(path: impl AsRef<Path>, capacity: u64) -> Result<Self, RingError> {
+        Self::attach(Ring::map(path.as_ref(), Some(capacity))?)
+    }
+
+    /// 连接到已经存在的缓冲区, 例如生产者崩溃重启后继续写入
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, RingError> {
+        Self::attach(Ring::map(path.as_ref(), None)?)
+    }
+
+    fn attach(ring: Ring) -> Result<Self, RingError> {
+        ring.attach(&ring.header().producer)?;
+        let head = ring.header().producer.pos.load(Ordering::Relaxed);
+        let cached_tail = ring.header().consumer.pos.load(Ordering::Acquire);
+        Ok(Self { ring, head, cached_tail })
+    }
+
+    pub fn max_record(&self) -> usize {
+        self.ring.max_record()
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.ring.capacity
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.ring.path
+    }
+
+    /// 写入一条 `len` 字节的记录需要的 (填充, 总计) 字节数. 当前位置到末尾放不下时需要先填充到末尾.
+    fn space_needed(&self, len: usize) -> (u64, u64) {
+        let size = record_size(len);
+        let to_end = self.ring.capacity - (self.head & (self.ring.capacity - 1));
+        let padding = if size > to_end { to_end } else { 0 };
+        (padding, padding + size)
+    }
+
+    pub fn try_push(&mut self, record: &[u8]) -> Result<(), RingError> {
+        let max = self.max_record();
+        if record.len() > max {
+            return Err(RingError::RecordTooLarge { len: record.len(), max });
+        }
+
+        let (padding, total) = self.space_needed(record.len());
+        let capacity = self.ring.capacity;
+        let offset = self.head & (capacity - 1);
+        if used_bytes(self.head, self.cached_tail)? + total > capacity {
+            self.cached_tail = self.ring.header().consumer.pos.load(Ordering::Acquire);
+            if used_bytes(self.head, self.cached_tail)? + total > capacity {
+                return Err(RingError::Full);
+            }
+        }
+
+        unsafe {
+            let mut at = offset;
+            if padding > 0 {
+                (self.ring.data.add(at as usize) as *mut u32).write(PADDING);
+                at = 0;
+            }
+            let dst = self.ring.data.add(at as usize);
+            (dst as *mut u32).write(record.len() as u32);
+            ptr::copy_nonoverlapping(record.as_ptr(), dst.add(RECORD_HEADER), record.len());
+        }
+
+        self.head += total;
+        let header = self.ring.header();
+        header.producer.pos.store(self.head, Ordering::Release);
+        Ring::notify(&header.producer, &header.consumer);
+        Ok(())
+    }
+
+    /// 没有空间时阻塞, 消费者退出后返回 [RingError::Disconnected]
+    pub fn push(&mut self, record: &[u8]) -> Result<(), RingError> {
+        self.push_until(record, None)
+    }
+
+    pub fn push_timeout(&mut self, record: &[u8], timeout: Duration) -> Result<(), RingError> {
+        self.push_until(record, Some(Instant::now() + timeout))
+    }
+
+    fn push_until(&mut self, record: &[u8], deadline: Option<Instant>) -> Result<(), RingError> {
+        loop {
+            match self.try_push(record) {
+                Err(RingError::Full) => {}
+                result => return result,
+            }
+            let (_, needed) = self.space_needed(record.len());
+            let (head, capacity) = (self.head, self.ring.capacity);
+            let header = self.ring.header();
+            // 位置不合法时也停止等待, 由 try_push 返回错误
+            Ring::wait(&header.producer, &header.consumer, deadline, || {
+                !matches!(used_bytes(head, header.consumer.pos.load(Ordering::Acquire)), Ok(used) if used + needed > capacity)
+            })?;
+        }
+    }
+}
+
+impl Drop for Producer {
+    fn drop(&mut self) {
+        self.ring.detach(&self.ring.header().producer);
+    }
+}
+
+pub struct Consumer {
+    ring: Ring,
+    tail: u64,
+    cached_head: u64,
+}
+
+impl Consumer {
+    /// 由消费者一方创建文件, 生产者之后用 [Producer::open] 连接
+    pub fn create(path: impl AsRef<Path>, capacity: u64) -> Result<Self, RingError> {
+        Self::attach(Ring::map(path.as_ref(), Some(capacity))?)
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, RingError> {
+        Self::attach(Ring::map(path.as_ref(), None)?)
+    }
+
+    fn attach(ring: Ring) -> Result<Self, RingError> {
+        let tail = ring.header().consumer.pos.load(Ordering::Relaxed);
+        if !tail.is_multiple_of(RECORD_ALIGN) {
+            return Err(RingError::InvalidHeader(ring.path.clone()));
+        }
+        ring.attach(&ring.header().consumer)?;
+        Ok(Self { ring, tail, cached_head: tail })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.ring.path
+    }
+
+    /// 缓冲区中待读取的字节数 (包括记录头和填充)
+    pub fn pending_bytes(&self) -> Result<u64, RingError> {
+        used_bytes(self.ring.header().producer.pos.load(Ordering::Acquire), self.tail)
+    }
+
+    /// 读取下一条记录, 没有数据时返回 `None`. 记录在 [Record] drop 之后才会释放空间.
+    ///
+    /// 长度字段来自共享内存, 不能信任: 超过最大记录长度或者越过数据区末尾时返回 [RingError::Corrupted].
+    pub fn try_recv(&mut self) -> Result<Option<Record<'_>>, RingError> {
+        let capacity = self.ring.capacity;
+        let max = self.ring.max_record();
+        loop {
+            if self.tail == self.cached_head {
+                self.cached_head = self.ring.header().producer.pos.load(Ordering::Acquire);
+                if used_bytes(self.cached_head, self.tail)? == 0 {
+                    return Ok(None);
+                }
+            }
+            // tail 总是按 RECORD_ALIGN 对齐, 长度字段不会越过数据区末尾
+            let offset = self.tail & (capacity - 1);
+            let len = unsafe { (self.ring.data.add(offset as usize) as *const u32).read() };
+            if len == PADDING {
+                self.advance(capacity - offset);
+                continue;
+            }
+            if len as usize > max || offset + RECORD_HEADER as u64 + len as u64 > capacity {
+                return Err(RingError::Corrupted { offset, len });
+            }
+            let data = unsafe { self.ring.data.add(offset as usize + RECORD_HEADER) };
+            return Ok(Some(Record { consumer: self, data, len: len as usize }));
+        }
+    }
+
+    /// 没有数据时阻塞, 生产者退出且数据读完后返回 [RingError::Disconnected]
+    pub fn recv(&mut self) -> Result<Record<'_>, RingError> {
+        self.recv_until(None)
+    }
+
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Record<'_>, RingError> {
+        self.recv_until(Some(Instant::now() + timeout))
+    }
+
+    fn recv_until(&mut self, deadline: Option<Instant>) -> Result<Record<'_>, RingError> {
+        let header = self.ring.header();
+        let tail = self.tail;
+        // 填充标记总是和后面的记录一起发布, 所以 head 前进了就一定有完整的记录. 只读到填充说明文件被写坏了.
+        Ring::wait(&header.consumer, &header.producer, deadline, || header.producer.pos.load(Ordering::Acquire) != tail)?;
+        let offset = tail & (self.ring.capacity - 1);
+        self.try_recv()?.ok_or(RingError::Corrupted { offset, len: PADDING })
+    }
+
+    fn advance(&mut self, bytes: u64) {
+        self.tail += bytes;
+        let header = self.ring.header();
+        header.consumer.pos.store(self.tail, Ordering::Release);
+        Ring::notify(&header.consumer, &header.producer);
+    }
+}
+
+impl Drop for Consumer {
+    fn drop(&mut self) {
+        self.ring.detach(&self.ring.header().consumer);
+    }
+}
+
+/// 直接指向共享内存的一条记录, drop 时释放空间
+pub struct Record<'a> {
+    consumer: &'a mut Consumer,
+    data: *const u8,
+    len: usize,
+}
+
+impl Deref for Record<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.data, self.len) }
+    }
+}
+
+impl Drop for Record<'_> {
+    fn drop(&mut self) {
+        let size = record_size(self.len);
+        self.consumer.advance(size);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::*;
+    use crate::rutex::test::{fork_child, wait_child};
+
+    fn ring_path() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ring.bin");
+        (dir, path)
+    }
+
+    #[test]
+    fn test_push_and_recv() {
+        let (_dir, path) = ring_path();
+        let mut producer = Producer::create(&path, 256).unwrap();
+        let mut consumer = Consumer::open(&path).unwrap();
+
+        assert!(consumer.try_recv().unwrap().is_none());
+        producer.try_push(b"hello").unwrap();
+        producer.try_push(b"").unwrap();
+        producer.try_push(b"world!!!").unwrap();
+        assert_eq!(&*consumer.try_recv().unwrap().unwrap(), b"hello");
+        assert_eq!(&*consumer.try_recv().unwrap().unwrap(), b"");
+        assert_eq!(&*consumer.recv().unwrap(), b"world!!!");
+        assert!(consumer.try_recv().unwrap().is_none());
+        assert_eq!(consumer.pending_bytes().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_full_and_limits() {
+        let (_dir, path) = ring_path();
+        let mut producer = Producer::create(&path, 64).unwrap();
+        let mut consumer = Consumer::open(&path).unwrap();
+
+        assert_eq!(producer.max_record(), 28);
+        assert!(matches!(producer.try_push(&[0; 29]), Err(RingError::RecordTooLarge { len: 29, max: 28 })));
+        producer.try_push(&[1; 28]).unwrap();
+        producer.try_push(&[2; 28]).unwrap();
+        assert!(matches!(producer.try_push(&[3; 1]), Err(RingError::Full)));
+        assert!(matches!(producer.push_timeout(&[3; 1], Duration::from_millis(20)), Err(RingError::Timeout)));
+
+        // 记录没有 drop 之前空间不会释放
+        let record = consumer.try_recv().unwrap().unwrap();
+        assert_eq!(&*record, &[1; 28]);
+        assert!(matches!(producer.try_push(&[3; 1]), Err(RingError::Full)));
+        drop(record);
+        producer.try_push(&[3; 1]).unwrap();
+    }
+
+    /// 长度字段被写坏时返回错误, 不会越界读取
+    #[test]
+    fn test_corrupted_length() {
+        let (_dir, path) = ring_path();
+        let mut producer = Producer::create(&path, 64).unwrap();
+        let mut consumer = Consumer::open(&path).unwrap();
+
+        producer.try_push(b"hello").unwrap();
+        unsafe { (consumer.ring.data as *mut u32).write(1 << 20) };
+        assert!(matches!(consumer.try_recv(), Err(RingError::Corrupted { offset: 0, len: 0x100000 })));
+    }
+
+    /// 位置被写坏 (head 落后于 tail) 时返回错误, 不会因为减法溢出而 panic
+    #[test]
+    fn test_corrupted_position() {
+        let (_dir, path) = ring_path();
+        let mut producer = Producer::create(&path, 64).unwrap();
+        let mut consumer = Consumer::open(&path).unwrap();
+
+        // 缓冲区满了之后生产者才会重新读取 tail
+        producer.try_push(&[1; 28]).unwrap();
+        producer.try_push(&[2; 28]).unwrap();
+        producer.ring.header().consumer.pos.store(200, Ordering::Release);
+        assert!(matches!(producer.try_push(b"x"), Err(RingError::CorruptedPosition { head: 64, tail: 200 })));
+        consumer.tail = 128;
+        assert!(matches!(consumer.pending_bytes(), Err(RingError::CorruptedPosition { head: 64, tail: 128 })));
+    }
+
+    /// head 前进了但只有填充标记, 阻塞读取返回错误而不是 panic
+    #[test]
+    fn test_padding_only() {
+        let (_dir, path) = ring_path();
+        let _producer = Producer::create(&path, 64).unwrap();
+        let mut consumer = Consumer::open(&path).unwrap();
+
+        unsafe { (consumer.ring.data as *mut u32).write(PADDING) };
+        consumer.ring.header().producer.pos.store(64, Ordering::Release);
+        assert!(matches!(consumer.recv(), Err(RingError::Corrupted { offset: 0, len: PADDING })));
+    }
+
+    /// 长度本身不超限, 但从当前位置开始会越过数据区末尾
+    #[test]
+    fn test_corrupted_length_past_end() {
+        let (_dir, path) = ring_path();
+        let mut producer = Producer::create(&path, 64).unwrap();
+        let mut consumer = Consumer::open(&path).unwrap();
+
+        // 分别占 32 和 8 字节, 读完后 tail 在 40
+        for len in [28, 4] {
+            producer.try_push(&vec![0; len]).unwrap();
+            consumer.try_recv().unwrap().unwrap();
+        }
+        producer.try_push(b"x").unwrap();
+        unsafe { (consumer.ring.data.add(40) as *mut u32).write(28) };
+        assert!(matches!(consumer.try_recv(), Err(RingError::Corrupted { offset: 40, len: 28 })));
+    }
+
+    #[test]
+    fn test_wraparound() {
+        let (_dir, path) = ring_path();
+        let mut producer = Producer::create(&path, 64).unwrap();
+        let mut consumer = Consumer::open(&path).unwrap();
+
+        // 长度不同的记录, 让末尾剩余空间不够时必须写填充标记
+        for round in 0..200u32 {
+            let len = (round % 29) as usize;
+            let record: Vec<u8> = (0..len).map(|i| (round as usize + i) as u8).collect();
+            producer.try_push(&record).unwrap();
+            assert_eq!(&*consumer.try_recv().unwrap().unwrap(), record.as_slice());
+        }
+        assert!(consumer.try_recv().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_single_role() {
+        let (_dir, path) = ring_path();
+        let producer = Producer::create(&path, 64).unwrap();
+        let _consumer = Consumer::open(&path).unwrap();
+        // 存活的使用者不能被替换, 不管是不是同一个进程; 离开之后可以重新连接
+        let child = fork_child(|| matches!(Consumer::open(&path), Err(RingError::AlreadyAttached(_))));
+        assert!(wait_child(child));
+        assert!(matches!(Producer::open(&path), Err(RingError::AlreadyAttached(pid)) if pid == current_pid()));
+        drop(producer);
+        Producer::open(&path).unwrap();
+
+        // 文件已经存在时不会清空
+        assert!(matches!(Consumer::create(&path, 64), Err(RingError::Io(e)) if e.kind() == io::ErrorKind::AlreadyExists));
+        assert!(matches!(Producer::create(&path, 100), Err(RingError::InvalidCapacity(100))));
+        std::fs::write(&path, [0u8; 512]).unwrap();
+        assert!(matches!(Consumer::open(&path), Err(RingError::InvalidHeader(_))));
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let (_dir, path) = ring_path();
+        let mut producer = Producer::create(&path, 256).unwrap();
+        let mut consumer = Consumer::open(&path).unwrap();
+
+        let reader = thread::spawn(move || {
+            let mut received = Vec::new();
+            loop {
+                match consumer.recv() {
+                    Ok(record) => received.push(record.to_vec()),
+                    Err(RingError::Disconnected) => return received,
+                    Err(e) => panic!("{}", e),
+                }
+            }
+        });
+        producer.push(b"a").unwrap();
+        producer.push(b"b").unwrap();
+        drop(producer);
+        // 生产者离开前写入的数据仍然能读到
+        assert_eq!(reader.join().unwrap(), vec![b"a".to_vec(), b"b".to_vec()]);
+    }
+
+    /// 生产者和消费者在不同进程中, 各自映射文件, 缓冲区远小于总数据量
+    #[test]
+    fn test_multi_process_stream() {
+        let (_dir, path) = ring_path();
+        let mut producer = Producer::create(&path, 1024).unwrap();
+
+        let child = fork_child(|| {
+            let mut consumer = Consumer::open(&path).unwrap();
+            for i in 0..20_000u32 {
+                let record = consumer.recv().unwrap();
+                let expected = i.to_le_bytes().repeat((i % 50) as usize);
+                if *record != *expected {
+                    return false;
+                }
+            }
+            let end = consumer.recv();
+            matches!(end, Err(RingError::Disconnected))
+        });
+
+        for i in 0..20_000u32 {
+            producer.push(&i.to_le_bytes().repeat((i % 50) as usize)).unwrap();
+        }
+        drop(producer);
+        assert!(wait_child(child));
+    }
+
+    /// 消费者被 kill 后, 阻塞的生产者不会一直等下去
+    #[test]
+    fn test_consumer_killed() {
+        let (_dir, path) = ring_path();
+        let mut producer = Producer::create(&path, 64).unwrap();
+        let child = fork_child(|| {
+            let _consumer = Consumer::open(&path).unwrap();
+            thread::sleep(Duration::from_secs(60));
+            true
+        });
+        producer.push(&[0; 28]).unwrap();
+        producer.push(&[0; 28]).unwrap();
+        while producer.ring.header().consumer.pid.load(Ordering::Acquire) != child as u32 {
+            thread::sleep(Duration::from_millis(5));
+        }
+        unsafe { libc::kill(child, libc::SIGKILL) };
+        assert!(!wait_child(child));
+        assert!(matches!(producer.push(&[0; 28]), Err(RingError::Disconnected)));
+    }
+}
@@ -0,0 +1,635 @@
+//! 带版本和布局校验的共享内存对象
+//!
+//! `examples/rustix_mmap.rs`, `examples/ipc_mem.rs` 和 `examples/rustix_mmap2.rs` 都是把 `mmap` 返回的指针直接
+//! 转成各自的结构体, 另一个进程只要结构体定义不同 (加了字段, 换了顺序, 甚至是另一个程序) 就会读到错乱的数据.
+//! [ShmSegment] 在数据前面放一个固定格式的头部:
+//!
+//! | 字段 | 含义 |
+//! |------|------|
+//! | magic | 固定值, 写完其他内容之后最后写入, 为 0 说明创建者在初始化时崩溃了 |
+//! | format | 头部自身的格式版本 |
+//! | version | `T` 的结构版本, 由 [ShmObject::VERSION] 指定 |
+//! | type hash | [ShmObject::NAME] 的哈希 |
+//! | size / align | `T` 的大小和对齐 |
+//! | layout checksum | 以上信息加上 [ShmObject::fields] 中每个字段的名字, 偏移和大小的校验和 |
+//!
+//! 打开已有的文件时逐项比较, 任何一项不一致都拒绝映射, 返回 [SegmentError::Mismatch].
+//!
+//! 写入通过 [ShmSegment::lock] 加跨进程互斥锁. 读取可以用 [ShmSegment::snapshot], 基于 seqlock, 不加锁也能拿到一致的副本:
+//! 写者修改前后各把序号加一, 读者复制前后序号相同且为偶数才算成功, 否则重试.
+//! 写者在修改途中退出时读到的值可能只改了一半, 通过 [Snapshot::recovered] 告诉调用者.
+//!
+//! ```no_run
+//! use demo01_rust::rutex::ShmSafe;
+//! use demo01_rust::shm_fields;
+//! use demo01_rust::shm_segment::{Field, ShmObject, ShmSegment};
+//!
+//! #[derive(Clone, Copy)]
+//! #[repr(C)]
+//! struct Config {
+//!     epoch: u64,
+//!     workers: u32,
+//!     verbose: bool,
+//! }
+//! unsafe impl ShmSafe for Config {}
+//!
+//! impl ShmObject for Config {
+//!     const NAME: &'static str = "demo.Config";
+//!     const VERSION: u32 = 2;
+//!     fn fields() -> Vec<Field> {
+//!         shm_fields!(Config { epoch, workers, verbose })
+//!     }
+//! }
+//!
+//! let segment = ShmSegment::open_or_create("/tmp/config.shm", || Config { epoch: 0, workers: 4, verbose: false }).unwrap();
+//! segment.lock().epoch += 1;
+//! let config = segment.snapshot().value;
+//! println!("epoch {}, workers {}", config.epoch, config.workers);
+//! ```
+
+use std::cell::UnsafeCell;
+use std::ffi::c_void;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use std::thread;
+
+use lock_api::RawMutex;
+use rustix::fs::{flock, FlockOperation};
+use rustix::mm::{mmap, munmap, MapFlags, ProtFlags};
+
+use crate::rutex::{IpcMutexRaw, ShmSafe};
+
+/// "DEMOSHM\0"
+const MAGIC: u64 = u64::from_le_bytes(*b"DEMOSHM\0");
+/// 头部格式的版本, 修改 [Header] 时递增
+const FORMAT: u32 = 1;
+/// 读者连续失败这么多次后改为加锁读取, 防止写者在修改途中退出后读者永远等下去
+const OPTIMISTIC_RETRIES: u32 = 100;
+
+/// 可以放进 [ShmSegment] 的类型
+pub trait ShmObject: ShmSafe {
+    /// 类型的标识, 写入头部的是它的哈希. 不用 `std::any::type_name`, 它的输出随编译器版本变化,
+    /// 也会随模块路径变化; 两个程序要共享同一块内存时各自写上相同的名字即可.
+    const NAME: &'static str;
+
+    /// 结构版本. 字段含义改变而布局没有变化时 (例如单位从毫秒改成微秒), 也要递增这个值.
+    const VERSION: u32 = 1;
+
+    /// 参与布局校验的字段, 用 [shm_fields!](crate::shm_fields) 生成. 默认为空, 只校验整体的大小和对齐.
+    fn fields() -> Vec<Field> {
+        Vec::new()
+    }
+}
+
+/// 字段的名字, 偏移和大小
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Field {
+    pub name: &'static str,
+    pub offset: usize,
+    pub size: usize,
+}
+
+/// 生成 [ShmObject::fields] 的返回值
+///
+/// ```
+/// use demo01_rust::shm_fields;
+/// use demo01_rust::shm_segment::Field;
+///
+/// #[repr(C)]
+/// struct Point { x: u32, y: u64 }
+///
+/// assert_eq!(shm_fields!(Point { x, y })[1], Field { name: "y", offset: 8, size: 8 });
+/// ```
+#[macro_export]
+macro_rules! shm_fields {
+    ($t:ty { $($field:ident),* $(,)? }) => {
+        vec![$(
+            $crate::shm_segment::Field {
+                name: stringify!($field),
+                offset: ::std::mem::offset_of!($t, $field),
+                size: $crate::shm_segment::field_size(|value: &$t| &value.$field),
+            }
+        ),*]
+    };
+}
+
+#[doc(hidden)]
+pub fn field_size<T, F>(_: fn(&T) -> &F) -> usize {
+    size_of::<F>()
+}
+
+#[derive(Debug)]
+pub enum SegmentError {
+    Io(io::Error),
+    /// 头部中的 `field` 和当前程序的 `T` 不一致
+    Mismatch { path: PathBuf, field: &'static str, expected: u64, found: u64 },
+    /// 文件比头部还短, 或者 magic 不对, 不是 [ShmSegment] 创建的文件
+    NotASegment(PathBuf),
+}
+
+impl Display for SegmentError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SegmentError::Io(e) => write!(f, "io error: {}", e),
+            SegmentError::Mismatch { path, field, expected, found } => {
+                write!(f, "shared memory {:?} has {} {:#x}, expected {:#x}", path, field, found, expected)
+            }
+            SegmentError::NotASegment(path) => write!(f, "{:?} is not a shared memory segment", path),
+        }
+    }
+}
+
+impl std::error::Error for SegmentError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SegmentError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SegmentError {
+    fn from(e: io::Error) -> Self {
+        SegmentError::Io(e)
+    }
+}
+
+impl From<rustix::io::Errno> for SegmentError {
+    fn from(e: rustix::io::Errno) -> Self {
+        SegmentError::Io(e.into())
+    }
+}
+
+/// 文件开头的固定格式头部, 所有字段都是 8 字节对齐的整数, 不同编译器版本下布局也相同
+#[repr(C, align(64))]
+struct Header {
+    magic: AtomicU64,
+    format: u64,
+    version: u64,
+    type_hash: u64,
+    size: u64,
+    align: u64,
+    layout_checksum: u64,
+    /// seqlock 序号, 奇数表示正在写
+    seq: AtomicU64,
+    lock: IpcMutexRaw,
+}
+
+#[repr(C)]
+struct Segment<T> {
+    header: Header,
+    data: UnsafeCell<T>,
+}
+
+/// 头部中除了 magic 以外需要校验的内容
+struct Expected {
+    version: u64,
+    type_hash: u64,
+    size: u64,
+    align: u64,
+    layout_checksum: u64,
+}
+
+impl Expected {
+    fn of<T: ShmObject>() -> Self {
+        let type_hash = fnv1a(T::NAME.as_bytes(), FNV_OFFSET);
+        let (version, size, align) = (T::VERSION as u64, size_of::<T>() as u64, align_of::<T>() as u64);
+        let data_offset = std::mem::offset_of!(Segment<T>, data) as u64;
+        let mut checksum = FNV_OFFSET;
+        for value in [FORMAT as u64, version, type_hash, size, align, data_offset] {
+            checksum = fnv1a(&value.to_le_bytes(), checksum);
+        }
+        for field in T::fields() {
+            checksum = fnv1a(field.name.as_bytes(), checksum);
+            checksum = fnv1a(&(field.offset as u64).to_le_bytes(), checksum);
+            checksum = fnv1a(&(field.size as u64).to_le_bytes(), checksum);
+        }
+        Expected { version, type_hash, size, align, layout_checksum: checksum }
+    }
+
+    /// 和 [Header] 中的字段按同样的顺序排列
+    fn values(&self) -> [(&'static str, u64); 6] {
+        [
+            ("format", FORMAT as u64),
+            ("version", self.version),
+            ("type hash", self.type_hash),
+            ("size", self.size),
+            ("align", self.align),
+            ("layout checksum", self.layout_checksum),
+        ]
+    }
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+fn fnv1a(bytes: &[u8], mut hash: u64) -> u64 {
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// 共享内存中的一个 `T`, 多个进程用同一个路径打开时共享同一份数据.
+///
+/// 和 [Rutex](crate::rutex::Rutex) 不同, 最后一个使用者退出时不会删除文件, 数据一直保留到文件被删除.
+pub struct ShmSegment<T: ShmObject> {
+    segment: *mut Segment<T>,
+    path: PathBuf,
+    created: bool,
+}
+
+unsafe impl<T: ShmObject> Send for ShmSegment<T> {}
+unsafe impl<T: ShmObject> Sync for ShmSegment<T> {}
+
+impl<T: ShmObject> ShmSegment<T> {
+    /// 打开已有的段, 不存在时用 `init` 创建. 已有的段与 `T` 不一致时返回错误, 不会覆盖其中的数据.
+    pub fn open_or_create(path: impl AsRef<Path>, init: impl FnOnce() -> T) -> Result<Self, SegmentError> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+        Self::attach(file, path, Some(init))
+    }
+
+    /// 只打开已有的段
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, SegmentError> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().read(true).write(true).open(&path)?;
+        Self::attach(file, path, None::<fn() -> T>)
+    }
+
+    /// 初始化和校验期间持有 flock, 同时打开的进程不会看到初始化了一半的头部.
+    /// 映射会引用打开的文件, 关闭描述符并不会释放 flock, 所以要显式解锁.
+    fn attach(file: File, path: PathBuf, init: Option<impl FnOnce() -> T>) -> Result<Self, SegmentError> {
+        flock(&file, FlockOperation::LockExclusive)?;
+        let result = Self::attach_locked(&file, path, init);
+        flock(&file, FlockOperation::Unlock)?;
+        result
+    }
+
+    fn attach_locked(file: &File, path: PathBuf, init: Option<impl FnOnce() -> T>) -> Result<Self, SegmentError> {
+        let size = size_of::<Segment<T>>();
+        let len = file.metadata()?.len();
+        if len != 0 && len < size_of::<Header>() as u64 {
+            return Err(SegmentError::NotASegment(path));
+        }
+        if len == 0 {
+            if init.is_none() {
+                return Err(SegmentError::NotASegment(path));
+            }
+            file.set_len(size as u64)?;
+        }
+
+        // 先只映射头部, 文件的大小可能和当前的 `T` 不一致
+        let header = unsafe {
+            mmap(ptr::null_mut(), size_of::<Header>(), ProtFlags::READ, MapFlags::SHARED, file, 0)? as *const Header
+        };
+        let checked = Self::check_header(unsafe { &*header }, &path);
+        unsafe {
+            let _ = munmap(header as *mut c_void, size_of::<Header>());
+        }
+        if checked? {
+            if len != size as u64 {
+                return Err(SegmentError::Mismatch { path, field: "file size", expected: size as u64, found: len });
+            }
+            let segment = Self::map(file)?;
+            return Ok(Self { segment, path, created: false });
+        }
+
+        // 新文件, 或者上一个创建者在写入 magic 之前崩溃了
+        let Some(init) = init else { return Err(SegmentError::NotASegment(path)) };
+        file.set_len(size as u64)?;
+        let segment = Self::map(file)?;
+        let expected = Expected::of::<T>();
+        unsafe {
+            ptr::write(segment, Segment {
+                header: Header {
+                    magic: AtomicU64::new(0),
+                    format: FORMAT as u64,
+                    version: expected.version,
+                    type_hash: expected.type_hash,
+                    size: expected.size,
+                    align: expected.align,
+                    layout_checksum: expected.layout_checksum,
+                    seq: AtomicU64::new(0),
+                    lock: IpcMutexRaw::new(),
+                },
+                data: UnsafeCell::new(init()),
+            });
+            (*segment).header.magic.store(MAGIC, Ordering::Release);
+        }
+        Ok(Self { segment, path, created: true })
+    }
+
+    /// 返回头部是否已经初始化. magic 为 0 时说明还没有初始化完成, 不比较其他字段.
+    fn check_header(header: &Header, path: &Path) -> Result<bool, SegmentError> {
+        match header.magic.load(Ordering::Acquire) {
+            0 => return Ok(false),
+            MAGIC => {}
+            _ => return Err(SegmentError::NotASegment(path.to_path_buf())),
+        }
+        let found = [header.format, header.version, header.type_hash, header.size, header.align, header.layout_checksum];
+        for ((field, expected), found) in Expected::of::<T>().values().into_iter().zip(found) {
+            if expected != found {
+                return Err(SegmentError::Mismatch { path: path.to_path_buf(), field, expected, found });
+            }
+        }
+        Ok(true)
+    }
+
+    fn map(file: &File) -> Result<*mut Segment<T>, SegmentError> {
+        let size = size_of::<Segment<T>>();
+        Ok(unsafe { mmap(ptr::null_mut(), size, ProtFlags::READ | ProtFlags::WRITE, MapFlags::SHARED, file, 0)? as *mut Segment<T> })
+    }
+
+    fn segment(&self) -> &Segment<T> {
+        unsafe { &*self.segment }
+    }
+
+    /// 加跨进程互斥锁, 通过返回的守卫修改数据
+    pub fn lock(&self) -> SegmentGuard<'_, T> {
+        let recovered = self.segment().header.lock.lock_robust();
+        // 上一个写者在修改途中退出时序号停在奇数, 由这次写入在结束时恢复成偶数
+        let writing = self.segment().header.seq.load(Ordering::Relaxed) % 2 == 1;
+        SegmentGuard { segment: self, recovered, writing }
+    }
+
+    /// 本次打开时是否创建并初始化了数据
+    pub fn is_creator(&self) -> bool {
+        self.created
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// seqlock 序号, 每完成一次修改加 2
+    pub fn sequence(&self) -> u64 {
+        self.segment().header.seq.load(Ordering::Acquire)
+    }
+}
+
+/// [ShmSegment::snapshot] 的结果
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Snapshot<T> {
+    pub value: T,
+    /// 上一个写者在修改途中退出, 锁是从它手里接管过来的. 这时 `value` 可能只改了一半, 调用者需要自己检查,
+    /// 再通过 [ShmSegment::lock] 修复. 之后的读取不会再报告这次恢复.
+    pub recovered: bool,
+}
+
+impl<T: ShmObject + Copy> ShmSegment<T> {
+    /// 不加锁读取一份一致的副本
+    ///
+    /// 写者正在修改时重试. 连续失败 [OPTIMISTIC_RETRIES] 次后改为加锁读取, 这样写者在修改途中退出时,
+    /// 读者会接管锁, 把序号恢复成偶数, 并在返回值中设置 [Snapshot::recovered].
+    pub fn snapshot(&self) -> Snapshot<T> {
+        for attempt in 0..OPTIMISTIC_RETRIES {
+            if let Some(value) = self.try_snapshot() {
+                return Snapshot { value, recovered: false };
+            }
+            if attempt > OPTIMISTIC_RETRIES / 2 {
+                thread::yield_now();
+            } else {
+                std::hint::spin_loop();
+            }
+        }
+        let guard = self.lock();
+        Snapshot { value: *guard, recovered: guard.recovered() }
+    }
+
+    /// 只尝试一次, 期间有写者时返回 `None`
+    pub fn try_snapshot(&self) -> Option<T> {
+        let segment = self.segment();
+        let before = segment.header.seq.load(Ordering::Acquire);
+        if before % 2 == 1 {
+            return None;
+        }
+        // 和写者并发时读到的可能是撕裂的值, 只有序号前后一致时才使用它. `T: Copy` 保证丢弃撕裂的值没有副作用.
+        let value = unsafe { ptr::read_volatile(segment.data.get()) };
+        fence(Ordering::Acquire);
+        (segment.header.seq.load(Ordering::Relaxed) == before).then_some(value)
+    }
+}
+
+impl<T: ShmObject> Drop for ShmSegment<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = munmap(self.segment as *mut c_void, size_of::<Segment<T>>());
+        }
+    }
+}
+
+pub struct SegmentGuard<'a, T: ShmObject> {
+    segment: &'a ShmSegment<T>,
+    recovered: bool,
+    /// 是否已经把序号改成了奇数
+    writing: bool,
+}
+
+impl<T: ShmObject> SegmentGuard<'_, T> {
+    /// 锁是否从一个已经退出的进程手里接管过来的, 见 [RutexGuard::recovered](crate::rutex::RutexGuard::recovered)
+    pub fn recovered(&self) -> bool {
+        self.recovered
+    }
+}
+
+impl<T: ShmObject> Deref for SegmentGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.segment.segment().data.get() }
+    }
+}
+
+/// 第一次取得可变引用时才开始写, 只读的加锁不会让无锁读者重试
+impl<T: ShmObject> DerefMut for SegmentGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        if !self.writing {
+            self.segment.segment().header.seq.fetch_add(1, Ordering::Relaxed);
+            fence(Ordering::Release);
+            self.writing = true;
+        }
+        unsafe { &mut *self.segment.segment().data.get() }
+    }
+}
+
+impl<T: ShmObject> Drop for SegmentGuard<'_, T> {
+    fn drop(&mut self) {
+        let header = &self.segment.segment().header;
+        if self.writing {
+            header.seq.fetch_add(1, Ordering::Release);
+        }
+        unsafe { header.lock.unlock() }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::rutex::test::{fork_child, wait_child};
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    #[repr(C)]
+    struct Pair {
+        a: u64,
+        b: u64,
+    }
+    unsafe impl ShmSafe for Pair {}
+
+    impl ShmObject for Pair {
+        const NAME: &'static str = "test.Pair";
+        fn fields() -> Vec<Field> {
+            shm_fields!(Pair { a, b })
+        }
+    }
+
+    /// 大小和对齐都和 [Pair] 相同, 只是字段名不同
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    struct Renamed {
+        a: u64,
+        c: u64,
+    }
+    unsafe impl ShmSafe for Renamed {}
+
+    impl ShmObject for Renamed {
+        const NAME: &'static str = "test.Renamed";
+        fn fields() -> Vec<Field> {
+            shm_fields!(Renamed { a, c })
+        }
+    }
+
+    mod v2 {
+        use super::*;
+
+        #[derive(Clone, Copy)]
+        #[repr(C)]
+        pub(super) struct Pair {
+            pub(super) a: u64,
+            pub(super) b: u64,
+        }
+        unsafe impl ShmSafe for Pair {}
+
+        impl ShmObject for Pair {
+            const NAME: &'static str = "test.Pair";
+            const VERSION: u32 = 2;
+        }
+    }
+
+    #[test]
+    fn test_create_and_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pair.shm");
+        assert!(matches!(ShmSegment::<Pair>::open(&path), Err(SegmentError::Io(e)) if e.kind() == io::ErrorKind::NotFound));
+
+        let segment = ShmSegment::open_or_create(&path, || Pair { a: 1, b: 2 }).unwrap();
+        assert!(segment.is_creator());
+        segment.lock().a = 10;
+        assert_eq!(segment.sequence(), 2);
+        // 只读的加锁不修改序号
+        assert_eq!(segment.lock().b, 2);
+        assert_eq!(segment.sequence(), 2);
+        drop(segment);
+
+        // 最后一个使用者退出后数据仍然保留
+        let reopened = ShmSegment::open_or_create(&path, || Pair { a: 0, b: 0 }).unwrap();
+        assert!(!reopened.is_creator());
+        assert_eq!(reopened.snapshot(), Snapshot { value: Pair { a: 10, b: 2 }, recovered: false });
+    }
+
+    #[test]
+    fn test_layout_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pair.shm");
+        let _segment = ShmSegment::open_or_create(&path, || Pair { a: 1, b: 2 }).unwrap();
+
+        let field = |result: Result<_, SegmentError>| match result {
+            Err(SegmentError::Mismatch { field, .. }) => field,
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("attached to a mismatched segment"),
+        };
+        assert_eq!(field(ShmSegment::open_or_create(&path, || v2::Pair { a: 0, b: 0 }).map(drop)), "version");
+        assert_eq!(field(ShmSegment::open_or_create(&path, || Renamed { a: 0, c: 0 }).map(drop)), "type hash");
+
+        // 不是 ShmSegment 创建的文件
+        let other = dir.path().join("other.bin");
+        std::fs::write(&other, [0xffu8; 128]).unwrap();
+        assert!(matches!(ShmSegment::<Pair>::open(&other), Err(SegmentError::NotASegment(_))));
+    }
+
+    #[test]
+    fn test_layout_checksum_covers_fields() {
+        let (pair, renamed) = (Expected::of::<Pair>(), Expected::of::<Renamed>());
+        assert_eq!((pair.size, pair.align), (renamed.size, renamed.align));
+        assert_ne!(pair.layout_checksum, renamed.layout_checksum);
+    }
+
+    /// 写者不断修改两个字段并保持 a + b == 1000, 无锁读者读到的副本必须满足这个不变式
+    #[test]
+    fn test_snapshot_is_consistent() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pair.shm");
+        let segment = Arc::new(ShmSegment::open_or_create(&path, || Pair { a: 0, b: 1000 }).unwrap());
+
+        let child = fork_child(|| {
+            let segment = ShmSegment::<Pair>::open(&path).unwrap();
+            for i in 0..20_000u64 {
+                let mut guard = segment.lock();
+                guard.a = i % 1000;
+                std::hint::black_box(&mut *guard);
+                guard.b = 1000 - i % 1000;
+            }
+            true
+        });
+
+        // 每次写入让序号加 2, 一直读到写者全部写完
+        let mut reads = 0;
+        while segment.sequence() < 40_000 {
+            if let Some(pair) = segment.try_snapshot() {
+                assert_eq!(pair.a + pair.b, 1000, "{:?}", pair);
+                reads += 1;
+            }
+        }
+        assert!(wait_child(child));
+        assert!(reads > 0);
+        assert_eq!(segment.snapshot().value, Pair { a: 999, b: 1 });
+    }
+
+    /// 写者在修改途中被 kill, 读者最终加锁接管, 序号恢复成偶数. 读到的是只改了一半的值, 必须报告出来.
+    #[test]
+    fn test_snapshot_recovers_from_dead_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pair.shm");
+        let segment = ShmSegment::open_or_create(&path, || Pair { a: 0, b: 1000 }).unwrap();
+
+        let child = fork_child(|| {
+            let segment = ShmSegment::<Pair>::open(&path).unwrap();
+            let mut guard = segment.lock();
+            guard.a = 500;
+            std::mem::forget(guard);
+            true
+        });
+        assert!(wait_child(child));
+        assert_eq!(segment.sequence() % 2, 1);
+        assert_eq!(segment.try_snapshot(), None);
+
+        let start = std::time::Instant::now();
+        let snapshot = segment.snapshot();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(snapshot.recovered);
+        assert_ne!(snapshot.value.a + snapshot.value.b, 1000);
+        assert_eq!(segment.sequence() % 2, 0);
+
+        // 修复之后正常读取
+        *segment.lock() = Pair { a: 0, b: 1000 };
+        assert_eq!(segment.snapshot(), Snapshot { value: Pair { a: 0, b: 1000 }, recovered: false });
+    }
+}
@@ -0,0 +1,383 @@
+//! 进程因为信号退出前执行清理
+//!
+//! 整理自 `examples/libc_sigaction.rs`. 原来的做法在信号处理函数中直接调用 `cleanup()`, 里面会锁 `Mutex`, 打印, 分配内存,
+//! 这些都不是异步信号安全的: 信号打断的线程如果正好持有同一把锁或者在 `malloc` 里, 进程就会死锁.
+//!
+//! - 信号处理函数只做两件事: 把信号编号写进一个管道 (self-pipe), 然后在 futex 上等待清理完成.
+//!   真正的清理在一个普通的后台线程中执行, 可以随意加锁, 分配内存, 删除文件.
+//! - 清理回调通过 [register] 注册, 返回的 [CleanupHandle] 可以用来注销. 回调按注册的相反顺序执行, 只执行一次.
+//! - 清理完成后处理之前的信号处置: 之前安装了处理函数就调用它; 之前是默认行为就恢复默认行为并重新发送信号,
+//!   进程仍然以原来的信号退出 (父进程看到的退出状态, core dump 都不变). 之前被忽略的信号不会安装.
+//! - 清理线程卡住 (比如回调需要的锁被收到信号的线程持有) 时, 最多等待 [CLEANUP_TIMEOUT] 就放弃清理.
+//!
+//! ```no_run
+//! use demo01_rust::signal_cleanup::{self, EXIT_SIGNALS};
+//!
+//! signal_cleanup::install(&EXIT_SIGNALS).unwrap();
+//! let handle = signal_cleanup::remove_file_on_exit("rustix_mmap3.bin");
+//! // 文件交给别的进程负责后, 不再需要删除
+//! handle.deregister();
+//! ```
+
+use std::cell::UnsafeCell;
+use std::ffi::c_void;
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem::{self, MaybeUninit};
+use std::os::fd::FromRawFd;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc::{c_int, siginfo_t};
+use rustix::process::Signal;
+
+use crate::rutex::{futex_wait, futex_wake};
+
+/// 默认行为是终止进程, 并且可以捕获的信号. `SIGKILL` 和 `SIGSTOP` 无法捕获.
+pub const EXIT_SIGNALS: [Signal; 14] = [
+    Signal::HUP,
+    Signal::INT,
+    Signal::QUIT,
+    Signal::ILL,
+    Signal::ABORT,
+    Signal::FPE,
+    Signal::SEGV,
+    Signal::BUS,
+    Signal::PIPE,
+    Signal::ALARM,
+    Signal::TERM,
+    Signal::XCPU,
+    Signal::XFSZ,
+    Signal::SYS,
+];
+
+/// 信号处理函数等待清理完成的最长时间
+pub const CLEANUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+const MAX_SIGNAL: usize = 65;
+
+/// 安装之前的信号处置, 在安装之前写入, 信号处理函数中只读
+struct OldActions(UnsafeCell<[MaybeUninit<libc::sigaction>; MAX_SIGNAL]>);
+
+unsafe impl Sync for OldActions {}
+
+static OLD_ACTIONS: OldActions = OldActions(UnsafeCell::new([const { MaybeUninit::uninit() }; MAX_SIGNAL]));
+static INSTALLED: [AtomicBool; MAX_SIGNAL] = [const { AtomicBool::new(false) }; MAX_SIGNAL];
+/// self-pipe 的写端, 非阻塞
+static PIPE_WRITE: AtomicI32 = AtomicI32::new(-1);
+/// 清理线程每执行完一轮回调加一. 信号处理函数记下发信号之前的值, 等到它变化为止,
+/// 所以同一个进程多次收到信号 (之前的处置不退出进程时) 每次都会等待清理.
+static GENERATION: AtomicU32 = AtomicU32::new(0);
+static INSTALL_LOCK: Mutex<()> = Mutex::new(());
+static START: Once = Once::new();
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+static CALLBACKS: Mutex<Vec<(u64, Callback)>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// [register] 的返回值, 丢弃时不会注销
+#[derive(Debug)]
+#[must_use = "丢弃后无法再注销这个回调"]
+pub struct CleanupHandle(u64);
+
+impl CleanupHandle {
+    /// 注销回调, 回调已经执行过时返回 `false`
+    pub fn deregister(self) -> bool {
+        let mut callbacks = CALLBACKS.lock().unwrap_or_else(|e| e.into_inner());
+        let before = callbacks.len();
+        callbacks.retain(|(id, _)| *id != self.0);
+        callbacks.len() != before
+    }
+}
+
+/// 注册清理回调, 在收到信号 (或者调用 [run_cleanup]) 时在清理线程中执行
+pub fn register(callback: impl FnOnce() + Send + 'static) -> CleanupHandle {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    CALLBACKS.lock().unwrap_or_else(|e| e.into_inner()).push((id, Box::new(callback)));
+    CleanupHandle(id)
+}
+
+/// 退出时删除文件, 比如共享内存使用的文件
+pub fn remove_file_on_exit(path: impl Into<PathBuf>) -> CleanupHandle {
+    let path = path.into();
+    register(move || {
+        let _ = std::fs::remove_file(&path);
+    })
+}
+
+/// 立即按注册的相反顺序执行并清空所有回调, 返回执行的数量. 正常退出的路径上可以手动调用.
+/// 单个回调 panic 不影响其他回调.
+pub fn run_cleanup() -> usize {
+    let callbacks = mem::take(&mut *CALLBACKS.lock().unwrap_or_else(|e| e.into_inner()));
+    let count = callbacks.len();
+    for (_, callback) in callbacks.into_iter().rev() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(callback));
+    }
+    count
+}
+
+/// 为 `signals` 安装处理函数, 可以多次调用, 已经安装过的信号会跳过.
+/// 当前被忽略 (`SIG_IGN`) 的信号不会安装, 比如 Rust 运行时默认忽略的 `SIGPIPE`, `nohup` 忽略的 `SIGHUP`.
+pub fn install(signals: &[Signal]) -> io::Result<()> {
+    let _guard = INSTALL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    start_cleanup_thread()?;
+
+    for signal in signals {
+        let sig = signal.as_raw();
+        if INSTALLED[sig as usize].load(Ordering::Acquire) {
+            continue;
+        }
+        unsafe {
+            let mut old: libc::sigaction = mem::zeroed();
+            if libc::sigaction(sig, ptr::null(), &mut old) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if old.sa_sigaction == libc::SIG_IGN {
+                continue;
+            }
+            // 写入之后才安装处理函数, sigaction 系统调用保证了处理函数能看到这次写入
+            (*OLD_ACTIONS.0.get())[sig as usize].write(old);
+            INSTALLED[sig as usize].store(true, Ordering::Release);
+
+            let mut action: libc::sigaction = mem::zeroed();
+            action.sa_sigaction = handle_signal as *const () as usize;
+            // SA_ONSTACK: 栈溢出导致的 SIGSEGV 只能在备用栈上处理
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK | libc::SA_RESTART;
+            libc::sigfillset(&mut action.sa_mask);
+            if libc::sigaction(sig, &action, ptr::null_mut()) != 0 {
+                INSTALLED[sig as usize].store(false, Ordering::Release);
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn start_cleanup_thread() -> io::Result<()> {
+    let mut result = Ok(());
+    START.call_once(|| result = spawn_cleanup_thread());
+    result?;
+    if PIPE_WRITE.load(Ordering::Acquire) < 0 {
+        return Err(io::Error::other("cleanup thread failed to start"));
+    }
+    Ok(())
+}
+
+fn spawn_cleanup_thread() -> io::Result<()> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut read_end = unsafe { File::from_raw_fd(fds[0]) };
+    unsafe {
+        // 写端非阻塞, 管道满了说明已经有信号在排队, 丢掉也没关系
+        libc::fcntl(fds[1], libc::F_SETFL, libc::O_NONBLOCK);
+    }
+
+    // 在屏蔽所有信号的状态下创建线程, 新线程继承屏蔽字, 信号永远不会递送给清理线程
+    let spawned = with_all_signals_blocked(|| {
+        thread::Builder::new().name("signal-cleanup".to_string()).spawn(move || {
+            let mut buf = [0u8; 64];
+            loop {
+                match read_end.read(&mut buf) {
+                    Ok(0) => return,
+                    Ok(_) => {
+                        run_cleanup();
+                        GENERATION.fetch_add(1, Ordering::Release);
+                        futex_wake(&GENERATION, i32::MAX as u32);
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(_) => return,
+                }
+            }
+        })
+    });
+    spawned?;
+    PIPE_WRITE.store(fds[1], Ordering::Release);
+    Ok(())
+}
+
+fn with_all_signals_blocked<R>(f: impl FnOnce() -> R) -> R {
+    unsafe {
+        let mut all: libc::sigset_t = mem::zeroed();
+        let mut old: libc::sigset_t = mem::zeroed();
+        libc::sigfillset(&mut all);
+        libc::pthread_sigmask(libc::SIG_SETMASK, &all, &mut old);
+        let result = f();
+        libc::pthread_sigmask(libc::SIG_SETMASK, &old, ptr::null_mut());
+        result
+    }
+}
+
+/// 信号处理函数, 只调用异步信号安全的函数
+extern "C" fn handle_signal(sig: c_int, info: *mut siginfo_t, context: *mut c_void) {
+    unsafe {
+        let errno = *libc::__errno_location();
+
+        // 必须在写管道之前读取, 否则清理可能在读取之前就完成了, 之后一直等到超时
+        let generation = GENERATION.load(Ordering::Acquire);
+        let fd = PIPE_WRITE.load(Ordering::Acquire);
+        let byte = sig as u8;
+        libc::write(fd, &byte as *const u8 as *const c_void, 1);
+        let deadline = Instant::now() + CLEANUP_TIMEOUT;
+        while GENERATION.load(Ordering::Acquire) == generation && futex_wait(&GENERATION, generation, Some(deadline)) {}
+
+        chain(sig, info, context);
+        *libc::__errno_location() = errno;
+    }
+}
+
+/// 交给安装之前的处置处理
+unsafe fn chain(sig: c_int, info: *mut siginfo_t, context: *mut c_void) {
+    let old = (*OLD_ACTIONS.0.get())[sig as usize].assume_init_ref();
+    match old.sa_sigaction {
+        libc::SIG_DFL => {
+            // 恢复默认行为后重新发送. 处理函数执行期间这个信号被屏蔽, 返回后才会递送, 进程以这个信号终止.
+            // 硬件异常 (SIGSEGV 等) 返回后会重新执行出错的指令, 同样以默认行为终止.
+            let mut default: libc::sigaction = mem::zeroed();
+            default.sa_sigaction = libc::SIG_DFL;
+            libc::sigaction(sig, &default, ptr::null_mut());
+            libc::raise(sig);
+        }
+        libc::SIG_IGN => {}
+        handler if old.sa_flags & libc::SA_SIGINFO != 0 => {
+            let handler: extern "C" fn(c_int, *mut siginfo_t, *mut c_void) = mem::transmute(handler);
+            handler(sig, info, context);
+        }
+        handler => {
+            let handler: extern "C" fn(c_int) = mem::transmute(handler);
+            handler(sig);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+    use crate::rutex::test::fork_child;
+
+    /// 返回子进程的原始等待状态
+    fn wait_status(pid: libc::pid_t) -> c_int {
+        let mut status = 0;
+        unsafe { libc::waitpid(pid, &mut status, 0) };
+        status
+    }
+
+    fn append(path: PathBuf, text: &'static str) -> impl FnOnce() + Send + 'static {
+        move || {
+            let old = fs::read_to_string(&path).unwrap_or_default();
+            fs::write(&path, old + text).unwrap();
+        }
+    }
+
+    /// 所有测试都在子进程中收发信号, 不影响测试进程里的其他线程
+    #[test]
+    fn test_cleanup_then_reraise() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let child = fork_child(|| {
+            install(&[Signal::TERM]).unwrap();
+            let _a = register(append(log.clone(), "a"));
+            let _b = register(append(log.clone(), "b"));
+            let c = register(append(log.clone(), "c"));
+            assert!(c.deregister());
+            unsafe { libc::raise(libc::SIGTERM) };
+            false
+        });
+        let status = wait_status(child);
+        assert!(libc::WIFSIGNALED(status));
+        assert_eq!(libc::WTERMSIG(status), libc::SIGTERM);
+        assert_eq!(fs::read_to_string(&log).unwrap(), "ba");
+    }
+
+    static PREVIOUS_CALLED: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn previous_handler(_: c_int) {
+        PREVIOUS_CALLED.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// 之前安装的处理函数在清理之后被调用, 它不退出进程时进程继续运行
+    #[test]
+    fn test_chain_previous_handler() {
+        let child = fork_child(|| unsafe {
+            libc::signal(libc::SIGUSR1, previous_handler as *const () as usize);
+            install(&[Signal::USR1]).unwrap();
+            let cleaned = std::sync::Arc::new(AtomicBool::new(false));
+            let flag = cleaned.clone();
+            let _handle = register(move || flag.store(true, Ordering::SeqCst));
+            libc::raise(libc::SIGUSR1);
+            cleaned.load(Ordering::SeqCst) && PREVIOUS_CALLED.load(Ordering::SeqCst) == 1
+        });
+        assert_eq!(wait_status(child), 0);
+    }
+
+    /// 进程收到信号后继续运行, 再次收到信号时仍然要等新注册的回调执行完
+    #[test]
+    fn test_repeated_signal() {
+        let child = fork_child(|| unsafe {
+            libc::signal(libc::SIGUSR2, previous_handler as *const () as usize);
+            install(&[Signal::USR2]).unwrap();
+            (0..3).all(|_| {
+                let cleaned = std::sync::Arc::new(AtomicBool::new(false));
+                let flag = cleaned.clone();
+                let _handle = register(move || flag.store(true, Ordering::SeqCst));
+                libc::raise(libc::SIGUSR2);
+                cleaned.load(Ordering::SeqCst)
+            })
+        });
+        assert_eq!(wait_status(child), 0);
+    }
+
+    #[test]
+    fn test_ignored_signal_not_installed() {
+        let child = fork_child(|| unsafe {
+            libc::signal(libc::SIGHUP, libc::SIG_IGN);
+            install(&[Signal::HUP]).unwrap();
+            let _handle = register(|| libc::_exit(3));
+            libc::raise(libc::SIGHUP);
+            thread::sleep(Duration::from_millis(50));
+            true
+        });
+        assert_eq!(wait_status(child), 0);
+    }
+
+    /// 段错误之类的硬件异常同样先清理, 最后以原来的信号终止
+    #[test]
+    fn test_fatal_fault() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let child = fork_child(|| {
+            install(&EXIT_SIGNALS).unwrap();
+            let _handle = register(append(log.clone(), "cleaned"));
+            unsafe { ptr::write_volatile(ptr::null_mut::<u8>(), 1) };
+            false
+        });
+        let status = wait_status(child);
+        assert!(libc::WIFSIGNALED(status));
+        assert_eq!(libc::WTERMSIG(status), libc::SIGSEGV);
+        assert_eq!(fs::read_to_string(&log).unwrap(), "cleaned");
+    }
+
+    #[test]
+    fn test_run_cleanup() {
+        let child = fork_child(|| {
+            let counter = std::sync::Arc::new(AtomicUsize::new(0));
+            let c = counter.clone();
+            let _handle = register(move || {
+                c.fetch_add(1, Ordering::SeqCst);
+            });
+            let _panics = register(|| panic!("cleanup failed"));
+            run_cleanup() == 2 && run_cleanup() == 0 && counter.load(Ordering::SeqCst) == 1
+        });
+        assert_eq!(wait_status(child), 0);
+    }
+}
@@ -0,0 +1,756 @@
+//! 子进程监督
+//!
+//! 整理自 `examples/rustix_process.rs`, `examples/ipc_pipe.rs` 和 `examples/rustix_eventfd.rs` 中各自启动子进程的代码:
+//!
+//! - [ChildSpec] 描述怎么启动一个子进程. 需要继承的文件描述符 (管道, eventfd, 共享内存文件) 用 [ChildSpec::fd] 显式传递,
+//!   在子进程中从 3 开始依次编号, 名字和编号写在环境变量 [FDS_ENV] 中, 子进程用 [inherited_fd] 按名字取出.
+//!   不再需要 `cargo run` 套一层, 也不用自己约定环境变量.
+//! - [Supervisor] 在后台线程中运行: 用 `pidfd` + `poll` 等待子进程退出并立即回收, 按 [RestartPolicy] 决定是否重启,
+//!   连续失败时按 [Backoff] 指数退避. 可以把收到的信号通过 `signalfd` 转发给所有子进程.
+//! - 每个子进程的状态通过 [Supervisor::status] 查询.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use demo01_rust::supervisor::{ChildSpec, RestartPolicy, Supervisor};
+//! use rustix::process::Signal;
+//!
+//! let event_fd = rustix::event::eventfd(0, rustix::event::EventfdFlags::empty()).unwrap();
+//! let supervisor = Supervisor::builder()
+//!     .child(ChildSpec::new("worker", "./worker").arg("--verbose").fd("eventfd", event_fd).restart(RestartPolicy::Always))
+//!     .forward_signals(&[Signal::TERM, Signal::INT, Signal::HUP])
+//!     .start()
+//!     .unwrap();
+//! println!("{:?}", supervisor.status());
+//! supervisor.stop(Duration::from_secs(5));
+//! ```
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus};
+use std::sync::{mpsc, Arc, Condvar, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rustix::event::{eventfd, poll, EventfdFlags, PollFd, PollFlags};
+use rustix::process::{kill_process, pidfd_open, set_child_subreaper, waitpid, Pid, PidfdFlags, Signal, WaitOptions};
+
+/// 子进程中保存继承的文件描述符的环境变量, 格式为 `name=3,other=4`
+pub const FDS_ENV: &str = "SUPERVISOR_FDS";
+const FIRST_FD: RawFd = 3;
+/// 开启 [SupervisorBuilder::reap_orphans] 后, 没有其他事件时也会按这个间隔回收孤儿进程
+const ORPHAN_REAP_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartPolicy {
+    Never,
+    /// 只在退出码不为 0 或者被信号杀死时重启
+    OnFailure,
+    Always,
+}
+
+/// 连续第 n 次重启前等待 `initial * 2^(n-1)`, 不超过 `max`.
+/// 子进程运行超过 `reset_after` 后退出, 视为不是连续失败, 重新从 `initial` 开始.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub reset_after: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self { initial: Duration::from_millis(100), max: Duration::from_secs(10), reset_after: Duration::from_secs(10) }
+    }
+}
+
+impl Backoff {
+    pub fn delay(&self, failures: u32) -> Duration {
+        let factor = 1u32.checked_shl(failures.saturating_sub(1)).unwrap_or(u32::MAX);
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChildSpec {
+    name: String,
+    program: PathBuf,
+    args: Vec<OsString>,
+    envs: Vec<(OsString, OsString)>,
+    fds: Vec<(String, Arc<OwnedFd>)>,
+    restart: RestartPolicy,
+    backoff: Backoff,
+}
+
+impl ChildSpec {
+    pub fn new(name: impl Into<String>, program: impl Into<PathBuf>) -> Self {
+        Self {
+            name: name.into(),
+            program: program.into(),
+            args: Vec::new(),
+            envs: Vec::new(),
+            fds: Vec::new(),
+            restart: RestartPolicy::OnFailure,
+            backoff: Backoff::default(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    pub fn args(mut self, args: impl IntoIterator<Item = impl AsRef<OsStr>>) -> Self {
+        self.args.extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+        self
+    }
+
+    pub fn env(mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> Self {
+        self.envs.push((key.as_ref().to_owned(), value.as_ref().to_owned()));
+        self
+    }
+
+    /// 传给子进程的文件描述符, 按添加顺序编号为 3, 4, 5...
+    /// 重启时会再次传递同一个描述符, 所以父进程会一直持有它.
+    pub fn fd(mut self, name: impl Into<String>, fd: impl Into<OwnedFd>) -> Self {
+        self.fds.push((name.into(), Arc::new(fd.into())));
+        self
+    }
+
+    pub fn restart(mut self, policy: RestartPolicy) -> Self {
+        self.restart = policy;
+        self
+    }
+
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// 生成对应的 `Command`, 可以继续设置 stdin/stdout 等
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args).envs(self.envs.iter().map(|(k, v)| (k, v)));
+        if !self.fds.is_empty() {
+            let names: Vec<String> =
+                self.fds.iter().enumerate().map(|(i, (name, _))| format!("{}={}", name, FIRST_FD + i as RawFd)).collect();
+            command.env(FDS_ENV, names.join(","));
+        }
+
+        let sources: Vec<RawFd> = self.fds.iter().map(|(_, fd)| fd.as_raw_fd()).collect();
+        // 临时描述符的位置在 fork 之前分配好, pre_exec 中不能分配内存
+        let mut temps: Vec<RawFd> = vec![-1; sources.len()];
+        unsafe {
+            // fork 之后 exec 之前执行, 只能调用异步信号安全的函数, 不能分配内存
+            command.pre_exec(move || {
+                let mut empty: libc::sigset_t = mem::zeroed();
+                libc::sigemptyset(&mut empty);
+                libc::pthread_sigmask(libc::SIG_SETMASK, &empty, std::ptr::null_mut());
+
+                // 分两遍: 先把所有源描述符复制到目标编号之后, 再逐个 dup2 到目标编号.
+                // 如果在同一遍中 dup2, 某个源描述符刚好等于前面一项的目标编号时会先被覆盖.
+                // F_DUPFD_CLOEXEC 得到的临时描述符在 exec 时自动关闭, dup2 得到的目标描述符会清除 CLOEXEC.
+                let base = FIRST_FD + sources.len() as RawFd;
+                for (temp, &source) in temps.iter_mut().zip(&sources) {
+                    *temp = libc::fcntl(source, libc::F_DUPFD_CLOEXEC, base);
+                    if *temp < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                for (i, &temp) in temps.iter().enumerate() {
+                    if libc::dup2(temp, FIRST_FD + i as RawFd) < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+        command
+    }
+
+    /// 直接启动一次, 不受 [Supervisor] 管理
+    pub fn spawn(&self) -> io::Result<Child> {
+        self.command().spawn()
+    }
+}
+
+fn parse_fds(value: &str) -> HashMap<String, RawFd> {
+    value
+        .split(',')
+        .filter_map(|item| item.split_once('='))
+        .filter_map(|(name, fd)| Some((name.to_string(), fd.parse().ok()?)))
+        .collect()
+}
+
+/// 在子进程中按名字取出父进程通过 [ChildSpec::fd] 传递的描述符, 同一个名字只能取一次
+pub fn inherited_fd(name: &str) -> Option<OwnedFd> {
+    static FDS: OnceLock<Mutex<HashMap<String, RawFd>>> = OnceLock::new();
+    let fds = FDS.get_or_init(|| Mutex::new(std::env::var(FDS_ENV).map(|value| parse_fds(&value)).unwrap_or_default()));
+    let fd = fds.lock().unwrap().remove(name)?;
+    Some(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChildState {
+    Running { pid: u32 },
+    /// 等待退避时间结束后重启
+    Restarting { at: Instant },
+    /// 已经退出, 不会再重启
+    Exited(ExitStatus),
+    /// 启动失败且不会再重试
+    Failed,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChildStatus {
+    pub name: String,
+    pub state: ChildState,
+    /// 已经重启的次数
+    pub restarts: u32,
+    pub last_exit: Option<ExitStatus>,
+    pub last_error: Option<String>,
+}
+
+impl ChildStatus {
+    pub fn is_running(&self) -> bool {
+        matches!(self.state, ChildState::Running { .. })
+    }
+
+    /// 已经退出且不会再启动
+    pub fn is_finished(&self) -> bool {
+        matches!(self.state, ChildState::Exited(_) | ChildState::Failed)
+    }
+}
+
+enum Control {
+    Signal(Signal),
+    Stop(Duration),
+}
+
+struct Shared {
+    status: Mutex<Vec<ChildStatus>>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+pub struct SupervisorBuilder {
+    children: Vec<ChildSpec>,
+    forward: Vec<Signal>,
+    reap_orphans: bool,
+}
+
+impl SupervisorBuilder {
+    pub fn child(mut self, spec: ChildSpec) -> Self {
+        self.children.push(spec);
+        self
+    }
+
+    /// 把这些信号转发给所有子进程. 收到 `SIGTERM` / `SIGINT` / `SIGQUIT` 后不再重启子进程.
+    ///
+    /// 信号通过 `signalfd` 接收, 需要在调用 [SupervisorBuilder::start] 的线程中屏蔽它们,
+    /// 之后创建的线程会继承这个屏蔽字. 所以要在启动其他线程之前, 在主线程中调用.
+    pub fn forward_signals(mut self, signals: &[Signal]) -> Self {
+        self.forward.extend_from_slice(signals);
+        self
+    }
+
+    /// 注册为 child subreaper, 子进程的后代成为孤儿后由我们回收, 不会留下僵尸进程
+    ///
+    /// 过继来的孤儿进程无法和本进程自己启动的其他子进程区分, 所以开启后会用 `waitpid(-1)` 回收本进程的**所有**子进程.
+    /// 只适合 supervisor 独占整个进程的情况: 其他代码启动的子进程的退出状态会被取走,
+    /// 对它们调用 `Child::wait` 等会得到 `ECHILD`.
+    pub fn reap_orphans(mut self, enable: bool) -> Self {
+        self.reap_orphans = enable;
+        self
+    }
+
+    pub fn start(self) -> io::Result<Supervisor> {
+        let signal_fd = if self.forward.is_empty() { None } else { Some(block_and_signalfd(&self.forward)?) };
+        if self.reap_orphans {
+            set_child_subreaper(Some(rustix::process::getpid()))?;
+        }
+        let wake = Arc::new(eventfd(0, EventfdFlags::CLOEXEC | EventfdFlags::NONBLOCK)?);
+
+        let now = Instant::now();
+        let status = self
+            .children
+            .iter()
+            .map(|spec| ChildStatus {
+                name: spec.name.clone(),
+                state: ChildState::Restarting { at: now },
+                restarts: 0,
+                last_exit: None,
+                last_error: None,
+            })
+            .collect();
+        let shared = Arc::new(Shared { status: Mutex::new(status), changed: Condvar::new() });
+
+        let (control, receiver) = mpsc::channel();
+        let mut worker = Worker {
+            shared: shared.clone(),
+            wake: wake.clone(),
+            control: receiver,
+            signal_fd,
+            reap_orphans: self.reap_orphans,
+            children: self.children.into_iter().map(|spec| Managed { spec, process: None, failures: 0 }).collect(),
+            stopping: false,
+            kill_at: None,
+        };
+        let thread = thread::Builder::new().name("supervisor".to_string()).spawn(move || worker.run())?;
+        Ok(Supervisor { shared, control, wake, thread: Some(thread) })
+    }
+}
+
+/// 在当前线程屏蔽 `signals` 并创建对应的 `signalfd`
+fn block_and_signalfd(signals: &[Signal]) -> io::Result<OwnedFd> {
+    unsafe {
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for signal in signals {
+            libc::sigaddset(&mut set, signal.as_raw());
+        }
+        let result = libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+        if result != 0 {
+            return Err(io::Error::from_raw_os_error(result));
+        }
+        let fd = libc::signalfd(-1, &set, libc::SFD_CLOEXEC | libc::SFD_NONBLOCK);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(OwnedFd::from_raw_fd(fd))
+    }
+}
+
+pub struct Supervisor {
+    shared: Arc<Shared>,
+    control: mpsc::Sender<Control>,
+    wake: Arc<OwnedFd>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Supervisor {
+    pub fn builder() -> SupervisorBuilder {
+        SupervisorBuilder::default()
+    }
+
+    pub fn status(&self) -> Vec<ChildStatus> {
+        self.shared.status.lock().unwrap().clone()
+    }
+
+    /// 等待直到 `condition` 返回 `true`, 超时返回 `false`
+    pub fn wait_until(&self, timeout: Duration, mut condition: impl FnMut(&[ChildStatus]) -> bool) -> bool {
+        let status = self.shared.status.lock().unwrap();
+        let (_status, result) =
+            self.shared.changed.wait_timeout_while(status, timeout, |status| !condition(status)).unwrap();
+        !result.timed_out()
+    }
+
+    /// 给所有正在运行的子进程发送信号
+    pub fn signal(&self, signal: Signal) {
+        self.send(Control::Signal(signal));
+    }
+
+    /// 停止监督: 向子进程发送 `SIGTERM`, 超过 `grace` 仍未退出的发送 `SIGKILL`, 回收后返回最终状态
+    pub fn stop(mut self, grace: Duration) -> Vec<ChildStatus> {
+        self.shutdown(grace);
+        self.status()
+    }
+
+    fn shutdown(&mut self, grace: Duration) {
+        if let Some(thread) = self.thread.take() {
+            self.send(Control::Stop(grace));
+            let _ = thread.join();
+        }
+    }
+
+    fn send(&self, control: Control) {
+        if self.control.send(control).is_ok() {
+            let _ = rustix::io::write(&*self.wake, &1u64.to_ne_bytes());
+        }
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        self.shutdown(Duration::from_secs(1));
+    }
+}
+
+struct Process {
+    pid: Pid,
+    pidfd: OwnedFd,
+    started: Instant,
+}
+
+struct Managed {
+    spec: ChildSpec,
+    process: Option<Process>,
+    /// 连续失败的次数
+    failures: u32,
+}
+
+/// 后台线程持有的状态
+struct Worker {
+    shared: Arc<Shared>,
+    wake: Arc<OwnedFd>,
+    control: mpsc::Receiver<Control>,
+    signal_fd: Option<OwnedFd>,
+    reap_orphans: bool,
+    children: Vec<Managed>,
+    /// 正在停止, 不再重启子进程, 全部退出后线程结束
+    stopping: bool,
+    /// 超过这个时间还没退出的子进程会被强制杀死
+    kill_at: Option<Instant>,
+}
+
+impl Worker {
+    fn run(&mut self) {
+        loop {
+            self.start_due();
+            if self.stopping && self.children.iter().all(|child| child.process.is_none()) {
+                return;
+            }
+
+            let timeout = self.next_timeout();
+            let mut fds: Vec<PollFd<'_>> = Vec::with_capacity(self.children.len() + 2);
+            fds.push(PollFd::new(&*self.wake, PollFlags::IN));
+            if let Some(signal_fd) = &self.signal_fd {
+                fds.push(PollFd::new(signal_fd, PollFlags::IN));
+            }
+            for child in &self.children {
+                if let Some(process) = &child.process {
+                    fds.push(PollFd::new(&process.pidfd, PollFlags::IN));
+                }
+            }
+            let timeout = timeout.map(crate::rutex::timespec);
+            match poll(&mut fds, timeout.as_ref()) {
+                Ok(_) | Err(rustix::io::Errno::INTR) => {}
+                Err(e) => panic!("supervisor poll failed: {}", e),
+            }
+            drop(fds);
+
+            self.handle_control();
+            self.handle_signals();
+            self.reap();
+            if self.kill_at.is_some_and(|deadline| Instant::now() >= deadline) {
+                self.kill_all(Signal::KILL);
+            }
+        }
+    }
+
+    /// 距离下一次需要醒来处理的时间
+    fn next_timeout(&self) -> Option<Duration> {
+        let now = Instant::now();
+        let mut next = self.kill_at;
+        if !self.stopping {
+            let status = self.shared.status.lock().unwrap();
+            for child in status.iter() {
+                if let ChildState::Restarting { at } = child.state {
+                    next = Some(next.map_or(at, |next| next.min(at)));
+                }
+            }
+        }
+        let timeout = next.map(|next| next.saturating_duration_since(now));
+        if self.reap_orphans {
+            Some(timeout.map_or(ORPHAN_REAP_INTERVAL, |timeout| timeout.min(ORPHAN_REAP_INTERVAL)))
+        } else {
+            timeout
+        }
+    }
+
+    fn update(&self, index: usize, f: impl FnOnce(&mut ChildStatus)) {
+        f(&mut self.shared.status.lock().unwrap()[index]);
+        self.shared.changed.notify_all();
+    }
+
+    fn start_due(&mut self) {
+        if self.stopping {
+            return;
+        }
+        let now = Instant::now();
+        for index in 0..self.children.len() {
+            let due = matches!(self.shared.status.lock().unwrap()[index].state, ChildState::Restarting { at } if at <= now);
+            if due {
+                self.start(index);
+            }
+        }
+    }
+
+    fn start(&mut self, index: usize) {
+        let child = &mut self.children[index];
+        let spawned = child.spec.spawn().and_then(|process| {
+            let pid = Pid::from_child(&process);
+            // 子进程已经退出也能打开, 之后 poll 立即返回
+            let pidfd = pidfd_open(pid, PidfdFlags::empty())?;
+            Ok(Process { pid, pidfd, started: Instant::now() })
+        });
+        match spawned {
+            Ok(process) => {
+                let pid = process.pid.as_raw_nonzero().get() as u32;
+                child.process = Some(process);
+                self.update(index, |status| status.state = ChildState::Running { pid });
+            }
+            Err(e) => {
+                child.failures += 1;
+                let state = match child.spec.restart {
+                    RestartPolicy::Never => ChildState::Failed,
+                    _ => ChildState::Restarting { at: Instant::now() + child.spec.backoff.delay(child.failures) },
+                };
+                self.update(index, |status| {
+                    status.state = state;
+                    status.last_error = Some(e.to_string());
+                });
+            }
+        }
+    }
+
+    fn handle_control(&mut self) {
+        let mut buf = [0u8; 8];
+        let _ = rustix::io::read(&*self.wake, &mut buf);
+        while let Ok(control) = self.control.try_recv() {
+            match control {
+                Control::Signal(signal) => self.kill_all(signal),
+                Control::Stop(grace) => self.begin_stop(grace),
+            }
+        }
+    }
+
+    fn begin_stop(&mut self, grace: Duration) {
+        if !self.stopping {
+            self.kill_all(Signal::TERM);
+        }
+        let deadline = Instant::now() + grace;
+        self.kill_at = Some(self.kill_at.map_or(deadline, |kill_at| kill_at.min(deadline)));
+        self.finish_restarting();
+    }
+
+    /// 进入停止状态, 等待重启的子进程不会再启动
+    fn finish_restarting(&mut self) {
+        self.stopping = true;
+        for index in 0..self.children.len() {
+            self.update(index, |status| {
+                if let (ChildState::Restarting { .. }, Some(exit)) = (status.state, status.last_exit) {
+                    status.state = ChildState::Exited(exit);
+                } else if let ChildState::Restarting { .. } = status.state {
+                    status.state = ChildState::Failed;
+                }
+            });
+        }
+    }
+
+    fn handle_signals(&mut self) {
+        let Some(signal_fd) = &self.signal_fd else { return };
+        let mut received = Vec::new();
+        let mut info: libc::signalfd_siginfo = unsafe { mem::zeroed() };
+        let size = mem::size_of::<libc::signalfd_siginfo>();
+        loop {
+            let n = unsafe { libc::read(signal_fd.as_raw_fd(), &mut info as *mut _ as *mut libc::c_void, size) };
+            if n != size as isize {
+                break;
+            }
+            if let Some(signal) = Signal::from_named_raw(info.ssi_signo as i32) {
+                received.push(signal);
+            }
+        }
+        for signal in received {
+            self.kill_all(signal);
+            if matches!(signal, Signal::TERM | Signal::INT | Signal::QUIT) {
+                // 子进程自己决定怎么退出, 我们只是不再重启它们
+                self.finish_restarting();
+            }
+        }
+    }
+
+    fn kill_all(&self, signal: Signal) {
+        for child in &self.children {
+            if let Some(process) = &child.process {
+                let _ = kill_process(process.pid, signal);
+            }
+        }
+    }
+
+    fn reap(&mut self) {
+        if self.reap_orphans {
+            // 回收任意子进程, 包括被过继给我们的孤儿进程, 见 [SupervisorBuilder::reap_orphans]
+            while let Ok(Some((pid, status))) = waitpid(None, WaitOptions::NOHANG) {
+                if let Some(index) = self.children.iter().position(|c| c.process.as_ref().is_some_and(|p| p.pid == pid)) {
+                    self.exited(index, ExitStatus::from_raw(status.as_raw()));
+                }
+            }
+            return;
+        }
+        for index in 0..self.children.len() {
+            let Some(process) = &self.children[index].process else { continue };
+            if let Ok(Some((_, status))) = waitpid(Some(process.pid), WaitOptions::NOHANG) {
+                self.exited(index, ExitStatus::from_raw(status.as_raw()));
+            }
+        }
+    }
+
+    fn exited(&mut self, index: usize, exit: ExitStatus) {
+        let stopping = self.stopping;
+        let child = &mut self.children[index];
+        let Some(process) = child.process.take() else { return };
+        if process.started.elapsed() >= child.spec.backoff.reset_after {
+            child.failures = 0;
+        }
+        let restart = !stopping
+            && match child.spec.restart {
+                RestartPolicy::Never => false,
+                RestartPolicy::OnFailure => !exit.success(),
+                RestartPolicy::Always => true,
+            };
+        let state = if restart {
+            child.failures += 1;
+            ChildState::Restarting { at: Instant::now() + child.spec.backoff.delay(child.failures) }
+        } else {
+            ChildState::Exited(exit)
+        };
+        self.update(index, |status| {
+            status.state = state;
+            status.last_exit = Some(exit);
+            if restart {
+                status.restarts += 1;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use std::os::unix::net::UnixStream;
+
+    use super::*;
+
+    fn sh(name: &str, script: &str) -> ChildSpec {
+        ChildSpec::new(name, "/bin/sh").arg("-c").arg(script)
+    }
+
+    fn fast_backoff() -> Backoff {
+        Backoff { initial: Duration::from_millis(20), max: Duration::from_millis(80), reset_after: Duration::from_secs(10) }
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        let backoff = fast_backoff();
+        let delays: Vec<_> = (1..=5).map(|n| backoff.delay(n).as_millis()).collect();
+        assert_eq!(delays, [20, 40, 80, 80, 80]);
+        assert_eq!(backoff.delay(100), Duration::from_millis(80));
+    }
+
+    /// 描述符按顺序编号, 名字写在环境变量里
+    #[test]
+    fn test_pass_fds() {
+        let (first, mut first_peer) = UnixStream::pair().unwrap();
+        let (second, mut second_peer) = UnixStream::pair().unwrap();
+        let spec = sh("fds", "echo \"$SUPERVISOR_FDS\" >&3; echo second >&4").fd("first", first).fd("second", second);
+        assert!(spec.spawn().unwrap().wait().unwrap().success());
+        drop(spec);
+
+        let mut output = String::new();
+        first_peer.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "first=3,second=4\n");
+        output.clear();
+        second_peer.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "second\n");
+
+        let fds = parse_fds("a=3,b=4,broken,c=x");
+        assert_eq!(fds, HashMap::from([("a".to_string(), 3), ("b".to_string(), 4)]));
+    }
+
+    /// 源描述符的编号落在目标范围内时, 不能在用到它之前被前面一项的 dup2 覆盖
+    #[test]
+    fn test_pass_fds_overlapping_targets() {
+        let (low, mut low_peer) = UnixStream::pair().unwrap();
+        let (high, mut high_peer) = UnixStream::pair().unwrap();
+        // low 放在最后, 目标编号为 low + 1, 前面各项的目标 3..=low 一定覆盖 low 本身
+        let low_fd = low.as_raw_fd();
+        let low_target = low_fd + 1;
+        let padding = (low_target - FIRST_FD - 1) as usize;
+        // dash 的重定向只支持一位数的描述符
+        let script = format!("echo high >&3; echo low >&{}", low_target);
+        let mut spec = ChildSpec::new("overlap", "/bin/bash").arg("-c").arg(script).fd("high", high);
+        for i in 0..padding {
+            spec = spec.fd(format!("pad{}", i), std::fs::File::open("/dev/null").unwrap());
+        }
+        let spec = spec.fd("low", low);
+        assert!(spec.spawn().unwrap().wait().unwrap().success());
+        drop(spec);
+
+        let mut output = String::new();
+        high_peer.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "high\n");
+        output.clear();
+        low_peer.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "low\n");
+    }
+
+    #[test]
+    fn test_restart_on_failure() {
+        let start = Instant::now();
+        let supervisor =
+            Supervisor::builder().child(sh("failing", "exit 3").backoff(fast_backoff())).start().unwrap();
+        assert!(supervisor.wait_until(Duration::from_secs(5), |status| status[0].restarts >= 3));
+        // 第三次退出时, 前两次重启已经分别等待了 20 和 40 毫秒
+        assert!(start.elapsed() >= Duration::from_millis(60));
+
+        let status = supervisor.stop(Duration::from_secs(1));
+        assert_eq!(status[0].last_exit.unwrap().code(), Some(3));
+        assert!(status[0].is_finished());
+    }
+
+    #[test]
+    fn test_no_restart_on_success() {
+        let supervisor = Supervisor::builder()
+            .child(sh("ok", "exit 0"))
+            .child(sh("once", "exit 1").restart(RestartPolicy::Never))
+            .start()
+            .unwrap();
+        assert!(supervisor.wait_until(Duration::from_secs(5), |status| status.iter().all(ChildStatus::is_finished)));
+        let status = supervisor.status();
+        assert_eq!(status[0].state, ChildState::Exited(ExitStatus::from_raw(0)));
+        assert_eq!(status[1].last_exit.unwrap().code(), Some(1));
+        assert!(status.iter().all(|child| child.restarts == 0));
+    }
+
+    #[test]
+    fn test_signal_and_stop() {
+        let supervisor = Supervisor::builder()
+            .child(sh("sleeper", "exec sleep 30").restart(RestartPolicy::Always).backoff(fast_backoff()))
+            .start()
+            .unwrap();
+        assert!(supervisor.wait_until(Duration::from_secs(5), |status| status[0].is_running()));
+
+        // 被信号杀死后重启
+        supervisor.signal(Signal::USR1);
+        assert!(supervisor.wait_until(Duration::from_secs(5), |status| status[0].restarts == 1 && status[0].is_running()));
+        assert_eq!(supervisor.status()[0].last_exit.unwrap().signal(), Some(libc::SIGUSR1));
+
+        let start = Instant::now();
+        let status = supervisor.stop(Duration::from_secs(5));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(status[0].state, ChildState::Exited(ExitStatus::from_raw(libc::SIGTERM)));
+    }
+
+    /// 忽略 `SIGTERM` 的子进程在宽限期之后被强制杀死
+    #[test]
+    fn test_stop_escalates_to_kill() {
+        let supervisor = Supervisor::builder().child(sh("stubborn", "trap '' TERM; exec sleep 30")).start().unwrap();
+        assert!(supervisor.wait_until(Duration::from_secs(5), |status| status[0].is_running()));
+        // 等 shell 执行完 trap
+        thread::sleep(Duration::from_millis(100));
+
+        let status = supervisor.stop(Duration::from_millis(100));
+        assert_eq!(status[0].last_exit.unwrap().signal(), Some(libc::SIGKILL));
+    }
+}
@@ -0,0 +1,17 @@
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+
+/// The `audit` section of the service configuration.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AuditConfiguration {
+    pub db_filename: PathBuf,
+}
+
+impl Default for AuditConfiguration {
+    fn default() -> Self {
+        Self {
+            db_filename: PathBuf::from("audit.db"),
+        }
+    }
+}
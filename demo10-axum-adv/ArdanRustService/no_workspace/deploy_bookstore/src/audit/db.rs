@@ -0,0 +1,181 @@
+use std::path::Path;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, sqlite::SqliteConnectOptions, types::Json, QueryBuilder, Sqlite, SqlitePool, Transaction};
+use crate::auth::auth_layers::ValidUser;
+
+#[derive(Clone)]
+pub struct AuditDb(pub sqlx::SqlitePool);
+
+pub async fn get_connection_pool(filename: &Path) -> Result<AuditDb> {
+    let options = SqliteConnectOptions::new()
+        .filename(filename)
+        .create_if_missing(true);
+
+    let connection_pool = sqlx::SqlitePool::connect_with(options)
+        .await?;
+    Ok(AuditDb(connection_pool))
+}
+
+pub async fn perform_migrations(db_pool: AuditDb) -> Result<()> {
+    sqlx::migrate!("src/audit/migrations")
+        .run(&db_pool.0)
+        .await?;
+    Ok(())
+}
+
+/// What kind of record changed
+#[derive(Serialize, Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum Entity {
+    Book,
+    User,
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum Action {
+    Create,
+    Update,
+    Delete,
+}
+
+/// Starts the transaction for an audited change to a database that has the
+/// audit database attached, see `super::attach`. `table` is any table of
+/// that database.
+///
+/// The change reads its `before` state first, so a plain transaction would
+/// start as a reader and upgrade to a writer later, and SQLite fails one of
+/// two racing upgrades with "database is locked" instead of waiting. sqlx
+/// can't send `BEGIN IMMEDIATE`, so a write that touches no rows takes the
+/// write lock up front instead.
+pub async fn begin(db_pool: &SqlitePool, table: &str) -> Result<Transaction<'static, Sqlite>> {
+    let mut tx = db_pool.begin().await?;
+    sqlx::query(&format!("UPDATE main.{table} SET rowid = rowid WHERE 0"))
+        .execute(&mut *tx)
+        .await?;
+    Ok(tx)
+}
+
+/// Appends a change to the log as part of the change's own transaction, so
+/// either both are committed or neither is. `before` is `None` for a create
+/// and `after` is `None` for a delete.
+pub async fn record<T: Serialize>(
+    tx: &mut Transaction<'_, Sqlite>,
+    actor: ValidUser,
+    entity: Entity,
+    entity_id: i32,
+    before: Option<&T>,
+    after: Option<&T>,
+) -> Result<()> {
+    let action = match (before, after) {
+        (None, Some(_)) => Action::Create,
+        (Some(_), Some(_)) => Action::Update,
+        (Some(_), None) => Action::Delete,
+        (None, None) => bail!("an audit entry needs a before or an after"),
+    };
+    let before = before.map(serde_json::to_string).transpose()?;
+    let after = after.map(serde_json::to_string).transpose()?;
+
+    sqlx::query(
+        "INSERT INTO audit.audit_log (actor_id, entity, entity_id, action, before, after)
+         VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(actor.id)
+    .bind(entity)
+    .bind(entity_id)
+    .bind(action)
+    .bind(before)
+    .bind(after)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Serialize, Debug, FromRow)]
+pub struct AuditEntry {
+    id: i64,
+    at: DateTime<Utc>,
+    actor_id: i32,
+    entity: Entity,
+    entity_id: i32,
+    action: Action,
+    before: Option<Json<serde_json::Value>>,
+    after: Option<Json<serde_json::Value>>,
+}
+
+pub const DEFAULT_PAGE_SIZE: u32 = 50;
+pub const MAX_PAGE_SIZE: u32 = 200;
+
+/// Query string of `GET /audit`, e.g.
+/// `?entity=book&entity_id=3&since=2024-03-01T00:00:00Z&limit=20`.
+/// Every filter is optional.
+#[derive(Deserialize, Debug, Default)]
+pub struct AuditQuery {
+    pub entity: Option<Entity>,
+    pub entity_id: Option<i32>,
+    pub actor_id: Option<i32>,
+    pub action: Option<Action>,
+    /// Only changes made at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// Only changes made before this time
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<u32>,
+    #[serde(default)]
+    pub offset: u32,
+}
+
+/// One page of entries, plus how many entries match the filter in total.
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    pub total: i64,
+}
+
+/// Appends the filter of `query` as a WHERE clause.
+fn push_filter(builder: &mut QueryBuilder<'_, Sqlite>, query: &AuditQuery) {
+    builder.push(" WHERE 1 = 1");
+    if let Some(entity) = query.entity {
+        builder.push(" AND entity = ").push_bind(entity);
+    }
+    if let Some(entity_id) = query.entity_id {
+        builder.push(" AND entity_id = ").push_bind(entity_id);
+    }
+    if let Some(actor_id) = query.actor_id {
+        builder.push(" AND actor_id = ").push_bind(actor_id);
+    }
+    if let Some(action) = query.action {
+        builder.push(" AND action = ").push_bind(action);
+    }
+    // `at` is stored as SQLite's "YYYY-MM-DD HH:MM:SS", so the bounds are
+    // brought to the same form before comparing
+    if let Some(since) = query.since {
+        builder.push(" AND at >= datetime(").push_bind(since).push(")");
+    }
+    if let Some(until) = query.until {
+        builder.push(" AND at < datetime(").push_bind(until).push(")");
+    }
+}
+
+/// Newest entries first
+pub async fn list_entries(db_pool: AuditDb, query: &AuditQuery) -> Result<AuditPage> {
+    let mut count = QueryBuilder::new("SELECT COUNT(*) FROM audit_log");
+    push_filter(&mut count, query);
+    let total: i64 = count.build_query_scalar().fetch_one(&db_pool.0).await?;
+
+    let mut select = QueryBuilder::new(
+        "SELECT id, at, actor_id, entity, entity_id, action, before, after FROM audit_log",
+    );
+    push_filter(&mut select, query);
+    select
+        .push(" ORDER BY id DESC LIMIT ")
+        .push_bind(query.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE))
+        .push(" OFFSET ")
+        .push_bind(query.offset);
+
+    let entries = select.build_query_as::<AuditEntry>().fetch_all(&db_pool.0).await?;
+    Ok(AuditPage { entries, total })
+}
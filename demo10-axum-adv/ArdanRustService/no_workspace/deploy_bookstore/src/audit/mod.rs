@@ -0,0 +1,97 @@
+//! Append-only log of every change made to books and users through the API.
+pub mod configuration;
+mod db;
+mod web_service;
+use anyhow::Result;
+use axum::{extract::FromRef, middleware, routing::get, Router};
+use sqlx::sqlite::SqlitePoolOptions;
+use crate::auth::{auth_layers, client::AuthClient};
+pub use configuration::AuditConfiguration;
+use db::AuditDb;
+pub use db::{begin, record, Entity};
+
+/// Handed to every handler through axum's `State`
+#[derive(Clone, FromRef)]
+pub struct AuditState {
+    pub db: AuditDb,
+    pub auth_client: AuthClient,
+}
+
+/// Opens and migrates the audit database. Call it before the services that
+/// record changes connect, see `attach`.
+pub async fn open(config: &AuditConfiguration) -> Result<AuditDb> {
+    let db_pool = db::get_connection_pool(&config.db_filename).await?;
+    db::perform_migrations(db_pool.clone()).await?;
+    Ok(db_pool)
+}
+
+/// Attaches the audit database as `audit` to every connection of a pool, so
+/// a service can write a change and its audit entry in one transaction.
+/// SQLite commits a transaction across attached databases atomically as long
+/// as neither is in WAL mode.
+pub fn attach(options: SqlitePoolOptions, config: &AuditConfiguration) -> SqlitePoolOptions {
+    let filename = config.db_filename.to_string_lossy().into_owned();
+    options.after_connect(move |conn, _meta| {
+        let filename = filename.clone();
+        Box::pin(async move {
+            sqlx::query("ATTACH DATABASE ? AS audit")
+                .bind(filename)
+                .execute(conn)
+                .await?;
+            Ok(())
+        })
+    })
+}
+
+/// The read side of the log, for admins only. Tokens are checked the same
+/// way the bookstore checks them.
+pub fn setup_service(db_pool: AuditDb, auth_client: AuthClient) -> Router {
+    let state = AuditState {
+        db: db_pool,
+        auth_client,
+    };
+
+    Router::new()
+        .route("/", get(web_service::list_entries))
+        .route_layer(middleware::from_fn_with_state(state.clone(), auth_layers::require_remote_token))
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod test {
+    use sqlx::sqlite::SqliteConnectOptions;
+    use crate::auth::auth_layers::ValidUser;
+    use super::*;
+
+    /// The entry is written by the change's own transaction, so rolling the
+    /// change back takes the entry with it.
+    #[tokio::test]
+    async fn entries_commit_with_the_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = AuditConfiguration { db_filename: dir.path().join("audit.db") };
+        let audit_db = open(&config).await.unwrap();
+        let options = SqliteConnectOptions::new()
+            .filename(dir.path().join("service.db"))
+            .create_if_missing(true);
+        let pool = attach(SqlitePoolOptions::new(), &config).connect_with(options).await.unwrap();
+        sqlx::query("CREATE TABLE things (id INTEGER PRIMARY KEY)").execute(&pool).await.unwrap();
+        let actor = ValidUser { id: 1, is_admin: true };
+
+        for (id, commit) in [(1, true), (2, false)] {
+            let mut tx = begin(&pool, "things").await.unwrap();
+            sqlx::query("INSERT INTO things (id) VALUES (?)").bind(id).execute(&mut *tx).await.unwrap();
+            record(&mut tx, actor, Entity::Book, id, None, Some(&id)).await.unwrap();
+            if commit {
+                tx.commit().await.unwrap();
+            } else {
+                tx.rollback().await.unwrap();
+            }
+        }
+
+        let things: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM things").fetch_one(&pool).await.unwrap();
+        assert_eq!(things, 1);
+        let page = db::list_entries(audit_db, &db::AuditQuery::default()).await.unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.entries.len(), 1);
+    }
+}
@@ -0,0 +1,18 @@
+use axum::{extract::State, response::IntoResponse, Extension};
+use crate::auth::auth_layers::ValidUser;
+use crate::error::{ApiError, Json, Query};
+use super::db::{self, AuditDb, AuditQuery};
+
+/// Like the book list, the body is a plain array and the number of matching
+/// entries is in the `X-Total-Count` header.
+pub async fn list_entries(
+    State(db_pool): State<AuditDb>,
+    Extension(user): Extension<ValidUser>,
+    Query(query): Query<AuditQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !user.is_admin {
+        return Err(ApiError::Forbidden("only admins may read the audit log".to_string()));
+    }
+    let page = db::list_entries(db_pool, &query).await?;
+    Ok(([("x-total-count", page.total.to_string())], Json(page.entries)))
+}
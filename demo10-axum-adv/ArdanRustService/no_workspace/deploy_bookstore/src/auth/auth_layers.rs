@@ -1,73 +1,83 @@
 use axum::{
-    extract::Request, http::{HeaderMap, StatusCode}, middleware::Next, response::IntoResponse, Extension
+    extract::{Request, State}, http::HeaderMap, middleware::Next, response::IntoResponse
 };
-use super::db;
+use crate::error::ApiError;
+use super::{client::AuthClient, db};
 
-#[derive(Clone, Copy, Debug)]
-pub struct ValidUser(pub i32);
-
-pub async fn require_token(
-    Extension(db_pool): Extension<db::AuthDb>,
-    headers: HeaderMap,
-    mut req: Request,
-    next: Next,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
-    if let Some(auth_header) = headers.get("Token") {
-        let token = auth_header.to_str().map_err(|_| {
-            (
-                StatusCode::UNAUTHORIZED,
-                "invalid header".to_string(),
-            )
-        })?;
+/// The user a request was authenticated as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ValidUser {
+    pub id: i32,
+    pub is_admin: bool,
+}
 
-        if let Some(user_id) = db::get_user_id_from_token(db_pool, token)
-            .await
-            .map_err(|_| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "database error".to_string(),
-                )
-            })?
-        {
-            req.extensions_mut().insert(ValidUser(user_id));
-            return Ok(next.run(req).await);
+impl ValidUser {
+    /// Creating and deleting accounts is for admins only.
+    pub fn require_admin(&self, action: &str) -> Result<(), ApiError> {
+        if self.is_admin {
+            Ok(())
+        } else {
+            Err(ApiError::Forbidden(format!("only admins may {action}")))
         }
     }
 
-    Err((StatusCode::UNAUTHORIZED, "invalid header".to_string()))
+    /// Everyone may change their own account, admins may change any.
+    pub fn require_self_or_admin(&self, user_id: i32) -> Result<(), ApiError> {
+        if self.id == user_id {
+            Ok(())
+        } else {
+            self.require_admin("change other users")
+        }
+    }
 }
 
-/// Used to target requests to the auth service. In a large service,
-/// you'd use a service locator system or multiple environment
-/// variables. We're using this to keep it simple.
-#[derive(Clone)]
-pub struct ListenPort(pub String);
+/// The token the current request was authenticated with, so that `/logout`
+/// knows which one to revoke.
+#[derive(Clone, Debug)]
+pub struct SessionToken(pub String);
 
-pub async fn require_remote_token(
-    Extension(listen_port): Extension<ListenPort>,
+fn token_header(headers: &HeaderMap) -> Result<&str, ApiError> {
+    headers
+        .get("Token")
+        .ok_or_else(|| ApiError::Unauthorized("missing Token header".to_string()))?
+        .to_str()
+        .map_err(|_| ApiError::Unauthorized("invalid header".to_string()))
+}
+
+pub async fn require_token(
+    State(db_pool): State<db::AuthDb>,
     headers: HeaderMap,
-    req: Request,
+    mut req: Request,
     next: Next,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
-    if let Some(auth_header) = headers.get("Token") {
-        let token = auth_header.to_str().map_err(|_| {
-            (
-                StatusCode::UNAUTHORIZED,
-                "invalid header".to_string(),
-            )
-        })?;
+) -> Result<impl IntoResponse, ApiError> {
+    let token = token_header(&headers)?;
+    let user = db::get_user_from_token(db_pool, token)
+        .await?
+        .ok_or_else(|| ApiError::Unauthorized("invalid or expired token".to_string()))?;
 
-        let url = format!("http://localhost:{}/api/auth/is_token_valid/{token}",
-                          listen_port.0);
-        let _body = reqwest::get(&url)
-            .await.map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid token request".to_string()))?
-            .text()
-            .await.map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid token request".to_string()))?
-            ;
-        tracing::info!("Validated Token");
+    req.extensions_mut().insert(user);
+    req.extensions_mut().insert(SessionToken(token.to_string()));
+    Ok(next.run(req).await)
+}
 
-        return Ok(next.run(req).await);
-    }
+/// Like `require_token`, but asks the auth service over HTTP instead of
+/// reading its database, so it works from a separately deployed service.
+pub async fn require_remote_token(
+    State(auth_client): State<AuthClient>,
+    headers: HeaderMap,
+    mut req: Request,
+    next: Next,
+) -> Result<impl IntoResponse, ApiError> {
+    let token = token_header(&headers)?;
+    let user = auth_client
+        .validate(token)
+        .await
+        .map_err(|e| {
+            tracing::warn!("Unable to validate token: {e}");
+            ApiError::Unavailable("auth service unavailable".to_string())
+        })?
+        .ok_or_else(|| ApiError::Unauthorized("invalid or expired token".to_string()))?;
 
-    Err((StatusCode::UNAUTHORIZED, "invalid header".to_string()))
+    req.extensions_mut().insert(user);
+    Ok(next.run(req).await)
 }
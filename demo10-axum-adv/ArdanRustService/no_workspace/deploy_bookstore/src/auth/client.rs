@@ -0,0 +1,176 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use anyhow::{anyhow, Result};
+use reqwest::StatusCode;
+use super::{
+    auth_layers::ValidUser,
+    web_service::{ValidateTokenRequest, ValidateTokenResponse},
+};
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(10);
+/// Expired entries are swept out once the cache grows past this many tokens.
+const CACHE_SWEEP_THRESHOLD: usize = 1024;
+
+/// Validates tokens against a (possibly remote) auth service by calling its
+/// `POST /validate` endpoint.
+///
+/// Successful validations are cached for a short TTL so that every request to
+/// a protected route doesn't cost a round trip. The flip side is that a token
+/// revoked at the auth service keeps working here until its entry expires.
+/// Failures are never cached.
+#[derive(Clone)]
+pub struct AuthClient {
+    http: reqwest::Client,
+    validate_url: String,
+    cache_ttl: Duration,
+    cache: Arc<Mutex<HashMap<String, (ValidUser, Instant)>>>,
+}
+
+impl AuthClient {
+    /// `base_url` is where the auth router is mounted, e.g.
+    /// `http://localhost:3001/api/v1/auth`.
+    pub fn with_settings(base_url: &str, timeout: Duration, cache_ttl: Duration) -> Result<Self> {
+        let http = reqwest::Client::builder()
+            .connect_timeout(timeout)
+            .timeout(timeout)
+            .build()?;
+
+        Ok(Self {
+            http,
+            validate_url: format!("{}/validate", base_url.trim_end_matches('/')),
+            cache_ttl,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// Returns the user owning `token`, `None` if the auth service rejected
+    /// it, or an error if the auth service couldn't be asked.
+    pub async fn validate(&self, token: &str) -> Result<Option<ValidUser>> {
+        if let Some(user) = self.cached(token) {
+            return Ok(Some(user));
+        }
+
+        let response = self
+            .http
+            .post(&self.validate_url)
+            .json(&ValidateTokenRequest { token: token.to_string() })
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => {
+                let body: ValidateTokenResponse = response.json().await?;
+                let user = ValidUser { id: body.user_id, is_admin: body.is_admin };
+                self.remember(token, user);
+                Ok(Some(user))
+            }
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Ok(None),
+            status => Err(anyhow!("auth service answered {status}")),
+        }
+    }
+
+    fn cached(&self, token: &str) -> Option<ValidUser> {
+        let cache = self.cache.lock().unwrap();
+        cache
+            .get(token)
+            .filter(|(_, expires)| *expires > Instant::now())
+            .map(|(user, _)| *user)
+    }
+
+    fn remember(&self, token: &str, user: ValidUser) {
+        if self.cache_ttl.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= CACHE_SWEEP_THRESHOLD {
+            cache.retain(|_, (_, expires)| *expires > now);
+        }
+        cache.insert(token.to_string(), (user, now + self.cache_ttl));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use axum::{http::StatusCode, routing::post, Extension, Json, Router};
+    use super::*;
+
+    const GOOD_USER: Option<ValidUser> = Some(ValidUser { id: 7, is_admin: false });
+
+    /// Stands in for the auth service: "good" belongs to user 7, "bad" is
+    /// rejected, "broken" fails and "slow" never answers in time.
+    async fn stub_validate(
+        Extension(calls): Extension<Arc<AtomicUsize>>,
+        Json(request): Json<ValidateTokenRequest>,
+    ) -> Result<Json<ValidateTokenResponse>, StatusCode> {
+        calls.fetch_add(1, Ordering::SeqCst);
+        match request.token.as_str() {
+            "good" => Ok(Json(ValidateTokenResponse { user_id: 7, is_admin: false })),
+            "broken" => Err(StatusCode::INTERNAL_SERVER_ERROR),
+            "slow" => {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Err(StatusCode::UNAUTHORIZED)
+            }
+            _ => Err(StatusCode::UNAUTHORIZED),
+        }
+    }
+
+    async fn stub_server() -> (String, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let router = Router::new()
+            .route("/api/v1/auth/validate", post(stub_validate))
+            .layer(Extension(calls.clone()));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/api/v1/auth", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        (base_url, calls)
+    }
+
+    #[tokio::test]
+    async fn valid_tokens_are_cached() {
+        let (base_url, calls) = stub_server().await;
+        let client = AuthClient::with_settings(&base_url, DEFAULT_TIMEOUT, DEFAULT_CACHE_TTL).unwrap();
+
+        assert_eq!(client.validate("good").await.unwrap(), GOOD_USER);
+        assert_eq!(client.validate("good").await.unwrap(), GOOD_USER);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn cache_entries_expire() {
+        let (base_url, calls) = stub_server().await;
+        let client = AuthClient::with_settings(&base_url, DEFAULT_TIMEOUT, Duration::from_millis(50)).unwrap();
+
+        assert_eq!(client.validate("good").await.unwrap(), GOOD_USER);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(client.validate("good").await.unwrap(), GOOD_USER);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn rejected_tokens_are_not_cached() {
+        let (base_url, calls) = stub_server().await;
+        let client = AuthClient::with_settings(&base_url, DEFAULT_TIMEOUT, DEFAULT_CACHE_TTL).unwrap();
+
+        assert_eq!(client.validate("bad").await.unwrap(), None);
+        assert_eq!(client.validate("bad").await.unwrap(), None);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn server_errors_and_timeouts_are_errors() {
+        let (base_url, _calls) = stub_server().await;
+        let client = AuthClient::with_settings(&base_url, Duration::from_millis(200), DEFAULT_CACHE_TTL).unwrap();
+
+        assert!(client.validate("broken").await.is_err());
+        assert!(client.validate("slow").await.is_err());
+
+        let unreachable = AuthClient::with_settings("http://127.0.0.1:1/api/v1/auth", DEFAULT_TIMEOUT, DEFAULT_CACHE_TTL).unwrap();
+        assert!(unreachable.validate("good").await.is_err());
+    }
+}
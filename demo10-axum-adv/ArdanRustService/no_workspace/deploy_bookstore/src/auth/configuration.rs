@@ -1,26 +1,34 @@
-use anyhow::Result;
-use config::Config;
+use std::{path::PathBuf, time::Duration};
+use anyhow::{ensure, Result};
 use serde::{Deserialize, Serialize};
 
+/// The `auth` section of the service configuration.
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct AuthConfiguration {
-    pub db_filename: String,
+    pub db_filename: PathBuf,
+    /// How long a token issued at login stays valid, e.g. "24h"
+    #[serde(with = "humantime_serde")]
+    pub token_lifetime: Duration,
+    /// How often expired tokens are deleted from the database
+    #[serde(with = "humantime_serde")]
+    pub token_purge_interval: Duration,
 }
 
-impl AuthConfiguration {
-    pub fn load() -> Result<Self> {
-        // Load any .env files
-        // Ignore the result of loading .env --- it's ok if it doesn't exist
-        let _ = dotenvy::dotenv();
-
-        let settings_reader = Config::builder()
-            .add_source(config::File::with_name("settings").required(false))
-            .add_source(config::Environment::with_prefix("AUTH"))
-            .build()?;
-
-        let settings = settings_reader
-            .try_deserialize()?;
+impl Default for AuthConfiguration {
+    fn default() -> Self {
+        Self {
+            db_filename: PathBuf::from("auth.db"),
+            token_lifetime: Duration::from_secs(24 * 60 * 60),
+            token_purge_interval: Duration::from_secs(60 * 60),
+        }
+    }
+}
 
-        Ok(settings)
+impl AuthConfiguration {
+    pub fn validate(&self) -> Result<()> {
+        ensure!(!self.token_lifetime.is_zero(), "auth.token_lifetime must be greater than zero");
+        ensure!(!self.token_purge_interval.is_zero(), "auth.token_purge_interval must be greater than zero");
+        Ok(())
     }
-}
\ No newline at end of file
+}
@@ -1,16 +1,31 @@
+use std::{path::Path, time::Duration};
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{prelude::FromRow, sqlite::SqliteConnectOptions, Row};
+use sqlx::{prelude::FromRow, sqlite::{SqliteConnectOptions, SqlitePoolOptions}, Row, Sqlite, SqliteConnection, Transaction};
+use validator::Validate;
+use crate::audit::{self, AuditConfiguration};
+use crate::validation::non_blank;
+use super::{auth_layers::ValidUser, password};
 
 #[derive(Clone)]
 pub struct AuthDb(pub sqlx::SqlitePool);
 
-pub async fn get_connection_pool(filename: &str) -> Result<AuthDb> {
+impl AuthDb {
+    /// Starts the transaction for a change to a user, which records itself in
+    /// the audit log before committing.
+    pub async fn begin(&self) -> Result<Transaction<'static, Sqlite>> {
+        audit::begin(&self.0, "users").await
+    }
+}
+
+pub async fn get_connection_pool(filename: &Path, audit: &AuditConfiguration) -> Result<AuthDb> {
     let options = SqliteConnectOptions::new()
         .filename(filename)
         .create_if_missing(true);
 
-    let connection_pool = sqlx::SqlitePool::connect_with(options)
+    let connection_pool = audit::attach(SqlitePoolOptions::new(), audit)
+        .connect_with(options)
         .await?;
     Ok(AuthDb(connection_pool))
 }
@@ -19,51 +34,216 @@ pub async fn perform_migrations(db_pool: AuthDb) -> Result<()> {
     sqlx::migrate!("src/auth/migrations")
         .run(&db_pool.0)
         .await?;
+    rehash_plaintext_passwords(db_pool).await?;
+    Ok(())
+}
+
+/// Data half of the `password_hash` migration: replaces any plaintext
+/// password left over from before hashing was introduced with its Argon2id
+/// hash. Once every row is hashed this finds nothing to do.
+async fn rehash_plaintext_passwords(db_pool: AuthDb) -> Result<()> {
+    let mut tx = db_pool.0.begin().await?;
+    let plaintext = sqlx::query_as::<_, (i32, String)>(
+        "SELECT id, password_hash FROM users WHERE password_hash NOT LIKE ? || '%'",
+    )
+    .bind(password::HASH_PREFIX)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for (user_id, plaintext_password) in plaintext {
+        let hash = password::hash(plaintext_password).await?;
+        sqlx::query("UPDATE users SET password_hash = ? WHERE id = ?")
+            .bind(hash)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        tracing::info!("Rehashed plaintext password for user {user_id}");
+    }
+    tx.commit().await?;
     Ok(())
 }
 
 pub async fn login(db_pool: AuthDb, username: &str, password: &str) -> Result<Option<i32>> {
-    let user_id = sqlx::query("SELECT id FROM users WHERE username = ? AND password = ?")
+    let user = sqlx::query_as::<_, (i32, String)>("SELECT id, password_hash FROM users WHERE username = ?")
         .bind(username)
-        .bind(password)
         .fetch_optional(&db_pool.0)
-        .await?
-        .map(|row| row.get::<i32, _>(0) as i32);
+        .await?;
 
-    Ok(user_id)
+    let Some((user_id, hash)) = user else {
+        password::verify_dummy(password.to_string()).await?;
+        return Ok(None);
+    };
+    if password::verify(password.to_string(), hash).await? {
+        Ok(Some(user_id))
+    } else {
+        Ok(None)
+    }
 }
 
-pub async fn add_token(db_pool: AuthDb, user_id: i32) -> Result<String> {
+pub async fn add_token(db_pool: AuthDb, user_id: i32, lifetime: Duration) -> Result<String> {
     let new_token = uuid::Uuid::new_v4().to_string();
 
-    sqlx::query("INSERT INTO tokens (user_id, token) VALUES (?, ?)")
+    sqlx::query("INSERT INTO tokens (user_id, token, expires_at) VALUES (?, ?, datetime('now', ? || ' seconds'))")
         .bind(user_id)
         .bind(&new_token)
+        .bind(lifetime.as_secs() as i64)
         .execute(&db_pool.0)
         .await?;
 
     Ok(new_token)
 }
 
-pub async fn get_user_id_from_token(db_pool: AuthDb, token: &str) -> Result<Option<i32>> {
-    let user_id = sqlx::query("SELECT user_id FROM tokens WHERE token = ?")
+/// Looks up the owner of an unexpired token and records that it was used.
+pub async fn get_user_from_token(db_pool: AuthDb, token: &str) -> Result<Option<ValidUser>> {
+    // SQLite only commits an UPDATE ... RETURNING once the statement is
+    // finished, which `fetch_optional` doesn't wait for. The explicit
+    // transaction makes sure it's done before the next query.
+    let mut tx = db_pool.0.begin().await?;
+    let user = sqlx::query(
+        "UPDATE tokens SET last_used_at = CURRENT_TIMESTAMP
+         WHERE token = ? AND expires_at > CURRENT_TIMESTAMP
+         RETURNING user_id, (SELECT is_admin FROM users WHERE users.id = tokens.user_id)",
+    )
+    .bind(token)
+    .fetch_optional(&mut *tx)
+    .await?
+    .map(|row| ValidUser {
+        id: row.get(0),
+        is_admin: row.get::<Option<bool>, _>(1).unwrap_or(false),
+    });
+    tx.commit().await?;
+
+    Ok(user)
+}
+
+/// A token as shown to its owner. The token value itself is never listed.
+#[derive(Serialize, Debug, FromRow)]
+pub struct TokenInfo {
+    id: i32,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+pub async fn list_tokens(db_pool: AuthDb, user_id: i32) -> Result<Vec<TokenInfo>> {
+    let tokens = sqlx::query_as::<_, TokenInfo>(
+        "SELECT id, created_at, expires_at, last_used_at FROM tokens
+         WHERE user_id = ? AND expires_at > CURRENT_TIMESTAMP
+         ORDER BY created_at DESC",
+    )
+    .bind(user_id)
+    .fetch_all(&db_pool.0)
+    .await?;
+
+    Ok(tokens)
+}
+
+/// Deletes the token with the given value. Returns false if it didn't exist.
+pub async fn delete_token(db_pool: AuthDb, token: &str) -> Result<bool> {
+    let result = sqlx::query("DELETE FROM tokens WHERE token = ?")
         .bind(token)
-        .fetch_optional(&db_pool.0)
-        .await?
-        .map(|row| row.get::<i32, _>(0) as i32);
+        .execute(&db_pool.0)
+        .await?;
 
-    Ok(user_id)
+    Ok(result.rows_affected() > 0)
 }
 
-#[derive(Deserialize, Serialize, Debug, FromRow)]
+/// Deletes one of a user's tokens by id. Returns false if the user has no
+/// token with that id, so nobody can revoke someone else's session.
+pub async fn revoke_token(db_pool: AuthDb, user_id: i32, token_id: i32) -> Result<bool> {
+    let result = sqlx::query("DELETE FROM tokens WHERE id = ? AND user_id = ?")
+        .bind(token_id)
+        .bind(user_id)
+        .execute(&db_pool.0)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn purge_expired_tokens(db_pool: AuthDb) -> Result<u64> {
+    let result = sqlx::query("DELETE FROM tokens WHERE expires_at <= CURRENT_TIMESTAMP")
+        .execute(&db_pool.0)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// A user as returned by the API. The password hash never leaves the service.
+#[derive(Serialize, Debug, FromRow)]
 pub struct User {
-    id: i32,
+    pub id: i32,
+    username: String,
+    is_admin: bool,
+}
+
+/// Body of a request to create a user.
+#[derive(Deserialize, Debug, Validate)]
+pub struct NewUser {
+    #[validate(length(min = 1, max = 64, message = "must be 1 to 64 characters"), custom(function = "non_blank"))]
     username: String,
+    #[validate(length(min = 8, max = 128, message = "must be 8 to 128 characters"))]
     password: String,
 }
 
+/// Body of `PUT /users/:id`, which sets both the name and the password.
+#[derive(Deserialize, Debug, Validate)]
+pub struct ReplaceUser {
+    #[validate(length(min = 1, max = 64, message = "must be 1 to 64 characters"), custom(function = "non_blank"))]
+    username: String,
+    #[validate(length(min = 8, max = 128, message = "must be 8 to 128 characters"))]
+    password: String,
+}
+
+/// Body of a request to update a user. Fields that are left out keep their
+/// current value.
+#[derive(Deserialize, Debug, Validate)]
+pub struct UserUpdate {
+    #[validate(length(min = 1, max = 64, message = "must be 1 to 64 characters"), custom(function = "non_blank"))]
+    username: Option<String>,
+    #[validate(length(min = 8, max = 128, message = "must be 8 to 128 characters"))]
+    password: Option<String>,
+}
+
+/// What to write to a user, with the password already hashed. Hashing is
+/// slow, so it's done before the write transaction takes its lock.
+pub struct UserFields {
+    username: Option<String>,
+    password_hash: Option<String>,
+}
+
+impl NewUser {
+    pub async fn hash_password(&self) -> Result<UserFields> {
+        Ok(UserFields {
+            username: Some(self.username.clone()),
+            password_hash: Some(password::hash(self.password.clone()).await?),
+        })
+    }
+}
+
+impl UserUpdate {
+    pub async fn hash_password(&self) -> Result<UserFields> {
+        let password_hash = match &self.password {
+            Some(new_password) => Some(password::hash(new_password.clone()).await?),
+            None => None,
+        };
+        Ok(UserFields {
+            username: self.username.clone(),
+            password_hash,
+        })
+    }
+}
+
+impl From<ReplaceUser> for UserUpdate {
+    fn from(user: ReplaceUser) -> Self {
+        Self {
+            username: Some(user.username),
+            password: Some(user.password),
+        }
+    }
+}
+
 pub async fn get_all_users(db_pool: AuthDb) -> Result<Vec<User>> {
-    let users = sqlx::query_as::<_, User>("SELECT id, username, password FROM users")
+    let users = sqlx::query_as::<_, User>("SELECT id, username, is_admin FROM users")
         .fetch_all(&db_pool.0)
         .await?;
 
@@ -71,40 +251,48 @@ pub async fn get_all_users(db_pool: AuthDb) -> Result<Vec<User>> {
 }
 
 pub async fn get_user(db_pool: AuthDb, user_id: i32) -> Result<Option<User>> {
-    let user = sqlx::query_as::<_, User>("SELECT id, username, password FROM users WHERE id = ?")
+    let mut conn = db_pool.0.acquire().await?;
+    read_user(&mut conn, user_id).await
+}
+
+/// Like `get_user`, on a connection that may be inside a transaction.
+pub async fn read_user(conn: &mut SqliteConnection, user_id: i32) -> Result<Option<User>> {
+    let user = sqlx::query_as::<_, User>("SELECT id, username, is_admin FROM users WHERE id = ?")
         .bind(user_id)
-        .fetch_optional(&db_pool.0)
+        .fetch_optional(conn)
         .await?;
 
     Ok(user)
 }
 
-pub async fn delete_user(db_pool: AuthDb, user_id: i32) -> Result<()> {
-    sqlx::query("DELETE FROM users WHERE id = ?")
+/// Returns false if there's no user with that id.
+pub async fn delete_user(tx: &mut Transaction<'_, Sqlite>, user_id: i32) -> Result<bool> {
+    let result = sqlx::query("DELETE FROM users WHERE id = ?")
         .bind(user_id)
-        .execute(&db_pool.0)
+        .execute(&mut **tx)
         .await?;
 
-    Ok(())
+    Ok(result.rows_affected() > 0)
 }
 
-pub async fn update_user(db_pool: AuthDb, user_id: i32, user: &User) -> Result<()> {
-    sqlx::query("UPDATE users SET username = ?, password = ? WHERE id = ?")
+/// Returns false if there's no user with that id.
+pub async fn update_user(tx: &mut Transaction<'_, Sqlite>, user_id: i32, user: &UserFields) -> Result<bool> {
+    let result = sqlx::query("UPDATE users SET username = COALESCE(?, username), password_hash = COALESCE(?, password_hash) WHERE id = ?")
         .bind(&user.username)
-        .bind(&user.password)
+        .bind(&user.password_hash)
         .bind(user_id)
-        .execute(&db_pool.0)
+        .execute(&mut **tx)
         .await?;
 
-    Ok(())
+    Ok(result.rows_affected() > 0)
 }
 
-pub async fn add_user(db_pool: AuthDb, user: &User) -> Result<()> {
-    sqlx::query("INSERT INTO users (username, password) VALUES (?, ?)")
+pub async fn add_user(tx: &mut Transaction<'_, Sqlite>, user: &UserFields) -> Result<i32> {
+    let result = sqlx::query("INSERT INTO users (username, password_hash) VALUES (?, ?)")
         .bind(&user.username)
-        .bind(&user.password)
-        .execute(&db_pool.0)
+        .bind(&user.password_hash)
+        .execute(&mut **tx)
         .await?;
 
-    Ok(())
-}
\ No newline at end of file
+    Ok(result.last_insert_rowid() as i32)
+}
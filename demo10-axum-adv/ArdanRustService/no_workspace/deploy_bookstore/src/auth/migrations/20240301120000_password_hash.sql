@@ -0,0 +1,5 @@
+-- Passwords are stored as Argon2id hashes from now on. SQLite can't compute
+-- Argon2, so the rows that still hold plaintext (including the seeded admin
+-- account) are rehashed by `db::rehash_plaintext_passwords` right after this
+-- migration runs.
+ALTER TABLE users RENAME COLUMN password TO password_hash;
@@ -0,0 +1,19 @@
+-- SQLite can't add columns with a non-constant default, so the tokens table is
+-- rebuilt. Tokens issued before this migration get a day to live.
+CREATE TABLE tokens_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    token TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL,
+    last_used_at TIMESTAMP
+);
+
+INSERT INTO tokens_new (id, user_id, token, expires_at)
+    SELECT id, user_id, token, datetime('now', '+1 day') FROM tokens;
+
+DROP TABLE tokens;
+ALTER TABLE tokens_new RENAME TO tokens;
+
+CREATE INDEX idx_tokens_user_id ON tokens (user_id);
+CREATE INDEX idx_tokens_expires_at ON tokens (expires_at);
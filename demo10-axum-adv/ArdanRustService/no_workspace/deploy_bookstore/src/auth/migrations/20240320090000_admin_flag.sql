@@ -0,0 +1,6 @@
+-- Admins may read the audit log. The user created by the initial migration is
+-- the first one; the flag isn't exposed through the API, so further admins
+-- are made in the database. The seeded row is picked by id, since other users
+-- may have been given the name 'admin' before usernames were unique.
+ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE users SET is_admin = TRUE WHERE id = 1;
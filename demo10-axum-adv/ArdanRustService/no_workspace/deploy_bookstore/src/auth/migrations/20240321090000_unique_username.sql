@@ -0,0 +1,7 @@
+-- Nothing stopped two users from sharing a username, which made logins
+-- ambiguous. Any duplicates that already exist keep the oldest account's name;
+-- the others get their id appended so the index can be built.
+UPDATE users SET username = username || '#' || id
+    WHERE id NOT IN (SELECT MIN(id) FROM users GROUP BY username);
+
+CREATE UNIQUE INDEX idx_users_username ON users (username);
@@ -1,35 +1,81 @@
 mod configuration;
 mod db;
+mod password;
 mod web_service;
 pub mod auth_layers;
+pub mod client;
+use std::time::Duration;
 use anyhow::Result;
-use axum::{middleware, routing::{get, post}, Extension, Router};
+use axum::{extract::FromRef, middleware, routing::{delete, get, post}, Router};
 use tower_http::cors::CorsLayer;
+use crate::audit::AuditConfiguration;
+use crate::legacy;
+pub use configuration::AuthConfiguration;
 
-pub async fn setup_service() -> Result<Router> {
-    let config = configuration::AuthConfiguration::load()?;
-    let db_pool = db::get_connection_pool(&config.db_filename).await?;
+/// Handed to every handler through axum's `State`. Handlers pick out the part
+/// they need, e.g. `State<AuthDb>`.
+#[derive(Clone, FromRef)]
+pub struct AuthState {
+    pub db: db::AuthDb,
+    pub config: AuthConfiguration,
+}
+
+/// The audit database must already be migrated, see `audit::open`.
+pub async fn setup_service(config: &AuthConfiguration, audit: &AuditConfiguration) -> Result<Router> {
+    let db_pool = db::get_connection_pool(&config.db_filename, audit).await?;
 
     db::perform_migrations(db_pool.clone()).await?;
+    tokio::spawn(purge_expired_tokens(db_pool.clone(), config.token_purge_interval));
+    let state = AuthState {
+        db: db_pool,
+        config: config.clone(),
+    };
 
     let secure_router = Router::new()
         .layer(CorsLayer::very_permissive())
-        .route("/users", get(web_service::list_users))
-        .route("/users/:id", get(web_service::get_user))
-        .route("/users/delete/:id", get(web_service::delete_user))
-        .route("/users/add", post(web_service::add_user))
-        .route("/users/update/:id", post(web_service::update_user))
-        .layer(Extension(config.clone()))
-        .layer(Extension(db_pool.clone()))
-        .route_layer(middleware::from_fn(auth_layers::require_token));
+        .route("/logout", post(web_service::do_logout))
+        .route("/tokens", get(web_service::list_tokens))
+        .route("/tokens/:id", delete(web_service::revoke_token))
+        .route("/users", get(web_service::list_users).post(web_service::create_user))
+        .route(
+            "/users/:id",
+            get(web_service::get_user)
+                .put(web_service::replace_user)
+                .patch(web_service::patch_user)
+                .delete(web_service::delete_user),
+        )
+        .merge(legacy_routes())
+        .route_layer(middleware::from_fn_with_state(state.clone(), auth_layers::require_token));
 
     let router = Router::new()
         .layer(CorsLayer::very_permissive())
         .route("/login", post(web_service::do_login))
-        .route("/is_token_valid/:token", get(web_service::is_token_valid))
-        .nest("/", secure_router)
-        .layer(Extension(config))
-        .layer(Extension(db_pool));
+        .route("/validate", post(web_service::validate_token))
+        .merge(secure_router)
+        .with_state(state);
 
     Ok(router)
 }
+
+/// The pre-REST user routes used by the static admin pages
+fn legacy_routes() -> Router<AuthState> {
+    Router::new()
+        .route("/users/delete/:id", get(web_service::delete_user))
+        .route("/users/add", post(web_service::add_user))
+        .route("/users/update/:id", post(web_service::update_user))
+        .route_layer(middleware::from_fn(legacy::deprecated))
+}
+
+/// Expired tokens are already rejected at lookup, this only keeps the table
+/// from growing forever.
+async fn purge_expired_tokens(db_pool: db::AuthDb, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+    loop {
+        interval.tick().await;
+        match db::purge_expired_tokens(db_pool.clone()).await {
+            Ok(0) => {}
+            Ok(purged) => tracing::info!("Purged {purged} expired tokens"),
+            Err(e) => tracing::warn!("Unable to purge expired tokens: {e}"),
+        }
+    }
+}
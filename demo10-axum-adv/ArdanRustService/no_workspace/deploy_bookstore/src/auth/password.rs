@@ -0,0 +1,63 @@
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, Result};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+
+/// Prefix of every hash produced by `hash`. Anything else in the
+/// `password_hash` column is a legacy plaintext password.
+pub const HASH_PREFIX: &str = "$argon2id$";
+
+/// Hashes a password with Argon2id and a fresh random salt. The result is a
+/// PHC string that carries its own salt and parameters, so it can be verified
+/// even if the defaults change later.
+///
+/// Argon2 is deliberately slow, so the work runs on the blocking thread pool.
+pub async fn hash(password: String) -> Result<String> {
+    tokio::task::spawn_blocking(move || hash_blocking(password.as_bytes())).await?
+}
+
+fn hash_blocking(password: &[u8]) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password, &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| anyhow!("unable to hash password: {e}"))
+}
+
+/// Checks a password against a stored hash. A malformed hash never matches.
+pub async fn verify(password: String, hash: String) -> Result<bool> {
+    let matches = tokio::task::spawn_blocking(move || {
+        PasswordHash::new(&hash)
+            .map(|parsed| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &parsed)
+                    .is_ok()
+            })
+            .unwrap_or(false)
+    })
+    .await?;
+    Ok(matches)
+}
+
+/// Runs a full verification against a throwaway hash and discards the
+/// result. Logins for unknown usernames call this so they take as long as a
+/// wrong password, instead of revealing which usernames exist by answering
+/// early.
+pub async fn verify_dummy(password: String) -> Result<()> {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+    let hash = tokio::task::spawn_blocking(|| {
+        DUMMY_HASH
+            .get_or_init(|| {
+                let secret = uuid::Uuid::new_v4();
+                hash_blocking(secret.as_bytes()).expect("hashing with default parameters cannot fail")
+            })
+            .clone()
+    })
+    .await?;
+    verify(password, hash).await?;
+    Ok(())
+}
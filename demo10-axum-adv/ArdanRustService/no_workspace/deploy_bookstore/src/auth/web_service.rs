@@ -1,7 +1,14 @@
-use axum::{http::StatusCode, Extension, Json};
+use axum::{extract::State, http::{header, StatusCode}, response::IntoResponse, Extension};
 use serde::{Deserialize, Serialize};
-use crate::auth::db::get_user_id_from_token;
-use super::{auth_layers::ValidUser, db::{self, User}};
+use crate::audit::{self, Entity};
+use crate::auth::db::get_user_from_token;
+use crate::error::{ApiError, Json, Path};
+use crate::validation::ValidJson;
+use super::{
+    auth_layers::{SessionToken, ValidUser},
+    configuration::AuthConfiguration,
+    db::{self, NewUser, ReplaceUser, TokenInfo, User, UserUpdate},
+};
 
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -10,106 +17,233 @@ pub struct LoginRequest {
     password: String,
 }
 
+/// Kept as an enum so the body stays `{"Success": {"token": ...}}` for
+/// existing clients. A failed login is a 401 problem response.
 #[derive(Deserialize, Serialize, Debug)]
 pub enum LoginResponse {
     Success { token: String },
-    Failure { reason: String },
 }
 
 pub async fn do_login(
-    Extension(db_pool): Extension<db::AuthDb>,
-    login_request: Json<LoginRequest>,
-) -> Result<Json<LoginResponse>, StatusCode> {
-    match db::login(
+    State(db_pool): State<db::AuthDb>,
+    State(config): State<AuthConfiguration>,
+    Json(login_request): Json<LoginRequest>,
+) -> Result<Json<LoginResponse>, ApiError> {
+    let user_id = db::login(
         db_pool.clone(),
         &login_request.username,
         &login_request.password,
     )
-    .await
-    {
-        Ok(Some(user_id)) => {
-            let token = db::add_token(db_pool.clone(), user_id)
-                .await
-                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-            Ok(Json(LoginResponse::Success { token }))
-        }
-        Ok(None) => Ok(Json(LoginResponse::Failure {
-            reason: "Invalid username or password".to_string(),
-        })),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    .await?
+    .ok_or_else(|| ApiError::Unauthorized("Invalid username or password".to_string()))?;
+
+    let token = db::add_token(db_pool, user_id, config.token_lifetime).await?;
+    Ok(Json(LoginResponse::Success { token }))
+}
+
+pub async fn do_logout(
+    State(db_pool): State<db::AuthDb>,
+    Extension(SessionToken(token)): Extension<SessionToken>,
+) -> Result<StatusCode, ApiError> {
+    db::delete_token(db_pool, &token).await?;
+
+    Ok(StatusCode::OK)
+}
+
+pub async fn list_tokens(
+    State(db_pool): State<db::AuthDb>,
+    Extension(valid_user): Extension<ValidUser>,
+) -> Result<Json<Vec<TokenInfo>>, ApiError> {
+    let tokens = db::list_tokens(db_pool, valid_user.id).await?;
+
+    Ok(Json(tokens))
+}
+
+pub async fn revoke_token(
+    State(db_pool): State<db::AuthDb>,
+    Extension(valid_user): Extension<ValidUser>,
+    Path(token_id): Path<i32>,
+) -> Result<StatusCode, ApiError> {
+    if db::revoke_token(db_pool, valid_user.id, token_id).await? {
+        Ok(StatusCode::OK)
+    } else {
+        Err(ApiError::not_found("token"))
     }
 }
 
 pub async fn list_users(
-    Extension(db_pool): Extension<db::AuthDb>,
+    State(db_pool): State<db::AuthDb>,
     Extension(_valid_user): Extension<ValidUser>,
-) -> Result<Json<Vec<User>>, StatusCode> {
-    let users = db::get_all_users(db_pool)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+) -> Result<Json<Vec<User>>, ApiError> {
+    let users = db::get_all_users(db_pool).await?;
 
     Ok(Json(users))
 }
 
 pub async fn get_user(
-    Extension(db_pool): Extension<db::AuthDb>,
+    State(db_pool): State<db::AuthDb>,
     Extension(_valid_user): Extension<ValidUser>,
-    path: axum::extract::Path<i32>,
-) -> Result<Json<Option<User>>, StatusCode> {
-    let user = db::get_user(db_pool, path.0)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    Ok(Json(user))
+    Path(user_id): Path<i32>,
+) -> Result<Json<User>, ApiError> {
+    db::get_user(db_pool, user_id)
+        .await?
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found("user"))
 }
 
 pub async fn delete_user(
-    Extension(db_pool): Extension<db::AuthDb>,
-    Extension(_valid_user): Extension<ValidUser>,
-    path: axum::extract::Path<i32>,
-) -> Result<StatusCode, StatusCode> {
-    db::delete_user(db_pool, path.0)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    State(db_pool): State<db::AuthDb>,
+    Extension(valid_user): Extension<ValidUser>,
+    Path(user_id): Path<i32>,
+) -> Result<StatusCode, ApiError> {
+    valid_user.require_admin("delete users")?;
+    let mut tx = db_pool.begin().await?;
+    let before = db::read_user(&mut tx, user_id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("user"))?;
+    if !db::delete_user(&mut tx, user_id).await? {
+        return Err(ApiError::not_found("user"));
+    }
+    audit::record(&mut tx, valid_user, Entity::User, user_id, Some(&before), None).await?;
+    tx.commit().await?;
 
     Ok(StatusCode::OK)
 }
 
+/// Usernames are the only unique value a user has, so say so rather than
+/// giving the generic conflict message.
+fn write_error(e: anyhow::Error) -> ApiError {
+    match ApiError::from(e) {
+        ApiError::Conflict(_) => ApiError::Conflict("a user with this username already exists".to_string()),
+        e => e,
+    }
+}
+
+/// Adds a user and records it in the audit log. Only admins may do this.
+async fn insert_user(
+    db_pool: db::AuthDb,
+    valid_user: ValidUser,
+    new_user: &NewUser,
+) -> Result<User, ApiError> {
+    valid_user.require_admin("create users")?;
+    let fields = new_user.hash_password().await?;
+    let mut tx = db_pool.begin().await?;
+    let user_id = db::add_user(&mut tx, &fields).await
+        .map_err(write_error)?;
+    let user = db::read_user(&mut tx, user_id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("user"))?;
+    audit::record(&mut tx, valid_user, Entity::User, user_id, None, Some(&user)).await?;
+    tx.commit().await?;
+
+    Ok(user)
+}
+
+/// `POST /users`. Answers 201 with the new user and its URL.
+pub async fn create_user(
+    State(db_pool): State<db::AuthDb>,
+    Extension(valid_user): Extension<ValidUser>,
+    ValidJson(new_user): ValidJson<NewUser>,
+) -> Result<impl IntoResponse, ApiError> {
+    let user = insert_user(db_pool, valid_user, &new_user).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, format!("/api/v1/auth/users/{}", user.id))],
+        Json(user),
+    ))
+}
+
+/// Shared by every route that updates a user, answers with the updated user.
+/// Users may update themselves, anyone else needs an admin.
+/// Password changes show up in the audit log as an update with no visible
+/// difference, the hash is never logged.
+async fn write_user(
+    db_pool: db::AuthDb,
+    valid_user: ValidUser,
+    user_id: i32,
+    update: &UserUpdate,
+) -> Result<Json<User>, ApiError> {
+    valid_user.require_self_or_admin(user_id)?;
+    let fields = update.hash_password().await?;
+    let mut tx = db_pool.begin().await?;
+    let before = db::read_user(&mut tx, user_id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("user"))?;
+    if !db::update_user(&mut tx, user_id, &fields).await.map_err(write_error)? {
+        return Err(ApiError::not_found("user"));
+    }
+    let after = db::read_user(&mut tx, user_id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("user"))?;
+    audit::record(&mut tx, valid_user, Entity::User, user_id, Some(&before), Some(&after)).await?;
+    tx.commit().await?;
+
+    Ok(Json(after))
+}
+
+pub async fn replace_user(
+    State(db_pool): State<db::AuthDb>,
+    Extension(valid_user): Extension<ValidUser>,
+    Path(user_id): Path<i32>,
+    ValidJson(user): ValidJson<ReplaceUser>,
+) -> Result<Json<User>, ApiError> {
+    write_user(db_pool, valid_user, user_id, &user.into()).await
+}
+
+pub async fn patch_user(
+    State(db_pool): State<db::AuthDb>,
+    Extension(valid_user): Extension<ValidUser>,
+    Path(user_id): Path<i32>,
+    ValidJson(update): ValidJson<UserUpdate>,
+) -> Result<Json<User>, ApiError> {
+    write_user(db_pool, valid_user, user_id, &update).await
+}
+
+/// Deprecated `POST /users/update/:id`, use `PATCH /users/:id`
 pub async fn update_user(
-    Extension(db_pool): Extension<db::AuthDb>,
-    Extension(_valid_user): Extension<ValidUser>,
-    path: axum::extract::Path<i32>,
-    update: Json<User>,
-) -> Result<StatusCode, StatusCode> {
-    db::update_user(db_pool, path.0, &update)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    State(db_pool): State<db::AuthDb>,
+    Extension(valid_user): Extension<ValidUser>,
+    Path(user_id): Path<i32>,
+    ValidJson(update): ValidJson<UserUpdate>,
+) -> Result<StatusCode, ApiError> {
+    write_user(db_pool, valid_user, user_id, &update).await?;
 
     Ok(StatusCode::OK)
 }
 
+/// Deprecated `POST /users/add`, use `POST /users`
 pub async fn add_user(
-    Extension(db_pool): Extension<db::AuthDb>,
-    Extension(_valid_user): Extension<ValidUser>,
-    new_user: Json<User>,
-) -> Result<StatusCode, StatusCode> {
-    db::add_user(db_pool, &new_user)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    State(db_pool): State<db::AuthDb>,
+    Extension(valid_user): Extension<ValidUser>,
+    ValidJson(new_user): ValidJson<NewUser>,
+) -> Result<StatusCode, ApiError> {
+    insert_user(db_pool, valid_user, &new_user).await?;
 
     Ok(StatusCode::OK)
 }
 
-pub async fn is_token_valid(
-    Extension(db_pool): Extension<db::AuthDb>,
-    token: axum::extract::Path<String>,
-) -> Result<StatusCode, StatusCode> {
-    if let Ok(Some(_user_id)) = get_user_id_from_token(
-        db_pool.clone(),
-        &token
-    ).await {
-        Ok(StatusCode::OK)
-    } else {
-        Ok(StatusCode::UNAUTHORIZED)
-    }
-}
\ No newline at end of file
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ValidateTokenRequest {
+    pub token: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ValidateTokenResponse {
+    pub user_id: i32,
+    /// Missing from auth services that predate admins, which means not an admin
+    #[serde(default)]
+    pub is_admin: bool,
+}
+
+/// Used by other services to check a token. The token travels in the body
+/// rather than the URL so it doesn't end up in access logs.
+pub async fn validate_token(
+    State(db_pool): State<db::AuthDb>,
+    Json(request): Json<ValidateTokenRequest>,
+) -> Result<Json<ValidateTokenResponse>, ApiError> {
+    get_user_from_token(db_pool, &request.token)
+        .await?
+        .map(|user| Json(ValidateTokenResponse { user_id: user.id, is_admin: user.is_admin }))
+        .ok_or_else(|| ApiError::Unauthorized("invalid token".to_string()))
+}
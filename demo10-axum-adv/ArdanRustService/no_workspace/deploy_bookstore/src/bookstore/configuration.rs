@@ -1,27 +1,48 @@
-use anyhow::Result;
-use config::Config;
+use std::{path::PathBuf, time::Duration};
+use anyhow::{ensure, Context, Result};
 use serde::{Deserialize, Serialize};
+use crate::auth::client::{self, AuthClient};
 
+/// The `bookstore` section of the service configuration.
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct BookstoreConfiguration {
-    pub db_filename: String,
+    pub db_filename: PathBuf,
+    /// Where the auth router is mounted. Defaults to this service's own
+    /// `/api/v1/auth` on the listen port.
+    pub auth_url: Option<String>,
+    /// Time limit for a token check against the auth service
+    #[serde(with = "humantime_serde")]
+    pub auth_timeout: Duration,
+    /// How long a successful token check is trusted before asking again
+    #[serde(with = "humantime_serde")]
+    pub auth_cache_ttl: Duration,
 }
 
-impl BookstoreConfiguration {
-    pub fn load() -> Result<Self> {
-        // Load any .env files
-        // Ignore the result of loading .env --- it's ok if it doesn't exist
-        let _ = dotenvy::dotenv();
-
-        let settings_reader = Config::builder()
-            .add_source(config::File::with_name("settings").required(false))
-            .add_source(config::Environment::with_prefix("BOOKSTORE"))
-            .build()?;
-
-        let settings = settings_reader
-            .try_deserialize()?;
+impl Default for BookstoreConfiguration {
+    fn default() -> Self {
+        Self {
+            db_filename: PathBuf::from("bookstore.db"),
+            auth_url: None,
+            auth_timeout: client::DEFAULT_TIMEOUT,
+            auth_cache_ttl: client::DEFAULT_CACHE_TTL,
+        }
+    }
+}
 
-        Ok(settings)
+impl BookstoreConfiguration {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(auth_url) = &self.auth_url {
+            reqwest::Url::parse(auth_url)
+                .with_context(|| format!("bookstore.auth_url {auth_url:?} is not a valid URL"))?;
+        }
+        ensure!(!self.auth_timeout.is_zero(), "bookstore.auth_timeout must be greater than zero");
+        Ok(())
+    }
 
+    /// The client the bookstore, and the audit log, check tokens with
+    pub fn auth_client(&self) -> Result<AuthClient> {
+        let auth_url = self.auth_url.as_deref().context("bookstore.auth_url is not set")?;
+        AuthClient::with_settings(auth_url, self.auth_timeout, self.auth_cache_ttl)
     }
-}
\ No newline at end of file
+}
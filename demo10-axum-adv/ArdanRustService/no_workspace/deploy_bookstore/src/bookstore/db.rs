@@ -1,16 +1,30 @@
+use std::path::Path;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use sqlx::{prelude::FromRow, sqlite::SqliteConnectOptions};
+use sqlx::{prelude::FromRow, sqlite::{SqliteConnectOptions, SqlitePoolOptions}, QueryBuilder, Sqlite, SqliteConnection, Transaction};
+use validator::Validate;
+use crate::audit::{self, AuditConfiguration};
+use crate::validation::non_blank;
+use super::isbn::Isbn;
 
 #[derive(Clone)]
 pub struct StoreDb(pub sqlx::SqlitePool);
 
-pub async fn get_connection_pool(filename: &str) -> Result<StoreDb> {
+impl StoreDb {
+    /// Starts the transaction for a change to the catalogue, which records
+    /// itself in the audit log before committing.
+    pub async fn begin(&self) -> Result<Transaction<'static, Sqlite>> {
+        audit::begin(&self.0, "books").await
+    }
+}
+
+pub async fn get_connection_pool(filename: &Path, audit: &AuditConfiguration) -> Result<StoreDb> {
     let options = SqliteConnectOptions::new()
         .filename(filename)
         .create_if_missing(true);
 
-    let connection_pool = sqlx::SqlitePool::connect_with(options)
+    let connection_pool = audit::attach(SqlitePoolOptions::new(), audit)
+        .connect_with(options)
         .await?;
     Ok(StoreDb(connection_pool))
 }
@@ -29,44 +43,355 @@ pub struct Book {
     pub author: String,
 }
 
-pub async fn list_books(db_pool: StoreDb) -> Result<Vec<Book>> {
-    let books = sqlx::query_as::<_, Book>("SELECT * FROM books")
-        .fetch_all(&db_pool.0)
-        .await?;
-    Ok(books)
+pub const MAX_PAGE_SIZE: u32 = 200;
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SortField {
+    #[default]
+    Id,
+    Title,
+    Author,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Query string of `GET /books/`, e.g. `?title=rust&sort=author&order=desc&limit=10&offset=20`.
+/// `title` and `author` match anywhere in the field, ignoring case.
+/// Without `limit` every matching book is returned, as before paging existed,
+/// so the static pages and legacy clients still see the whole catalogue.
+#[derive(Deserialize, Debug, Default)]
+pub struct BookQuery {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    #[serde(default)]
+    pub sort: SortField,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub limit: Option<u32>,
+    #[serde(default)]
+    pub offset: u32,
+}
+
+/// One page of books, plus how many books match the filter in total.
+pub struct BookPage {
+    pub books: Vec<Book>,
+    pub total: i64,
+}
+
+/// Appends the search filter of `query` as a WHERE clause.
+///
+/// Terms of three or more characters go through the trigram index. Shorter
+/// ones can't be matched by trigrams, so they fall back to a LIKE scan.
+fn push_filter(builder: &mut QueryBuilder<'_, Sqlite>, query: &BookQuery) {
+    builder.push(" WHERE 1 = 1");
+    let terms = [("title", &query.title), ("author", &query.author)];
+    for (column, term) in terms {
+        let Some(term) = term.as_deref().filter(|t| !t.is_empty()) else {
+            continue;
+        };
+        if term.chars().count() >= 3 {
+            builder
+                .push(" AND books.id IN (SELECT rowid FROM books_fts WHERE books_fts MATCH ")
+                .push_bind(format!("{column} : \"{}\"", term.replace('"', "\"\"")))
+                .push(")");
+        } else {
+            let pattern = term.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+            builder
+                .push(format!(" AND books.{column} LIKE '%' || "))
+                .push_bind(pattern)
+                .push(" || '%' ESCAPE '\\'");
+        }
+    }
+}
+
+pub async fn list_books(db_pool: StoreDb, query: &BookQuery) -> Result<BookPage> {
+    let mut count = QueryBuilder::new("SELECT COUNT(*) FROM books");
+    push_filter(&mut count, query);
+    let total: i64 = count.build_query_scalar().fetch_one(&db_pool.0).await?;
+
+    let mut select = QueryBuilder::new("SELECT books.id, books.title, books.author FROM books");
+    push_filter(&mut select, query);
+    // Column and direction come from enums, never from user text
+    let column = match query.sort {
+        SortField::Id => "books.id",
+        SortField::Title => "books.title COLLATE NOCASE",
+        SortField::Author => "books.author COLLATE NOCASE",
+    };
+    let direction = match query.order {
+        SortOrder::Asc => "ASC",
+        SortOrder::Desc => "DESC",
+    };
+    select.push(format!(" ORDER BY {column} {direction}, books.id {direction}"));
+    // SQLite only accepts OFFSET after a LIMIT, and a negative LIMIT means none
+    let limit = query.limit.map_or(-1, |limit| i64::from(limit.min(MAX_PAGE_SIZE)));
+    select
+        .push(" LIMIT ")
+        .push_bind(limit)
+        .push(" OFFSET ")
+        .push_bind(query.offset);
+
+    let books = select.build_query_as::<Book>().fetch_all(&db_pool.0).await?;
+    Ok(BookPage { books, total })
 }
 
-pub async fn get_book(db_pool: StoreDb, id: i32) -> Result<Book> {
-    let book = sqlx::query_as::<_, Book>("SELECT * FROM books WHERE id = ?")
+pub async fn get_book(db_pool: StoreDb, id: i32) -> Result<Option<Book>> {
+    let book = sqlx::query_as::<_, Book>("SELECT id, title, author FROM books WHERE id = ?")
         .bind(id)
-        .fetch_one(&db_pool.0)
+        .fetch_optional(&db_pool.0)
         .await?;
     Ok(book)
 }
 
-pub async fn delete_book(db_pool: StoreDb, id: i32) -> Result<()> {
-    sqlx::query("DELETE FROM books WHERE id = ?")
+/// Returns false if there's no book with that id.
+pub async fn delete_book(tx: &mut Transaction<'_, Sqlite>, id: i32) -> Result<bool> {
+    let result = sqlx::query("DELETE FROM books WHERE id = ?")
         .bind(id)
-        .execute(&db_pool.0)
+        .execute(&mut **tx)
         .await?;
-    Ok(())
+    Ok(result.rows_affected() > 0)
+}
+
+/// An author as the catalogue stores it.
+#[derive(Serialize, Debug, FromRow)]
+pub struct Author {
+    pub id: i32,
+    pub last_name: String,
+    pub first_name: String,
+}
+
+/// An author as a client names it. Authors are matched by exact name, and
+/// created the first time they're used.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Validate)]
+pub struct AuthorName {
+    #[validate(length(min = 1, max = 100, message = "must be 1 to 100 characters"), custom(function = "non_blank"))]
+    pub last_name: String,
+    #[serde(default)]
+    #[validate(length(max = 100, message = "must be at most 100 characters"))]
+    pub first_name: String,
 }
 
-pub async fn add_book(db_pool: StoreDb, title: String, author: String) -> Result<()> {
-    sqlx::query("INSERT INTO books (title, author) VALUES (?, ?)")
-        .bind(title)
-        .bind(author)
-        .execute(&db_pool.0)
+impl AuthorName {
+    /// Parses the legacy free-text form, "Last, First" or just "Name".
+    pub fn parse(text: &str) -> Option<Self> {
+        let (last_name, first_name) = text.split_once(',').unwrap_or((text, ""));
+        let last_name = last_name.trim();
+        (!last_name.is_empty()).then(|| Self {
+            last_name: last_name.to_string(),
+            first_name: first_name.trim().to_string(),
+        })
+    }
+
+    /// The legacy free-text form, as stored in `books.author`
+    pub fn display(&self) -> String {
+        if self.first_name.is_empty() {
+            self.last_name.clone()
+        } else {
+            format!("{}, {}", self.last_name, self.first_name)
+        }
+    }
+}
+
+/// Everything the catalogue knows about a book.
+#[derive(Serialize, Debug, FromRow)]
+pub struct BookDetails {
+    pub id: i32,
+    pub title: String,
+    pub isbn: Option<String>,
+    pub publication_year: Option<i32>,
+    pub price_cents: Option<i64>,
+    pub stock: i64,
+    #[sqlx(skip)]
+    pub authors: Vec<Author>,
+}
+
+/// What to write to a book. In an update, `None` leaves the field alone
+/// unless the mode is `UpdateMode::Replace`.
+#[derive(Debug, Default)]
+pub struct BookFields {
+    pub title: Option<String>,
+    pub authors: Option<Vec<AuthorName>>,
+    pub isbn: Option<Isbn>,
+    pub publication_year: Option<i32>,
+    pub price_cents: Option<i64>,
+    pub stock: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpdateMode {
+    /// Only the fields that are given change
+    Merge,
+    /// ISBN, publication year and price that aren't given are cleared. Stock
+    /// is still only changed if given, it belongs to the inventory endpoint.
+    Replace,
+}
+
+/// Replaces the authors of a book, creating any that don't exist yet, and
+/// refreshes the display string in `books.author`.
+async fn set_authors(tx: &mut Transaction<'_, Sqlite>, book_id: i32, authors: &[AuthorName]) -> Result<()> {
+    sqlx::query("DELETE FROM book_authors WHERE book_id = ?")
+        .bind(book_id)
+        .execute(&mut **tx)
+        .await?;
+
+    for (position, author) in authors.iter().enumerate() {
+        // The no-op update makes RETURNING produce the id of an existing author too
+        let author_id: i32 = sqlx::query_scalar(
+            "INSERT INTO authors (last_name, first_name) VALUES (?, ?)
+             ON CONFLICT (last_name, first_name) DO UPDATE SET last_name = excluded.last_name
+             RETURNING id",
+        )
+        .bind(&author.last_name)
+        .bind(&author.first_name)
+        .fetch_one(&mut **tx)
+        .await?;
+
+        sqlx::query("INSERT OR IGNORE INTO book_authors (book_id, author_id, position) VALUES (?, ?, ?)")
+            .bind(book_id)
+            .bind(author_id)
+            .bind(position as i32)
+            .execute(&mut **tx)
+            .await?;
+    }
+
+    let display = authors.iter().map(AuthorName::display).collect::<Vec<_>>().join("; ");
+    sqlx::query("UPDATE books SET author = ? WHERE id = ?")
+        .bind(display)
+        .bind(book_id)
+        .execute(&mut **tx)
         .await?;
     Ok(())
 }
 
-pub async fn update_book(db_pool: StoreDb, id: i32, title: String, author: String) -> Result<()> {
-    sqlx::query("UPDATE books SET title = ?, author = ? WHERE id = ?")
-        .bind(title)
-        .bind(author)
+pub async fn add_book(tx: &mut Transaction<'_, Sqlite>, book: &BookFields) -> Result<i32> {
+    let id: i32 = sqlx::query_scalar(
+        "INSERT INTO books (title, isbn, publication_year, price_cents, stock)
+         VALUES (?, ?, ?, ?, ?)
+         RETURNING id",
+    )
+    .bind(&book.title)
+    .bind(book.isbn.as_ref().map(Isbn::as_str))
+    .bind(book.publication_year)
+    .bind(book.price_cents)
+    .bind(book.stock.unwrap_or(0))
+    .fetch_one(&mut **tx)
+    .await?;
+
+    set_authors(tx, id, book.authors.as_deref().unwrap_or_default()).await?;
+    Ok(id)
+}
+
+/// Returns false if there's no book with that id.
+pub async fn update_book(tx: &mut Transaction<'_, Sqlite>, id: i32, book: &BookFields, mode: UpdateMode) -> Result<bool> {
+    let sql = match mode {
+        UpdateMode::Merge => "UPDATE books SET
+             title = COALESCE(?, title),
+             isbn = COALESCE(?, isbn),
+             publication_year = COALESCE(?, publication_year),
+             price_cents = COALESCE(?, price_cents),
+             stock = COALESCE(?, stock)
+         WHERE id = ?",
+        UpdateMode::Replace => "UPDATE books SET
+             title = COALESCE(?, title),
+             isbn = ?,
+             publication_year = ?,
+             price_cents = ?,
+             stock = COALESCE(?, stock)
+         WHERE id = ?",
+    };
+
+    let result = sqlx::query(sql)
+        .bind(&book.title)
+        .bind(book.isbn.as_ref().map(Isbn::as_str))
+        .bind(book.publication_year)
+        .bind(book.price_cents)
+        .bind(book.stock)
         .bind(id)
-        .execute(&db_pool.0)
+        .execute(&mut **tx)
         .await?;
-    Ok(())
-}
\ No newline at end of file
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    if let Some(authors) = &book.authors {
+        set_authors(tx, id, authors).await?;
+    }
+    Ok(true)
+}
+
+pub async fn get_book_details(db_pool: StoreDb, id: i32) -> Result<Option<BookDetails>> {
+    let mut conn = db_pool.0.acquire().await?;
+    read_book_details(&mut conn, id).await
+}
+
+/// Like `get_book_details`, on a connection that may be inside a transaction.
+pub async fn read_book_details(conn: &mut SqliteConnection, id: i32) -> Result<Option<BookDetails>> {
+    let book = sqlx::query_as::<_, BookDetails>(
+        "SELECT id, title, isbn, publication_year, price_cents, stock FROM books WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let Some(mut book) = book else {
+        return Ok(None);
+    };
+    book.authors = sqlx::query_as::<_, Author>(
+        "SELECT authors.id, authors.last_name, authors.first_name
+         FROM book_authors JOIN authors ON authors.id = book_authors.author_id
+         WHERE book_authors.book_id = ?
+         ORDER BY book_authors.position",
+    )
+    .bind(id)
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(Some(book))
+}
+
+pub async fn list_authors(db_pool: StoreDb) -> Result<Vec<Author>> {
+    let authors = sqlx::query_as::<_, Author>(
+        "SELECT id, last_name, first_name FROM authors ORDER BY last_name, first_name",
+    )
+    .fetch_all(&db_pool.0)
+    .await?;
+    Ok(authors)
+}
+
+pub enum StockAdjustment {
+    Adjusted { stock: i64 },
+    NotFound,
+    /// The adjustment would take the stock below zero, nothing was changed
+    Insufficient { stock: i64 },
+}
+
+/// Adds `delta` (negative to take books out) to a book's stock in a single
+/// statement, so concurrent adjustments can't oversell. Nothing is committed,
+/// the caller commits once the change is audited.
+pub async fn adjust_stock(tx: &mut Transaction<'_, Sqlite>, id: i32, delta: i64) -> Result<StockAdjustment> {
+    let adjusted: Option<i64> = sqlx::query_scalar(
+        "UPDATE books SET stock = stock + ? WHERE id = ? AND stock + ? >= 0 RETURNING stock",
+    )
+    .bind(delta)
+    .bind(id)
+    .bind(delta)
+    .fetch_optional(&mut **tx)
+    .await?;
+    if let Some(stock) = adjusted {
+        return Ok(StockAdjustment::Adjusted { stock });
+    }
+
+    let current: Option<i64> = sqlx::query_scalar("SELECT stock FROM books WHERE id = ?")
+        .bind(id)
+        .fetch_optional(&mut **tx)
+        .await?;
+    Ok(match current {
+        Some(stock) => StockAdjustment::Insufficient { stock },
+        None => StockAdjustment::NotFound,
+    })
+}
@@ -0,0 +1,149 @@
+use std::fmt;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A checksum-validated ISBN, always held in its 13-digit form.
+///
+/// ISBN-10s are accepted on input and converted by prefixing `978` and
+/// recomputing the check digit, so both spellings of the same book compare
+/// equal and the unique index on `books.isbn` catches duplicates.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Isbn(String);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum IsbnError {
+    /// Not 10 or 13 digits (ignoring hyphens and spaces), or stray characters
+    Format,
+    /// The digits are fine but the check digit doesn't match
+    Checksum,
+}
+
+impl fmt::Display for IsbnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IsbnError::Format => write!(f, "an ISBN must have 10 or 13 digits"),
+            IsbnError::Checksum => write!(f, "the ISBN check digit is wrong"),
+        }
+    }
+}
+
+impl std::error::Error for IsbnError {}
+
+impl Isbn {
+    pub fn parse(input: &str) -> Result<Self, IsbnError> {
+        let compact: Vec<char> = input
+            .chars()
+            .filter(|c| *c != '-' && *c != ' ')
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+
+        match compact.len() {
+            10 => {
+                let (body, check) = compact.split_at(9);
+                let body = digits(body)?;
+                let check = match check[0] {
+                    'X' => 10,
+                    c => c.to_digit(10).ok_or(IsbnError::Format)?,
+                };
+                if isbn10_check(&body) != check {
+                    return Err(IsbnError::Checksum);
+                }
+                let mut isbn13 = vec![9, 7, 8];
+                isbn13.extend(body);
+                let check = isbn13_check(&isbn13);
+                isbn13.push(check);
+                Ok(Self(to_string(&isbn13)))
+            }
+            13 => {
+                let isbn13 = digits(&compact)?;
+                if !(isbn13.starts_with(&[9, 7, 8]) || isbn13.starts_with(&[9, 7, 9])) {
+                    return Err(IsbnError::Format);
+                }
+                if isbn13_check(&isbn13[..12]) != isbn13[12] {
+                    return Err(IsbnError::Checksum);
+                }
+                Ok(Self(to_string(&isbn13)))
+            }
+            _ => Err(IsbnError::Format),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+fn digits(chars: &[char]) -> Result<Vec<u32>, IsbnError> {
+    chars
+        .iter()
+        .map(|c| c.to_digit(10).ok_or(IsbnError::Format))
+        .collect()
+}
+
+fn to_string(digits: &[u32]) -> String {
+    digits.iter().map(|d| char::from_digit(*d, 10).unwrap()).collect()
+}
+
+/// Check digit of an ISBN-10 from its first nine digits, 10 meaning `X`
+fn isbn10_check(body: &[u32]) -> u32 {
+    let sum: u32 = body.iter().zip((2..=10).rev()).map(|(d, w)| d * w).sum();
+    (11 - sum % 11) % 11
+}
+
+/// Check digit of an ISBN-13 from its first twelve digits
+fn isbn13_check(body: &[u32]) -> u32 {
+    let sum: u32 = body
+        .iter()
+        .enumerate()
+        .map(|(i, d)| if i % 2 == 0 { *d } else { d * 3 })
+        .sum();
+    (10 - sum % 10) % 10
+}
+
+impl fmt::Display for Isbn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Serialize for Isbn {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Isbn {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let input = String::deserialize(deserializer)?;
+        Isbn::parse(&input).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn isbn13_is_kept() {
+        let isbn = Isbn::parse("978-1-68050-816-1").unwrap();
+        assert_eq!(isbn.as_str(), "9781680508161");
+    }
+
+    #[test]
+    fn isbn10_is_converted() {
+        // The Rust Programming Language, first edition
+        assert_eq!(Isbn::parse("1-59327-828-4").unwrap(), Isbn::parse("9781593278281").unwrap());
+        // Check digit X
+        assert_eq!(Isbn::parse("0-8044-2957-X").unwrap().as_str(), "9780804429573");
+        assert_eq!(Isbn::parse("080442957x").unwrap().as_str(), "9780804429573");
+    }
+
+    #[test]
+    fn bad_input_is_rejected() {
+        assert_eq!(Isbn::parse("978-1-68050-816-2"), Err(IsbnError::Checksum));
+        assert_eq!(Isbn::parse("1-59327-828-5"), Err(IsbnError::Checksum));
+        assert_eq!(Isbn::parse("12345"), Err(IsbnError::Format));
+        assert_eq!(Isbn::parse("97816805081X1"), Err(IsbnError::Format));
+        assert_eq!(Isbn::parse("1234567890123"), Err(IsbnError::Format));
+        assert_eq!(Isbn::parse(""), Err(IsbnError::Format));
+    }
+}
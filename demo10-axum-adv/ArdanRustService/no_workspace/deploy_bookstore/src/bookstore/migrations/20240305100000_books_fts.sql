@@ -0,0 +1,27 @@
+-- Full text index over title and author. The trigram tokenizer lets MATCH find
+-- any substring of three or more characters, case-insensitively. It's an
+-- external content table, so the text itself lives only in `books` and the
+-- triggers below keep the index in step with it.
+CREATE VIRTUAL TABLE books_fts USING fts5(
+    title,
+    author,
+    content = 'books',
+    content_rowid = 'id',
+    tokenize = 'trigram'
+);
+
+CREATE TRIGGER books_fts_insert AFTER INSERT ON books BEGIN
+    INSERT INTO books_fts (rowid, title, author) VALUES (new.id, new.title, new.author);
+END;
+
+CREATE TRIGGER books_fts_delete AFTER DELETE ON books BEGIN
+    INSERT INTO books_fts (books_fts, rowid, title, author) VALUES ('delete', old.id, old.title, old.author);
+END;
+
+CREATE TRIGGER books_fts_update AFTER UPDATE ON books BEGIN
+    INSERT INTO books_fts (books_fts, rowid, title, author) VALUES ('delete', old.id, old.title, old.author);
+    INSERT INTO books_fts (rowid, title, author) VALUES (new.id, new.title, new.author);
+END;
+
+-- Index the books that already exist
+INSERT INTO books_fts (books_fts) VALUES ('rebuild');
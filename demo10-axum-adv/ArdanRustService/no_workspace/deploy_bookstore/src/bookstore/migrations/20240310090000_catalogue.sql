@@ -0,0 +1,42 @@
+-- Authors get their own table, linked to books many-to-many. `books.author`
+-- stays as a display string ("Last, First; Last, First") maintained by the
+-- service, so the legacy JSON shape and the search index keep working.
+CREATE TABLE authors (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    last_name TEXT NOT NULL,
+    first_name TEXT NOT NULL DEFAULT '',
+    UNIQUE (last_name, first_name)
+);
+
+CREATE TABLE book_authors (
+    book_id INTEGER NOT NULL REFERENCES books (id) ON DELETE CASCADE,
+    author_id INTEGER NOT NULL REFERENCES authors (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (book_id, author_id)
+);
+
+CREATE INDEX idx_book_authors_author_id ON book_authors (author_id);
+
+-- ISBNs are stored as 13 digits, validated and normalised by the service
+ALTER TABLE books ADD COLUMN isbn TEXT;
+ALTER TABLE books ADD COLUMN publication_year INTEGER;
+ALTER TABLE books ADD COLUMN price_cents INTEGER CHECK (price_cents >= 0);
+ALTER TABLE books ADD COLUMN stock INTEGER NOT NULL DEFAULT 0 CHECK (stock >= 0);
+
+CREATE UNIQUE INDEX idx_books_isbn ON books (isbn) WHERE isbn IS NOT NULL;
+
+-- Split the existing "Last, First" strings into authors
+INSERT OR IGNORE INTO authors (last_name, first_name)
+    SELECT DISTINCT
+        CASE WHEN instr(author, ',') > 0 THEN trim(substr(author, 1, instr(author, ',') - 1)) ELSE trim(author) END,
+        CASE WHEN instr(author, ',') > 0 THEN trim(substr(author, instr(author, ',') + 1)) ELSE '' END
+    FROM books
+    WHERE trim(coalesce(author, '')) <> '';
+
+INSERT INTO book_authors (book_id, author_id)
+    SELECT books.id, authors.id
+    FROM books
+    JOIN authors ON authors.last_name =
+            CASE WHEN instr(books.author, ',') > 0 THEN trim(substr(books.author, 1, instr(books.author, ',') - 1)) ELSE trim(books.author) END
+        AND authors.first_name =
+            CASE WHEN instr(books.author, ',') > 0 THEN trim(substr(books.author, instr(books.author, ',') + 1)) ELSE '' END;
@@ -0,0 +1,8 @@
+-- Only title and author are indexed, so stock and price changes don't need to
+-- touch the full text index.
+DROP TRIGGER books_fts_update;
+
+CREATE TRIGGER books_fts_update AFTER UPDATE OF title, author ON books BEGIN
+    INSERT INTO books_fts (books_fts, rowid, title, author) VALUES ('delete', old.id, old.title, old.author);
+    INSERT INTO books_fts (rowid, title, author) VALUES (new.id, new.title, new.author);
+END;
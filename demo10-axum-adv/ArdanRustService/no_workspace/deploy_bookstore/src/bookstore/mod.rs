@@ -1,35 +1,65 @@
 mod configuration;
 mod db;
+mod isbn;
+mod requests;
 mod web_service;
 use anyhow::Result;
-use axum::{middleware, routing::{get, post}, Extension, Router};
+use axum::{extract::FromRef, middleware, routing::{delete, get, post}, Router};
 use tower_http::cors::CorsLayer;
+use crate::audit::AuditConfiguration;
 use crate::auth::auth_layers;
-use crate::auth::auth_layers::ListenPort;
+use crate::auth::client::AuthClient;
+use crate::legacy;
+pub use configuration::BookstoreConfiguration;
 
-pub async fn setup_service(listen_port: String) -> Result<Router> {
-    let config = configuration::BookstoreConfiguration::load()?;
-    let db_pool = db::get_connection_pool(&config.db_filename).await?;
+/// Handed to every handler through axum's `State`
+#[derive(Clone, FromRef)]
+pub struct BookstoreState {
+    pub db: db::StoreDb,
+    pub config: BookstoreConfiguration,
+    pub auth_client: AuthClient,
+}
+
+/// The audit database must already be migrated, see `audit::open`.
+pub async fn setup_service(config: &BookstoreConfiguration, audit: &AuditConfiguration) -> Result<Router> {
+    let db_pool = db::get_connection_pool(&config.db_filename, audit).await?;
 
     db::perform_migrations(db_pool.clone()).await?;
+    let state = BookstoreState {
+        db: db_pool,
+        config: config.clone(),
+        auth_client: config.auth_client()?,
+    };
 
     let secure_router = Router::new()
         .layer(CorsLayer::very_permissive())
-        .layer(Extension(config.clone()))
-        .layer(Extension(db_pool.clone()))
-        .route("/add", post(web_service::add_book))
-        .route("/delete/:id", get(web_service::delete_book))
-        .route("/update/:id", post(web_service::update_book))
-        .route_layer(middleware::from_fn(auth_layers::require_remote_token))
-        .layer(Extension(ListenPort(listen_port)))
-        ;
+        .route("/", post(web_service::create_book))
+        .route(
+            "/:id",
+            delete(web_service::delete_book)
+                .put(web_service::replace_book)
+                .patch(web_service::patch_book),
+        )
+        .route("/:id/stock", post(web_service::adjust_stock))
+        .merge(legacy_routes())
+        .route_layer(middleware::from_fn_with_state(state.clone(), auth_layers::require_remote_token));
 
     let router = Router::new()
         .merge(secure_router)
         .route("/", get(web_service::all_books))
+        .route("/authors", get(web_service::list_authors))
         .route("/:id", get(web_service::get_book))
-        .layer(Extension(config))
-        .layer(Extension(db_pool));
+        .route("/:id/details", get(web_service::get_book_details))
+        .with_state(state);
 
     Ok(router)
 }
+
+/// The pre-REST routes used by the static admin pages
+fn legacy_routes() -> Router<BookstoreState> {
+    Router::new()
+        .route("/add", post(web_service::add_book))
+        .route("/delete/:id", get(web_service::delete_book))
+        .route("/update/:id", post(web_service::update_book))
+        .route_layer(middleware::from_fn(legacy::deprecated))
+}
@@ -0,0 +1,155 @@
+use chrono::{Datelike, Utc};
+use serde::Deserialize;
+use validator::{Validate, ValidationError};
+use crate::validation::non_blank;
+use super::{db::{AuthorName, BookFields}, isbn::Isbn};
+
+/// Upper bound for a book's stock and for a single stock adjustment. Keeping
+/// both well below `i64::MAX` means `stock + delta` can't overflow, which
+/// SQLite would otherwise turn into a REAL.
+pub const MAX_STOCK: i64 = 1_000_000_000;
+
+/// Printing with movable type started around 1450, and nothing is published
+/// further ahead than next year.
+fn valid_publication_year(year: i32) -> Result<(), ValidationError> {
+    let max_year = Utc::now().year() + 1;
+    if !(1450..=max_year).contains(&year) {
+        return Err(ValidationError::new("range")
+            .with_message(format!("must be between 1450 and {max_year}").into()));
+    }
+    Ok(())
+}
+
+/// Body of `POST /books`.
+#[derive(Deserialize, Debug, Validate)]
+pub struct NewBook {
+    #[validate(length(min = 1, max = 200, message = "must be 1 to 200 characters"), custom(function = "non_blank"))]
+    pub title: String,
+    #[serde(default)]
+    #[validate(length(max = 20, message = "at most 20 authors"), nested)]
+    pub authors: Vec<AuthorName>,
+    pub isbn: Option<Isbn>,
+    #[validate(custom(function = "valid_publication_year"))]
+    pub publication_year: Option<i32>,
+    #[validate(range(min = 0, message = "must not be negative"))]
+    pub price_cents: Option<i64>,
+    #[validate(range(min = 0, max = MAX_STOCK, message = "must be between 0 and 1000000000"))]
+    pub stock: Option<i64>,
+}
+
+impl From<NewBook> for BookFields {
+    fn from(book: NewBook) -> Self {
+        Self {
+            title: Some(book.title),
+            authors: Some(book.authors),
+            isbn: book.isbn,
+            publication_year: book.publication_year,
+            price_cents: book.price_cents,
+            stock: book.stock,
+        }
+    }
+}
+
+/// Body of `PUT /books/:id`, the whole book. Optional fields that are left
+/// out are cleared. Stock is changed through `POST /books/:id/stock`.
+#[derive(Deserialize, Debug, Validate)]
+pub struct ReplaceBook {
+    #[validate(length(min = 1, max = 200, message = "must be 1 to 200 characters"), custom(function = "non_blank"))]
+    pub title: String,
+    #[serde(default)]
+    #[validate(length(max = 20, message = "at most 20 authors"), nested)]
+    pub authors: Vec<AuthorName>,
+    pub isbn: Option<Isbn>,
+    #[validate(custom(function = "valid_publication_year"))]
+    pub publication_year: Option<i32>,
+    #[validate(range(min = 0, message = "must not be negative"))]
+    pub price_cents: Option<i64>,
+}
+
+impl From<ReplaceBook> for BookFields {
+    fn from(book: ReplaceBook) -> Self {
+        Self {
+            title: Some(book.title),
+            authors: Some(book.authors),
+            isbn: book.isbn,
+            publication_year: book.publication_year,
+            price_cents: book.price_cents,
+            stock: None,
+        }
+    }
+}
+
+/// Body of `PATCH /books/:id`. Only the fields that are given change.
+#[derive(Deserialize, Debug, Validate)]
+pub struct BookPatch {
+    #[validate(length(min = 1, max = 200, message = "must be 1 to 200 characters"), custom(function = "non_blank"))]
+    pub title: Option<String>,
+    #[validate(length(max = 20, message = "at most 20 authors"), nested)]
+    pub authors: Option<Vec<AuthorName>>,
+    pub isbn: Option<Isbn>,
+    #[validate(custom(function = "valid_publication_year"))]
+    pub publication_year: Option<i32>,
+    #[validate(range(min = 0, message = "must not be negative"))]
+    pub price_cents: Option<i64>,
+}
+
+impl From<BookPatch> for BookFields {
+    fn from(book: BookPatch) -> Self {
+        Self {
+            title: book.title,
+            authors: book.authors,
+            isbn: book.isbn,
+            publication_year: book.publication_year,
+            price_cents: book.price_cents,
+            stock: None,
+        }
+    }
+}
+
+/// Body of the deprecated `POST /add` and `POST /update/:id`. It accepts the
+/// `{"id", "title", "author"}` shape sent by the admin pages as well as the
+/// full catalogue fields. Fields left out of an update keep their value.
+#[derive(Deserialize, Debug, Validate)]
+pub struct BookRequest {
+    #[validate(length(min = 1, max = 200, message = "must be 1 to 200 characters"), custom(function = "non_blank"))]
+    pub title: String,
+    /// Free-text authors, "Last, First", several separated by `;`.
+    /// Ignored if `authors` is given.
+    #[validate(length(max = 1000, message = "must be at most 1000 characters"))]
+    pub author: Option<String>,
+    #[validate(length(max = 20, message = "at most 20 authors"), nested)]
+    pub authors: Option<Vec<AuthorName>>,
+    pub isbn: Option<Isbn>,
+    #[validate(custom(function = "valid_publication_year"))]
+    pub publication_year: Option<i32>,
+    #[validate(range(min = 0, message = "must not be negative"))]
+    pub price_cents: Option<i64>,
+    #[validate(range(min = 0, max = MAX_STOCK, message = "must be between 0 and 1000000000"))]
+    pub stock: Option<i64>,
+}
+
+impl From<BookRequest> for BookFields {
+    fn from(book: BookRequest) -> Self {
+        let authors = match (book.authors, book.author) {
+            (Some(authors), _) => Some(authors),
+            (None, Some(text)) => Some(text.split(';').filter_map(AuthorName::parse).collect()),
+            (None, None) => None,
+        };
+        Self {
+            title: Some(book.title),
+            authors,
+            isbn: book.isbn,
+            publication_year: book.publication_year,
+            price_cents: book.price_cents,
+            stock: book.stock,
+        }
+    }
+}
+
+/// Body of `POST /books/:id/stock`.
+#[derive(Deserialize, Debug, Validate)]
+pub struct StockRequest {
+    /// Positive to receive stock, negative to sell or remove it
+    #[validate(range(min = -MAX_STOCK, max = MAX_STOCK, message = "must be between -1000000000 and 1000000000"))]
+    pub delta: i64,
+}
@@ -1,47 +1,183 @@
-use axum::{http::StatusCode, Extension, Json};
-use super::db::{self, Book, StoreDb};
+use axum::{extract::State, http::{header, StatusCode}, response::IntoResponse, Extension};
+use serde::Serialize;
+use crate::audit::{self, Entity};
+use crate::auth::auth_layers::ValidUser;
+use crate::error::{ApiError, Json, Path, Query};
+use crate::validation::ValidJson;
+use super::db::{self, Author, Book, BookDetails, BookFields, BookQuery, StockAdjustment, StoreDb, UpdateMode};
+use super::requests::{BookPatch, BookRequest, NewBook, ReplaceBook, StockRequest};
 
+/// The body stays a plain array so existing clients keep working; the number
+/// of matching books across all pages is in the `X-Total-Count` header.
 pub async fn all_books(
-    Extension(db_pool): Extension<StoreDb>
-) -> Result<Json<Vec<Book>>, StatusCode> {
-    let books = db::list_books(db_pool).await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    Ok(Json(books))
+    State(db_pool): State<StoreDb>,
+    Query(query): Query<BookQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let page = db::list_books(db_pool, &query).await?;
+    Ok(([("x-total-count", page.total.to_string())], Json(page.books)))
 }
 
 pub async fn get_book(
-    Extension(db_pool): Extension<StoreDb>,
-    path: axum::extract::Path<i32>
-) -> Result<Json<Book>, StatusCode> {
-    let book = db::get_book(db_pool, path.0).await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    Ok(Json(book))
+    State(db_pool): State<StoreDb>,
+    Path(id): Path<i32>
+) -> Result<Json<Book>, ApiError> {
+    db::get_book(db_pool, id).await?
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found("book"))
 }
 
 pub async fn delete_book(
-    Extension(db_pool): Extension<StoreDb>,
-    path: axum::extract::Path<i32>
-) -> Result<StatusCode, StatusCode> {
-    db::delete_book(db_pool, path.0).await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    State(db_pool): State<StoreDb>,
+    Extension(valid_user): Extension<ValidUser>,
+    Path(id): Path<i32>
+) -> Result<StatusCode, ApiError> {
+    let mut tx = db_pool.begin().await?;
+    let before = db::read_book_details(&mut tx, id).await?
+        .ok_or_else(|| ApiError::not_found("book"))?;
+    if !db::delete_book(&mut tx, id).await? {
+        return Err(ApiError::not_found("book"));
+    }
+    audit::record(&mut tx, valid_user, Entity::Book, id, Some(&before), None).await?;
+    tx.commit().await?;
     Ok(StatusCode::OK)
 }
 
+/// The only unique value a book has is its ISBN, so say so rather than
+/// giving the generic conflict message.
+fn write_error(e: anyhow::Error) -> ApiError {
+    match ApiError::from(e) {
+        ApiError::Conflict(_) => ApiError::Conflict("a book with this ISBN already exists".to_string()),
+        e => e,
+    }
+}
+
+/// Adds a book and records it in the audit log.
+async fn insert_book(db_pool: StoreDb, valid_user: ValidUser, book: BookFields) -> Result<BookDetails, ApiError> {
+    let mut tx = db_pool.begin().await?;
+    let id = db::add_book(&mut tx, &book).await
+        .map_err(write_error)?;
+    let book = db::read_book_details(&mut tx, id).await?
+        .ok_or_else(|| ApiError::not_found("book"))?;
+    audit::record(&mut tx, valid_user, Entity::Book, id, None, Some(&book)).await?;
+    tx.commit().await?;
+    Ok(book)
+}
+
+/// `POST /books`. Answers 201 with the new book and its URL.
+pub async fn create_book(
+    State(db_pool): State<StoreDb>,
+    Extension(valid_user): Extension<ValidUser>,
+    ValidJson(book): ValidJson<NewBook>
+) -> Result<impl IntoResponse, ApiError> {
+    let book = insert_book(db_pool, valid_user, book.into()).await?;
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, format!("/api/v1/books/{}", book.id))],
+        Json(book),
+    ))
+}
+
+/// Shared by every route that updates a book, answers with the updated book.
+async fn write_book(
+    db_pool: StoreDb,
+    valid_user: ValidUser,
+    id: i32,
+    book: BookFields,
+    mode: UpdateMode,
+) -> Result<Json<BookDetails>, ApiError> {
+    let mut tx = db_pool.begin().await?;
+    let before = db::read_book_details(&mut tx, id).await?
+        .ok_or_else(|| ApiError::not_found("book"))?;
+    if !db::update_book(&mut tx, id, &book, mode).await.map_err(write_error)? {
+        return Err(ApiError::not_found("book"));
+    }
+    let after = db::read_book_details(&mut tx, id).await?
+        .ok_or_else(|| ApiError::not_found("book"))?;
+    audit::record(&mut tx, valid_user, Entity::Book, id, Some(&before), Some(&after)).await?;
+    tx.commit().await?;
+    Ok(Json(after))
+}
+
+pub async fn replace_book(
+    State(db_pool): State<StoreDb>,
+    Extension(valid_user): Extension<ValidUser>,
+    Path(id): Path<i32>,
+    ValidJson(book): ValidJson<ReplaceBook>
+) -> Result<Json<BookDetails>, ApiError> {
+    write_book(db_pool, valid_user, id, book.into(), UpdateMode::Replace).await
+}
+
+pub async fn patch_book(
+    State(db_pool): State<StoreDb>,
+    Extension(valid_user): Extension<ValidUser>,
+    Path(id): Path<i32>,
+    ValidJson(book): ValidJson<BookPatch>
+) -> Result<Json<BookDetails>, ApiError> {
+    write_book(db_pool, valid_user, id, book.into(), UpdateMode::Merge).await
+}
+
+/// Deprecated `POST /add`, use `POST /books`
 pub async fn add_book(
-    Extension(db_pool): Extension<StoreDb>,
-    Json(book): Json<Book>
-) -> Result<StatusCode, StatusCode> {
-    db::add_book(db_pool, book.title, book.author).await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    State(db_pool): State<StoreDb>,
+    Extension(valid_user): Extension<ValidUser>,
+    ValidJson(book): ValidJson<BookRequest>
+) -> Result<StatusCode, ApiError> {
+    insert_book(db_pool, valid_user, book.into()).await?;
     Ok(StatusCode::OK)
 }
 
+/// Deprecated `POST /update/:id`, use `PATCH /books/:id`
 pub async fn update_book(
-    Extension(db_pool): Extension<StoreDb>,
-    path: axum::extract::Path<i32>,
-    Json(book): Json<Book>
-) -> Result<StatusCode, StatusCode> {
-    db::update_book(db_pool, path.0, book.title, book.author).await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    State(db_pool): State<StoreDb>,
+    Extension(valid_user): Extension<ValidUser>,
+    Path(id): Path<i32>,
+    ValidJson(book): ValidJson<BookRequest>
+) -> Result<StatusCode, ApiError> {
+    write_book(db_pool, valid_user, id, book.into(), UpdateMode::Merge).await?;
     Ok(StatusCode::OK)
-}
\ No newline at end of file
+}
+
+pub async fn get_book_details(
+    State(db_pool): State<StoreDb>,
+    Path(id): Path<i32>
+) -> Result<Json<BookDetails>, ApiError> {
+    db::get_book_details(db_pool, id).await?
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found("book"))
+}
+
+pub async fn list_authors(
+    State(db_pool): State<StoreDb>
+) -> Result<Json<Vec<Author>>, ApiError> {
+    let authors = db::list_authors(db_pool).await?;
+    Ok(Json(authors))
+}
+
+#[derive(Serialize, Debug)]
+pub struct StockResponse {
+    pub id: i32,
+    pub stock: i64,
+}
+
+/// Recorded in the audit log like any other update of the book.
+pub async fn adjust_stock(
+    State(db_pool): State<StoreDb>,
+    Extension(valid_user): Extension<ValidUser>,
+    Path(id): Path<i32>,
+    ValidJson(request): ValidJson<StockRequest>
+) -> Result<Json<StockResponse>, ApiError> {
+    let mut tx = db_pool.begin().await?;
+    let before = db::read_book_details(&mut tx, id).await?
+        .ok_or_else(|| ApiError::not_found("book"))?;
+    match db::adjust_stock(&mut tx, id, request.delta).await? {
+        StockAdjustment::Adjusted { stock } => {
+            let after = db::read_book_details(&mut tx, id).await?
+                .ok_or_else(|| ApiError::not_found("book"))?;
+            audit::record(&mut tx, valid_user, Entity::Book, id, Some(&before), Some(&after)).await?;
+            tx.commit().await?;
+            Ok(Json(StockResponse { id, stock }))
+        }
+        StockAdjustment::NotFound => Err(ApiError::not_found("book")),
+        StockAdjustment::Insufficient { stock } => Err(ApiError::Conflict(format!("only {stock} in stock"))),
+    }
+}
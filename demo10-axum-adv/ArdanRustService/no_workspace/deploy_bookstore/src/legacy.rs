@@ -0,0 +1,12 @@
+use axum::{extract::Request, http::HeaderValue, middleware::Next, response::Response};
+
+/// Layer for the old `/add`, `/delete/:id` and `/update/:id` style routes,
+/// which are kept until the static admin pages move to the RESTful ones. The
+/// `Deprecation` header lets clients notice they're on the way out.
+pub async fn deprecated(req: Request, next: Next) -> Response {
+    let mut response = next.run(req).await;
+    response
+        .headers_mut()
+        .insert("deprecation", HeaderValue::from_static("true"));
+    response
+}
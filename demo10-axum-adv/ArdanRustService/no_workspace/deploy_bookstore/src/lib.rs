@@ -0,0 +1,35 @@
+mod audit;
+mod auth;
+mod bookstore;
+mod error;
+mod legacy;
+pub mod service_config;
+mod validation;
+use anyhow::Result;
+use axum::Router;
+use tower_http::{cors::CorsLayer, services::ServeDir};
+use tower::ServiceBuilder;
+pub use service_config::ServiceConfig;
+
+/// Builds the master router: the auth, bookstore and audit services under
+/// `/api/v1` and the static pages for everything else.
+pub async fn build_router(config: &ServiceConfig) -> Result<Router> {
+    let audit_db = audit::open(&config.audit).await?;
+    let auth_router = auth::setup_service(&config.auth, &config.audit).await?;
+    let books_router = bookstore::setup_service(&config.bookstore, &config.audit).await?;
+    let audit_router = audit::setup_service(audit_db, config.bookstore.auth_client()?);
+
+    // The default web server
+    let static_content = ServiceBuilder::new()
+        .layer(CorsLayer::very_permissive())
+        .service(ServeDir::new(&config.static_content));
+
+    let master_router = Router::new()
+        .nest("/api/v1/auth", auth_router)
+        .nest("/api/v1/books", books_router)
+        .nest("/api/v1/audit", audit_router)
+        .layer(CorsLayer::very_permissive())
+        .nest_service("/", static_content);
+
+    Ok(master_router)
+}
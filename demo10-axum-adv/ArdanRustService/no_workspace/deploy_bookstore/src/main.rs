@@ -1,38 +1,20 @@
-mod auth;
-mod bookstore;
-mod service_config;
 use anyhow::Result;
-use axum::Extension;
-use tower_http::{cors::CorsLayer, services::ServeDir};
-use tower::ServiceBuilder;
+use deploy_bookstore::ServiceConfig;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
-    let service_settings = service_config::ServiceConfig::load()?;
-    let auth_router = auth::setup_service().await?;
-    let books_router = bookstore::setup_service(service_settings.listen_port.clone()).await?;
-
-    // Listen address from configuration
-    let listen_address = format!(
-        "{}:{}",
-        service_settings.listen_address, service_settings.listen_port
-    );
-    let listener = tokio::net::TcpListener::bind(&listen_address).await?;
-    tracing::info!("Listening on {}", listen_address);
-
-    // The default web server
-    let static_content = ServiceBuilder::new()
-        .layer(CorsLayer::very_permissive())
-        .service(ServeDir::new(&service_settings.static_content));
+    let config = ServiceConfig::load()?;
+    if std::env::args().any(|arg| arg == "--print-config") {
+        println!("{}", serde_json::to_string_pretty(&config)?);
+        return Ok(());
+    }
 
     // Build the master router
-    let master_router = axum::Router::new()
-        .layer(CorsLayer::very_permissive())
-        .nest("/api/v1/auth", auth_router)
-        .nest("/api/v1/books", books_router)
-        .layer(Extension(service_settings))
-        .nest_service("/", static_content);
+    let master_router = deploy_bookstore::build_router(&config).await?;
+
+    let listener = tokio::net::TcpListener::bind(config.listen).await?;
+    tracing::info!("Listening on {}", config.listen);
 
     // Launch Axum
     axum::serve(listener, master_router).await?;
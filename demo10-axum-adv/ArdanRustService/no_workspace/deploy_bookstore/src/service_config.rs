@@ -1,12 +1,43 @@
+use std::{net::SocketAddr, path::PathBuf};
+use anyhow::Result;
 use config::Config;
 use serde::{Deserialize, Serialize};
-use anyhow::Result;
+use crate::{audit::AuditConfiguration, auth::AuthConfiguration, bookstore::BookstoreConfiguration};
 
+/// The whole configuration of the service. Every value has a default, and can
+/// be overridden from a `settings` file (TOML, YAML, JSON...) or from `APP_`
+/// environment variables, with `__` between nested keys:
+///
+/// ```text
+/// APP_LISTEN=0.0.0.0:3001
+/// APP_STATIC_CONTENT=static_html
+/// APP_AUTH__DB_FILENAME=auth.db
+/// APP_AUTH__TOKEN_LIFETIME=24h
+/// APP_BOOKSTORE__AUTH_URL=http://auth:3001/api/v1/auth
+/// APP_AUDIT__DB_FILENAME=audit.db
+/// ```
+///
+/// Run with `--print-config` to see the values in effect.
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct ServiceConfig {
-    pub listen_address: String,
-    pub listen_port: String,
-    pub static_content: String,
+    pub listen: SocketAddr,
+    pub static_content: PathBuf,
+    pub auth: AuthConfiguration,
+    pub bookstore: BookstoreConfiguration,
+    pub audit: AuditConfiguration,
+}
+
+impl Default for ServiceConfig {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([0, 0, 0, 0], 3001)),
+            static_content: PathBuf::from("static_html"),
+            auth: AuthConfiguration::default(),
+            bookstore: BookstoreConfiguration::default(),
+            audit: AuditConfiguration::default(),
+        }
+    }
 }
 
 impl ServiceConfig {
@@ -17,13 +48,27 @@ impl ServiceConfig {
 
         let settings_reader = Config::builder()
             .add_source(config::File::with_name("settings").required(false))
-            .add_source(config::Environment::with_prefix("APP"))
+            .add_source(
+                config::Environment::with_prefix("APP")
+                    .prefix_separator("_")
+                    .separator("__"),
+            )
             .build()?;
 
-        let settings = settings_reader
+        let mut settings: Self = settings_reader
             .try_deserialize()?;
 
+        settings.bookstore.auth_url.get_or_insert_with(|| {
+            format!("http://localhost:{}/api/v1/auth", settings.listen.port())
+        });
+        settings.validate()?;
+
         Ok(settings)
+    }
 
+    pub fn validate(&self) -> Result<()> {
+        self.auth.validate()?;
+        self.bookstore.validate()?;
+        Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,406 @@
+//! Drives the whole service through its master router, each test with its own
+//! SQLite databases in a temporary directory.
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    Router,
+};
+use deploy_bookstore::ServiceConfig;
+use serde_json::{json, Value};
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+struct TestApp {
+    router: Router,
+    _dir: TempDir,
+}
+
+struct TestResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Value,
+}
+
+impl TestApp {
+    /// The bookstore checks tokens over HTTP, so the router is also served on
+    /// a local port for it to call back into. Tests themselves use `oneshot`.
+    async fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let mut config = ServiceConfig {
+            listen: address,
+            static_content: concat!(env!("CARGO_MANIFEST_DIR"), "/static_html").into(),
+            ..Default::default()
+        };
+        config.auth.db_filename = dir.path().join("auth.db");
+        config.bookstore.db_filename = dir.path().join("bookstore.db");
+        config.audit.db_filename = dir.path().join("audit.db");
+        config.bookstore.auth_url = Some(format!("http://{address}/api/v1/auth"));
+        config.validate().unwrap();
+
+        let router = deploy_bookstore::build_router(&config).await.unwrap();
+        let server = router.clone();
+        tokio::spawn(async move { axum::serve(listener, server).await.unwrap() });
+
+        Self { router, _dir: dir }
+    }
+
+    async fn send(&self, request: Request<Body>) -> TestResponse {
+        let response = self.router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+        TestResponse { status, headers, body }
+    }
+
+    async fn call(&self, method: Method, uri: &str, token: Option<&str>, body: Option<Value>) -> TestResponse {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            request = request.header("Token", token);
+        }
+        let request = match body {
+            Some(body) => request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        };
+        self.send(request.unwrap()).await
+    }
+
+    async fn login(&self, username: &str, password: &str) -> TestResponse {
+        let body = json!({ "username": username, "password": password });
+        self.call(Method::POST, "/api/v1/auth/login", None, Some(body)).await
+    }
+
+    /// Logs in as the admin user created by the initial migration
+    async fn admin_token(&self) -> String {
+        let response = self.login("admin", "admin").await;
+        assert_eq!(response.status, StatusCode::OK);
+        response.body["Success"]["token"].as_str().unwrap().to_string()
+    }
+}
+
+#[tokio::test]
+async fn login_issues_tokens() {
+    let app = TestApp::new().await;
+
+    let response = app.login("admin", "wrong").await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers[header::CONTENT_TYPE], "application/problem+json");
+
+    let token = app.admin_token().await;
+    let response = app.call(Method::POST, "/api/v1/auth/validate", None, Some(json!({ "token": token }))).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["user_id"], 1);
+    assert_eq!(response.body["is_admin"], true);
+
+    let response = app.call(Method::POST, "/api/v1/auth/logout", Some(&token), None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let response = app.call(Method::GET, "/api/v1/auth/users", Some(&token), None).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn user_routes_require_a_token() {
+    let app = TestApp::new().await;
+
+    let response = app.call(Method::GET, "/api/v1/auth/users", None, None).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    let response = app.call(Method::GET, "/api/v1/auth/users", Some("not-a-token"), None).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn only_admins_manage_other_users() {
+    let app = TestApp::new().await;
+    let admin = app.admin_token().await;
+    let new_user = json!({ "username": "bob", "password": "not an admin" });
+    let response = app.call(Method::POST, "/api/v1/auth/users", Some(&admin), Some(new_user)).await;
+    let bob_id = response.body["id"].as_i64().unwrap();
+    let bob = app.login("bob", "not an admin").await;
+    let bob = Some(bob.body["Success"]["token"].as_str().unwrap());
+
+    let admin_location = "/api/v1/auth/users/1";
+    let takeover = json!({ "password": "mine now" });
+    let replacement = json!({ "username": "admin", "password": "mine now" });
+    let new_user = json!({ "username": "mallory", "password": "sneaky but long" });
+    let forbidden = [
+        (Method::PATCH, admin_location, Some(takeover.clone())),
+        (Method::PUT, admin_location, Some(replacement)),
+        (Method::DELETE, admin_location, None),
+        (Method::POST, "/api/v1/auth/users", Some(new_user.clone())),
+        (Method::POST, "/api/v1/auth/users/update/1", Some(takeover)),
+        (Method::POST, "/api/v1/auth/users/add", Some(new_user)),
+        (Method::GET, "/api/v1/auth/users/delete/1", None),
+    ];
+    for (method, uri, body) in forbidden {
+        let response = app.call(method.clone(), uri, bob, body).await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN, "{method} {uri}");
+    }
+    assert_eq!(app.login("admin", "admin").await.status, StatusCode::OK);
+
+    // Their own account is fine
+    let location = format!("/api/v1/auth/users/{bob_id}");
+    let patch = json!({ "password": "still not an admin" });
+    let response = app.call(Method::PATCH, &location, bob, Some(patch)).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(app.login("bob", "still not an admin").await.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn user_crud() {
+    let app = TestApp::new().await;
+    let token = app.admin_token().await;
+    let token = Some(token.as_str());
+
+    let new_user = json!({ "username": "alice", "password": "correct horse" });
+    let response = app.call(Method::POST, "/api/v1/auth/users", token, Some(new_user)).await;
+    assert_eq!(response.status, StatusCode::CREATED);
+    let id = response.body["id"].as_i64().unwrap();
+    let location = format!("/api/v1/auth/users/{id}");
+    assert_eq!(response.headers[header::LOCATION], location.as_str());
+    assert!(response.body.get("password_hash").is_none());
+
+    let response = app.call(Method::GET, &location, token, None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["username"], "alice");
+    assert_eq!(app.login("alice", "correct horse").await.status, StatusCode::OK);
+
+    // Usernames are unique, whether a user is created or renamed
+    let duplicate = json!({ "username": "alice", "password": "another horse" });
+    let response = app.call(Method::POST, "/api/v1/auth/users", token, Some(duplicate)).await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+    assert_eq!(response.body["detail"], "a user with this username already exists");
+    let response = app.call(Method::PATCH, &location, token, Some(json!({ "username": "admin" }))).await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+
+    let patch = json!({ "username": "alice2" });
+    let response = app.call(Method::PATCH, &location, token, Some(patch)).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["username"], "alice2");
+    // The password wasn't part of the patch
+    assert_eq!(app.login("alice2", "correct horse").await.status, StatusCode::OK);
+
+    let replacement = json!({ "username": "bob", "password": "battery staple" });
+    let response = app.call(Method::PUT, &location, token, Some(replacement)).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(app.login("bob", "battery staple").await.status, StatusCode::OK);
+
+    let response = app.call(Method::GET, "/api/v1/auth/users", token, None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body.as_array().unwrap().len(), 2);
+
+    let response = app.call(Method::DELETE, &location, token, None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let response = app.call(Method::GET, &location, token, None).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let response = app.call(Method::DELETE, &location, token, None).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn invalid_users_are_rejected() {
+    let app = TestApp::new().await;
+    let token = app.admin_token().await;
+    let token = Some(token.as_str());
+
+    let short_password = json!({ "username": "carol", "password": "short" });
+    let response = app.call(Method::POST, "/api/v1/auth/users", token, Some(short_password)).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let blank_name = json!({ "username": " ", "password": "long enough" });
+    let response = app.call(Method::POST, "/api/v1/auth/users", token, Some(blank_name)).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let response = app.call(Method::POST, "/api/v1/auth/users", token, Some(json!({}))).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn book_crud() {
+    let app = TestApp::new().await;
+    let token = app.admin_token().await;
+    let token = Some(token.as_str());
+
+    let new_book = json!({
+        "title": "Rust for Rustaceans",
+        "authors": [{ "last_name": "Gjengset", "first_name": "Jon" }],
+        "isbn": "978-1-7185-0185-0",
+        "publication_year": 2021,
+        "price_cents": 4795,
+        "stock": 3,
+    });
+    let response = app.call(Method::POST, "/api/v1/books", None, Some(new_book.clone())).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+
+    let response = app.call(Method::POST, "/api/v1/books", token, Some(new_book.clone())).await;
+    assert_eq!(response.status, StatusCode::CREATED);
+    assert_eq!(response.body["isbn"], "9781718501850");
+    let id = response.body["id"].as_i64().unwrap();
+    let location = format!("/api/v1/books/{id}");
+    assert_eq!(response.headers[header::LOCATION], location.as_str());
+
+    let response = app.call(Method::POST, "/api/v1/books", token, Some(new_book)).await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+
+    let response = app.call(Method::GET, &location, None, None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["title"], "Rust for Rustaceans");
+
+    let response = app.call(Method::GET, "/api/v1/books?title=rustaceans", None, None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.headers["x-total-count"], "1");
+
+    // Without a limit the whole catalogue comes back; with one it's a page
+    let response = app.call(Method::GET, "/api/v1/books", None, None).await;
+    let total: usize = response.headers["x-total-count"].to_str().unwrap().parse().unwrap();
+    assert_eq!(response.body.as_array().unwrap().len(), total);
+    let response = app.call(Method::GET, "/api/v1/books?offset=1", None, None).await;
+    assert_eq!(response.body.as_array().unwrap().len(), total - 1);
+    let response = app.call(Method::GET, "/api/v1/books?limit=1", None, None).await;
+    assert_eq!(response.body.as_array().unwrap().len(), 1);
+
+    let patch = json!({ "price_cents": 3995 });
+    let response = app.call(Method::PATCH, &location, token, Some(patch)).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["price_cents"], 3995);
+    assert_eq!(response.body["title"], "Rust for Rustaceans");
+
+    let replacement = json!({ "title": "Rust for Rustaceans, 2nd edition" });
+    let response = app.call(Method::PUT, &location, token, Some(replacement)).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["isbn"], Value::Null);
+    assert_eq!(response.body["authors"], json!([]));
+    // Stock isn't part of a replacement
+    assert_eq!(response.body["stock"], 3);
+    // The search index follows the new title
+    let response = app.call(Method::GET, "/api/v1/books?title=2nd%20edition", None, None).await;
+    assert_eq!(response.headers["x-total-count"], "1");
+
+    let adjustment = json!({ "delta": -5 });
+    let response = app.call(Method::POST, &format!("{location}/stock"), token, Some(adjustment)).await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+    // Large enough to overflow the stock column
+    let adjustment = json!({ "delta": i64::MAX });
+    let response = app.call(Method::POST, &format!("{location}/stock"), token, Some(adjustment)).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let response = app.call(Method::DELETE, &location, None, None).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    let response = app.call(Method::DELETE, &location, token, None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let response = app.call(Method::GET, &location, None, None).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn invalid_books_are_rejected() {
+    let app = TestApp::new().await;
+    let token = app.admin_token().await;
+    let token = Some(token.as_str());
+
+    let bad_isbn = json!({ "title": "Checksum", "isbn": "978-1-68050-816-2" });
+    let response = app.call(Method::POST, "/api/v1/books", token, Some(bad_isbn)).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let blank_title = json!({ "title": "   " });
+    let response = app.call(Method::POST, "/api/v1/books", token, Some(blank_title)).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let response = app.call(Method::GET, "/api/v1/books/not-a-number", None, None).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn changes_are_audited() {
+    let app = TestApp::new().await;
+    let token = app.admin_token().await;
+    let token = Some(token.as_str());
+
+    let response = app.call(Method::POST, "/api/v1/books", token, Some(json!({ "title": "Audited" }))).await;
+    let book = response.body["id"].as_i64().unwrap();
+    let location = format!("/api/v1/books/{book}");
+    app.call(Method::PATCH, &location, token, Some(json!({ "price_cents": 1000 }))).await;
+    app.call(Method::POST, &format!("{location}/stock"), token, Some(json!({ "delta": 2 }))).await;
+    app.call(Method::DELETE, &location, token, None).await;
+    let new_user = json!({ "username": "clerk", "password": "not an admin" });
+    let response = app.call(Method::POST, "/api/v1/auth/users", token, Some(new_user)).await;
+    let user = response.body["id"].as_i64().unwrap();
+
+    let response = app.call(Method::GET, "/api/v1/audit?entity=book", token, None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.headers["x-total-count"], "4");
+    let entries = response.body.as_array().unwrap();
+    let actions: Vec<_> = entries.iter().map(|e| e["action"].as_str().unwrap()).collect();
+    assert_eq!(actions, ["delete", "update", "update", "create"]);
+    assert!(entries.iter().all(|e| e["actor_id"] == 1 && e["entity_id"] == book));
+    assert_eq!(entries[0]["after"], Value::Null);
+    // Stock changes are recorded with the whole book, like other updates
+    assert_eq!(entries[1]["before"]["stock"], 0);
+    assert_eq!(entries[1]["after"]["stock"], 2);
+    assert_eq!(entries[1]["after"]["title"], "Audited");
+    assert_eq!(entries[2]["before"]["price_cents"], Value::Null);
+    assert_eq!(entries[2]["after"]["price_cents"], 1000);
+    assert_eq!(entries[3]["before"], Value::Null);
+    assert_eq!(entries[3]["after"]["title"], "Audited");
+
+    let uri = format!("/api/v1/audit?entity=user&entity_id={user}&action=create");
+    let response = app.call(Method::GET, &uri, token, None).await;
+    assert_eq!(response.headers["x-total-count"], "1");
+    assert_eq!(response.body[0]["after"]["username"], "clerk");
+    assert!(response.body[0]["after"].get("password_hash").is_none());
+
+    let response = app.call(Method::GET, "/api/v1/audit?since=2999-01-01T00:00:00Z", token, None).await;
+    assert_eq!(response.headers["x-total-count"], "0");
+
+    let response = app.call(Method::GET, "/api/v1/audit", None, None).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    let clerk = app.login("clerk", "not an admin").await;
+    let clerk = clerk.body["Success"]["token"].as_str().unwrap();
+    let response = app.call(Method::GET, "/api/v1/audit", Some(clerk), None).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn api_answers_cors_requests() {
+    let app = TestApp::new().await;
+
+    let preflight = Request::builder()
+        .method(Method::OPTIONS)
+        .uri("/api/v1/books")
+        .header(header::ORIGIN, "http://example.com")
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+        .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "token,content-type")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.send(preflight).await;
+    assert!(response.status.is_success());
+    assert_eq!(response.headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "http://example.com");
+    assert!(response.headers.contains_key(header::ACCESS_CONTROL_ALLOW_METHODS));
+
+    let request = Request::builder()
+        .uri("/api/v1/books")
+        .header(header::ORIGIN, "http://example.com")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.send(request).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "http://example.com");
+}
+
+#[tokio::test]
+async fn static_pages_are_the_fallback() {
+    let app = TestApp::new().await;
+
+    for page in ["/", "/book.html", "/admin/index.html"] {
+        let response = app.call(Method::GET, page, None, None).await;
+        assert_eq!(response.status, StatusCode::OK, "{page}");
+        assert!(response.headers[header::CONTENT_TYPE].to_str().unwrap().starts_with("text/html"));
+    }
+
+    let response = app.call(Method::GET, "/no-such-page.html", None, None).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,22 @@
+-- 用户角色: 映射到 Rust 的 `UserRole` 枚举
+CREATE TYPE user_role AS ENUM ('admin', 'member', 'guest');
+
+-- 地址: 映射到 Rust 的 `Address` 复合类型
+CREATE TYPE address AS (
+    street TEXT,
+    city   TEXT,
+    zip    TEXT
+);
+
+CREATE TABLE users (
+    id      SERIAL PRIMARY KEY,
+    name    TEXT      NOT NULL,
+    email   TEXT      UNIQUE,
+    role    user_role NOT NULL DEFAULT 'member',
+    age     INT,
+    address address,
+    tags    TEXT[]    NOT NULL DEFAULT '{}',
+    profile JSONB     NOT NULL DEFAULT '{}'
+);
+
+CREATE INDEX idx_users_tags ON users USING GIN (tags);
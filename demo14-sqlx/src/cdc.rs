@@ -0,0 +1,517 @@
+//! 基于 `LISTEN/NOTIFY` 的表变更捕获 (CDC)
+//!
+//! 1. [ChangeListener::install] 为每张表创建 `AFTER INSERT OR UPDATE OR DELETE` 触发器,
+//!    触发器把 `{table, op, key, old, new}` 以 JSON 的形式 `pg_notify` 到指定频道.
+//! 2. [ChangeListener::start] 启动后台任务, 把通知解析为 [TableChangeEvent] 发送到返回的 channel.
+//!
+//! 需要注意的几个问题:
+//!
+//! - `NOTIFY` 的 payload 不能超过 8000 字节. 超过时触发器只发送主键 (`truncated: true`),
+//!   监听端再根据主键把整行查出来. 此时 `old` 无法还原, 只能拿到主键.
+//! - 连接断开期间的通知会丢失, 所以每次 (重新) 建立监听之后, 都会对每张表发送一次 [TableChangeEvent::Resync],
+//!   其中是整张表的快照, 下游应当用它替换本地状态.
+//! - 先 `LISTEN` 再读快照, 这样两者之间发生的变更会以通知的形式重复到达, 而不会丢失.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::Value;
+use sqlx::postgres::{PgListener, PgNotification};
+use sqlx::{PgPool, Row};
+use tokio::sync::mpsc;
+
+/// 创建通知函数. 触发器参数: `TG_ARGV[0]` 频道, `TG_ARGV[1]` 表名, 其余为主键列.
+const NOTIFY_FUNCTION: &str = r#"
+CREATE OR REPLACE FUNCTION cdc_notify_change()
+RETURNS TRIGGER AS $$
+DECLARE
+    rec     RECORD;
+    key     JSONB := '{}'::jsonb;
+    payload TEXT;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        rec := OLD;
+    ELSE
+        rec := NEW;
+    END IF;
+
+    FOR i IN 2 .. TG_NARGS - 1 LOOP
+        key := key || jsonb_build_object(TG_ARGV[i], to_jsonb(rec) -> TG_ARGV[i]);
+    END LOOP;
+
+    payload := jsonb_build_object(
+        'table', TG_ARGV[1],
+        'op', TG_OP,
+        'key', key,
+        'old', CASE WHEN TG_OP <> 'INSERT' THEN to_jsonb(OLD) END,
+        'new', CASE WHEN TG_OP <> 'DELETE' THEN to_jsonb(NEW) END
+    )::text;
+
+    -- NOTIFY 的 payload 必须小于 8000 字节, 超出时只发送主键
+    IF octet_length(payload) >= 8000 THEN
+        payload := jsonb_build_object(
+            'table', TG_ARGV[1],
+            'op', TG_OP,
+            'key', key,
+            'truncated', true
+        )::text;
+    END IF;
+
+    PERFORM pg_notify(TG_ARGV[0], payload);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+"#;
+
+/// 监听到的变更. `table` 是 [TableSpec] 中配置的表名, `key` 是主键列组成的 JSON 对象.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableChangeEvent {
+    Insert { table: String, key: Value, new: Value },
+    /// payload 超长时 `old` 为 `None`
+    Update { table: String, key: Value, old: Option<Value>, new: Value },
+    /// payload 超长时 `old` 只包含主键
+    Delete { table: String, key: Value, old: Value },
+    /// (重新) 建立监听后的全量快照
+    Resync { table: String, rows: Vec<Value> },
+}
+
+impl TableChangeEvent {
+    pub fn table(&self) -> &str {
+        match self {
+            TableChangeEvent::Insert { table, .. }
+            | TableChangeEvent::Update { table, .. }
+            | TableChangeEvent::Delete { table, .. }
+            | TableChangeEvent::Resync { table, .. } => table,
+        }
+    }
+}
+
+/// 需要监听的表, 以及用来定位一行数据的主键列
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableSpec {
+    pub name: String,
+    pub key_columns: Vec<String>,
+}
+
+impl TableSpec {
+    pub fn new(name: impl Into<String>, key_columns: &[&str]) -> Self {
+        Self {
+            name: name.into(),
+            key_columns: key_columns.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    /// 触发器名称, 例如 `public.users` -> `cdc_notify_public_users`
+    fn trigger_name(&self) -> String {
+        format!("cdc_notify_{}", self.name.replace('.', "_"))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Payload {
+    table: String,
+    op: String,
+    key: Value,
+    #[serde(default)]
+    old: Option<Value>,
+    #[serde(default)]
+    new: Option<Value>,
+    #[serde(default)]
+    truncated: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChangeListener {
+    pool: PgPool,
+    channel: String,
+    tables: Vec<TableSpec>,
+    buffer: usize,
+    max_backoff: Duration,
+}
+
+impl ChangeListener {
+    pub fn new(pool: PgPool, channel: impl Into<String>) -> Self {
+        Self {
+            pool,
+            channel: channel.into(),
+            tables: Vec::new(),
+            buffer: 100,
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+
+    pub fn table(mut self, spec: TableSpec) -> Self {
+        self.tables.push(spec);
+        self
+    }
+
+    pub fn tables(mut self, specs: impl IntoIterator<Item = TableSpec>) -> Self {
+        self.tables.extend(specs);
+        self
+    }
+
+    /// 返回的 channel 的容量
+    pub fn buffer(mut self, buffer: usize) -> Self {
+        self.buffer = buffer;
+        self
+    }
+
+    /// 重连的最大间隔, 间隔从 100ms 开始翻倍
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// 创建通知函数, 并为每张表 (重新) 创建触发器. 可以重复执行.
+    pub async fn install(&self) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(NOTIFY_FUNCTION).execute(&mut *tx).await?;
+
+        for table in &self.tables {
+            let trigger = quote_ident(&table.trigger_name());
+            let target = quote_table(&table.name);
+            let mut args = vec![quote_literal(&self.channel), quote_literal(&table.name)];
+            args.extend(table.key_columns.iter().map(|c| quote_literal(c)));
+
+            sqlx::query(sqlx::AssertSqlSafe(format!("DROP TRIGGER IF EXISTS {trigger} ON {target}")))
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(sqlx::AssertSqlSafe(format!(
+                "CREATE TRIGGER {trigger} AFTER INSERT OR UPDATE OR DELETE ON {target} \
+                 FOR EACH ROW EXECUTE FUNCTION cdc_notify_change({})",
+                args.join(", ")
+            )))
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await
+    }
+
+    /// 启动后台监听任务. 当返回的 `Receiver` 被 drop 后, 任务自动退出.
+    pub async fn start(self) -> Result<mpsc::Receiver<TableChangeEvent>, sqlx::Error> {
+        // 第一次连接在这里完成, 配置错误可以直接返回给调用者
+        let listener = self.listen().await?;
+        let (tx, rx) = mpsc::channel(self.buffer);
+
+        tokio::spawn(async move {
+            let mut listener = Some(listener);
+            let mut backoff = Duration::from_millis(100);
+            loop {
+                let current = match listener.take() {
+                    Some(listener) => Ok(listener),
+                    None => self.listen().await,
+                };
+                let result = match current {
+                    Ok(listener) => {
+                        backoff = Duration::from_millis(100);
+                        self.run(listener, &tx).await
+                    }
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(()) => return,
+                    Err(e) => {
+                        eprintln!("change listener on {:?} failed: {}, retry in {:?}", self.channel, e, backoff);
+                        tokio::select! {
+                            _ = tokio::time::sleep(backoff) => {}
+                            _ = tx.closed() => return,
+                        }
+                        backoff = (backoff * 2).min(self.max_backoff);
+                    }
+                }
+            }
+        });
+
+        Ok(rx)
+    }
+
+    async fn listen(&self) -> Result<PgListener, sqlx::Error> {
+        let mut listener = PgListener::connect_with(&self.pool).await?;
+        listener.listen(&self.channel).await?;
+        Ok(listener)
+    }
+
+    /// 返回 `Ok(())` 表示接收端已关闭, 返回错误则需要重建监听.
+    async fn run(&self, mut listener: PgListener, tx: &mpsc::Sender<TableChangeEvent>) -> Result<(), sqlx::Error> {
+        if !self.resync(tx).await? {
+            return Ok(());
+        }
+
+        loop {
+            let notification = tokio::select! {
+                notification = listener.try_recv() => notification?,
+                _ = tx.closed() => return Ok(()),
+            };
+
+            let Some(notification) = notification else {
+                // 连接断开, 下一次 try_recv 会自动重连, 但是断开期间的通知已经丢失
+                eprintln!("change listener on {:?} lost connection, resync", self.channel);
+                if !self.resync(tx).await? {
+                    return Ok(());
+                }
+                continue;
+            };
+
+            match self.parse(&notification).await {
+                Ok(Some(event)) => {
+                    if tx.send(event).await.is_err() {
+                        return Ok(());
+                    }
+                }
+                Ok(None) => {}
+                Err(ParseError::Database(e)) => return Err(e),
+                Err(e) => eprintln!("invalid change notification {:?}: {}", notification.payload(), e),
+            }
+        }
+    }
+
+    /// 对每张表发送一次全量快照, 返回 `false` 表示接收端已关闭
+    async fn resync(&self, tx: &mpsc::Sender<TableChangeEvent>) -> Result<bool, sqlx::Error> {
+        for table in &self.tables {
+            let rows = sqlx::query(sqlx::AssertSqlSafe(format!("SELECT to_jsonb(t) FROM {} t", quote_table(&table.name))))
+                .fetch_all(&self.pool)
+                .await?
+                .into_iter()
+                .map(|row| row.get::<Value, _>(0))
+                .collect();
+            let event = TableChangeEvent::Resync { table: table.name.clone(), rows };
+            if tx.send(event).await.is_err() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// 解析通知. 超长的通知需要回表查询, 如果这一行已经不存在了 (随后会收到 DELETE), 返回 `None`.
+    async fn parse(&self, notification: &PgNotification) -> Result<Option<TableChangeEvent>, ParseError> {
+        let payload: Payload = serde_json::from_str(notification.payload())?;
+        let spec = self
+            .tables
+            .iter()
+            .find(|t| t.name == payload.table)
+            .ok_or_else(|| ParseError::UnknownTable(payload.table.clone()))?;
+
+        let new = match (payload.truncated, payload.op.as_str()) {
+            (true, "INSERT" | "UPDATE") => match self.fetch_row(spec, &payload.key).await? {
+                Some(row) => Some(row),
+                None => return Ok(None),
+            },
+            _ => payload.new,
+        };
+
+        let event = match payload.op.as_str() {
+            "INSERT" => TableChangeEvent::Insert {
+                table: payload.table,
+                key: payload.key,
+                new: new.ok_or(ParseError::MissingRow("new"))?,
+            },
+            "UPDATE" => TableChangeEvent::Update {
+                table: payload.table,
+                key: payload.key,
+                old: payload.old,
+                new: new.ok_or(ParseError::MissingRow("new"))?,
+            },
+            "DELETE" => TableChangeEvent::Delete {
+                table: payload.table,
+                old: match payload.old {
+                    Some(old) => old,
+                    None if payload.truncated => payload.key.clone(),
+                    None => return Err(ParseError::MissingRow("old")),
+                },
+                key: payload.key,
+            },
+            op => return Err(ParseError::UnknownOp(op.to_string())),
+        };
+        Ok(Some(event))
+    }
+
+    async fn fetch_row(&self, spec: &TableSpec, key: &Value) -> Result<Option<Value>, sqlx::Error> {
+        let table = quote_table(&spec.name);
+        // jsonb_populate_record 把主键 JSON 转换成表的行类型, 这样比较时类型正确, 也能用上索引
+        let condition = spec
+            .key_columns
+            .iter()
+            .map(|c| format!("t.{0} = k.{0}", quote_ident(c)))
+            .collect::<Vec<_>>()
+            .join(" AND ");
+        let sql = format!("SELECT to_jsonb(t) FROM {table} t, jsonb_populate_record(NULL::{table}, $1) k WHERE {condition}");
+        let row = sqlx::query(sqlx::AssertSqlSafe(sql)).bind(key).fetch_optional(&self.pool).await?;
+        Ok(row.map(|row| row.get::<Value, _>(0)))
+    }
+}
+
+/// 为表安装触发器并启动监听, 使用默认频道 `table_changes`
+pub async fn start_table_change_listener(
+    pool: PgPool,
+    tables: impl IntoIterator<Item = TableSpec>,
+) -> Result<mpsc::Receiver<TableChangeEvent>, sqlx::Error> {
+    let listener = ChangeListener::new(pool, "table_changes").tables(tables);
+    listener.install().await?;
+    listener.start().await
+}
+
+#[derive(Debug)]
+enum ParseError {
+    Json(serde_json::Error),
+    Database(sqlx::Error),
+    UnknownTable(String),
+    UnknownOp(String),
+    MissingRow(&'static str),
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Json(e) => write!(f, "invalid json: {}", e),
+            ParseError::Database(e) => write!(f, "database error: {}", e),
+            ParseError::UnknownTable(table) => write!(f, "unknown table: {}", table),
+            ParseError::UnknownOp(op) => write!(f, "unknown operation: {}", op),
+            ParseError::MissingRow(which) => write!(f, "missing {} row", which),
+        }
+    }
+}
+
+impl From<serde_json::Error> for ParseError {
+    fn from(e: serde_json::Error) -> Self {
+        ParseError::Json(e)
+    }
+}
+
+impl From<sqlx::Error> for ParseError {
+    fn from(e: sqlx::Error) -> Self {
+        ParseError::Database(e)
+    }
+}
+
+fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+/// `schema.table` 分别加引号
+fn quote_table(name: &str) -> String {
+    name.split('.').map(quote_ident).collect::<Vec<_>>().join(".")
+}
+
+fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    async fn next(rx: &mut mpsc::Receiver<TableChangeEvent>) -> TableChangeEvent {
+        timeout(Duration::from_secs(10), rx.recv()).await.expect("timed out").expect("channel closed")
+    }
+
+    async fn setup(pool: &PgPool) -> mpsc::Receiver<TableChangeEvent> {
+        sqlx::query("CREATE TABLE items (id INT PRIMARY KEY, body TEXT NOT NULL)").execute(pool).await.unwrap();
+        sqlx::query("INSERT INTO items VALUES (1, 'a')").execute(pool).await.unwrap();
+        start_table_change_listener(pool.clone(), [TableSpec::new("items", &["id"]), TableSpec::new("public.users", &["id"])])
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_insert_update_delete(pool: PgPool) {
+        let mut rx = setup(&pool).await;
+        assert_eq!(
+            next(&mut rx).await,
+            TableChangeEvent::Resync { table: "items".into(), rows: vec![json!({"id": 1, "body": "a"})] }
+        );
+        assert_eq!(next(&mut rx).await, TableChangeEvent::Resync { table: "public.users".into(), rows: vec![] });
+
+        sqlx::query("INSERT INTO items VALUES (2, 'b')").execute(&pool).await.unwrap();
+        sqlx::query("UPDATE items SET body = 'c' WHERE id = 2").execute(&pool).await.unwrap();
+        sqlx::query("DELETE FROM items WHERE id = 1").execute(&pool).await.unwrap();
+
+        assert_eq!(
+            next(&mut rx).await,
+            TableChangeEvent::Insert { table: "items".into(), key: json!({"id": 2}), new: json!({"id": 2, "body": "b"}) }
+        );
+        assert_eq!(
+            next(&mut rx).await,
+            TableChangeEvent::Update {
+                table: "items".into(),
+                key: json!({"id": 2}),
+                old: Some(json!({"id": 2, "body": "b"})),
+                new: json!({"id": 2, "body": "c"}),
+            }
+        );
+        assert_eq!(
+            next(&mut rx).await,
+            TableChangeEvent::Delete { table: "items".into(), key: json!({"id": 1}), old: json!({"id": 1, "body": "a"}) }
+        );
+
+        sqlx::query("INSERT INTO users (name) VALUES ('Alice')").execute(&pool).await.unwrap();
+        match next(&mut rx).await {
+            TableChangeEvent::Insert { table, new, .. } => {
+                assert_eq!(table, "public.users");
+                assert_eq!(new["name"], "Alice");
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    /// 超过 8000 字节的行只通知主键, 由监听端回表查询
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_large_payload(pool: PgPool) {
+        let mut rx = setup(&pool).await;
+        next(&mut rx).await;
+        next(&mut rx).await;
+
+        let body = "x".repeat(10_000);
+        // 通知在提交时才发出, 回表时这一行已经被删除了, INSERT 被跳过, 只剩下 DELETE
+        let mut tx = pool.begin().await.unwrap();
+        sqlx::query("INSERT INTO items VALUES (3, $1)").bind(&body).execute(&mut *tx).await.unwrap();
+        sqlx::query("DELETE FROM items WHERE id = 3").execute(&mut *tx).await.unwrap();
+        tx.commit().await.unwrap();
+
+        assert_eq!(
+            next(&mut rx).await,
+            TableChangeEvent::Delete { table: "items".into(), key: json!({"id": 3}), old: json!({"id": 3}) }
+        );
+
+        sqlx::query("INSERT INTO items VALUES (4, $1)").bind(&body).execute(&pool).await.unwrap();
+        assert_eq!(
+            next(&mut rx).await,
+            TableChangeEvent::Insert { table: "items".into(), key: json!({"id": 4}), new: json!({"id": 4, "body": body}) }
+        );
+    }
+
+    /// 监听连接被杀掉之后, 自动重连并发送全量快照
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_reconnect_resync(pool: PgPool) {
+        let mut rx = setup(&pool).await;
+        next(&mut rx).await;
+        next(&mut rx).await;
+
+        sqlx::query("SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE datname = current_database() AND query LIKE 'LISTEN%'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO items VALUES (5, 'e')").execute(&pool).await.unwrap();
+
+        // 断开期间的 INSERT 通知会丢失, 但一定会出现在重连后的快照里; 如果重连更早完成, 则会正常收到通知
+        let row = json!({"id": 5, "body": "e"});
+        loop {
+            match next(&mut rx).await {
+                TableChangeEvent::Resync { table, rows } if table == "items" && rows.contains(&row) => break,
+                TableChangeEvent::Insert { new, .. } if new == row => break,
+                _ => {}
+            }
+        }
+
+        sqlx::query("UPDATE items SET body = 'f' WHERE id = 5").execute(&pool).await.unwrap();
+        loop {
+            if let TableChangeEvent::Update { new, .. } = next(&mut rx).await {
+                assert_eq!(new, json!({"id": 5, "body": "f"}));
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,25 @@
+//! 基于 `users` 表的数据访问层
+//!
+//! - [types] 模块: 枚举 / 复合类型 / 数组 / JSONB 与 Rust 类型的映射
+//! - [user] 模块: 增删查, 流式查询, `UNNEST` 批量插入
+//! - [query] 模块: 安全的动态查询构造器
+//! - [cdc] 模块: 基于 `LISTEN/NOTIFY` 的表变更捕获
+//!
+//! 表结构在 `migrations/` 目录下, 通过 [MIGRATOR] 执行.
+
+pub mod cdc;
+pub mod query;
+pub mod types;
+pub mod user;
+
+use sqlx::migrate::Migrator;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// 连接数据库并执行迁移
+pub async fn connect(url: &str) -> Result<PgPool, sqlx::Error> {
+    let pool = PgPoolOptions::new().max_connections(5).connect(url).await?;
+    MIGRATOR.run(&pool).await?;
+    Ok(pool)
+}
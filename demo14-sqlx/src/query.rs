@@ -0,0 +1,145 @@
+//! 动态查询
+//!
+//! 条件是运行时才确定的, 但不能用 `format!` 拼接用户输入, 否则就是 SQL 注入.
+//! 这里用 `sqlx::QueryBuilder`: 结构 (列名, 运算符) 由代码决定, 值一律通过 `push_bind` 绑定为参数.
+//! 排序列也不接受字符串, 只能从 [SortField] 中选择.
+
+use sqlx::{PgExecutor, Postgres, QueryBuilder};
+
+use crate::types::UserRole;
+use crate::user::{User, USER_COLUMNS};
+
+/// 可排序的列, 每一项都对应一个固定的列名
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortField {
+    #[default]
+    Id,
+    Name,
+    Age,
+}
+
+impl SortField {
+    fn column(self) -> &'static str {
+        match self {
+            SortField::Id => "id",
+            SortField::Name => "name",
+            SortField::Age => "age",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UserQuery {
+    name_contains: Option<String>,
+    roles: Vec<UserRole>,
+    min_age: Option<i32>,
+    max_age: Option<i32>,
+    tag: Option<String>,
+    sort: SortField,
+    descending: bool,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+impl UserQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 名字包含, 不区分大小写. `%` 和 `_` 会被转义, 按字面匹配.
+    pub fn name_contains(mut self, name: impl Into<String>) -> Self {
+        self.name_contains = Some(name.into());
+        self
+    }
+
+    pub fn role(mut self, role: UserRole) -> Self {
+        self.roles.push(role);
+        self
+    }
+
+    pub fn min_age(mut self, age: i32) -> Self {
+        self.min_age = Some(age);
+        self
+    }
+
+    pub fn max_age(mut self, age: i32) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    pub fn order_by(mut self, field: SortField, descending: bool) -> Self {
+        self.sort = field;
+        self.descending = descending;
+        self
+    }
+
+    pub fn limit(mut self, limit: i64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn offset(mut self, offset: i64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// 生成 SQL, 可以通过 `builder.sql()` 查看最终语句.
+    pub fn build(&self) -> QueryBuilder<Postgres> {
+        let mut builder = QueryBuilder::new(format!("SELECT {USER_COLUMNS} FROM users WHERE 1=1"));
+
+        if let Some(name) = &self.name_contains {
+            builder.push(" AND name ILIKE ").push_bind(format!("%{}%", escape_like(name)));
+        }
+        if !self.roles.is_empty() {
+            builder.push(" AND role = ANY(").push_bind(self.roles.clone()).push(")");
+        }
+        if let Some(min_age) = self.min_age {
+            builder.push(" AND age >= ").push_bind(min_age);
+        }
+        if let Some(max_age) = self.max_age {
+            builder.push(" AND age <= ").push_bind(max_age);
+        }
+        if let Some(tag) = &self.tag {
+            builder.push(" AND ").push_bind(tag.as_str()).push(" = ANY(tags)");
+        }
+
+        builder
+            .push(" ORDER BY ")
+            .push(self.sort.column())
+            .push(if self.descending { " DESC" } else { " ASC" });
+        // 保证排序稳定, 分页时不会出现重复或遗漏
+        if self.sort != SortField::Id {
+            builder.push(", id ASC");
+        }
+
+        if let Some(limit) = self.limit {
+            builder.push(" LIMIT ").push_bind(limit);
+        }
+        if let Some(offset) = self.offset {
+            builder.push(" OFFSET ").push_bind(offset);
+        }
+
+        builder
+    }
+
+    pub async fn fetch_all<'e, E: PgExecutor<'e>>(&self, executor: E) -> Result<Vec<User>, sqlx::Error> {
+        let mut builder = self.build();
+        builder.build_query_as::<User>().fetch_all(executor).await
+    }
+}
+
+fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
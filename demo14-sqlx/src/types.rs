@@ -0,0 +1,136 @@
+//! Postgres 自定义类型与 Rust 类型的映射
+//!
+//! | Postgres          | Rust                  | 方式                          |
+//! | :---------------- | :-------------------- | :---------------------------- |
+//! | `user_role` 枚举  | [UserRole]            | `#[derive(sqlx::Type)]`       |
+//! | `address` 复合类型 | [Address]             | `#[derive(sqlx::Type)]`       |
+//! | `TEXT` (邮箱)     | [Email]               | 手动实现 `Type/Encode/Decode` |
+//! | `TEXT[]`          | `Vec<String>`         | sqlx 内置                     |
+//! | `JSONB`           | `Json<Profile>`       | `sqlx::types::Json`           |
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::postgres::{PgArgumentBuffer, PgHasArrayType, PgTypeInfo, PgValueRef};
+use sqlx::{Decode, Encode, Postgres, Type};
+
+/// 对应 `CREATE TYPE user_role AS ENUM (...)`
+///
+/// derive 会同时实现 `PgHasArrayType`, 所以 `Vec<UserRole>` 可以直接绑定为 `user_role[]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[sqlx(type_name = "user_role", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum UserRole {
+    Admin,
+    Member,
+    Guest,
+}
+
+/// 对应 `CREATE TYPE address AS (street, city, zip)`, 字段顺序必须和数据库中一致.
+///
+/// 和 [UserRole] 一样, derive 也实现了 `PgHasArrayType`, 批量插入时 `Vec<Option<Address>>` 绑定为 `address[]`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[sqlx(type_name = "address")]
+pub struct Address {
+    pub street: Option<String>,
+    pub city: Option<String>,
+    pub zip: Option<String>,
+}
+
+/// 存放在 `profile JSONB` 中的数据, 读写时用 `sqlx::types::Json<Profile>` 包一层.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bio: Option<String>,
+    #[serde(default)]
+    pub theme: String,
+    #[serde(default)]
+    pub newsletter: bool,
+}
+
+/// 邮箱, 数据库中是普通的 `TEXT`.
+///
+/// 手动实现 `Type/Encode/Decode`, 这样在编码和解码时都可以做校验,
+/// 不合法的邮箱既写不进去, 也读不出来.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Email(String);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidEmail(pub String);
+
+impl fmt::Display for InvalidEmail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid email: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidEmail {}
+
+impl Email {
+    pub fn parse(value: impl Into<String>) -> Result<Self, InvalidEmail> {
+        let value = value.into();
+        match value.split_once('@') {
+            Some((user, domain)) if !user.is_empty() && domain.contains('.') && !domain.starts_with('.') && !domain.ends_with('.') => {
+                Ok(Email(value))
+            }
+            _ => Err(InvalidEmail(value)),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Email {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl TryFrom<String> for Email {
+    type Error = InvalidEmail;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Email::parse(value)
+    }
+}
+
+impl From<Email> for String {
+    fn from(email: Email) -> Self {
+        email.0
+    }
+}
+
+impl Type<Postgres> for Email {
+    fn type_info() -> PgTypeInfo {
+        <String as Type<Postgres>>::type_info()
+    }
+
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        <String as Type<Postgres>>::compatible(ty)
+    }
+}
+
+/// `Vec<Email>` 绑定为 `TEXT[]`, 批量插入时 `UNNEST` 需要它.
+impl PgHasArrayType for Email {
+    fn array_type_info() -> PgTypeInfo {
+        <String as PgHasArrayType>::array_type_info()
+    }
+}
+
+impl Encode<'_, Postgres> for Email {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull, BoxDynError> {
+        <&str as Encode<Postgres>>::encode(self.0.as_str(), buf)
+    }
+}
+
+impl Decode<'_, Postgres> for Email {
+    fn decode(value: PgValueRef<'_>) -> Result<Self, BoxDynError> {
+        let raw = <String as Decode<Postgres>>::decode(value)?;
+        Ok(Email::parse(raw)?)
+    }
+}
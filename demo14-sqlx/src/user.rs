@@ -0,0 +1,143 @@
+//! `users` 表的数据访问
+
+use futures::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use sqlx::{FromRow, PgExecutor};
+
+use crate::types::{Address, Email, Profile, UserRole};
+
+/// 查询时使用的列, 保证 `SELECT` 的顺序和 [User] 字段一致
+pub(crate) const USER_COLUMNS: &str = "id, name, email, role, age, address, tags, profile";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct User {
+    pub id: i32,
+    pub name: String,
+    pub email: Option<Email>,
+    pub role: UserRole,
+    pub age: Option<i32>,
+    pub address: Option<Address>,
+    pub tags: Vec<String>,
+    pub profile: Json<Profile>,
+}
+
+/// 新建用户时需要的字段, `id` 由数据库生成
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NewUser {
+    pub name: String,
+    pub email: Option<Email>,
+    pub role: UserRole,
+    pub age: Option<i32>,
+    pub address: Option<Address>,
+    pub tags: Vec<String>,
+    pub profile: Profile,
+}
+
+impl NewUser {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            email: None,
+            role: UserRole::Member,
+            age: None,
+            address: None,
+            tags: Vec::new(),
+            profile: Profile::default(),
+        }
+    }
+}
+
+/// 插入单个用户, 返回完整的行
+pub async fn insert<'e, E: PgExecutor<'e>>(executor: E, user: &NewUser) -> Result<User, sqlx::Error> {
+    let sql = format!(
+        "INSERT INTO users (name, email, role, age, address, tags, profile) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING {USER_COLUMNS}"
+    );
+    sqlx::query_as::<_, User>(sqlx::AssertSqlSafe(sql))
+        .bind(&user.name)
+        .bind(&user.email)
+        .bind(user.role)
+        .bind(user.age)
+        .bind(&user.address)
+        .bind(&user.tags)
+        .bind(Json(&user.profile))
+        .fetch_one(executor)
+        .await
+}
+
+/// 批量插入
+///
+/// 不再循环执行 N 次 `INSERT`, 而是把每一列打包成数组, 用 `UNNEST` 展开成多行, 一次往返完成.
+/// 有两列不能直接交给 `UNNEST`:
+/// - `address`: 复合类型的数组会被 `UNNEST` 展开成 street/city/zip 三列, 所以 `address[]` 单独绑定,
+///   按 `WITH ORDINALITY` 得到的行号取下标.
+/// - `tags`: `UNNEST` 会把多维数组整个摊平, `TEXT[]` 没法打包成 `text[][]`, 所以每行的标签先编码成
+///   一个 JSONB 数组, 展开后再按原来的顺序转回 `TEXT[]`.
+pub async fn insert_batch<'e, E: PgExecutor<'e>>(executor: E, users: &[NewUser]) -> Result<Vec<User>, sqlx::Error> {
+    if users.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut names = Vec::with_capacity(users.len());
+    let mut emails = Vec::with_capacity(users.len());
+    let mut roles = Vec::with_capacity(users.len());
+    let mut ages = Vec::with_capacity(users.len());
+    let mut addresses = Vec::with_capacity(users.len());
+    let mut tags = Vec::with_capacity(users.len());
+    let mut profiles = Vec::with_capacity(users.len());
+    for user in users {
+        names.push(user.name.as_str());
+        emails.push(user.email.clone());
+        roles.push(user.role);
+        ages.push(user.age);
+        addresses.push(user.address.clone());
+        tags.push(Json(&user.tags));
+        profiles.push(Json(&user.profile));
+    }
+
+    let sql = format!(
+        "INSERT INTO users (name, email, role, age, address, tags, profile) \
+         SELECT name, email, role, age, ($5::address[])[i], \
+                ARRAY(SELECT tag FROM jsonb_array_elements_text(tags) WITH ORDINALITY AS t(tag, n) ORDER BY n), \
+                profile \
+         FROM UNNEST($1::text[], $2::text[], $3::user_role[], $4::int[], $6::jsonb[], $7::jsonb[]) \
+              WITH ORDINALITY AS batch(name, email, role, age, tags, profile, i) \
+         ORDER BY i \
+         RETURNING {USER_COLUMNS}"
+    );
+    sqlx::query_as::<_, User>(sqlx::AssertSqlSafe(sql))
+        .bind(names)
+        .bind(emails)
+        .bind(roles)
+        .bind(ages)
+        .bind(addresses)
+        .bind(tags)
+        .bind(profiles)
+        .fetch_all(executor)
+        .await
+}
+
+pub async fn get<'e, E: PgExecutor<'e>>(executor: E, id: i32) -> Result<Option<User>, sqlx::Error> {
+    let sql = format!("SELECT {USER_COLUMNS} FROM users WHERE id = $1");
+    sqlx::query_as::<_, User>(sqlx::AssertSqlSafe(sql))
+        .bind(id)
+        .fetch_optional(executor)
+        .await
+}
+
+pub async fn delete<'e, E: PgExecutor<'e>>(executor: E, id: i32) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM users WHERE id = $1")
+        .bind(id)
+        .execute(executor)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// 流式查询
+///
+/// 和 `fetch_all` 不同, 行是一条一条从连接里读出来的, 不会一次性把整张表加载进内存.
+pub fn stream_all<'e, E: PgExecutor<'e> + 'e>(executor: E) -> BoxStream<'e, Result<User, sqlx::Error>> {
+    let sql = format!("SELECT {USER_COLUMNS} FROM users ORDER BY id");
+    sqlx::query_as::<_, User>(sqlx::AssertSqlSafe(sql)).fetch(executor)
+}
@@ -0,0 +1,2 @@
+pub mod observable;
+pub mod state;
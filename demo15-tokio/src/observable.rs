@@ -0,0 +1,413 @@
+//! 可订阅的共享 Map
+//!
+//! - 快照隔离: [ObservableMap::snapshot] 返回某个版本的只读快照, 之后的写入不会影响它 (写时复制).
+//! - 版本号: 每次产生实际变更的写入都会让版本号加一, 版本号单调递增.
+//! - 订阅: [ObservableMap::subscribe] 先给出一个一致的快照, 之后只推送增量 ([Delta]).
+//!   订阅时取快照和注册接收端在同一把锁内完成, 所以不会漏掉, 也不会重复任何变更.
+//!   如果订阅者处理得太慢错过了增量, 会重新收到一个快照, 而不是悄悄丢数据.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::ops::Deref;
+use std::sync::{Arc, RwLock, Weak};
+
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use tokio::sync::{broadcast, watch};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change<K, V> {
+    Added { key: K, value: V },
+    Updated { key: K, old: V, new: V },
+    Removed { key: K, old: V },
+}
+
+impl<K, V> Change<K, V> {
+    pub fn key(&self) -> &K {
+        match self {
+            Change::Added { key, .. } | Change::Updated { key, .. } | Change::Removed { key, .. } => key,
+        }
+    }
+}
+
+/// 一次写入产生的所有变更, 对应一个版本
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delta<K, V> {
+    pub version: u64,
+    pub changes: Vec<Change<K, V>>,
+}
+
+/// 某个版本的只读快照
+#[derive(Debug)]
+pub struct Snapshot<K, V> {
+    version: u64,
+    map: Arc<HashMap<K, V>>,
+}
+
+impl<K, V> Snapshot<K, V> {
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+impl<K, V> Clone for Snapshot<K, V> {
+    fn clone(&self) -> Self {
+        Self { version: self.version, map: self.map.clone() }
+    }
+}
+
+impl<K, V> Deref for Snapshot<K, V> {
+    type Target = HashMap<K, V>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Update<K, V> {
+    /// 订阅后的第一条消息, 以及错过增量之后的重新同步
+    Snapshot(Snapshot<K, V>),
+    Delta(Arc<Delta<K, V>>),
+}
+
+impl<K, V> Update<K, V> {
+    pub fn version(&self) -> u64 {
+        match self {
+            Update::Snapshot(snapshot) => snapshot.version,
+            Update::Delta(delta) => delta.version,
+        }
+    }
+}
+
+struct Inner<K, V> {
+    version: u64,
+    map: Arc<HashMap<K, V>>,
+}
+
+pub struct ObservableMap<K, V> {
+    inner: RwLock<Inner<K, V>>,
+    version: watch::Sender<u64>,
+    deltas: broadcast::Sender<Arc<Delta<K, V>>>,
+}
+
+impl<K, V> ObservableMap<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: Clone + PartialEq + Send + Sync + 'static,
+{
+    pub fn new() -> Arc<Self> {
+        Self::with_capacity(64)
+    }
+
+    /// `capacity` 是每个订阅者最多积压的增量数, 超过后订阅者会重新收到快照
+    pub fn with_capacity(capacity: usize) -> Arc<Self> {
+        let (version, _) = watch::channel(0);
+        let (deltas, _) = broadcast::channel(capacity);
+        Arc::new(Self {
+            inner: RwLock::new(Inner { version: 0, map: Arc::new(HashMap::new()) }),
+            version,
+            deltas,
+        })
+    }
+
+    pub fn version(&self) -> u64 {
+        self.inner.read().unwrap().version
+    }
+
+    pub fn snapshot(&self) -> Snapshot<K, V> {
+        let inner = self.inner.read().unwrap();
+        Snapshot { version: inner.version, map: inner.map.clone() }
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner.read().unwrap().map.get(key).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.read().unwrap().map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<Delta<K, V>> {
+        self.write(|tx| tx.insert(key, value))
+    }
+
+    pub fn remove(&self, key: &K) -> Option<Delta<K, V>> {
+        self.write(|tx| tx.remove(key))
+    }
+
+    /// 用 `entries` 替换全部内容, 只发布实际变化的部分
+    pub fn replace_all(&self, entries: HashMap<K, V>) -> Option<Delta<K, V>> {
+        self.write(|tx| tx.replace_all(entries))
+    }
+
+    /// 在一个版本中完成多次修改. 没有实际变更时版本号不变, 返回 `None`.
+    pub fn write(&self, f: impl FnOnce(&mut Transaction<'_, K, V>)) -> Option<Delta<K, V>> {
+        let mut inner = self.inner.write().unwrap();
+        let mut tx = Transaction { map: &mut inner.map, changes: Vec::new() };
+        f(&mut tx);
+        let changes = tx.changes;
+        if changes.is_empty() {
+            return None;
+        }
+
+        inner.version += 1;
+        let delta = Delta { version: inner.version, changes };
+        // 仍然持有写锁, 保证订阅者看到的增量顺序与版本号一致
+        self.version.send_replace(inner.version);
+        let _ = self.deltas.send(Arc::new(delta.clone()));
+        Some(delta)
+    }
+
+    /// 只关心版本号时使用
+    pub fn subscribe_version(&self) -> watch::Receiver<u64> {
+        self.version.subscribe()
+    }
+
+    pub fn subscribe(self: &Arc<Self>) -> Subscription<K, V> {
+        let inner = self.inner.read().unwrap();
+        let rx = self.deltas.subscribe();
+        let snapshot = Snapshot { version: inner.version, map: inner.map.clone() };
+        Subscription { map: Arc::downgrade(self), rx, pending: Some(snapshot) }
+    }
+}
+
+/// [ObservableMap::write] 中使用, 记录每一次修改
+pub struct Transaction<'a, K, V> {
+    map: &'a mut Arc<HashMap<K, V>>,
+    changes: Vec<Change<K, V>>,
+}
+
+impl<K, V> Transaction<'_, K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone + PartialEq,
+{
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.map.get(key)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.map.get(&key) == Some(&value) {
+            return;
+        }
+        // 有快照引用旧的 Map 时, 这里会复制一份
+        let old = Arc::make_mut(self.map).insert(key.clone(), value.clone());
+        self.changes.push(match old {
+            None => Change::Added { key, value },
+            Some(old) => Change::Updated { key, old, new: value },
+        });
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        if !self.map.contains_key(key) {
+            return;
+        }
+        if let Some(old) = Arc::make_mut(self.map).remove(key) {
+            self.changes.push(Change::Removed { key: key.clone(), old });
+        }
+    }
+
+    pub fn replace_all(&mut self, entries: HashMap<K, V>) {
+        let removed: Vec<K> = self.map.keys().filter(|k| !entries.contains_key(*k)).cloned().collect();
+        for key in &removed {
+            self.remove(key);
+        }
+        for (key, value) in entries {
+            self.insert(key, value);
+        }
+    }
+}
+
+pub struct Subscription<K, V> {
+    map: Weak<ObservableMap<K, V>>,
+    rx: broadcast::Receiver<Arc<Delta<K, V>>>,
+    pending: Option<Snapshot<K, V>>,
+}
+
+impl<K, V> Subscription<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: Clone + PartialEq + Send + Sync + 'static,
+{
+    /// 第一次调用返回快照, 之后返回增量. `ObservableMap` 被 drop 后返回 `None`.
+    pub async fn next(&mut self) -> Option<Update<K, V>> {
+        if let Some(snapshot) = self.pending.take() {
+            return Some(Update::Snapshot(snapshot));
+        }
+        match self.rx.recv().await {
+            Ok(delta) => Some(Update::Delta(delta)),
+            Err(broadcast::error::RecvError::Lagged(_)) => {
+                let map = self.map.upgrade()?;
+                let fresh = map.subscribe();
+                drop(map);
+                *self = fresh;
+                self.pending.take().map(Update::Snapshot)
+            }
+            Err(broadcast::error::RecvError::Closed) => None,
+        }
+    }
+
+    pub fn into_stream(self) -> BoxStream<'static, Update<K, V>> {
+        futures::stream::unfold(self, |mut sub| async move { sub.next().await.map(|update| (update, sub)) }).boxed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_snapshot_isolation() {
+        let map = ObservableMap::new();
+        map.insert("a", 1);
+        let snapshot = map.snapshot();
+        map.insert("a", 2);
+        map.insert("b", 3);
+
+        assert_eq!(snapshot.version(), 1);
+        assert_eq!(snapshot.get("a"), Some(&1));
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(map.get("a"), Some(2));
+        assert_eq!(map.version(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_versions_and_changes() {
+        let map = ObservableMap::new();
+        assert_eq!(map.insert(1, "x").unwrap().changes, vec![Change::Added { key: 1, value: "x" }]);
+        // 值没有变化, 不产生新版本
+        assert_eq!(map.insert(1, "x"), None);
+        assert_eq!(map.remove(&2), None);
+        assert_eq!(map.version(), 1);
+
+        let delta = map
+            .write(|tx| {
+                tx.insert(1, "y");
+                tx.insert(2, "z");
+                tx.remove(&1);
+            })
+            .unwrap();
+        assert_eq!(delta.version, 2);
+        assert_eq!(
+            delta.changes,
+            vec![
+                Change::Updated { key: 1, old: "x", new: "y" },
+                Change::Added { key: 2, value: "z" },
+                Change::Removed { key: 1, old: "y" },
+            ]
+        );
+
+        let delta = map.replace_all(HashMap::from([(2, "z"), (3, "w")])).unwrap();
+        assert_eq!(delta.version, 3);
+        assert_eq!(delta.changes, vec![Change::Added { key: 3, value: "w" }]);
+    }
+
+    #[tokio::test]
+    async fn test_late_subscriber() {
+        let map = ObservableMap::new();
+        map.insert("a", 1);
+        map.insert("b", 2);
+
+        let mut sub = map.subscribe();
+        map.insert("c", 3);
+        map.remove(&"a");
+
+        let Some(Update::Snapshot(snapshot)) = sub.next().await else { panic!("expected snapshot") };
+        assert_eq!(snapshot.version(), 2);
+        assert_eq!(*snapshot, HashMap::from([("a", 1), ("b", 2)]));
+
+        let Some(Update::Delta(delta)) = sub.next().await else { panic!("expected delta") };
+        assert_eq!(*delta, Delta { version: 3, changes: vec![Change::Added { key: "c", value: 3 }] });
+        let Some(Update::Delta(delta)) = sub.next().await else { panic!("expected delta") };
+        assert_eq!(*delta, Delta { version: 4, changes: vec![Change::Removed { key: "a", old: 1 }] });
+    }
+
+    #[tokio::test]
+    async fn test_lagged_subscriber_resyncs() {
+        let map = ObservableMap::with_capacity(4);
+        let mut sub = map.subscribe();
+        assert!(matches!(sub.next().await, Some(Update::Snapshot(_))));
+
+        for i in 0..10 {
+            map.insert(i, i);
+        }
+        let Some(Update::Snapshot(snapshot)) = sub.next().await else { panic!("expected snapshot") };
+        assert_eq!(snapshot.version(), 10);
+        assert_eq!(snapshot.len(), 10);
+
+        map.insert(10, 10);
+        let update = sub.next().await.unwrap();
+        assert_eq!(update.version(), 11);
+    }
+
+    #[tokio::test]
+    async fn test_subscription_ends_when_map_dropped() {
+        let map = ObservableMap::<u32, u32>::new();
+        let mut stream = map.subscribe().into_stream();
+        assert!(matches!(stream.next().await, Some(Update::Snapshot(_))));
+        drop(map);
+        assert!(stream.next().await.is_none());
+    }
+
+    /// 并发写入时, 订阅者把快照和增量合并后得到的结果与最终状态一致
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_writers_converge() {
+        let map = ObservableMap::with_capacity(1024);
+        let mut sub = map.subscribe();
+
+        let writers: Vec<_> = (0..4)
+            .map(|w| {
+                let map = map.clone();
+                tokio::spawn(async move {
+                    for i in 0..200u32 {
+                        map.insert(i % 50, w * 1000 + i);
+                        if i % 7 == 0 {
+                            map.remove(&(i % 50));
+                        }
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.await.unwrap();
+        }
+
+        let target = map.version();
+        let mut local = HashMap::new();
+        let mut last_version = 0;
+        while last_version < target {
+            match sub.next().await.unwrap() {
+                Update::Snapshot(snapshot) => {
+                    local = (*snapshot).clone();
+                    last_version = snapshot.version();
+                }
+                Update::Delta(delta) => {
+                    assert_eq!(delta.version, last_version + 1);
+                    last_version = delta.version;
+                    for change in &delta.changes {
+                        match change {
+                            Change::Added { key, value } | Change::Updated { key, new: value, .. } => {
+                                local.insert(*key, *value);
+                            }
+                            Change::Removed { key, .. } => {
+                                local.remove(key);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        assert_eq!(local, *map.snapshot());
+    }
+}
@@ -0,0 +1,256 @@
+//! `_sqlx_mapping` 表在内存中的只读缓存
+//!
+//! 数据来源于 Postgres 触发器的通知 (见 `demo14_sqlx::cdc`), 而不是手动调用 `update_mapping`:
+//!
+//! - 启动时监听建立后的第一次 `Resync` 就是全量加载, [AppState::load] 会等它完成才返回.
+//! - 之后的 INSERT / UPDATE / DELETE 通知逐条应用到缓存中.
+//! - 重连后的 `Resync` 与当前缓存做对比, 只把真正变化的部分作为增量发出.
+//!
+//! 版本号和订阅都由 [ObservableMap] 提供, 见 [crate::observable].
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use demo14_sqlx::cdc::{ChangeListener, TableChangeEvent, TableSpec};
+use serde::Deserialize;
+use serde_json::Value;
+use sqlx::PgPool;
+use tokio::sync::{mpsc, watch};
+
+use crate::observable::{Change, ObservableMap, Snapshot, Subscription, Update};
+
+pub const MAPPING_TABLE: &str = "_sqlx_mapping";
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SqlxMapping {
+    #[serde(deserialize_with = "deserialize_oid")]
+    pub oid: u32,
+    pub table_name: String,
+}
+
+pub type MappingChange = Change<u32, SqlxMapping>;
+pub type MappingUpdate = Update<u32, SqlxMapping>;
+
+pub struct AppState {
+    mappings: Arc<ObservableMap<u32, SqlxMapping>>,
+}
+
+impl AppState {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self { mappings: ObservableMap::new() })
+    }
+
+    /// 安装触发器, 全量加载 `_sqlx_mapping`, 然后在后台持续应用变更通知.
+    pub async fn load(pool: PgPool) -> Result<Arc<Self>, sqlx::Error> {
+        let listener = ChangeListener::new(pool, "sqlx_mapping_changes").table(TableSpec::new(MAPPING_TABLE, &["oid"]));
+        listener.install().await?;
+        let events = listener.start().await?;
+        Ok(Self::from_events(events).await)
+    }
+
+    /// 等待第一次全量同步完成后返回, 后台任务随 `events` 结束而退出.
+    pub async fn from_events(mut events: mpsc::Receiver<TableChangeEvent>) -> Arc<Self> {
+        let state = Self::new();
+        while let Some(event) = events.recv().await {
+            let is_resync = matches!(event, TableChangeEvent::Resync { .. });
+            state.apply(event);
+            if is_resync {
+                break;
+            }
+        }
+
+        let weak = Arc::downgrade(&state);
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                let Some(state) = weak.upgrade() else { break };
+                state.apply(event);
+            }
+        });
+        state
+    }
+
+    /// 把一条变更通知应用到缓存中
+    pub fn apply(&self, event: TableChangeEvent) {
+        if event.table() != MAPPING_TABLE {
+            return;
+        }
+        match event {
+            TableChangeEvent::Insert { new, .. } | TableChangeEvent::Update { new, .. } => {
+                if let Some(new) = parse(new) {
+                    self.mappings.insert(new.oid, new);
+                }
+            }
+            TableChangeEvent::Delete { key, .. } => match key.get("oid").and_then(parse_oid) {
+                Some(oid) => {
+                    self.mappings.remove(&oid);
+                }
+                None => println!("Invalid delete key: {}", key),
+            },
+            TableChangeEvent::Resync { rows, .. } => {
+                let snapshot = rows.into_iter().filter_map(parse).map(|m| (m.oid, m)).collect();
+                self.mappings.replace_all(snapshot);
+            }
+        }
+    }
+
+    /// 手动更新, 与数据库通知走同一条增量通道
+    pub fn update_mapping(&self, mapping: SqlxMapping) {
+        self.mappings.insert(mapping.oid, mapping);
+    }
+
+    pub fn get(&self, oid: u32) -> Option<SqlxMapping> {
+        self.mappings.get(&oid)
+    }
+
+    pub fn get_all(&self) -> HashMap<u32, SqlxMapping> {
+        (*self.mappings.snapshot()).clone()
+    }
+
+    /// 不复制数据的只读快照
+    pub fn snapshot(&self) -> Snapshot<u32, SqlxMapping> {
+        self.mappings.snapshot()
+    }
+
+    pub fn version(&self) -> u64 {
+        self.mappings.version()
+    }
+
+    /// 版本号, 只关心 "有没有变" 时使用
+    pub fn subscribe_version(&self) -> watch::Receiver<u64> {
+        self.mappings.subscribe_version()
+    }
+
+    /// 先收到当前的全量快照, 之后是增量. 在 `AppState` 被 drop 之前不会结束.
+    pub fn subscribe_changes(&self) -> Subscription<u32, SqlxMapping> {
+        self.mappings.subscribe()
+    }
+}
+
+fn parse(row: Value) -> Option<SqlxMapping> {
+    match serde_json::from_value(row.clone()) {
+        Ok(mapping) => Some(mapping),
+        Err(e) => {
+            println!("Invalid mapping row {}: {}", row, e);
+            None
+        }
+    }
+}
+
+/// `oid` 类型的列经过 `to_jsonb` 之后是字符串, 例如 `{"oid": "16384"}`, 这里两种形式都接受
+fn parse_oid(value: &Value) -> Option<u32> {
+    match value {
+        Value::Number(n) => n.as_u64().and_then(|n| u32::try_from(n).ok()),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+fn deserialize_oid<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let value = Value::deserialize(deserializer)?;
+    parse_oid(&value).ok_or_else(|| serde::de::Error::custom(format!("invalid oid: {}", value)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn mapping(oid: u32, table_name: &str) -> SqlxMapping {
+        SqlxMapping { oid, table_name: table_name.to_string() }
+    }
+
+    fn resync(rows: Vec<Value>) -> TableChangeEvent {
+        TableChangeEvent::Resync { table: MAPPING_TABLE.to_string(), rows }
+    }
+
+    async fn next_changes(sub: &mut Subscription<u32, SqlxMapping>) -> (u64, Vec<MappingChange>) {
+        match sub.next().await {
+            Some(Update::Delta(delta)) => (delta.version, delta.changes.clone()),
+            update => panic!("unexpected update {:?}", update),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_load_and_apply() {
+        let (tx, rx) = mpsc::channel(8);
+        tx.send(resync(vec![json!({"oid": "1", "table_name": "users"})])).await.unwrap();
+        let state = AppState::from_events(rx).await;
+        assert_eq!(state.get(1), Some(mapping(1, "users")));
+        assert_eq!(state.version(), 1);
+
+        let mut changes = state.subscribe_changes();
+        let mut version = state.subscribe_version();
+        let Some(Update::Snapshot(snapshot)) = changes.next().await else { panic!("expected snapshot") };
+        assert_eq!(snapshot.version(), 1);
+        assert_eq!(*snapshot, HashMap::from([(1, mapping(1, "users"))]));
+
+        tx.send(TableChangeEvent::Insert {
+            table: MAPPING_TABLE.to_string(),
+            key: json!({"oid": 2}),
+            new: json!({"oid": 2, "table_name": "books"}),
+        })
+        .await
+        .unwrap();
+        assert_eq!(next_changes(&mut changes).await, (2, vec![Change::Added { key: 2, value: mapping(2, "books") }]));
+        version.changed().await.unwrap();
+        assert_eq!(*version.borrow_and_update(), 2);
+
+        tx.send(TableChangeEvent::Update {
+            table: MAPPING_TABLE.to_string(),
+            key: json!({"oid": 2}),
+            old: None,
+            new: json!({"oid": 2, "table_name": "authors"}),
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            next_changes(&mut changes).await,
+            (3, vec![Change::Updated { key: 2, old: mapping(2, "books"), new: mapping(2, "authors") }])
+        );
+
+        tx.send(TableChangeEvent::Delete { table: MAPPING_TABLE.to_string(), key: json!({"oid": "1"}), old: json!({"oid": "1"}) })
+            .await
+            .unwrap();
+        assert_eq!(next_changes(&mut changes).await, (4, vec![Change::Removed { key: 1, old: mapping(1, "users") }]));
+        assert_eq!(state.get_all(), HashMap::from([(2, mapping(2, "authors"))]));
+    }
+
+    /// 重连后的全量同步只产生真正变化的增量
+    #[tokio::test]
+    async fn test_resync_diff() {
+        let state = AppState::new();
+        state.apply(resync(vec![json!({"oid": 1, "table_name": "a"}), json!({"oid": 2, "table_name": "b"})]));
+        let mut changes = state.subscribe_changes();
+        assert!(matches!(changes.next().await, Some(Update::Snapshot(_))));
+
+        state.apply(resync(vec![json!({"oid": 1, "table_name": "a"}), json!({"oid": 3, "table_name": "c"})]));
+        let (version, mut changes) = next_changes(&mut changes).await;
+        changes.sort_by_key(|c| *c.key());
+        assert_eq!(version, 2);
+        assert_eq!(
+            changes,
+            vec![Change::Removed { key: 2, old: mapping(2, "b") }, Change::Added { key: 3, value: mapping(3, "c") }]
+        );
+
+        // 内容相同的全量同步不产生新版本
+        state.apply(resync(vec![json!({"oid": 1, "table_name": "a"}), json!({"oid": 3, "table_name": "c"})]));
+        assert_eq!(state.version(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_lagged_subscriber_gets_snapshot() {
+        let state = AppState::new();
+        let mut changes = state.subscribe_changes();
+        assert!(matches!(changes.next().await, Some(Update::Snapshot(_))));
+        for oid in 0..100 {
+            state.update_mapping(mapping(oid, "t"));
+        }
+        match changes.next().await {
+            Some(Update::Snapshot(snapshot)) => {
+                assert_eq!(snapshot.version(), 100);
+                assert_eq!(snapshot.len(), 100);
+            }
+            update => panic!("unexpected update {:?}", update),
+        }
+    }
+}